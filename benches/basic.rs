@@ -1,6 +1,7 @@
 use criterion::{black_box, criterion_group, Criterion};
 use rustc_hash::FxHashSet;
 use stost::connection::StopInfo;
+use stost::distribution::Distribution;
 use stost::distribution_store;
 use stost::gtfs;
 use stost::query::topocsa;
@@ -11,6 +12,7 @@ use stost::wire::serde;
 fn from_relevant(c: &mut Criterion) {
     let mut store = distribution_store::Store::new();
     store.load_distributions("./data/de_db.csv");
+    let ticks_per_minute = store.ticks_per_minute();
 
     let bytes: Vec<u8> = serde::read_protobuf("./tests/fixtures/basic.pb");
     let mut stations = vec![];
@@ -19,14 +21,14 @@ fn from_relevant(c: &mut Criterion) {
     let mut order = vec![];
     let mut cut = FxHashSet::default();
     let meta =
-        serde::deserialize_protobuf(bytes, &mut stations, &mut routes, &mut connections, false);
+        serde::deserialize_protobuf(bytes, &mut stations, &mut routes, &mut connections, false, ticks_per_minute);
     let mut env = topocsa::prepare(
         &mut store,
         &mut connections,
         &stations,
         &mut cut,
         &mut order,
-        serde::to_mtime(meta.now, meta.start_ts),
+        serde::to_mtime(meta.now, meta.start_ts, ticks_per_minute),
         0.0,
         true,
     );
@@ -47,6 +49,7 @@ fn from_relevant(c: &mut Criterion) {
 fn measure_prepare(c: &mut Criterion) {
     let mut store = distribution_store::Store::new();
     store.load_distributions("./data/de_db.csv");
+    let ticks_per_minute = store.ticks_per_minute();
 
     let bytes: Vec<u8> = serde::read_protobuf("./tests/fixtures/basic.pb");
     let mut stations = vec![];
@@ -55,7 +58,7 @@ fn measure_prepare(c: &mut Criterion) {
     let mut cut = FxHashSet::default();
 
     let meta =
-        serde::deserialize_protobuf(bytes, &mut stations, &mut routes, &mut connections, false);
+        serde::deserialize_protobuf(bytes, &mut stations, &mut routes, &mut connections, false, ticks_per_minute);
 
     let mut group = c.benchmark_group("once");
     group.bench_function("measure_prepare", |b| {
@@ -67,7 +70,7 @@ fn measure_prepare(c: &mut Criterion) {
                 black_box(&stations),
                 black_box(&mut cut),
                 black_box(&mut order),
-                black_box(serde::to_mtime(meta.now, meta.start_ts)),
+                black_box(serde::to_mtime(meta.now, meta.start_ts, ticks_per_minute)),
                 black_box(0.0),
                 black_box(true),
             );
@@ -80,7 +83,7 @@ fn from_gtfs(c: &mut Criterion) {
     let mut store = distribution_store::Store::new();
     store.load_distributions("./data/ch_sbb.csv");
 
-    let mut tt = gtfs::load_gtfs_cache("./tests/fixtures/timetable.ign.cache");
+    let mut tt = gtfs::load_gtfs_cache("./tests/fixtures/timetable.ign.cache", store.ticks_per_minute());
     let mut env = topocsa::Environment::new(
         &mut store,
         &mut tt.connections,
@@ -94,7 +97,7 @@ fn from_gtfs(c: &mut Criterion) {
         false,
     );
     let contr = gtfs::get_station_contraction(&tt.stations);
-    env.set_station_contraction(&contr);
+    env.set_station_contraction(&contr).unwrap();
     let q = Query {
         origin_idx: 10000,
         destination_idx: 20000,
@@ -124,10 +127,57 @@ fn before_probability(c: &mut Criterion) {
     group.finish();
 }
 
+fn wide_distributions(count: usize) -> Vec<Distribution> {
+    (0..count)
+        .map(|i| {
+            let mut d = Distribution::uniform(i as i32 * 7, 180);
+            for (j, p) in d.histogram.iter_mut().enumerate() {
+                *p = (j % 13 + 1) as f32;
+            }
+            d.normalize();
+            d
+        })
+        .collect()
+}
+
+fn before_probability_wide(c: &mut Criterion) {
+    let ds = wide_distributions(2);
+    let coarse: Vec<Distribution> = ds.iter().map(|d| d.coarsen(5)).collect();
+
+    let mut group = c.benchmark_group("once");
+    for (name, ds) in [("before_probability_wide", &ds), ("before_probability_wide_coarse_5", &coarse)] {
+        group.bench_function(name, |b| {
+            b.iter(|| black_box(&ds[0]).before_probability(black_box(&ds[1]), 0))
+        });
+    }
+    group.finish();
+}
+
+fn add_with(c: &mut Criterion) {
+    let ds = wide_distributions(20);
+    let coarse: Vec<Distribution> = ds.iter().map(|d| d.coarsen(5)).collect();
+
+    let mut group = c.benchmark_group("once");
+    for (name, ds) in [("add_with", &ds), ("add_with_coarse_5", &coarse)] {
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                let mut d = Distribution::empty(0);
+                for other in ds.iter().rev() {
+                    d.add_with(black_box(other), 0.05, false);
+                }
+                d
+            })
+        });
+    }
+    group.finish();
+}
+
 criterion_group!(
     benches,
     from_relevant,
     from_gtfs,
     before_probability,
+    before_probability_wide,
+    add_with,
     measure_prepare
 );
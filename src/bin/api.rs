@@ -1,3 +1,4 @@
+#[cfg(any(feature = "nigiri", feature = "native-gtfs"))]
 use chrono::Days;
 use rouille::Response;
 use rstar::RTree;
@@ -11,20 +12,33 @@ use std::sync::Mutex;
 use stost::connection;
 use stost::connection::Route;
 use stost::distribution_store;
-use stost::distribution_store::Store;
+use stost::distribution_store::{CsvSchema, Store};
+#[cfg(any(feature = "nigiri", feature = "native-gtfs"))]
 use stost::gtfs;
 use stost::gtfs::GtfsTimetable;
 use stost::gtfs::StationContraction;
+use stost::itinerary::Itinerary;
+use stost::online_correction::OnlineCorrectionConfig;
+use stost::query::csameat;
 use stost::query::topocsa;
-use stost::query::Queriable;
+use stost::query::{Algorithm, Queriable};
+use stost::types;
 use stost::walking;
-use stost::walking::StationLocation;
+use stost::walking::{StationLocation, WalkingProfileConfig};
 use stost::wire::serde::to_mtime;
 use stost::wire::serde::QueryMetadata;
+#[cfg(feature = "nigiri")]
+use motis_nigiri::Journey;
+#[cfg(feature = "nigiri")]
+use std::sync::mpsc;
+#[cfg(feature = "nigiri")]
+use std::thread;
 
 #[derive(Deserialize)]
 struct ApiConfig {
     systems: HashMap<String, ApiSystem>,
+    #[serde(default = "types::one_tick_per_minute")]
+    ticks_per_minute: types::Mtime,
 }
 
 #[allow(dead_code)]
@@ -34,10 +48,27 @@ struct ApiSystem {
     provide_relevant_stations: bool,
     gtfs_glob: String,
     gtfsrt_glob: String,
+    #[serde(default)]
+    walking_profile: Option<WalkingProfileConfig>,
+    #[serde(default)]
+    distributions_schema: Option<CsvSchema>,
+    #[serde(default)]
+    algorithm: Algorithm,
+    #[serde(default)]
+    native_gtfs: bool,
+    #[serde(default)]
+    native_realtime: bool,
+    #[serde(default = "default_destination_bucket_width")]
+    destination_bucket_width: types::Mtime,
+    #[serde(default)]
+    csameat_max_dc: Option<types::Mtime>,
+    #[serde(default)]
+    online_correction: Option<OnlineCorrectionConfig>,
     #[serde(skip_deserializing)]
     store: Option<Store>,
-    //#[serde(skip_deserializing)]
-    //t: Option<Timetable>,
+    #[cfg(feature = "nigiri")]
+    #[serde(skip_deserializing)]
+    raptor: Option<Raptor>,
     #[serde(skip_deserializing)]
     tt: Option<GtfsTimetable>,
     #[serde(skip_deserializing)]
@@ -52,6 +83,26 @@ struct ApiSystem {
     reference_ts: i64,
 }
 
+#[cfg(feature = "nigiri")]
+type RaptorQuery = (usize, usize, i32, mpsc::Sender<Vec<Journey>>);
+
+// RAPTOR queries, answered by the thread owning the nigiri timetable.
+#[cfg(feature = "nigiri")]
+struct Raptor(mpsc::Sender<RaptorQuery>);
+
+#[cfg(feature = "nigiri")]
+impl Raptor {
+    fn journeys(&self, origin_idx: usize, destination_idx: usize, time: i32) -> Vec<Journey> {
+        let (tx, rx) = mpsc::channel();
+        self.0.send((origin_idx, destination_idx, time, tx)).unwrap();
+        rx.recv().unwrap()
+    }
+}
+
+fn default_destination_bucket_width() -> types::Mtime {
+    1
+}
+
 fn load_config() -> ApiConfig {
     let args: Vec<String> = env::args().collect();
     if args.len() < 2 {
@@ -61,6 +112,7 @@ fn load_config() -> ApiConfig {
     serde_json::from_slice(&buf).unwrap()
 }
 
+#[cfg(any(feature = "nigiri", feature = "native-gtfs"))]
 fn get_last_glob_path(glob: &str) -> String {
     let file = glob::glob(glob)
         .expect("Failed to read glob pattern")
@@ -71,48 +123,116 @@ fn get_last_glob_path(glob: &str) -> String {
     f
 }
 
-fn prepare_configured_systems(config: &mut ApiConfig) {
-    for c in config.systems.iter_mut() {
-        let mut store = distribution_store::Store::new();
-        store.load_distributions(&format!("./data/{}.csv", c.0));
-        if c.1.provide_timetable {
-            let now = chrono::offset::Local::now().date_naive(); //chrono::NaiveDate::from_ymd_opt(2023, 11, 2).unwrap();
-            let path = get_last_glob_path(&c.1.gtfs_glob);
-            let t = gtfs::load_timetable(
-                &path,
-                now,
-                now.checked_add_days(Days::new(1)).unwrap(),
-            );
-            println!("start_ts: {}", t.get_start_day_ts());
-            let mut tt = gtfs::GtfsTimetable::new();
-            tt.transport_and_day_to_connection_id =
-                gtfs::retrieve(&t, &mut tt.stations, &mut c.1.routes, &mut tt.connections);
-            walking::shorten_footpaths(&mut tt.stations);
-            c.1.contraction = Some(gtfs::get_station_contraction(&mut tt.stations));
-            c.1.station_idx = tt
-                .stations
-                .iter()
-                .enumerate()
-                .map(|s| (s.1.id.clone(), s.0))
-                .collect();
-            c.1.reference_ts = t.get_start_day_ts();
+// The realtime files to apply with the time they were observed at. With the online correction,
+// all files since the start of the timetable are replayed in order, as in the simulation.
+#[cfg(any(feature = "nigiri", feature = "native-gtfs"))]
+fn realtime_paths(gtfsrt_glob: &str, replay: bool, reference_ts: i64, ticks_per_minute: types::Mtime) -> Vec<(String, types::Mtime)> {
+    if !replay {
+        return vec![(get_last_glob_path(gtfsrt_glob), 0)];
+    }
+    glob::glob(gtfsrt_glob).expect("Failed to read glob pattern").filter_map(|f| {
+        let path = f.unwrap();
+        let mtime = std::fs::metadata(&path).unwrap().modified().unwrap().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs() as i64;
+        if mtime < reference_ts {
+            return None;
+        }
+        println!("Loading {}", path.display());
+        Some((path.to_str().unwrap().to_owned(), to_mtime(mtime, reference_ts, ticks_per_minute)))
+    }).collect()
+}
+
+fn load_provided_timetable(system: &mut ApiSystem, store: &mut Store) {
+    if system.native_gtfs {
+        load_native_timetable(system, store);
+    } else {
+        load_nigiri_timetable(system, store);
+    }
+}
+
+// The nigiri timetable is loaded by the thread that answers the RAPTOR queries, since its handle must not leave that thread.
+#[cfg(feature = "nigiri")]
+fn load_nigiri_timetable(system: &mut ApiSystem, store: &mut Store) {
+    let now = chrono::offset::Local::now().date_naive(); //chrono::NaiveDate::from_ymd_opt(2023, 11, 2).unwrap();
+    let path = get_last_glob_path(&system.gtfs_glob);
+    let gtfsrt_glob = system.gtfsrt_glob.clone();
+    let replay = system.online_correction.is_some();
+    let native_realtime = system.native_realtime;
+    assert!(!native_realtime || cfg!(feature = "native-gtfs"), "native_realtime requires the native-gtfs feature");
+    let ticks_per_minute = store.ticks_per_minute();
+    let mut thread_store = std::mem::replace(store, Store::new());
+    let (loaded_tx, loaded_rx) = mpsc::channel();
+    let (raptor_tx, raptor_rx) = mpsc::channel::<RaptorQuery>();
+    thread::spawn(move || {
+        let t = gtfs::load_timetable(
+            &path,
+            now,
+            now.checked_add_days(Days::new(1)).unwrap(),
+        );
+        println!("start_ts: {}", t.get_start_day_ts());
+        let mut tt = gtfs::GtfsTimetable::with_ticks_per_minute(ticks_per_minute);
+        let mut routes = vec![];
+        tt.transport_and_day_to_connection_id =
+            gtfs::retrieve(&t, &mut tt.stations, &mut routes, &mut tt.connections, ticks_per_minute);
+        walking::shorten_footpaths(&mut tt.stations, ticks_per_minute);
+        let reference_ts = t.get_start_day_ts();
+        // yesterday's service day for trips running after midnight, as for native_gtfs systems
+        #[cfg(feature = "native-gtfs")]
+        let native = native_realtime.then(|| gtfs::NativeRealtime::new(
+            &path,
+            now.checked_sub_days(Days::new(1)).unwrap(),
+            now.checked_add_days(Days::new(1)).unwrap(),
+            &t,
+            &tt,
+        ));
+        #[cfg(feature = "native-gtfs")]
+        if let Some(native) = &native {
+            native.apply_alerts(&get_last_glob_path(&gtfsrt_glob), &tt.stations, &mut tt.connections);
+        }
+        for (path, now) in realtime_paths(&gtfsrt_glob, replay, reference_ts, ticks_per_minute) {
             let mut env = topocsa::Environment::new(
-                &mut store,
+                &mut thread_store,
                 &mut tt.connections,
                 &tt.stations,
                 &mut tt.cut,
                 &mut tt.order,
-                0,
+                now,
                 0.01,
                 0.001,
                 true,
                 true,
             );
-            let path = get_last_glob_path(&c.1.gtfsrt_glob);
+            #[cfg(feature = "native-gtfs")]
+            if let Some(native) = &native {
+                native.load_realtime(
+                    &path,
+                    &t,
+                    |connection_id: usize,
+                     is_departure: bool,
+                     location_idx: Option<usize>,
+                     in_out_allowed: Option<bool>,
+                     delay: Option<i16>| {
+                        env.update(
+                            connection_id,
+                            is_departure,
+                            location_idx,
+                            in_out_allowed,
+                            delay,
+                        )
+                    },
+                );
+                native.load_vehicle_positions(
+                    &path,
+                    |connection_id: usize, is_departure: bool, min_delay: i16| {
+                        env.update_min_delay(connection_id, is_departure, min_delay)
+                    },
+                );
+                continue;
+            }
             gtfs::load_realtime(
                 &path,
                 &t,
                 &tt.transport_and_day_to_connection_id,
+                ticks_per_minute,
                 |connection_id: usize,
                  is_departure: bool,
                  location_idx: Option<usize>,
@@ -127,22 +247,160 @@ fn prepare_configured_systems(config: &mut ApiConfig) {
                     )
                 },
             );
-            c.1.rtree = walking::init_rtree(&tt.stations);
-            //c.1.t = Some(t);
-            c.1.tt = Some(tt);
+        }
+        loaded_tx.send((tt, routes, thread_store, reference_ts)).unwrap();
+        for (origin_idx, destination_idx, time, reply) in raptor_rx {
+            reply.send(t.get_journeys(origin_idx, destination_idx, time, false).journeys).unwrap();
+        }
+    });
+    let (mut tt, routes, loaded_store, reference_ts) = loaded_rx.recv().unwrap();
+    *store = loaded_store;
+    system.routes = routes;
+    system.contraction = Some(gtfs::get_station_contraction(&mut tt.stations));
+    system.station_idx = tt
+        .stations
+        .iter()
+        .enumerate()
+        .map(|s| (s.1.id.clone(), s.0))
+        .collect();
+    system.reference_ts = reference_ts;
+    system.rtree = walking::init_rtree(&tt.stations);
+    system.raptor = Some(Raptor(raptor_tx));
+    system.tt = Some(tt);
+}
+
+#[cfg(not(feature = "nigiri"))]
+fn load_nigiri_timetable(_system: &mut ApiSystem, _store: &mut Store) {
+    panic!("provide_timetable without native_gtfs requires the nigiri feature");
+}
+
+// Without RAPTOR baseline journeys, but with ADDED trips and ServiceAlerts of the GTFS-RT feed.
+#[cfg(feature = "native-gtfs")]
+fn load_native_timetable(system: &mut ApiSystem, store: &mut Store) {
+    let now = chrono::offset::Local::now().date_naive();
+    let path = get_last_glob_path(&system.gtfs_glob);
+    let ticks_per_minute = store.ticks_per_minute();
+    // yesterday's service day, whose trips may run after midnight, and today's
+    let mut t = gtfs::native::load_timetable(
+        &path,
+        now.checked_sub_days(Days::new(1)).unwrap(),
+        now.checked_add_days(Days::new(1)).unwrap(),
+        ticks_per_minute,
+    );
+    let rt = std::fs::read(get_last_glob_path(&system.gtfsrt_glob)).unwrap();
+    gtfs::native::add_realtime_trips(&rt, &mut t);
+    let mut tt = gtfs::GtfsTimetable::with_ticks_per_minute(ticks_per_minute);
+    tt.transport_and_day_to_connection_id =
+        gtfs::native::retrieve(&t, &mut tt.stations, &mut system.routes, &mut tt.connections);
+    gtfs::native::apply_alerts(&rt, &t, &tt.transport_and_day_to_connection_id, &tt.stations, Some(&mut system.routes), &mut tt.connections, t.get_start_day_ts());
+    walking::shorten_footpaths(&mut tt.stations, ticks_per_minute);
+    system.contraction = Some(gtfs::get_station_contraction(&tt.stations));
+    system.station_idx = tt
+        .stations
+        .iter()
+        .enumerate()
+        .map(|s| (s.1.id.clone(), s.0))
+        .collect();
+    system.reference_ts = t.get_start_day_ts();
+    for (path, now) in realtime_paths(&system.gtfsrt_glob, system.online_correction.is_some(), system.reference_ts, ticks_per_minute) {
+        let rt = std::fs::read(path).unwrap();
+        let mut env = topocsa::Environment::new(
+            store,
+            &mut tt.connections,
+            &tt.stations,
+            &mut tt.cut,
+            &mut tt.order,
+            now,
+            0.01,
+            0.001,
+            true,
+            true,
+        );
+        gtfs::native::apply_realtime(
+            &rt,
+            &t,
+            &tt.transport_and_day_to_connection_id,
+            |connection_id: usize,
+             is_departure: bool,
+             location_idx: Option<usize>,
+             in_out_allowed: Option<bool>,
+             delay: Option<i16>| {
+                env.update(
+                    connection_id,
+                    is_departure,
+                    location_idx,
+                    in_out_allowed,
+                    delay,
+                )
+            },
+        );
+        gtfs::native::apply_vehicle_positions(
+            &rt,
+            &t,
+            &tt.transport_and_day_to_connection_id,
+            |connection_id: usize, is_departure: bool, min_delay: i16| {
+                env.update_min_delay(connection_id, is_departure, min_delay)
+            },
+        );
+    }
+    system.rtree = walking::init_rtree(&tt.stations);
+    system.tt = Some(tt);
+}
+
+#[cfg(not(feature = "native-gtfs"))]
+fn load_native_timetable(_system: &mut ApiSystem, _store: &mut Store) {
+    panic!("native_gtfs requires the native-gtfs feature");
+}
+
+fn prepare_configured_systems(config: &mut ApiConfig) {
+    for c in config.systems.iter_mut() {
+        let mut store = distribution_store::Store::with_ticks_per_minute(config.ticks_per_minute);
+        let distributions_path = format!("./data/{}.csv", c.0);
+        match &c.1.distributions_schema {
+            Some(schema) => store.load_distributions_with_schema(&distributions_path, schema).print(),
+            None => store.load_distributions(&distributions_path)
+        }
+        if let Some(profile) = &c.1.walking_profile {
+            store.set_walking_profile(profile.profile().expect("unknown walking profile"));
+        }
+        if let Some(online_correction) = &c.1.online_correction {
+            store.enable_online_correction(online_correction.clone());
+        }
+        if c.1.provide_timetable {
+            load_provided_timetable(c.1, &mut store);
         }
         c.1.store = Some(store);
     }
 }
 
-fn query_on_timetable(system_conf: &mut ApiSystem, mut metadata: QueryMetadata) -> Vec<u8> {
-    let query_window = 720;
+fn new_env<'a: 'b, 'b>(algorithm: Algorithm, store: &'a mut Store, connections: &'a mut Vec<connection::Connection>, stations: &'a [connection::Station], cut: &'a mut FxHashSet<(usize, usize)>, order: &'a mut Vec<usize>, now: types::Mtime, destination_bucket_width: types::Mtime, csameat_max_dc: Option<types::Mtime>) -> Box<dyn Queriable<'a> + 'b> {
+    match algorithm {
+        Algorithm::Topocsa => {
+            let mut env = topocsa::Environment::new(store, connections, stations, cut, order, now, 0.001, 0.001, false, false);
+            env.set_destination_bucket_width(destination_bucket_width);
+            Box::new(env)
+        },
+        Algorithm::Csameat => {
+            let mut env = csameat::Environment::new(store, connections, stations, cut, order, now);
+            env.set_mean_only(false);
+            if let Some(max_dc) = csameat_max_dc {
+                env.set_max_dc(max_dc);
+            }
+            Box::new(env)
+        }
+    }
+}
+
+fn query_on_timetable(system_conf: &mut ApiSystem, algorithm: Algorithm, mut metadata: QueryMetadata) -> Result<Vec<u8>, String> {
     let tt = system_conf.tt.as_mut().unwrap();
-    let now = to_mtime(metadata.now, system_conf.reference_ts);
-    let start_time = to_mtime(metadata.start_ts, system_conf.reference_ts);
+    let ticks_per_minute = tt.ticks_per_minute;
+    let query_window = types::minutes(720, ticks_per_minute);
+    let now = to_mtime(metadata.now, system_conf.reference_ts, ticks_per_minute);
+    let start_time = to_mtime(metadata.start_ts, system_conf.reference_ts, ticks_per_minute);
+    let station_idx = |id: &str| system_conf.station_idx.get(id).copied().ok_or_else(|| format!("unknown station {}", id));
     let full_query = Query {
-        origin_idx: system_conf.station_idx[&metadata.origin_id],
-        destination_idx: system_conf.station_idx[&metadata.destination_id],
+        origin_idx: station_idx(&metadata.origin_id)?,
+        destination_idx: station_idx(&metadata.destination_id)?,
         start_time: start_time,
         max_time: start_time+query_window
     };
@@ -153,9 +411,10 @@ fn query_on_timetable(system_conf: &mut ApiSystem, mut metadata: QueryMetadata)
         now,
         system_conf.contraction.as_ref().unwrap(),
         &system_conf.rtree,
+        system_conf.destination_bucket_width,
     );
     if walking_tt.stations.is_empty() {
-        return vec![];
+        return Ok(vec![]);
     }
     let mut rel_env = topocsa::Environment::new(
         system_conf.store.as_mut().unwrap(),
@@ -180,61 +439,135 @@ fn query_on_timetable(system_conf: &mut ApiSystem, mut metadata: QueryMetadata)
     let relevant_connection_pairs = rel_env.relevant_connection_pairs(walking_query, &weights_by_station_idx, 10000);
     println!("rel. conns: {}", relevant_connection_pairs.len());
     let no_extended_walking = HashMap::new();
-    let relevant_timetable = walking::create_relevant_timetable_with_extended_walking(
+    let mut relevant_timetable = walking::create_relevant_timetable_with_extended_walking(
         &mut walking_tt.connections,
         &walking_tt.stations,
         &walking_tt.order,
         relevant_connection_pairs,
         &no_extended_walking,
-        walking_origin_idx, walking_destination_idx
+        walking_origin_idx, walking_destination_idx,
+        ticks_per_minute
     );
     println!("conns: {} stops: {}", relevant_timetable.0.connections.len(), relevant_timetable.0.stations.len());
     println!("fromto: {} {}", relevant_timetable.1, relevant_timetable.2);
+    if algorithm != Algorithm::Topocsa {
+        // the extraction is always done by topocsa, other algorithms replace the distributions on the extracted timetable
+        let tt = &mut relevant_timetable.0;
+        let mut env = new_env(algorithm, system_conf.store.as_mut().unwrap(), &mut tt.connections, &tt.stations, &mut tt.cut, &mut tt.order, now, system_conf.destination_bucket_width, system_conf.csameat_max_dc);
+        env.preprocess();
+        env.query(Query {
+            origin_idx: relevant_timetable.1,
+            destination_idx: relevant_timetable.2,
+            start_time: full_query.start_time,
+            max_time: full_query.max_time
+        });
+    }
     metadata.origin_idx = relevant_timetable.1;
     metadata.destination_idx = relevant_timetable.2;
     metadata.start_ts = system_conf.reference_ts;
-    stost::wire::serde::serialize_protobuf(
+    let journeys = baseline_journeys(system_conf, full_query, now);
+    let full_tt = system_conf.tt.as_mut().unwrap();
+    let itinerary = requested_itinerary(&metadata, system_conf.store.as_mut().unwrap(), &full_tt.stations, &system_conf.routes, &mut full_tt.connections, &mut full_tt.cut, &mut full_tt.order, system_conf.contraction.as_ref(), full_query, now)?;
+    let full_tt = system_conf.tt.as_ref().unwrap();
+    Ok(stost::wire::serde::serialize_protobuf(
         &relevant_timetable.0.stations,
         &system_conf.routes,
         &relevant_timetable.0.connections,
         system_conf.contraction.as_ref(),
-        &metadata
-    )
+        &metadata,
+        journeys.iter().map(|j| stost::wire::serde::to_wire_journey(j, &full_tt.stations, &system_conf.routes, &full_tt.connections, system_conf.reference_ts, ticks_per_minute)).collect(),
+        itinerary.map(|i| stost::wire::serde::to_wire_journey(&i, &full_tt.stations, &system_conf.routes, &full_tt.connections, system_conf.reference_ts, ticks_per_minute)),
+        ticks_per_minute
+    ))
+}
+
+// The fixed itinerary given by the client, evaluated with fallbacks from queries on the same timetable (contracted by
+// the given or, if None, a newly computed station contraction). Errors are caused by invalid itineraries.
+fn requested_itinerary(metadata: &QueryMetadata, store: &mut Store, stations: &[connection::Station], routes: &[connection::Route], connections: &mut Vec<connection::Connection>, cut: &mut FxHashSet<(usize, usize)>, order: &mut Vec<usize>, contraction: Option<&StationContraction>, query: Query, now: types::Mtime) -> Result<Option<Itinerary>, String> {
+    if metadata.itinerary.is_empty() {
+        return Ok(None);
+    }
+    let trip_legs = stost::wire::serde::resolve_itinerary(&metadata.itinerary, stations, routes, connections, order, metadata.start_ts, store.ticks_per_minute())?;
+    let mut itinerary = Itinerary::from_trip_legs(&trip_legs, connections, stations, order)?;
+    let computed_contraction;
+    let contraction = match contraction {
+        Some(contraction) => contraction,
+        None => {
+            computed_contraction = stost::gtfs::get_station_contraction(stations);
+            &computed_contraction
+        }
+    };
+    itinerary.find_fallbacks(store, connections, stations, cut, order, contraction, query.destination_idx, query.max_time, now);
+    itinerary.evaluate(store, connections, stations, query.start_time, now);
+    Ok(Some(itinerary))
+}
+
+// RAPTOR journeys on the full timetable, annotated with the stochastic arrival distribution and transfer probabilities. None for native_gtfs systems.
+#[cfg(feature = "nigiri")]
+fn baseline_journeys(system_conf: &mut ApiSystem, query: Query, now: types::Mtime) -> Vec<Itinerary> {
+    let raptor = match &system_conf.raptor {
+        Some(raptor) => raptor,
+        None => return vec![]
+    };
+    let tt = system_conf.tt.as_ref().unwrap();
+    let mut pareto = raptor.journeys(query.origin_idx, query.destination_idx, query.start_time/tt.ticks_per_minute);
+    pareto.sort_by_key(|j| j.dest_time);
+    pareto.iter().map(|j| {
+        let mut itinerary = Itinerary::from_journey(j, &tt.transport_and_day_to_connection_id, &tt.order, tt.ticks_per_minute);
+        itinerary.evaluate(system_conf.store.as_mut().unwrap(), &tt.connections, &tt.stations, query.start_time, now);
+        itinerary
+    }).collect()
+}
+
+#[cfg(not(feature = "nigiri"))]
+fn baseline_journeys(_system_conf: &mut ApiSystem, _query: Query, _now: types::Mtime) -> Vec<Itinerary> {
+    vec![]
 }
 
 fn query_on_given(
     system_conf: &mut ApiSystem,
+    algorithm: Algorithm,
     input_stations: &mut Vec<connection::Station>,
     input_routes: &Vec<connection::Route>,
     input_connections: &mut Vec<connection::Connection>,
     metadata: QueryMetadata,
-) -> Vec<u8> {
-    walking::create_quadratic_footpaths(input_stations);
-    walking::create_materialized_initial_footpaths(metadata.origin_idx, input_stations, input_connections);
+) -> Result<Vec<u8>, String> {
+    let ticks_per_minute = system_conf.store.as_ref().unwrap().ticks_per_minute();
+    walking::create_quadratic_footpaths(input_stations, ticks_per_minute);
+    walking::create_materialized_initial_footpaths(metadata.origin_idx, input_stations, input_connections, system_conf.store.as_ref().unwrap().walking_profile(), ticks_per_minute);
     println!("querying...");
     let mut cut = FxHashSet::default();
     let mut order = Vec::with_capacity(input_connections.len());
-    let mut e = topocsa::prepare(system_conf.store.as_mut().unwrap(), input_connections, input_stations, &mut cut, &mut order, to_mtime(metadata.now, metadata.start_ts), 0.001, false);
-    let station_labels = e.query(crate::Query {
+    let query = Query {
         origin_idx:  metadata.origin_idx,
         destination_idx: metadata.destination_idx,
         start_time: 0,
-        max_time: 1440 * 2,
-    });
-    e.get_relevant_stations(metadata.origin_idx, metadata.destination_idx, &station_labels, false);
+        max_time: types::minutes(1440 * 2, ticks_per_minute),
+    };
+    {
+        let mut env = new_env(algorithm, system_conf.store.as_mut().unwrap(), input_connections, input_stations, &mut cut, &mut order, to_mtime(metadata.now, metadata.start_ts, ticks_per_minute), system_conf.destination_bucket_width, system_conf.csameat_max_dc);
+        env.preprocess();
+        let station_labels = env.query(query);
+        env.relevant_stations(query, &station_labels);
+    }
     walking::update_footpath_relevance(metadata.origin_idx, metadata.destination_idx, &order, input_connections);
-    stost::wire::serde::serialize_protobuf(
+    let itinerary = requested_itinerary(&metadata, system_conf.store.as_mut().unwrap(), input_stations, input_routes, input_connections, &mut cut, &mut order, None, query, to_mtime(metadata.now, metadata.start_ts, ticks_per_minute))?;
+    Ok(stost::wire::serde::serialize_protobuf(
         &input_stations,
         &input_routes,
         &input_connections,
         system_conf.contraction.as_ref(),
         &metadata,
-    )
+        vec![],
+        itinerary.map(|i| stost::wire::serde::to_wire_journey(&i, input_stations, input_routes, input_connections, metadata.start_ts, ticks_per_minute)),
+        ticks_per_minute
+    ))
 }
 
 fn main() {
     println!("starting...");
     let mut conf = load_config();
+    let ticks_per_minute = conf.ticks_per_minute;
     prepare_configured_systems(&mut conf);
     let conf_mutex = Mutex::new(conf);
 
@@ -251,20 +584,36 @@ fn main() {
         let mut input_stations: Vec<connection::Station> = vec![];
         let mut input_routes = vec![];
         let mut input_connections = vec![];
-        let metadata = stost::wire::serde::deserialize_protobuf(
+        let mut metadata = stost::wire::serde::deserialize_protobuf(
             bytes,
             &mut input_stations,
             &mut input_routes,
             &mut input_connections,
             false,
+            ticks_per_minute,
         );
         let mut c = conf_mutex.lock().unwrap();
-        let system_conf = c.systems.get_mut(&metadata.system).expect("invalid system");
-        let bytes = if system_conf.provide_timetable {
-            query_on_timetable(system_conf, metadata)
+        // requests are rejected with status 400 instead of panicking, which would poison the lock
+        let system_conf = match c.systems.get_mut(&metadata.system) {
+            Some(system_conf) => system_conf,
+            None => return Response::text(format!("invalid system: {}", metadata.system)).with_status_code(400)
+        };
+        let algorithm = if metadata.algorithm.is_empty() {
+            system_conf.algorithm
+        } else {
+            match Algorithm::from_name(&metadata.algorithm) {
+                Some(algorithm) => algorithm,
+                None => return Response::text(format!("invalid algorithm: {}", metadata.algorithm)).with_status_code(400)
+            }
+        };
+        metadata.algorithm = algorithm.name().to_string();
+        println!("using {}", metadata.algorithm);
+        let result = if system_conf.provide_timetable {
+            query_on_timetable(system_conf, algorithm, metadata)
         } else {
             query_on_given(
                 system_conf,
+                algorithm,
                 &mut input_stations,
                 &input_routes,
                 &mut input_connections,
@@ -272,6 +621,9 @@ fn main() {
             )
         };
         println!("finished querying.");
-        Response::from_data("application/octet-stream", bytes)
+        match result {
+            Ok(bytes) => Response::from_data("application/octet-stream", bytes),
+            Err(e) => Response::text(e).with_status_code(400)
+        }
     });
 }
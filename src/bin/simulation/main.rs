@@ -0,0 +1,1849 @@
+#[macro_use]
+extern crate assert_float_eq;
+
+use glob::glob;
+use motis_nigiri::Timetable;
+use ndarray::{self, array, ArrayBase, Dim, OwnedRepr};
+use ndarray_stats::histogram::{Bins, Edges, Grid, Histogram};
+use ndarray_stats::interpolate::Higher;
+use ndarray_stats::interpolate::Lower;
+use ndarray_stats::QuantileExt;
+use noisy_float::types::{n32, n64, N32};
+use serde::Deserialize;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::io::Write;
+use std::sync::{mpsc, Arc};
+use std::time::Instant;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+use stost::distribution;
+use stost::distribution_store;
+use stost::gtfs;
+use stost::gtfs::GtfsTimetable;
+use stost::gtfs::OriginDestinationSample;
+use stost::gtfs::StationContraction;
+use stost::learning;
+use stost::online_correction::OnlineCorrectionConfig;
+use stost::query::topocsa;
+use stost::query::Queriable;
+use stost::synthetic::{SyntheticDelayConfig, SyntheticDelays};
+use rand::{rngs::StdRng, SeedableRng};
+use stost::types;
+use stost::{
+    connection,
+    query::csameat,
+    walking,
+};
+use strategy::{Pair, SimulationContext, TravelerStrategy};
+
+mod strategy;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct SimulationConfig {
+    distributions_path: String,
+    gtfs_path: String,
+    gtfs_cache_path: String,
+    #[serde(default)]
+    gtfsrt_glob: String,
+    samples_config_path: String,
+    #[serde(default)]
+    det_simulation: String,
+    #[serde(default)]
+    stoch_simulation: String,
+    #[serde(default)]
+    strategies: Vec<String>,
+    transfer: String,
+	#[serde(default)]
+    epsilon_reachable: types::MFloat,
+	#[serde(default)]
+    epsilon_feasible: types::MFloat,
+	#[serde(default)]
+    transfer_strategy: String,
+    samples: usize,
+	#[serde(default)]
+    start_mams: Vec<i32>,
+	#[serde(default)]
+    start_date: Vec<i32>,
+	#[serde(default)]
+    num_days: i32,
+    #[serde(default)]
+    query_window: i32,
+    #[serde(default)]
+    distributions_schema: Option<distribution_store::CsvSchema>,
+    #[serde(default)]
+    online_correction: Option<OnlineCorrectionConfig>,
+    #[serde(default)]
+    threads: usize,
+    #[serde(default)]
+    synthetic_delays: Option<SyntheticDelayConfig>,
+    #[serde(default)]
+    seed: Option<u64>,
+    #[serde(default)]
+    csameat_max_dc: Option<types::Mtime>,
+    #[serde(default)]
+    native_realtime: bool,
+    #[serde(default = "types::one_tick_per_minute")]
+    ticks_per_minute: types::Mtime
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct SamplesConfig {
+    gtfs_path: String,
+    start_date: Vec<i32>,
+    num_days: i32,
+    #[serde(default)]
+    seed: Option<u64>,
+    #[serde(default)]
+    demand_path: Option<String>,
+    // minute of the first day at which a RAPTOR journey must exist for the pair
+    #[serde(default)]
+    reachable_at: Option<i32>,
+    #[serde(flatten)]
+    sampling: gtfs::SamplingConfig
+}
+
+impl SimulationConfig {
+    fn strategies(&self) -> Vec<String> {
+        if self.strategies.is_empty() {
+            vec![self.det_simulation.clone(), self.stoch_simulation.clone()]
+        } else {
+            self.strategies.clone()
+        }
+    }
+
+    fn csameat(&self) -> bool {
+        self.strategies().iter().any(|s| strategy::parse(s).is_csameat())
+    }
+
+    // start_mams, query_window and the like are configured in minutes
+    fn minutes(&self, m: i32) -> types::Mtime {
+        types::minutes(m, self.ticks_per_minute)
+    }
+}
+
+fn load_config(path: &str) -> SimulationConfig {
+    let buf = std::fs::read(path).unwrap();
+    serde_json::from_slice(&buf).unwrap()
+}
+
+fn load_samples_config(path: &str) -> SamplesConfig {
+    let buf = std::fs::read(path).unwrap();
+    serde_json::from_slice(&buf).unwrap()
+}
+
+fn load_samples(path: &str) -> Vec<OriginDestinationSample> {
+    let buf = std::fs::read(path).unwrap();
+    serde_json::from_slice(&buf).unwrap()
+}
+
+fn load_simulation_run(path: &str) -> SimulationRun {
+    let buf = std::fs::read(path).unwrap();
+    let mut run: SimulationRun = serde_json::from_slice(&buf).unwrap();
+    for j in &mut run.results {
+        if let Some(det) = j.det.take() {
+            j.results.insert(run.config.det_simulation.clone(), det);
+        }
+        if let Some(stoch) = j.stoch.take() {
+            j.results.insert(run.config.stoch_simulation.clone(), stoch);
+        }
+    }
+    run
+}
+
+fn day(year: i32, month: i32, day: i32) -> chrono::NaiveDate {
+    chrono::NaiveDate::from_ymd_opt(year, month as u32, day as u32).unwrap()
+}
+
+fn resolve_connection_idx(
+    journey: &motis_nigiri::Journey,
+    leg_idx: usize,
+    to: bool,
+    mapping: &HashMap<(usize, u16), usize>,
+    order: &[usize]
+) -> usize {
+    let leg = &journey.legs[leg_idx];
+    let connid = mapping[&(leg.transport_idx, leg.day_idx)]
+        + if to {
+            leg.to_stop_idx - 1
+        } else {
+            leg.from_stop_idx
+        } as usize;
+    order[connid]
+}
+
+struct LogEntry {
+    conn_id: usize,
+    proj_dest_arr: types::MFloat,
+    arrival_time_lower_bound: i32
+}
+struct Alternative {
+    from_conn_idx: usize,
+    to_conn_idx: usize,
+    proj_dest_arr: types::MFloat
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct SimulationResult {
+    departure: i32,
+    original_dest_arrival_prediction: types::MFloat,
+    actual_dest_arrival: Option<i32>,
+    broken: bool,
+	#[serde(default)]
+    preprocessing_elapsed_ms: u128,
+    algo_elapsed_ms: Vec<u128>,
+    connections_taken: Vec<connection::Connection>,
+    // time at which the destination arrival distribution of each connection taken was computed
+    #[serde(default)]
+    prediction_times: Vec<i32>,
+    connection_missed: Option<connection::Connection>
+}
+
+impl SimulationResult {
+    fn is_completed(&self) -> bool {
+        self.broken || self.actual_dest_arrival.is_some()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct SimulationJourney {
+    pair: (usize, usize, i32),
+    start_time: u64,
+    from_station: String,
+    from_station_name: String,
+    to_station: String,
+    to_station_name: String,    
+    #[serde(default)]
+    stratum: String,
+    #[serde(default)]
+    results: BTreeMap<String, SimulationResult>,
+    // runs before there were more than two strategies
+    #[serde(default, skip_serializing)]
+    det: Option<SimulationResult>,
+    #[serde(default, skip_serializing)]
+    stoch: Option<SimulationResult>,
+}
+
+impl SimulationJourney {
+    fn is_completed(&self) -> bool {
+        self.results.values().all(|r| r.is_completed())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SimulationRun {
+    simulation_run_at: u64,
+    comment: String,
+    config: SimulationConfig,
+    #[serde(default)]
+    seed: u64,
+    results: Vec<SimulationJourney>
+}
+
+// Minutes from the start of the loaded nigiri timetable to the first simulated day, as nigiri loads some days before the given start date.
+const REFERENCE_OFFSET: i32 = 5*1440;
+
+// A realtime update as passed to Queriable::update.
+type Update = (usize, bool, Option<usize>, Option<bool>, Option<i16>);
+
+// The realtime information revealed at a step.
+#[derive(Default)]
+struct Realtime {
+    updates: Vec<Update>,
+    // lower bounds of delays from vehicle positions as passed to Queriable::update_min_delay
+    min_delays: Vec<(usize, bool, i16)>,
+    // new texts of service alerts by connection id
+    alerts: Vec<(usize, String)>
+}
+
+// Sent by the thread owning the nigiri timetable to every worker.
+enum Tick {
+    // timetable of the next day without realtime updates, its contraction and start timestamp
+    Day(Box<GtfsTimetable>, Option<StationContraction>, u64),
+    // pairs of this worker starting at the next start_mam, with the strata of all samples
+    Pairs(Vec<Pair>, HashMap<(usize, usize), String>),
+    Step(i32, Arc<Realtime>),
+    EndDay(i32)
+}
+
+// Sent by the workers to the thread owning the nigiri timetable.
+enum Reply {
+    Raptor(usize, usize, i32, mpsc::Sender<Option<motis_nigiri::Journey>>),
+    StepDone(bool),
+    Results(i32, Vec<SimulationJourney>)
+}
+
+// RAPTOR queries of a worker, answered by the thread owning the nigiri timetable, as nigiri is not thread-safe.
+pub struct Raptor(mpsc::Sender<Reply>);
+
+impl Raptor {
+    fn min_journey(&self, origin_idx: usize, destination_idx: usize, time: i32) -> Option<motis_nigiri::Journey> {
+        let (tx, rx) = mpsc::channel();
+        self.0.send(Reply::Raptor(origin_idx, destination_idx, time, tx)).unwrap();
+        rx.recv().unwrap()
+    }
+}
+
+// The travelers of every n-th sample pair with their own copy of the timetable and distributions.
+struct Simulation {
+    conf: SimulationConfig,
+    store: distribution_store::Store,
+    tt: GtfsTimetable,
+    // csameat strategies query their own copy of the timetable and nonnegative distributions, as csameat preprocesses them differently
+    csameat_store: Option<distribution_store::Store>,
+    csameat_tt: Option<GtfsTimetable>,
+    contr: Option<StationContraction>,
+    reference_ts: u64,
+    raptor: Raptor,
+    strategies: Vec<Box<dyn TravelerStrategy>>,
+    stop_pairs: Vec<Pair>,
+    active: HashSet<Pair>,
+    logs: HashMap<Pair, Vec<Vec<LogEntry>>>,
+    results: HashMap<(usize, usize, i32), SimulationJourney>,
+    strata: HashMap<(usize, usize), String>
+}
+
+// Handle of the thread owning the nigiri timetable on the workers.
+struct Workers {
+    ticks: Vec<mpsc::Sender<Tick>>,
+    replies: mpsc::Receiver<Reply>,
+    handles: Vec<std::thread::JoinHandle<()>>
+}
+
+impl Workers {
+    fn spawn(conf: &SimulationConfig, store: &distribution_store::Store, threads: usize) -> Workers {
+        let (reply_tx, replies) = mpsc::channel();
+        let mut ticks = vec![];
+        let mut handles = vec![];
+        for _ in 0..threads.max(1) {
+            let (tick_tx, tick_rx) = mpsc::channel();
+            let (conf, store, raptor) = (conf.clone(), store.clone(), Raptor(reply_tx.clone()));
+            handles.push(std::thread::spawn(move || Simulation::new(conf, store, raptor).work(tick_rx)));
+            ticks.push(tick_tx);
+        }
+        Workers {
+            ticks,
+            replies,
+            handles
+        }
+    }
+
+    fn day(&self, tt: &GtfsTimetable, contr: &Option<StationContraction>, reference_ts: u64) {
+        for w in &self.ticks {
+            w.send(Tick::Day(Box::new(tt.clone()), contr.clone(), reference_ts)).unwrap();
+        }
+    }
+
+    fn pairs(&self, pairs: Vec<Pair>, strata: HashMap<(usize, usize), String>) {
+        let n = self.ticks.len();
+        for (worker_idx, w) in self.ticks.iter().enumerate() {
+            let own = pairs.iter().enumerate().filter(|(i, _)| i % n == worker_idx).map(|(_, p)| *p).collect();
+            w.send(Tick::Pairs(own, strata.clone())).unwrap();
+        }
+    }
+
+    // Returns whether any journey is not yet completed.
+    fn step<F: FnMut(usize, usize, i32) -> Option<motis_nigiri::Journey>>(&self, current_time: i32, realtime: Realtime, mut raptor: F) -> bool {
+        let realtime = Arc::new(realtime);
+        for w in &self.ticks {
+            w.send(Tick::Step(current_time, realtime.clone())).unwrap();
+        }
+        let mut done = 0;
+        let mut do_continue = false;
+        while done < self.ticks.len() {
+            match self.replies.recv().expect("simulation worker panicked") {
+                Reply::Raptor(origin_idx, destination_idx, time, tx) => tx.send(raptor(origin_idx, destination_idx, time)).unwrap(),
+                Reply::StepDone(c) => {
+                    done += 1;
+                    do_continue |= c;
+                },
+                Reply::Results(..) => panic!("unexpected results during step")
+            }
+        }
+        do_continue
+    }
+
+    fn end_day(&self, day_idx: i32) -> Vec<SimulationJourney> {
+        for w in &self.ticks {
+            w.send(Tick::EndDay(day_idx)).unwrap();
+        }
+        let mut results = vec![];
+        for _ in 0..self.ticks.len() {
+            match self.replies.recv().expect("simulation worker panicked") {
+                Reply::Results(i, r) if i == day_idx => results.extend(r),
+                _ => panic!("unexpected reply at end of day")
+            }
+        }
+        results.sort_by_key(|j| j.pair);
+        results
+    }
+
+    fn join(self) {
+        drop(self.ticks);
+        for h in self.handles {
+            h.join().expect("simulation worker panicked");
+        }
+    }
+}
+
+fn load_store(conf: &SimulationConfig) -> distribution_store::Store {
+    let mut store = if conf.distributions_path.ends_with(".csv") {
+        let mut store = distribution_store::Store::with_ticks_per_minute(conf.ticks_per_minute);
+        match &conf.distributions_schema {
+            Some(schema) => store.load_distributions_with_schema(&conf.distributions_path, schema).print(),
+            None => store.load_distributions(&conf.distributions_path)
+        }
+        store
+    } else {
+        let store = distribution_store::Store::load_snapshot(&conf.distributions_path);
+        assert_eq!(store.ticks_per_minute(), conf.ticks_per_minute, "snapshot was saved with a different time resolution");
+        store
+    };
+    if let Some(online_correction) = &conf.online_correction {
+        store.enable_online_correction(online_correction.clone());
+    }
+    store
+}
+
+// nigiri works in minutes, the returned journey as well
+fn min_journey(t: &Timetable, origin_idx: usize, destination_idx: usize, time: types::Mtime, ticks_per_minute: types::Mtime) -> Option<motis_nigiri::Journey> {
+    t.get_journeys(origin_idx, destination_idx, time.div_euclid(ticks_per_minute), false).journeys.into_iter().min_by_key(|j| j.dest_time)
+}
+
+#[cfg(feature = "native-gtfs")]
+fn load_native_realtime(path: &str, t: &Timetable, native: &gtfs::NativeRealtime, stations: &[connection::Station], connections: &mut [connection::Connection], realtime: &mut Realtime) {
+    native.load_realtime(path, t, |connection_id: usize, is_departure: bool, location_idx: Option<usize>, in_out_allowed: Option<bool>, delay: Option<i16>| {
+        realtime.updates.push((connection_id, is_departure, location_idx, in_out_allowed, delay));
+    });
+    native.load_vehicle_positions(path, |connection_id: usize, is_departure: bool, min_delay: i16| {
+        realtime.min_delays.push((connection_id, is_departure, min_delay));
+    });
+    // alerts are only ever appended
+    let lengths: Vec<usize> = connections.iter().map(|c| c.message.len()).collect();
+    native.apply_alerts(path, stations, connections);
+    realtime.alerts = connections.iter().enumerate().filter(|(id, c)| c.message.len() != lengths[*id]).map(|(id, c)| (id, c.message.clone())).collect();
+}
+
+// The nigiri timetable is loaded and the GTFS-RT files are parsed once, on this thread, the resulting
+// timetable and updates are shared with the workers, each of which simulates every n-th sample pair.
+fn run_simulation(config_file: &str) -> Result<i32, Box<dyn std::error::Error>> {
+    let conf = load_config(config_file);
+    let store = load_store(&conf);
+    let seed = conf.seed.unwrap_or_else(rand::random);
+    let mut synthetic = conf.synthetic_delays.clone().map(SyntheticDelays::new);
+    let workers = Workers::spawn(&conf, &store, conf.threads);
+    let mut t: Option<Timetable> = None;
+    let mut transport_and_day_to_connection_id = HashMap::new();
+    assert!(!conf.native_realtime || cfg!(feature = "native-gtfs"), "native_realtime requires the native-gtfs feature");
+    // with native_realtime, the GTFS-RT files are parsed natively on the matched nigiri connections, whose alerts are tracked here
+    #[cfg(feature = "native-gtfs")]
+    let mut native: Option<(gtfs::NativeRealtime, Vec<connection::Station>, Vec<connection::Connection>)> = None;
+
+    let mut reference_ts = 0;
+    let mut next_start_mam_idx = 0;
+    let mut day_idx = 0;
+    let mut pending = false;
+
+    let simulation_run_at = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+
+    assert!(
+        conf.num_days == 1 || conf.start_mams.last().unwrap()+conf.query_window-1440 < conf.start_mams[0],
+        "last start_mam may not overlap with first start_mam of the next day"
+    );
+
+    let mut gtfsrt_files = if synthetic.is_none() {
+        Some(glob(&conf.gtfsrt_glob).expect("Failed to read glob pattern"))
+    } else {
+        None
+    };
+    let mut synthetic_mtime = 0;
+    loop {
+        let (mtime, path) = match &mut gtfsrt_files {
+            Some(files) => match files.next() {
+                Some(f) => {
+                    let path = f.as_ref().unwrap().to_str().unwrap().to_owned();
+                    (Simulation::get_mtime_unix(f)?, Some(path))
+                },
+                None => break
+            },
+            None => {
+                // synthetic clock: first tick loads the timetable, then jump to the first start_mam of the day
+                if reference_ts != 0 {
+                    let interval = synthetic.as_ref().unwrap().config.interval.max(1) as u64*60;
+                    synthetic_mtime = (synthetic_mtime+interval).max(reference_ts+(conf.start_mams[0]+REFERENCE_OFFSET) as u64*60);
+                }
+                (synthetic_mtime, None)
+            }
+        };
+        if reference_ts == 0 || next_start_mam_idx < conf.start_mams.len() && Simulation::get_current_time(mtime, reference_ts, conf.ticks_per_minute) >= conf.minutes(conf.start_mams[next_start_mam_idx]+REFERENCE_OFFSET) {
+            let next_start_mam = conf.start_mams[next_start_mam_idx];
+            println!("Beginning next start_mam {}", next_start_mam);
+            let number_of_days = 2;
+            if next_start_mam_idx == 0 {
+                println!("Loading GTFS day_idx {} days {}", day_idx, number_of_days);
+                let timetable = gtfs::load_timetable(&conf.gtfs_path, day(conf.start_date[0], conf.start_date[1], conf.start_date[2]+day_idx), day(conf.start_date[0], conf.start_date[1], conf.start_date[2]+day_idx+number_of_days));
+                reference_ts = timetable.get_start_day_ts() as u64;
+                let mut tt = gtfs::GtfsTimetable::with_ticks_per_minute(conf.ticks_per_minute);
+                let mut routes = vec![];
+                tt.transport_and_day_to_connection_id = gtfs::retrieve(&timetable, &mut tt.stations, &mut routes, &mut tt.connections, conf.ticks_per_minute);
+                if conf.transfer == "short" {
+                    walking::shorten_footpaths(&mut tt.stations, conf.ticks_per_minute);
+                }
+                let contr = Some(gtfs::get_station_contraction(&tt.stations));
+                if let Some(synthetic) = &mut synthetic {
+                    // same ground truth for every run with this seed
+                    synthetic.sample(&tt.connections, &store, &mut StdRng::seed_from_u64(seed.wrapping_add(day_idx as u64)));
+                }
+                #[cfg(feature = "native-gtfs")]
+                let native_realtime = (synthetic.is_none() && conf.native_realtime).then(|| {
+                    let start_date = day(conf.start_date[0], conf.start_date[1], conf.start_date[2]+day_idx);
+                    gtfs::NativeRealtime::new(&conf.gtfs_path, start_date.pred_opt().unwrap(), day(conf.start_date[0], conf.start_date[1], conf.start_date[2]+day_idx+number_of_days), &timetable, &tt)
+                });
+                workers.day(&tt, &contr, reference_ts);
+                #[cfg(feature = "native-gtfs")]
+                {
+                    native = native_realtime.map(|n| (n, tt.stations, tt.connections));
+                }
+                transport_and_day_to_connection_id = tt.transport_and_day_to_connection_id;
+                t = Some(timetable);
+            }
+            let samples = load_samples(&conf.samples_config_path);
+            let pairs = samples.iter().take(conf.samples).map(|s| (s.from_idx, s.to_idx, conf.minutes(next_start_mam+REFERENCE_OFFSET))).collect();
+            workers.pairs(pairs, samples.into_iter().take(conf.samples).map(|s| ((s.from_idx, s.to_idx), s.stratum)).collect());
+            pending = true;
+            next_start_mam_idx += 1;
+        }
+        let current_time = Simulation::get_current_time(mtime, reference_ts, conf.ticks_per_minute);
+        if current_time < conf.minutes(conf.start_mams[0]+REFERENCE_OFFSET) {
+            continue;
+        }
+        let mut realtime = Realtime::default();
+        match path {
+            Some(path) => {
+                println!("Loading GTFSRT {}", path);
+                #[cfg(feature = "native-gtfs")]
+                let loaded = native.as_mut().map(|(native, stations, connections)| load_native_realtime(&path, t.as_ref().unwrap(), native, stations, connections, &mut realtime)).is_some();
+                #[cfg(not(feature = "native-gtfs"))]
+                let loaded = false;
+                if !loaded {
+                    gtfs::load_realtime(&path, t.as_ref().unwrap(), &transport_and_day_to_connection_id, conf.ticks_per_minute, |connection_id: usize, is_departure: bool, location_idx: Option<usize>, in_out_allowed: Option<bool>, delay: Option<i16>| {
+                        realtime.updates.push((connection_id, is_departure, location_idx, in_out_allowed, delay));
+                    });
+                }
+            },
+            None => {
+                let n = synthetic.as_mut().unwrap().emit(current_time, |connection_id: usize, is_departure: bool, location_idx: Option<usize>, in_out_allowed: Option<bool>, delay: Option<i16>| {
+                    realtime.updates.push((connection_id, is_departure, location_idx, in_out_allowed, delay));
+                });
+                println!("Loaded {} synthetic updates at {}", n, current_time);
+            }
+        }
+        let do_continue = workers.step(current_time, realtime, |origin_idx, destination_idx, time| min_journey(t.as_ref().unwrap(), origin_idx, destination_idx, time, conf.ticks_per_minute));
+        if next_start_mam_idx == conf.start_mams.len() && (!do_continue || current_time-conf.minutes(1440+REFERENCE_OFFSET) >= conf.minutes(conf.start_mams[0])) {
+            println!("All simulations completed ({}) for the day. Stopping at current_time {}.", !do_continue, current_time);
+            write_simulation_run(&conf, seed, simulation_run_at, day_idx, workers.end_day(day_idx));
+            pending = false;
+            day_idx += 1;
+            next_start_mam_idx = 0;
+            if day_idx >= conf.num_days {
+                break;
+            }
+        }
+    }
+    if pending {
+        println!("Reached end of GTFSRT without having completed everything.");
+        write_simulation_run(&conf, seed, simulation_run_at, day_idx, workers.end_day(day_idx));
+    }
+    workers.join();
+    Ok(0)
+}
+
+impl Simulation {
+    fn new(conf: SimulationConfig, store: distribution_store::Store, raptor: Raptor) -> Simulation {
+        Simulation {
+            strategies: strategy::from_config(&conf),
+            strata: HashMap::new(),
+            csameat_store: conf.csameat().then(|| {
+                let mut store = store.clone();
+                store.nonnegative();
+                store
+            }),
+            csameat_tt: None,
+            conf: conf,
+            store: store,
+            tt: GtfsTimetable::new(),
+            contr: None,
+            reference_ts: 0,
+            raptor: raptor,
+            stop_pairs: vec![],
+            active: HashSet::new(),
+            logs: HashMap::new(),
+            results: HashMap::new()
+        }
+    }
+
+    fn work(&mut self, ticks: mpsc::Receiver<Tick>) {
+        for tick in ticks {
+            match tick {
+                Tick::Day(tt, contr, reference_ts) => {
+                    self.csameat_tt = self.csameat_store.as_ref().map(|_| (*tt).clone());
+                    self.tt = *tt;
+                    self.contr = contr;
+                    self.reference_ts = reference_ts;
+                },
+                Tick::Pairs(pairs, strata) => {
+                    self.stop_pairs.extend(pairs);
+                    self.strata.extend(strata);
+                },
+                Tick::Step(current_time, realtime) => {
+                    let mut tt = std::mem::replace(&mut self.tt, GtfsTimetable::new());
+                    let mut csameat_tt = self.csameat_tt.take();
+                    Self::apply_updates(&mut self.store, &mut tt, &self.contr, &self.conf, false, current_time, &realtime);
+                    if let (Some(store), Some(csameat_tt)) = (&mut self.csameat_store, &mut csameat_tt) {
+                        Self::apply_updates(store, csameat_tt, &self.contr, &self.conf, true, current_time, &realtime);
+                    }
+                    let do_continue = self.step_pairs(&mut tt, &mut csameat_tt, current_time);
+                    self.tt = tt;
+                    self.csameat_tt = csameat_tt;
+                    self.raptor.0.send(Reply::StepDone(do_continue)).unwrap();
+                },
+                Tick::EndDay(day_idx) => {
+                    let results = std::mem::take(&mut self.results).into_values().collect();
+                    self.raptor.0.send(Reply::Results(day_idx, results)).unwrap();
+                    self.stop_pairs.clear();
+                    self.strategies.iter_mut().for_each(|s| s.clear());
+                    self.active.clear();
+                    self.logs.clear();
+                }
+            }
+        }
+    }
+
+    fn apply_updates(store: &mut distribution_store::Store, tt: &mut GtfsTimetable, contr: &Option<StationContraction>, conf: &SimulationConfig, csameat: bool, current_time: i32, realtime: &Realtime) {
+        {
+            let mut env = Self::new_env(store, tt, contr, conf, csameat, current_time, true, false);
+            for (connection_id, is_departure, location_idx, in_out_allowed, delay) in &realtime.updates {
+                env.update(*connection_id, *is_departure, *location_idx, *in_out_allowed, *delay);
+            }
+            for (connection_id, is_departure, min_delay) in &realtime.min_delays {
+                env.update_min_delay(*connection_id, *is_departure, *min_delay);
+            }
+        }
+        for (connection_id, alert) in &realtime.alerts {
+            tt.connections[tt.order[*connection_id]].message = alert.clone();
+        }
+        gtfs::sort_station_departures_asc(&mut tt.stations, &tt.connections, &tt.order);
+    }
+
+    fn step_pairs(&mut self, tt: &mut GtfsTimetable, csameat_tt: &mut Option<GtfsTimetable>, current_time: i32) -> bool {
+        let mut timing_preprocessing = 0;
+        let mut csameat_timing_preprocessing = 0;
+        let mut do_continue = false;
+        let stop_pairs = std::mem::take(&mut self.stop_pairs);
+        for pair in &stop_pairs {
+            println!("Pair: {:?}", pair);
+            self.initialize_if_necessary(pair, tt, csameat_tt, current_time, &mut timing_preprocessing, &mut csameat_timing_preprocessing);
+            if !self.active.contains(pair) || pair.2 + self.conf.minutes(self.conf.query_window) < current_time {
+                continue;
+            }
+            let journey = self.results.get_mut(pair).unwrap();
+            let logs = self.logs.get_mut(pair).unwrap();
+            let mut ctx = SimulationContext {
+                store: &mut self.store,
+                tt: &mut *tt,
+                raptor: &self.raptor,
+                contr: &self.contr,
+                conf: &self.conf,
+                timing_preprocessing: &mut timing_preprocessing
+            };
+            let mut csameat_ctx = csameat_tt.as_mut().map(|tt| SimulationContext {
+                store: self.csameat_store.as_mut().unwrap(),
+                tt,
+                raptor: &self.raptor,
+                contr: &self.contr,
+                conf: &self.conf,
+                timing_preprocessing: &mut csameat_timing_preprocessing
+            });
+            for (i, strategy) in self.strategies.iter_mut().enumerate() {
+                let ctx = if strategy.csameat() { csameat_ctx.as_mut().unwrap() } else { &mut ctx };
+                strategy.replan(ctx, pair, current_time, &mut logs[i], journey.results.get_mut(strategy.name()).unwrap());
+            }
+            for (i, strategy) in self.strategies.iter_mut().enumerate() {
+                println!("{}...", strategy.name());
+                let ctx = if strategy.csameat() { csameat_ctx.as_mut().unwrap() } else { &mut ctx };
+                let result = journey.results.get_mut(strategy.name()).unwrap();
+                let mut repeat = true;
+                while repeat {
+                    repeat = false;
+                    let current_stop_idx = Self::get_current_stop_idx(current_time, *pair, &mut logs[i], result, ctx.tt);
+                    if current_stop_idx.is_some() {
+                        let alternatives = strategy.alternatives(ctx, pair, current_stop_idx.unwrap());
+                        repeat = Self::step(current_time, pair.2, current_stop_idx.unwrap(), &alternatives, &mut logs[i], result, ctx.tt);
+                    }
+                }
+                strategy.finish_step(pair);
+            }
+            if !journey.is_completed() {
+                do_continue = true;
+            }
+        }
+        self.stop_pairs = stop_pairs;
+        do_continue
+    }
+
+    fn initialize_if_necessary(&mut self, pair: &Pair, tt: &mut GtfsTimetable, csameat_tt: &mut Option<GtfsTimetable>, current_time: i32, timing_preprocessing: &mut u128, csameat_timing_preprocessing: &mut u128) {
+        if self.results.get(pair).is_none() {
+            let mut results: Vec<SimulationResult> = self.strategies.iter().map(|_| SimulationResult {
+                departure: 0,
+                original_dest_arrival_prediction: 0.0,
+                actual_dest_arrival: None,
+                preprocessing_elapsed_ms: 0,
+                algo_elapsed_ms: vec![],
+                broken: false,
+                connections_taken: vec![],
+                prediction_times: vec![],
+                connection_missed: None
+            }).collect();
+            let mut ctx = SimulationContext {
+                store: &mut self.store,
+                tt,
+                raptor: &self.raptor,
+                contr: &self.contr,
+                conf: &self.conf,
+                timing_preprocessing
+            };
+            let mut csameat_ctx = csameat_tt.as_mut().map(|tt| SimulationContext {
+                store: self.csameat_store.as_mut().unwrap(),
+                tt,
+                raptor: &self.raptor,
+                contr: &self.contr,
+                conf: &self.conf,
+                timing_preprocessing: csameat_timing_preprocessing
+            });
+            let mut infeasible = vec![];
+            for (strategy, result) in self.strategies.iter_mut().zip(results.iter_mut()) {
+                let ctx = if strategy.csameat() { csameat_ctx.as_mut().unwrap() } else { &mut ctx };
+                if !strategy.initialize(ctx, pair, current_time, result) {
+                    infeasible.push(strategy.name().to_string());
+                }
+            }
+            if infeasible.is_empty() {
+                self.active.insert(*pair);
+            } else {
+                println!("Infeasible for {:?}, skipping.", infeasible);
+                self.strategies.iter_mut().for_each(|s| s.discard(pair));
+            }
+            self.logs.insert(*pair, self.strategies.iter().map(|_| vec![]).collect());
+            let results = self.strategies.iter().map(|s| s.name().to_string()).zip(results).collect();
+            self.results.insert(*pair, SimulationJourney {
+                pair: *pair,
+                start_time: self.reference_ts+(pair.2*types::seconds_per_tick(self.conf.ticks_per_minute)) as u64,
+                from_station: tt.stations[pair.0].id.clone(),
+                from_station_name: tt.stations[pair.0].name.clone(),
+                to_station: tt.stations[pair.1].id.clone(),
+                to_station_name: tt.stations[pair.1].name.clone(),
+                stratum: self.strata.get(&(pair.0, pair.1)).cloned().unwrap_or_default(),
+                results,
+                det: None,
+                stoch: None
+            });
+        }
+    }
+
+    fn fix_if_sitting_in_cancelled_trip(log: &mut Vec<LogEntry>, result: &SimulationResult, start_time: i32, tt: &mut GtfsTimetable) -> Option<types::Mtime> {
+        if log.len() > 0 {
+            let mut c_id = log.last().unwrap().conn_id;
+            let mut c = &tt.connections[tt.order[c_id]];
+            if !c.arrival.in_out_allowed {
+                let boarded_trip_at_id = result.connections_taken.get(result.connections_taken.len()-2).map(|r| r.id).unwrap_or(c_id);
+                while !c.arrival.in_out_allowed && c_id > boarded_trip_at_id {
+                    c_id -= 1;
+                    c = &tt.connections[tt.order[c_id]];
+                }
+                if !c.arrival.in_out_allowed {
+                    log.pop();
+                    println!("Sitting in cancelled trip. Returning to stop where boarded, at {}. Now at {:?}", boarded_trip_at_id, log.last().map(|l| l.conn_id).unwrap_or(0));
+                } else {
+                    let last_log = log.last_mut().unwrap();
+                    println!("Sitting in cancelled trip. Returning to last valid stop, boarded at {}, updating connid {} to {}. Now at {:?}", boarded_trip_at_id, last_log.conn_id, c_id, c);
+                    last_log.conn_id = c_id;
+                }
+                return Some(Self::get_arrival_time(log, start_time, tt))
+            }
+        }
+        None
+    }
+
+    fn preprocess_if_necessary<'a>(env: &mut Box<dyn Queriable + 'a>, timing_preprocessing: &mut u128) {
+        if *timing_preprocessing != 0 {
+            return;
+        }
+        let start = Instant::now();
+        env.preprocess();
+        *timing_preprocessing = start.elapsed().as_millis();
+    }
+
+    fn get_mtime_unix(f: Result<std::path::PathBuf, glob::GlobError>) -> Result<u64, Box<dyn Error>> {
+        Ok(fs::metadata(f?)?
+            .modified()?
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs())
+    }
+
+    fn get_current_time(mtime: u64, reference_ts: u64, ticks_per_minute: types::Mtime) -> i32 {
+        (mtime.saturating_sub(reference_ts)/types::seconds_per_tick(ticks_per_minute) as u64) as i32
+    }
+
+    fn new_env<'a: 'b, 'b>(store: &'a mut distribution_store::Store, tt: &'a mut GtfsTimetable, contr: &'a Option<StationContraction>, conf: &SimulationConfig, csameat: bool, now: types::Mtime, mean_only: bool, initial: bool) -> Box<dyn Queriable<'a> + 'b> {
+        let mut env: Box<dyn Queriable> = if csameat {
+            let mut env = csameat::Environment::from_timetable(store, tt, now);
+            env.set_mean_only(mean_only);
+            if let Some(max_dc) = conf.csameat_max_dc {
+                env.set_max_dc(max_dc);
+            }
+            Box::new(env)
+        } else {
+            Box::new(topocsa::Environment::new(
+                store,
+                &mut tt.connections,
+                &tt.stations,
+                &mut tt.cut,
+                &mut tt.order,
+                now,
+                conf.epsilon_reachable,
+                conf.epsilon_feasible,
+                mean_only,
+                conf.transfer_strategy == "domination" || initial && conf.transfer_strategy == "fuzzy_repeated"
+            ))
+        };
+        if let Some(contraction) = contr {
+            env.set_station_contraction(contraction).unwrap()
+        }
+        env
+    }
+
+    fn get_current_stop_idx(current_time: i32, pair: (usize, usize, i32), log: &mut Vec<LogEntry>, result: &mut SimulationResult, tt: &GtfsTimetable) -> Option<usize> {
+        if result.actual_dest_arrival.is_some() {
+            println!("Completed.");
+            None
+        } else if log.len() == 0 {
+            Some(pair.0)
+        } else {
+            let last_c = &tt.connections[tt.order[log.last().unwrap().conn_id]];
+            let current_stop_idx = last_c.to_idx;
+            let footpaths = &tt.stations[current_stop_idx].footpaths;
+            let mut i = 0;
+            while i <= footpaths.len() {
+                let stop_idx = if i == footpaths.len() { current_stop_idx } else { footpaths[i].target_location_idx };
+                if stop_idx == pair.1 {
+                    if current_time >= last_c.arrival.projected() && last_c.arrival.in_out_allowed {
+                        Self::update_connections_taken_from_last_log(result, log, tt, current_time);
+                        let walking_time = if i == footpaths.len() { 0 } else { footpaths[i].duration as i32 };
+                        result.actual_dest_arrival = Some(last_c.arrival.projected()+walking_time);
+                    }
+                    return None;
+                }
+                i += 1;
+            }
+            Some(current_stop_idx)
+        }
+    }
+
+    fn step(current_time: i32, start_time: i32, current_stop_idx: usize, alternatives: &[Alternative], log: &mut Vec<LogEntry>, result: &mut SimulationResult, tt: &GtfsTimetable) -> bool {
+        if log.len() > 10000 || result.connections_taken.len() > 10000 {
+            panic!("Log len exceeded");
+        }
+        let arrival_time = Self::get_arrival_time(log, start_time, tt);
+        let mut alternatives_still_available = false;
+        println!("current_time: {} current_stop_idx: {} arrival: {}", current_time, current_stop_idx, arrival_time);
+        if current_time >= arrival_time {
+            for alt in alternatives {
+                let next_c = &tt.connections[alt.from_conn_idx];
+                let transfer_time = Self::get_transfer_time(current_stop_idx, next_c.from_idx, log.is_empty(), &tt.stations); 
+                if Self::can_take(next_c, arrival_time, transfer_time, log, tt) {    
+                    if current_time >= next_c.departure.projected() { // TODO require not too long ago?
+                        if log.len() > 0 {
+                            Self::update_connections_taken_from_last_log(result, log, tt, current_time);
+                        } else {
+                            result.departure = next_c.departure.projected();
+                            result.original_dest_arrival_prediction = alt.proj_dest_arr;
+                        }
+                        Self::update_connections_taken(result, &next_c, &tt.stations, alt.proj_dest_arr, current_time);
+                        println!("step {} {} {} from/to: {} {} trip: {} arr: {} {} dep: {} {} to_conn: dp: {} {} arr: {} {} from/to: {} {} trip: {}", arrival_time, alt.from_conn_idx, alt.to_conn_idx, next_c.from_idx, next_c.to_idx, next_c.trip_id, next_c.arrival.scheduled, next_c.arrival.projected(), next_c.departure.scheduled, next_c.departure.projected(), tt.connections[alt.to_conn_idx].departure.scheduled, tt.connections[alt.to_conn_idx].departure.projected(), tt.connections[alt.to_conn_idx].arrival.scheduled, tt.connections[alt.to_conn_idx].arrival.projected(), tt.connections[alt.to_conn_idx].from_idx, tt.connections[alt.to_conn_idx].to_idx, tt.connections[alt.to_conn_idx].trip_id);
+                        log.push(LogEntry{
+                            conn_id: tt.connections[alt.to_conn_idx].id,
+                            proj_dest_arr: alt.proj_dest_arr,
+                            arrival_time_lower_bound: std::cmp::max(next_c.departure.projected(), arrival_time)
+                        });
+                        return true;
+                    }
+                    alternatives_still_available = true;
+                    break;
+                }
+            }
+            if !alternatives_still_available && !result.broken {
+                if log.len() > 0 {
+                    Self::update_connections_taken_from_last_log(result, log, tt, current_time);
+                }
+                if alternatives.len() > 0 {
+                    result.connection_missed = Some(tt.connections[alternatives.last().unwrap().from_conn_idx].clone());
+                    println!("Missed: {:?}", tt.connections[alternatives.last().unwrap().from_conn_idx]);
+                }
+                println!("Setting to broken.");
+                result.broken = true;
+            }
+        }
+        return false;
+    }
+
+    fn get_arrival_time(log: &Vec<LogEntry>, start_time: i32, tt: &GtfsTimetable) -> i32 {
+        if log.len() == 0 {
+            start_time
+        } else {
+            let c = &tt.connections[tt.order[log.last().unwrap().conn_id]];
+            std::cmp::max(std::cmp::max(c.arrival.projected(), c.departure.projected()), log.last().unwrap().arrival_time_lower_bound)
+        }
+    }
+
+    fn can_take(next_c: &connection::Connection, arrival_time: i32, transfer_time: i32, log: &mut Vec<LogEntry>, tt: &GtfsTimetable) -> bool {
+        if log.len() > 0 {
+            let c = &tt.connections[tt.order[log.last().unwrap().conn_id]];
+            if c.is_consecutive(next_c) && c.id < next_c.id {
+                return true;
+            }
+            if !c.arrival.in_out_allowed {
+                return false;
+            }
+        }
+        if !next_c.departure.in_out_allowed {
+            return false;
+        }
+        next_c.departure.projected() >= arrival_time+transfer_time
+    }
+
+    fn update_connections_taken_from_last_log(result: &mut SimulationResult, log: &[LogEntry], tt: &GtfsTimetable, current_time: i32) {
+        Self::update_connections_taken(result, &tt.connections[tt.order[log.last().unwrap().conn_id]], &tt.stations, log.last().unwrap().proj_dest_arr, current_time);
+    }
+
+    fn update_connections_taken(result: &mut SimulationResult, connection: &connection::Connection, stations: &[connection::Station], proj_dest_arr: types::MFloat, current_time: i32) {
+        let mut conn = connection.clone();
+        conn.destination_arrival.borrow_mut().get_or_insert(distribution::Distribution::empty(0)).mean = proj_dest_arr;
+        conn.message = format!("from: {} {} to: {} {}", stations[conn.from_idx].id, stations[conn.from_idx].name, stations[conn.to_idx].id, stations[conn.to_idx].name);
+        result.connections_taken.push(conn);
+        result.prediction_times.push(current_time);
+        if result.broken {
+            println!("Updating broken journey to make it feasible again.");
+        }
+        result.broken = false;
+    }
+
+    fn get_transfer_time(from_stop_idx: usize, to_stop_idx: usize, is_first: bool, stations: &[connection::Station]) -> i32 {
+        if from_stop_idx == to_stop_idx {
+            if is_first {
+                return 0;
+            }
+            return stations[from_stop_idx].transfer_time as i32;
+        }
+        for f in &stations[from_stop_idx].footpaths {
+            if f.target_location_idx == to_stop_idx {
+                return std::cmp::max(f.duration, 1) as i32;
+            }
+        }
+        panic!("Tried walking where no walking connection exists from {} to {}", from_stop_idx, to_stop_idx);
+    }
+}
+
+fn write_simulation_run(conf: &SimulationConfig, seed: u64, simulation_run_at: u64, day_idx: i32, results: Vec<SimulationJourney>) {
+    let filename = format!("./simulation/runs/{}.{}.{}.{}.ign.json", simulation_run_at, conf.strategies().join("."), conf.transfer, day_idx);
+    let run = SimulationRun {
+        simulation_run_at: simulation_run_at,
+        comment: "".to_string(),
+        config: conf.clone(),
+        seed,
+        results,
+    };
+    let buf = serde_json::to_vec(&run).unwrap();
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(true)
+        .open(filename).expect("file not openable");
+    file.write_all(&buf).expect("error writing file");
+    println!("Results written.");
+}
+
+fn create_samples(config_file: &str, out: &str) {
+    let conf = load_samples_config(config_file);
+    let seed = conf.seed.unwrap_or_else(rand::random);
+    println!("Creating {} samples with seed {}", conf.sampling.samples, seed);
+    let start_date = day(conf.start_date[0], conf.start_date[1], conf.start_date[2]);
+    let (samples, shortfall) = gtfs::create_simulation_samples(
+        &conf.gtfs_path,
+        start_date,
+        start_date+chrono::Days::new(conf.num_days as u64),
+        &conf.sampling,
+        conf.demand_path.as_deref(),
+        conf.reachable_at.map(|mam| mam+REFERENCE_OFFSET),
+        &mut StdRng::seed_from_u64(seed)
+    );
+    if shortfall > 0 {
+        let mut strata: BTreeMap<&str, usize> = BTreeMap::new();
+        for s in &samples {
+            *strata.entry(&s.stratum).or_default() += 1;
+        }
+        println!("Only found {} of {} samples: {:?}", samples.len(), conf.sampling.samples, strata);
+    }
+    let buf = serde_json::to_vec(&samples).unwrap();
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(true)
+        .open(out).expect("file not openable");
+    file.write_all(&buf).expect("error writing file");
+}
+
+fn get_min_det_journey(raptor: &Raptor, origin_idx: usize, destination_idx: usize, current_time: i32) -> (Option<motis_nigiri::Journey>, u128) {
+    let start = Instant::now();
+    let min_journey = raptor.min_journey(origin_idx, destination_idx, current_time);
+    let timing_det = start.elapsed().as_millis();
+    (min_journey, timing_det)
+}
+
+#[derive(Serialize)]
+struct SimulationAnalysis {
+    baseline_infeasible: i32,
+    baseline_broken: i32,
+    target_infeasible: i32,
+    target_broken: i32,
+    baseline_and_target_infeasible: i32,
+    baseline_and_target_infeasible_original: i32,
+    #[serde(skip)]
+    delta_baseline_predicted_actual: Vec<types::MFloat>,
+    #[serde(skip)]
+    delta_target_predicted_actual: Vec<types::MFloat>,
+    #[serde(skip)]
+    delta_baseline_target_predicted: Vec<types::MFloat>,
+    #[serde(skip)]
+    delta_baseline_predicted_target_actual: Vec<types::MFloat>,
+    #[serde(skip)]
+    delta_baseline_target_actual_arrival: Vec<types::MFloat>,
+    #[serde(skip)]
+    delta_baseline_target_actual_arrival_relative: Vec<types::MFloat>,
+    #[serde(skip)]
+    delta_baseline_target_actual_arrival_relative_trimmed: Vec<types::MFloat>,
+    #[serde(skip)]
+    delta_baseline_target_actual_travel_time: Vec<types::MFloat>,
+    #[serde(skip)]
+    target_destination_arrival_percentiles: [i32; 100],
+    #[serde(skip)]
+    target_mean_percentiles: [i32; 100],
+    #[serde(skip)]
+    target_actual_travel_time: Vec<types::MFloat>,
+    #[serde(skip)]
+    baseline_algo_elapsed: Vec<types::MFloat>,
+    #[serde(skip)]
+    target_preprocessing_elapsed: Vec<types::MFloat>,
+    #[serde(skip)]
+    target_first_algo_elapsed: Vec<types::MFloat>,
+    #[serde(skip)]
+    target_algo_elapsed: Vec<types::MFloat>
+}
+
+#[derive(PartialEq, Clone, Copy)]
+pub enum OutputFormat {
+    Human,
+    Json,
+    Csv
+}
+
+#[derive(Serialize)]
+struct RunInfo {
+    simulation_run_at: u64,
+    comment: String,
+    seed: u64,
+    strategies: Vec<String>,
+    short: bool,
+    fuzzy: bool,
+    eps: bool
+}
+
+#[derive(Serialize)]
+struct Summary {
+    name: String,
+    count: usize,
+    failure_percent: types::MFloat,
+    mean: Option<types::MFloat>,
+    trimmed_mean: Option<types::MFloat>,
+    min: Option<types::MFloat>,
+    q5: Option<types::MFloat>,
+    q95: Option<types::MFloat>,
+    max: Option<types::MFloat>
+}
+
+#[derive(Serialize)]
+struct JourneyDelta {
+    origin: usize,
+    destination: usize,
+    departure: i32,
+    baseline_predicted: types::MFloat,
+    target_predicted: types::MFloat,
+    baseline_actual: Option<i32>,
+    target_actual: Option<i32>,
+    baseline_broken: bool,
+    target_broken: bool,
+    delta_actual_arrival: Option<types::MFloat>,
+    delta_actual_travel_time: Option<types::MFloat>
+}
+
+#[derive(Serialize)]
+struct ComparisonReport {
+    comparison: String,
+    stratum: String,
+    samples: usize,
+    repeated_queries: usize,
+    first_queries: usize,
+    analysis: SimulationAnalysis,
+    summaries: Vec<Summary>,
+    histograms: BTreeMap<String, Vec<(i32, types::MFloat)>>,
+    journeys: Vec<JourneyDelta>
+}
+
+#[derive(Serialize)]
+struct AnalysisReport {
+    runs: Vec<RunInfo>,
+    comparisons: Vec<ComparisonReport>
+}
+
+// Journeys of all runs are merged by pair and every two strategies are compared. Strategy names
+// already used by an earlier run are suffixed with @simulation_run_at.
+pub fn analyze_simulation(files: Vec<&String>, strategies: Option<Vec<String>>, format: OutputFormat, out: &str) {
+    let mut runs = vec![];
+    let mut names: Vec<String> = vec![];
+    let mut renames: HashMap<u64, HashMap<String, String>> = HashMap::new();
+    let mut day_idxs: HashMap<u64, i32> = HashMap::new();
+    let mut journeys: BTreeMap<(usize, usize, i32), SimulationJourney> = BTreeMap::new();
+    let mut ticks_per_minute = None;
+    for f in &files {
+        let run = load_simulation_run(f);
+        assert_eq!(*ticks_per_minute.get_or_insert(run.config.ticks_per_minute), run.config.ticks_per_minute, "runs with different time resolutions");
+        let rename = renames.entry(run.simulation_run_at).or_insert_with(|| {
+            let info = RunInfo {
+                simulation_run_at: run.simulation_run_at,
+                comment: run.comment.clone(),
+                seed: run.seed,
+                strategies: run.config.strategies(),
+                short: run.config.transfer == "short",
+                fuzzy: run.config.transfer_strategy != "domination",
+                eps: run.config.epsilon_feasible != 0.0
+            };
+            if format == OutputFormat::Human {
+                println!("run: {} seed: {} strategies: {:?} short: {} fuzzy: {} eps: {}", info.simulation_run_at, info.seed, info.strategies, info.short, info.fuzzy, info.eps);
+            }
+            let mut rename = HashMap::new();
+            for s in &info.strategies {
+                let name = if names.contains(s) { format!("{}@{}", s, run.simulation_run_at) } else { s.clone() };
+                names.push(name.clone());
+                rename.insert(s.clone(), name);
+            }
+            runs.push(info);
+            rename
+        });
+        let day_idx = day_idxs.entry(run.simulation_run_at).or_insert(0);
+        for mut j in run.results {
+            j.pair = (j.pair.0, j.pair.1, j.pair.2+run.config.minutes(*day_idx*1440));
+            let results = std::mem::take(&mut j.results);
+            let journey = journeys.entry(j.pair).or_insert(j);
+            journey.results.extend(results.into_iter().map(|(s, r)| (rename[&s].clone(), r)));
+        }
+        *day_idx += 1;
+    }
+    let names = strategies.unwrap_or(names);
+    let ticks_per_minute = ticks_per_minute.unwrap_or(types::one_tick_per_minute());
+    let human = format == OutputFormat::Human;
+    // all journeys first, then broken down by the strata of the samples, if any
+    let strata: BTreeSet<&String> = journeys.values().map(|j| &j.stratum).filter(|s| !s.is_empty()).collect();
+    let mut comparisons = vec![];
+    for stratum in std::iter::once(None).chain(strata.into_iter().map(Some)) {
+        let suffix = stratum.map(|s| format!("[{}]", s)).unwrap_or_default();
+        for (i, baseline) in names.iter().enumerate() {
+            for target in &names[i+1..] {
+                let meta: Vec<&SimulationJourney> = journeys.values().filter(|j| j.results.contains_key(baseline) && j.results.contains_key(target) && stratum.map(|s| &j.stratum == s).unwrap_or(true)).collect();
+                let mut comparison = analyze_run(
+                    &format!("{}_vs_{}{}", target, baseline, suffix),
+                    &format!("Comparison between target {} and baseline {}{}", target, baseline, stratum.map(|s| format!(" in stratum {}", s)).unwrap_or_default()),
+                    meta.iter().map(|j| &j.results[baseline]).collect(),
+                    meta.iter().map(|j| &j.results[target]).collect(),
+                    meta,
+                    human,
+                    ticks_per_minute
+                );
+                comparison.stratum = stratum.cloned().unwrap_or_default();
+                comparisons.push(comparison);
+                if stratum.is_none() && is_stochastic(baseline) && is_stochastic(target) {
+                    let meta: Vec<&SimulationJourney> = journeys.values().filter(|j| j.results.contains_key(baseline) && j.results.contains_key(target) && j.pair.2%types::minutes(1440, ticks_per_minute) < types::minutes(STOCH_COMPARISON_BEFORE_MAM, ticks_per_minute)).collect();
+                    comparisons.push(analyze_run(
+                        &format!("{}_vs_{}_before_19h", target, baseline),
+                        &format!("Comparison between stoch target {} and stoch baseline {} before 19h", target, baseline),
+                        meta.iter().map(|j| &j.results[baseline]).collect(),
+                        meta.iter().map(|j| &j.results[target]).collect(),
+                        meta,
+                        human,
+                        ticks_per_minute
+                    ));
+                }
+            }
+        }
+    }
+    let report = AnalysisReport {
+        runs,
+        comparisons
+    };
+    match format {
+        OutputFormat::Human => {},
+        OutputFormat::Json => write_analysis_json(&report, out),
+        OutputFormat::Csv => write_analysis_csv(&report, out)
+    }
+}
+
+// Stochastic strategies are additionally compared on the journeys departing before 19h.
+const STOCH_COMPARISON_BEFORE_MAM: i32 = 19*60;
+
+// Whether a (possibly @run suffixed) strategy name of the results files is one of the stochastic travelers.
+fn is_stochastic(name: &str) -> bool {
+    strategy::Strategy::from_name(name.split('@').next().unwrap()).is_some_and(|s| !s.is_raptor())
+}
+
+fn analyze_run(name: &str, title: &str, baseline: Vec<&SimulationResult>, target: Vec<&SimulationResult>, meta: Vec<&SimulationJourney>, human: bool, ticks_per_minute: types::Mtime) -> ComparisonReport {
+    let mut a = SimulationAnalysis {
+        baseline_infeasible: 0,
+        baseline_broken: 0,
+        target_infeasible: 0,
+        target_broken: 0,
+        baseline_and_target_infeasible: 0,
+        baseline_and_target_infeasible_original: 0,
+        delta_baseline_predicted_actual: vec![],
+        delta_target_predicted_actual: vec![],
+        delta_baseline_target_predicted: vec![],
+        delta_baseline_predicted_target_actual: vec![],
+        delta_baseline_target_actual_arrival: vec![],
+        delta_baseline_target_actual_arrival_relative: vec![],
+        delta_baseline_target_actual_arrival_relative_trimmed: vec![],
+        delta_baseline_target_actual_travel_time: vec![],
+        target_destination_arrival_percentiles: [0; 100],
+        target_mean_percentiles: [0; 100],
+        target_actual_travel_time: vec![],
+        baseline_algo_elapsed: vec![],
+        target_preprocessing_elapsed: vec![],
+        target_first_algo_elapsed: vec![],
+        target_algo_elapsed: vec![]
+    };
+    assert_eq!(baseline.len(), target.len());
+    let mut journeys = vec![];
+    for i in 0..baseline.len() {
+        analyze_result(&mut a, baseline[i], target[i], &meta[i], ticks_per_minute);
+        journeys.push(journey_delta(baseline[i], target[i], meta[i]));
+    }
+    if human {
+        println!("\n{}", title);
+        println!("repeated: {} first: {}", a.target_algo_elapsed.len(), a.target_first_algo_elapsed.len());
+        println!("infeasible: both: {} both original: {} baseline: {} target: {} broken: baseline: {} target: {} feasible: both: {} total: {}", a.baseline_and_target_infeasible, a.baseline_and_target_infeasible_original, a.baseline_infeasible, a.target_infeasible, a.baseline_broken, a.target_broken, a.delta_baseline_target_actual_travel_time.len(), baseline.len());
+    }
+    let samples = baseline.len();
+    let samples_target = a.target_actual_travel_time.len();
+    let mut summaries = vec![];
+    let mut summarize = |values: &[types::MFloat], name: &str| {
+        let (arr, s) = summary(values, name, samples);
+        if human {
+            s.print();
+        }
+        summaries.push(s);
+        arr
+    };
+    let delta_baseline_predicted_actual = summarize(&a.delta_baseline_predicted_actual, "delta_baseline_predicted_actual");
+    let delta_target_predicted_actual = summarize(&a.delta_target_predicted_actual, "delta_target_predicted_actual");
+    summarize(&a.delta_baseline_target_predicted, "delta_baseline_target_predicted");
+    summarize(&a.delta_baseline_predicted_target_actual, "delta_baseline_predicted_target_actual");
+    let delta_baseline_target_actual_arrival = summarize(&a.delta_baseline_target_actual_arrival, "delta_baseline_target_actual_arrival");
+    summarize(&a.delta_baseline_target_actual_arrival_relative, "delta_baseline_target_actual_arrival_relative");
+    summarize(&a.delta_baseline_target_actual_arrival_relative_trimmed, "delta_baseline_target_actual_arrival_relative_trimmed");
+    let delta_baseline_target_actual_travel_time = summarize(&a.delta_baseline_target_actual_travel_time, "delta_baseline_target_actual_travel_time");
+    summarize(&a.target_actual_travel_time, "target_actual_travel_time");
+    summarize(&a.baseline_algo_elapsed, "baseline_algo_elapsed");
+    summarize(&a.target_first_algo_elapsed, "target_first_algo_elapsed");
+    summarize(&a.target_algo_elapsed, "target_algo_elapsed");
+    summarize(&a.target_preprocessing_elapsed, "target_preprocessing_elapsed");
+    let mut histograms = BTreeMap::new();
+    histograms.insert("delta_baseline_predicted_actual".to_string(), histogram(delta_baseline_predicted_actual));
+    histograms.insert("delta_target_predicted_actual".to_string(), histogram(delta_target_predicted_actual));
+    let hist_arrival = histogram(delta_baseline_target_actual_arrival);
+    histograms.insert("delta_baseline_target_actual_arrival_cdf".to_string(), cdf(&hist_arrival));
+    histograms.insert("delta_baseline_target_actual_arrival".to_string(), hist_arrival);
+    histograms.insert("delta_baseline_target_actual_travel_time".to_string(), histogram(delta_baseline_target_actual_travel_time));
+    histograms.insert("target_destination_arrival_percentiles".to_string(), percentogram(&a.target_destination_arrival_percentiles, samples_target));
+    if human {
+        println!("delta_baseline_predicted_actual: {:?}", histograms["delta_baseline_predicted_actual"]);
+        println!("delta_target_predicted_actual: {:?}", histograms["delta_target_predicted_actual"]);
+        println!("delta_baseline_target_actual_arrival: {:?}", histograms["delta_baseline_target_actual_arrival"]);
+        println!("delta_baseline_target_actual_arrival cdf: {:?}", histograms["delta_baseline_target_actual_arrival_cdf"]);
+        println!("delta_baseline_target_actual_travel_time: {:?}", histograms["delta_baseline_target_actual_travel_time"]);
+        print_percentogram(&a.target_destination_arrival_percentiles, samples_target, "target_destination_arrival_percentiles");
+        //print_percentogram(&a.target_mean_percentiles, samples_target, "target_mean_percentiles");
+    }
+    ComparisonReport {
+        comparison: name.to_string(),
+        stratum: String::new(),
+        samples,
+        repeated_queries: a.target_algo_elapsed.len(),
+        first_queries: a.target_first_algo_elapsed.len(),
+        analysis: a,
+        summaries,
+        histograms,
+        journeys
+    }
+}
+
+fn journey_delta(baseline: &SimulationResult, target: &SimulationResult, meta: &SimulationJourney) -> JourneyDelta {
+    let both = baseline.actual_dest_arrival.zip(target.actual_dest_arrival);
+    JourneyDelta {
+        origin: meta.pair.0,
+        destination: meta.pair.1,
+        departure: meta.pair.2,
+        baseline_predicted: baseline.original_dest_arrival_prediction,
+        target_predicted: target.original_dest_arrival_prediction,
+        baseline_actual: baseline.actual_dest_arrival,
+        target_actual: target.actual_dest_arrival,
+        baseline_broken: baseline.broken,
+        target_broken: target.broken,
+        delta_actual_arrival: both.map(|(b, t)| (t-b) as types::MFloat),
+        delta_actual_travel_time: both.map(|(b, t)| ((t-target.departure)-(b-baseline.departure)) as types::MFloat)
+    }
+}
+
+fn write_analysis_json(report: &AnalysisReport, out: &str) {
+    fs::create_dir_all(out).expect("output directory not creatable");
+    let path = format!("{}/analysis.json", out);
+    fs::write(&path, serde_json::to_vec_pretty(report).unwrap()).expect("error writing file");
+    println!("Written {}", path);
+}
+
+fn write_analysis_csv(report: &AnalysisReport, out: &str) {
+    fs::create_dir_all(out).expect("output directory not creatable");
+    let path = format!("{}/analysis.csv", out);
+    let mut wtr = csv::Writer::from_path(&path).unwrap();
+    wtr.write_record(["comparison", "kind", "name", "key", "value"]).unwrap();
+    for c in &report.comparisons {
+        let mut write = |kind: &str, name: &str, key: String, value: String| wtr.write_record([&c.comparison, kind, name, &key, &value]).unwrap();
+        write("count", "samples", "".to_string(), c.samples.to_string());
+        write("count", "repeated_queries", "".to_string(), c.repeated_queries.to_string());
+        write("count", "first_queries", "".to_string(), c.first_queries.to_string());
+        if let serde_json::Value::Object(counts) = serde_json::to_value(&c.analysis).unwrap() {
+            for (k, v) in counts {
+                write("count", &k, "".to_string(), v.to_string());
+            }
+        }
+        for s in &c.summaries {
+            let stats = [("count", Some(s.count as types::MFloat)), ("failure_percent", Some(s.failure_percent)), ("mean", s.mean), ("trimmed_mean", s.trimmed_mean), ("min", s.min), ("q5", s.q5), ("q95", s.q95), ("max", s.max)];
+            for (k, v) in stats {
+                write("summary", &s.name, k.to_string(), v.map(|v| v.to_string()).unwrap_or_default());
+            }
+        }
+        for (name, h) in &c.histograms {
+            for (bin, v) in h {
+                write("histogram", name, bin.to_string(), v.to_string());
+            }
+        }
+    }
+    wtr.flush().unwrap();
+    let journeys_path = format!("{}/journeys.csv", out);
+    let mut wtr = csv::Writer::from_path(&journeys_path).unwrap();
+    wtr.write_record(["comparison", "origin", "destination", "departure", "baseline_predicted", "target_predicted", "baseline_actual", "target_actual", "baseline_broken", "target_broken", "delta_actual_arrival", "delta_actual_travel_time"]).unwrap();
+    let opt = |v: Option<String>| v.unwrap_or_default();
+    for c in &report.comparisons {
+        for j in &c.journeys {
+            wtr.write_record([
+                c.comparison.clone(), j.origin.to_string(), j.destination.to_string(), j.departure.to_string(),
+                j.baseline_predicted.to_string(), j.target_predicted.to_string(),
+                opt(j.baseline_actual.map(|v| v.to_string())), opt(j.target_actual.map(|v| v.to_string())),
+                j.baseline_broken.to_string(), j.target_broken.to_string(),
+                opt(j.delta_actual_arrival.map(|v| v.to_string())), opt(j.delta_actual_travel_time.map(|v| v.to_string()))
+            ]).unwrap();
+        }
+    }
+    wtr.flush().unwrap();
+    println!("Written {} and {}", path, journeys_path);
+}
+
+fn get_pair_mam(meta: &SimulationJourney, ticks_per_minute: types::Mtime) -> types::Mtime {
+    meta.pair.2%types::minutes(1440, ticks_per_minute)+types::minutes(REFERENCE_OFFSET, ticks_per_minute)
+}
+
+fn analyze_result(a: &mut SimulationAnalysis, baseline: &SimulationResult, target: &SimulationResult, meta: &SimulationJourney, ticks_per_minute: types::Mtime) {
+    if baseline.original_dest_arrival_prediction == 0.0 && target.original_dest_arrival_prediction == 0.0 {
+        a.baseline_and_target_infeasible_original += 1;
+    }
+    if baseline.actual_dest_arrival.is_some() {
+        a.delta_baseline_predicted_actual.push(baseline.actual_dest_arrival.unwrap() as types::MFloat-baseline.original_dest_arrival_prediction);
+        a.baseline_algo_elapsed.extend(baseline.algo_elapsed_ms.iter().skip(1).map(|e| *e as types::MFloat));
+    } else {
+        a.baseline_infeasible += 1;
+        if baseline.broken {
+            a.baseline_broken += 1;
+        }
+    }
+    if target.actual_dest_arrival.is_some() {
+        percentograms(a, target);
+        a.delta_target_predicted_actual.push(target.actual_dest_arrival.unwrap() as types::MFloat-target.original_dest_arrival_prediction);
+        a.target_actual_travel_time.push((target.actual_dest_arrival.unwrap()-target.departure) as types::MFloat);
+        a.target_first_algo_elapsed.push(target.algo_elapsed_ms[0] as types::MFloat);
+        a.target_algo_elapsed.extend(target.algo_elapsed_ms.iter().skip(1).map(|e| *e as types::MFloat));
+        a.target_preprocessing_elapsed.push(target.preprocessing_elapsed_ms as types::MFloat);
+    } else {
+        a.target_infeasible += 1;
+        if target.broken {
+            a.target_broken += 1;
+        }
+    }
+    if baseline.actual_dest_arrival.is_some() && target.actual_dest_arrival.is_some() {
+        /*if meta.pair.0 == 24491 && meta.pair.1 == 34985 {
+            print_distribution(target, baseline, meta, ticks_per_minute);
+        }*/
+        a.delta_baseline_target_predicted.push(target.original_dest_arrival_prediction-baseline.original_dest_arrival_prediction);
+        a.delta_baseline_predicted_target_actual.push(target.actual_dest_arrival.unwrap() as types::MFloat-baseline.original_dest_arrival_prediction);
+        let diff = target.actual_dest_arrival.unwrap() as types::MFloat-baseline.actual_dest_arrival.unwrap() as types::MFloat;
+        a.delta_baseline_target_actual_arrival.push(diff); 
+        let relative = (target.actual_dest_arrival.unwrap()-baseline.actual_dest_arrival.unwrap()) as types::MFloat/(baseline.actual_dest_arrival.unwrap()-get_pair_mam(meta, ticks_per_minute)) as types::MFloat*100.0;
+        if !relative.is_nan() {
+            a.delta_baseline_target_actual_arrival_relative.push(relative);
+            if diff.abs() <= types::minutes(130, ticks_per_minute) as types::MFloat {
+                a.delta_baseline_target_actual_arrival_relative_trimmed.push(relative);
+            }
+        }
+        a.delta_baseline_target_actual_travel_time.push(((target.actual_dest_arrival.unwrap()-target.departure)-(baseline.actual_dest_arrival.unwrap()-baseline.departure)) as types::MFloat);
+    } else if baseline.actual_dest_arrival.is_none() && target.actual_dest_arrival.is_none() {
+        a.baseline_and_target_infeasible += 1;
+    }
+}
+
+const PIT_BINS: usize = 20;
+const RELIABILITY_QUANTILES: usize = 19;
+const HORIZON_EDGES: [i32; 5] = [0, 15, 30, 60, 120];
+
+#[derive(Serialize, Default)]
+struct CalibrationGroup {
+    samples: usize,
+    pit_histogram: Vec<types::MFloat>,
+    reliability: Vec<(types::MFloat, types::MFloat)>,
+    crps: types::MFloat,
+    // None if every sample had zero probability
+    log_score: Option<types::MFloat>,
+    zero_probability: usize,
+    #[serde(skip)]
+    log_score_sum: types::MFloat,
+    #[serde(skip)]
+    pit_counts: [usize; PIT_BINS],
+    #[serde(skip)]
+    below_quantile: [usize; RELIABILITY_QUANTILES]
+}
+
+impl CalibrationGroup {
+    fn add(&mut self, distr: &distribution::Distribution, actual: i32) {
+        let total: types::MFloat = distr.histogram.iter().sum();
+        self.samples += 1;
+        self.pit_counts[((distr.pit(actual)*PIT_BINS as types::MFloat) as usize).min(PIT_BINS-1)] += 1;
+        for (i, below) in self.below_quantile.iter_mut().enumerate() {
+            if actual <= distr.quantile((i+1) as types::MFloat/(RELIABILITY_QUANTILES+1) as types::MFloat*total) {
+                *below += 1;
+            }
+        }
+        self.crps += distr.crps(actual);
+        let log_score = distr.log_score(actual);
+        if log_score.is_finite() {
+            self.log_score_sum += log_score;
+        } else {
+            self.zero_probability += 1;
+        }
+    }
+
+    fn finish(&mut self) {
+        let n = self.samples as types::MFloat;
+        self.pit_histogram = self.pit_counts.iter().map(|c| *c as types::MFloat/n).collect();
+        self.reliability = self.below_quantile.iter().enumerate().map(|(i, c)| ((i+1) as types::MFloat/(RELIABILITY_QUANTILES+1) as types::MFloat, *c as types::MFloat/n)).collect();
+        self.crps /= n;
+        let finite = self.samples-self.zero_probability;
+        self.log_score = if finite > 0 { Some(self.log_score_sum/finite as types::MFloat) } else { None };
+    }
+}
+
+#[derive(Serialize, Default)]
+struct CalibrationReport {
+    all: CalibrationGroup,
+    by_strategy: BTreeMap<String, CalibrationGroup>,
+    by_product_type: BTreeMap<i16, CalibrationGroup>,
+    by_horizon: BTreeMap<String, CalibrationGroup>
+}
+
+fn horizon_bucket(horizon: i32) -> String {
+    let i = HORIZON_EDGES.partition_point(|e| *e <= horizon);
+    match (i.checked_sub(1).map(|i| HORIZON_EDGES[i]), HORIZON_EDGES.get(i)) {
+        (None, Some(upper)) => format!("(,{})", upper),
+        (Some(lower), None) => format!("[{},)", lower),
+        (Some(lower), Some(upper)) => format!("[{},{})", lower, upper),
+        (None, None) => "(,)".to_string()
+    }
+}
+
+// Each connection taken by the stochastic traveler carries the destination arrival distribution it was taken with.
+// The horizon is the expected time to arrival when the distribution was computed; runs that did not record
+// prediction times are not grouped by horizon.
+fn analyze_calibration(files: Vec<&String>, format: OutputFormat, out: &str) {
+    let mut report = CalibrationReport::default();
+    for f in &files {
+        let run = load_simulation_run(f);
+        for (strategy, result) in run.results.iter().flat_map(|j| j.results.iter()) {
+            let actual = match result.actual_dest_arrival {
+                Some(a) => a,
+                None => continue
+            };
+            for (i, c) in result.connections_taken.iter().enumerate() {
+                let dest = c.destination_arrival.borrow();
+                let distr = match dest.as_ref() {
+                    Some(d) if !d.histogram.is_empty() => d,
+                    _ => continue
+                };
+                report.all.add(distr, actual);
+                report.by_strategy.entry(strategy.clone()).or_default().add(distr, actual);
+                report.by_product_type.entry(c.product_type).or_default().add(distr, actual);
+                if let Some(prediction_time) = result.prediction_times.get(i) {
+                    let horizon = (distr.mean.round() as i32-prediction_time).div_euclid(run.config.ticks_per_minute);
+                    report.by_horizon.entry(horizon_bucket(horizon)).or_default().add(distr, actual);
+                }
+            }
+        }
+    }
+    if report.all.samples == 0 {
+        println!("No destination arrival distributions found, the simulation needs to be run with_distr.");
+        return;
+    }
+    report.all.finish();
+    report.by_strategy.values_mut().for_each(|g| g.finish());
+    report.by_product_type.values_mut().for_each(|g| g.finish());
+    report.by_horizon.values_mut().for_each(|g| g.finish());
+    let format_log_score = |g: &CalibrationGroup| g.log_score.map(|l| format!("{:.2}", l)).unwrap_or("-".to_string());
+    println!("calibration: samples {} crps {:.2} log_score {} zero_probability {}", report.all.samples, report.all.crps, format_log_score(&report.all), report.all.zero_probability);
+    match format {
+        OutputFormat::Human => {
+            for (k, g) in &report.by_horizon {
+                println!("horizon {}: samples {} crps {:.2} log_score {} zero_probability {}", k, g.samples, g.crps, format_log_score(g), g.zero_probability);
+            }
+        },
+        OutputFormat::Json => write_calibration_json(&report, out),
+        OutputFormat::Csv => write_calibration_csv(&report, out)
+    }
+}
+
+fn write_calibration_json(report: &CalibrationReport, out: &str) {
+    fs::create_dir_all(out).expect("output directory not creatable");
+    let json_path = format!("{}/calibration.json", out);
+    fs::write(&json_path, serde_json::to_vec_pretty(report).unwrap()).expect("error writing file");
+    println!("Written {}", json_path);
+}
+
+fn write_calibration_csv(report: &CalibrationReport, out: &str) {
+    fs::create_dir_all(out).expect("output directory not creatable");
+    let csv_path = format!("{}/calibration.csv", out);
+    let mut wtr = csv::Writer::from_path(&csv_path).unwrap();
+    wtr.write_record(["group_by", "group", "metric", "key", "value"]).unwrap();
+    let mut groups = vec![("all", "all".to_string(), &report.all)];
+    groups.extend(report.by_strategy.iter().map(|(k, g)| ("strategy", k.clone(), g)));
+    groups.extend(report.by_product_type.iter().map(|(k, g)| ("product_type", k.to_string(), g)));
+    groups.extend(report.by_horizon.iter().map(|(k, g)| ("horizon", k.clone(), g)));
+    for (group_by, group, g) in groups {
+        let mut write = |metric: &str, key: String, value: types::MFloat| wtr.write_record([group_by, &group, metric, &key, &value.to_string()]).unwrap();
+        write("samples", "".to_string(), g.samples as types::MFloat);
+        write("crps", "".to_string(), g.crps);
+        if let Some(log_score) = g.log_score {
+            write("log_score", "".to_string(), log_score);
+        }
+        write("zero_probability", "".to_string(), g.zero_probability as types::MFloat);
+        for (i, p) in g.pit_histogram.iter().enumerate() {
+            write("pit", format!("{}", i as types::MFloat/PIT_BINS as types::MFloat), *p);
+        }
+        for (q, observed) in &g.reliability {
+            write("reliability", q.to_string(), *observed);
+        }
+    }
+    wtr.flush().unwrap();
+    println!("Written {}", csv_path);
+}
+
+fn cdf(histogram: &Vec<(i32, types::MFloat)>) -> Vec<(i32, types::MFloat)> {
+    let mut cdf = vec![];
+    let mut cum = 0.0;
+    for c in histogram {
+        cum += c.1;
+        cdf.push((c.0, cum));
+    }
+    cdf
+}
+
+fn percentograms(a: &mut SimulationAnalysis, target: &SimulationResult) {
+    let dest = target.connections_taken.first().unwrap().destination_arrival.borrow();
+    let distr = dest.as_ref().unwrap();
+    
+    let mut cum = 0.0;
+    let mut percentile = 1;
+    let mut i = 0;
+    let mut found = false;
+    let mut found_mean = false;
+    for v in &distr.histogram {
+        cum += v;
+        let last_percentile = percentile;
+        while cum*100.0 >= percentile as f32 {
+            percentile += 1;
+        }
+        let linear_percentile = ((percentile-1-last_percentile) as f32/2.0+last_percentile as f32).floor() as usize;
+        if !found && percentile > 1 && target.actual_dest_arrival.unwrap() >= distr.start && target.actual_dest_arrival.unwrap() <= distr.start+i {
+            a.target_destination_arrival_percentiles[linear_percentile-1] += 1;
+            found = true;
+        }
+        if !found_mean && percentile > 1 && distr.mean as i32 >= distr.start && distr.mean as i32 <= distr.start+i {
+            a.target_mean_percentiles[linear_percentile-1] += 1;
+            found_mean = true;
+        }
+        
+        if found && found_mean {
+            break;
+        }
+        i += 1;
+    }
+    if !found && target.actual_dest_arrival.unwrap() >= distr.start && target.actual_dest_arrival.unwrap() <= distr.start+distr.histogram.len() as i32-1 {
+        a.target_destination_arrival_percentiles[99] += 1; 
+    }
+    if !found_mean && distr.mean as i32 >= distr.start && distr.mean as i32 <= distr.start+distr.histogram.len() as i32-1 {
+        a.target_mean_percentiles[99] += 1; 
+    }
+}
+
+fn percentogram(percentiles: &[i32; 100], samples: usize) -> Vec<(i32, types::MFloat)> {
+    let mut acc = vec![];
+    let mut cum = 0;
+    for v in percentiles.iter().enumerate() {
+        cum += v.1;
+        acc.push((v.0 as i32+1, cum as f32*100.0/samples as f32));
+    }
+    acc
+}
+
+fn print_percentogram(percentiles: &[i32; 100], samples: usize, name: &str) {
+    println!("{}: {:?} {:?}", name, percentogram(percentiles, samples), percentiles);
+}
+
+#[allow(dead_code)]
+fn print_distribution(result: &SimulationResult, baseline: &SimulationResult, meta: &SimulationJourney, ticks_per_minute: types::Mtime) {
+    let d = result.connections_taken.first().unwrap().destination_arrival.borrow();
+    let distr = d.as_ref().unwrap();
+    println!("pair {:?} mean {} actual {} baseline {} baseline actual {}", meta.pair, result.original_dest_arrival_prediction-get_pair_mam(meta, ticks_per_minute) as types::MFloat, result.actual_dest_arrival.unwrap()-get_pair_mam(meta, ticks_per_minute), baseline.original_dest_arrival_prediction as i32-get_pair_mam(meta, ticks_per_minute), baseline.actual_dest_arrival.unwrap()-get_pair_mam(meta, ticks_per_minute));
+    println!("{:?}", distr.histogram.iter().enumerate().map(|v| (v.0 as types::Mtime+distr.start-get_pair_mam(meta, ticks_per_minute), *v.1*100.0)).collect::<Vec<(types::Mtime, types::MFloat)>>());
+}
+
+fn histogram(arr: ArrayBase<OwnedRepr<types::MFloat>, Dim<[usize; 1]>>) -> Vec<(i32, types::MFloat)> {
+    if arr.is_empty() {
+        return vec![];
+    }
+    let min = arr.min().unwrap().floor() as i32;
+    let max = arr.max().unwrap().ceil() as i32+2;
+    
+    let edges = Edges::from((min..max).map(|i| n32(i as f32)).collect::<Vec<N32>>());
+    let bins = Bins::new(edges);
+    let grid = Grid::from(vec![bins]);
+    let mut histogram = Histogram::new(grid);
+    for v in arr.iter() {
+        let _ = histogram.add_observation(&array![n32(*v as f32)]);
+    }
+    let histogram_matrix = histogram.counts();
+
+    histogram_matrix.iter().enumerate().map(|(idx, count)| (idx as i32+min, *count as types::MFloat / arr.len() as types::MFloat * 100.0)).collect::<Vec<(i32, types::MFloat)>>()
+}
+
+fn around2(x: types::MFloat) -> types::MFloat {
+    (x * 100.0).round() / 100.0
+}
+fn summary(values: &[types::MFloat], name: &str, samples: usize) -> (ArrayBase<OwnedRepr<types::MFloat>, Dim<[usize; 1]>>, Summary) {
+    let len = values.len();
+    let trimmed_mean = ndarray::Array::from_iter(values.iter().filter(|v| v.abs() <= 130.0).cloned()).mean().unwrap_or(0.0);
+    let failures = 1.0-len as types::MFloat/samples as types::MFloat;
+    let mut arr = ndarray::Array::from_vec(values.to_vec());
+    let mut s = Summary {
+        name: name.to_string(),
+        count: len,
+        failure_percent: failures*100.0,
+        mean: None,
+        trimmed_mean: None,
+        min: None,
+        q5: None,
+        q95: None,
+        max: None
+    };
+    if len == 0 {
+        return (arr, s);
+    }
+    let q5 = arr.quantile_axis_skipnan_mut(ndarray::Axis(0), n64(0.05), &Lower).unwrap().into_iter().last().unwrap();
+    let q95 = arr.quantile_axis_skipnan_mut(ndarray::Axis(0), n64(0.95), &Higher).unwrap().into_iter().last().unwrap();
+    s.mean = arr.mean();
+    s.trimmed_mean = Some(trimmed_mean);
+    s.min = Some(*arr.min().unwrap());
+    s.q5 = Some(q5);
+    s.q95 = Some(q95);
+    s.max = Some(*arr.max().unwrap());
+    (arr, s)
+}
+
+impl Summary {
+    fn print(&self) {
+        match (self.mean, self.trimmed_mean, self.min, self.q5, self.q95, self.max) {
+            (Some(mean), Some(trimmed_mean), Some(min), Some(q5), Some(q95), Some(max)) => {
+                println!("{}: fail mean trimmean min 5% 95% max: {:.2} & {:.2} & {:.2} & {} & {} & {} & {}", self.name, self.failure_percent, mean, trimmed_mean, around2(min), around2(q5), around2(q95), around2(max));
+            },
+            _ => println!("{}: empty", self.name)
+        }
+    }
+}
+
+// Splits the file arguments from --format, --out and --strategies.
+fn output_args(args: &[String]) -> (Vec<&String>, OutputFormat, &str, Option<Vec<String>>) {
+    let mut files = vec![];
+    let mut format = OutputFormat::Human;
+    let mut out = ".";
+    let mut strategies = None;
+    let mut rest = args.iter();
+    while let Some(a) = rest.next() {
+        match a.as_str() {
+            "--out" => out = rest.next().expect("--out requires a directory"),
+            "--format" => format = match rest.next().map(|f| f.as_str()) {
+                Some("json") => OutputFormat::Json,
+                Some("csv") => OutputFormat::Csv,
+                Some("human") => OutputFormat::Human,
+                f => panic!("unknown --format {:?}, expected json or csv", f)
+            },
+            "--strategies" => strategies = Some(rest.next().expect("--strategies requires a comma-separated list").split(',').map(|s| s.to_string()).collect()),
+            _ => files.push(a)
+        }
+    }
+    (files, format, out, strategies)
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 3 {
+        println!("Usage: simulation (run|analyze [--format json|csv] [--out DIR] [--strategies A,B,...]|analyze calibration [--format json|csv] [--out DIR]|learn|samples) [RUN_FILES]");
+        return;
+    }
+    match args[1].as_str() {
+        "run" => {
+            run_simulation(&args[2]).unwrap();
+        },
+        "analyze" if args[2] == "calibration" => {
+            let (files, format, out, _) = output_args(&args[3..]);
+            analyze_calibration(files, format, out);
+        },
+        "analyze" => {
+            let (files, format, out, strategies) = output_args(&args[2..]);
+            analyze_simulation(files, strategies, format, out);
+        },
+        "samples" if args.len() > 3 => {
+            create_samples(&args[2], &args[3]);
+        },
+        "learn" if args.len() > 3 => {
+            let learner = learning::learn_from_archive(&learning::load_archive_config(&args[2]));
+            if args[3].ends_with(".csv") {
+                learner.write_csv(&args[3]);
+            } else {
+                let mut store = distribution_store::Store::new();
+                learner.load_into(&mut store).print();
+                store.save_snapshot(&args[3]);
+            }
+        },
+        _ => println!("Usage: simulation (run|analyze) FILE | simulation learn CONFIG (OUT.csv|OUT.snapshot) | simulation samples CONFIG OUT.json") 
+    };
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn simulate(threads: usize) -> String {
+        let conf: SimulationConfig = serde_json::from_value(serde_json::json!({
+            "distributions_path": "",
+            "gtfs_path": "",
+            "gtfs_cache_path": "",
+            "samples_config_path": "",
+            "strategies": ["topocsa", "adaptive_online", "csameat"],
+            "transfer": "",
+            "samples": 3,
+            "query_window": 120
+        })).unwrap();
+        let mut store = distribution_store::Store::new();
+        store.insert_from_distribution(0..5, 0..60, false, 1, distribution::Distribution::uniform(-2, 6));
+        let mut tt = GtfsTimetable::new();
+        tt.stations = (0..4).map(|i| connection::Station::new(i.to_string(), format!("station{}", i), vec![])).collect();
+        tt.connections = vec![
+            connection::Connection::new(0, 0, 1, 0, false, 0, 10, None, 1, 20, None),
+            connection::Connection::new(1, 0, 1, 0, false, 1, 21, None, 2, 30, None),
+            connection::Connection::new(2, 1, 1, 1, false, 1, 25, None, 2, 35, None),
+            connection::Connection::new(3, 2, 1, 2, false, 2, 40, None, 3, 50, None),
+            connection::Connection::new(4, 3, 1, 3, false, 0, 15, None, 3, 70, None)
+        ];
+        for c in &tt.connections {
+            tt.stations[c.from_idx].add_departure(c.id);
+        }
+        let contr = Some(gtfs::get_station_contraction(&tt.stations));
+        let workers = Workers::spawn(&conf, &store, threads);
+        workers.day(&tt, &contr, 0);
+        workers.pairs(vec![(0, 3, 5), (1, 3, 5), (0, 2, 5)], HashMap::new());
+        let mut current_time = 5;
+        let mut realtime = Realtime::default();
+        while workers.step(current_time, realtime, |_, _, _| None) && current_time < 120 {
+            current_time += 5;
+            realtime = Realtime::default();
+            if current_time == 20 {
+                realtime.updates = vec![(0, false, None, None, Some(3)), (1, true, None, None, Some(3))];
+            }
+        }
+        let mut results = workers.end_day(0);
+        workers.join();
+        for j in &mut results {
+            for r in j.results.values_mut() {
+                r.preprocessing_elapsed_ms = 0;
+                r.algo_elapsed_ms.iter_mut().for_each(|e| *e = 0);
+            }
+        }
+        serde_json::to_string(&results).unwrap()
+    }
+
+    #[test]
+    fn strategy_registry() {
+        for s in strategy::Strategy::ALL {
+            assert_eq!(strategy::Strategy::from_name(s.name()), Some(s));
+        }
+        assert_eq!(strategy::Strategy::from_name("adaptive_online_csameat"), None);
+        assert_eq!(strategy::Strategy::from_name("priori_online_typo"), None);
+        assert!(std::panic::catch_unwind(|| strategy::parse("adaptive")).is_err());
+    }
+
+    #[test]
+    fn parallel_equals_sequential() {
+        let sequential = simulate(1);
+        // csameat has no fallback for a missed transfer at 2, so 1 to 3 is infeasible and skipped for all strategies, and it rides the direct connection from 0
+        assert_eq!(sequential.matches("\"actual_dest_arrival\":null").count(), 3);
+        assert_eq!(sequential.matches("\"actual_dest_arrival\":50").count(), 2);
+        assert_eq!(sequential.matches("\"actual_dest_arrival\":70").count(), 1);
+        assert_eq!(simulate(2), sequential);
+        assert_eq!(simulate(3), sequential);
+    }
+}
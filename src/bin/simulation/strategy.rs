@@ -0,0 +1,477 @@
+use std::collections::HashMap;
+use std::time::Instant;
+use stost::distribution_store;
+use stost::gtfs::GtfsTimetable;
+use stost::gtfs::StationContraction;
+use stost::query::Query;
+use stost::types;
+
+use super::{get_min_det_journey, resolve_connection_idx, Alternative, LogEntry, Raptor, Simulation, SimulationConfig, SimulationResult};
+
+pub type Pair = (usize, usize, i32);
+
+pub struct SimulationContext<'a> {
+    pub store: &'a mut distribution_store::Store,
+    pub tt: &'a mut GtfsTimetable,
+    pub raptor: &'a Raptor,
+    pub contr: &'a Option<StationContraction>,
+    pub conf: &'a SimulationConfig,
+    pub timing_preprocessing: &'a mut u128
+}
+
+// How a simulated traveler chooses and revises its journey for each sample pair.
+pub trait TravelerStrategy {
+    fn name(&self) -> &str;
+    // Initial query at the start of the query window, returns false if no journey was found.
+    fn initialize(&mut self, ctx: &mut SimulationContext, pair: &Pair, current_time: i32, result: &mut SimulationResult) -> bool;
+    fn replan(&mut self, ctx: &mut SimulationContext, pair: &Pair, current_time: i32, log: &mut Vec<LogEntry>, result: &mut SimulationResult);
+    // Connections the traveler would take from current_stop_idx, in order of preference.
+    fn alternatives(&self, ctx: &SimulationContext, pair: &Pair, current_stop_idx: usize) -> Vec<Alternative>;
+    fn finish_step(&mut self, _pair: &Pair) {}
+    // Whether the strategy queries the timetable and distributions as preprocessed by csameat.
+    fn csameat(&self) -> bool {
+        false
+    }
+    fn discard(&mut self, pair: &Pair);
+    fn clear(&mut self);
+}
+
+// The simulated travelers, by the name used in configs and results files.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Strategy {
+    Priori,
+    PrioriOnline,
+    PrioriOnlineBroken,
+    Topocsa,
+    AdaptiveOnline,
+    AdaptiveOnlineRelevant,
+    AdaptiveOnlineWithDistr,
+    AdaptiveOnlineRelevantWithDistr,
+    Csameat,
+    CsameatWithDistr
+}
+
+impl Strategy {
+    pub const ALL: [Strategy; 10] = [
+        Strategy::Priori,
+        Strategy::PrioriOnline,
+        Strategy::PrioriOnlineBroken,
+        Strategy::Topocsa,
+        Strategy::AdaptiveOnline,
+        Strategy::AdaptiveOnlineRelevant,
+        Strategy::AdaptiveOnlineWithDistr,
+        Strategy::AdaptiveOnlineRelevantWithDistr,
+        Strategy::Csameat,
+        Strategy::CsameatWithDistr
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Strategy::Priori => "priori",
+            Strategy::PrioriOnline => "priori_online",
+            Strategy::PrioriOnlineBroken => "priori_online_broken",
+            Strategy::Topocsa => "topocsa",
+            Strategy::AdaptiveOnline => "adaptive_online",
+            Strategy::AdaptiveOnlineRelevant => "adaptive_online_relevant",
+            Strategy::AdaptiveOnlineWithDistr => "adaptive_online_with_distr",
+            Strategy::AdaptiveOnlineRelevantWithDistr => "adaptive_online_relevant_with_distr",
+            Strategy::Csameat => "csameat",
+            Strategy::CsameatWithDistr => "csameat_with_distr"
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Strategy> {
+        Strategy::ALL.into_iter().find(|s| s.name() == name)
+    }
+
+    pub fn is_raptor(&self) -> bool {
+        matches!(self, Strategy::Priori | Strategy::PrioriOnline | Strategy::PrioriOnlineBroken)
+    }
+
+    pub fn is_csameat(&self) -> bool {
+        matches!(self, Strategy::Csameat | Strategy::CsameatWithDistr)
+    }
+
+    fn create(self) -> Box<dyn TravelerStrategy> {
+        match self {
+            Strategy::Priori => Box::new(RaptorStrategy::new(self, false, true)),
+            Strategy::PrioriOnline => Box::new(RaptorStrategy::new(self, true, false)),
+            Strategy::PrioriOnlineBroken => Box::new(RaptorStrategy::new(self, true, true)),
+            Strategy::Topocsa => Box::new(StochStrategy::new(self, false, false, false)),
+            Strategy::AdaptiveOnline => Box::new(StochStrategy::new(self, true, false, false)),
+            Strategy::AdaptiveOnlineRelevant => Box::new(StochStrategy::new(self, true, true, false)),
+            Strategy::AdaptiveOnlineWithDistr => Box::new(StochStrategy::new(self, true, false, true)),
+            Strategy::AdaptiveOnlineRelevantWithDistr => Box::new(StochStrategy::new(self, true, true, true)),
+            Strategy::Csameat => Box::new(CsameatStrategy::new(self, false)),
+            Strategy::CsameatWithDistr => Box::new(CsameatStrategy::new(self, true))
+        }
+    }
+}
+
+pub fn parse(name: &str) -> Strategy {
+    Strategy::from_name(name).unwrap_or_else(|| panic!(
+        "unknown strategy {}, expected one of {:?}", name, Strategy::ALL.iter().map(|s| s.name()).collect::<Vec<_>>()
+    ))
+}
+
+pub fn from_config(conf: &SimulationConfig) -> Vec<Box<dyn TravelerStrategy>> {
+    let strategies: Vec<Strategy> = conf.strategies().iter().map(|n| parse(n)).collect();
+    for (i, s) in strategies.iter().enumerate() {
+        assert!(!strategies[..i].contains(s), "strategy {} configured twice", s.name());
+    }
+    strategies.into_iter().map(|s| s.create()).collect()
+}
+
+// Minimum arrival time journey from nigiri's RAPTOR, optionally requeried when the traveler arrives or the journey broke.
+pub struct RaptorStrategy {
+    strategy: Strategy,
+    online: bool,
+    only_when_broken: bool,
+    journeys: HashMap<Pair, motis_nigiri::Journey>
+}
+
+impl RaptorStrategy {
+    pub fn new(strategy: Strategy, online: bool, only_when_broken: bool) -> RaptorStrategy {
+        RaptorStrategy {
+            strategy,
+            online,
+            only_when_broken,
+            journeys: HashMap::new()
+        }
+    }
+}
+
+impl TravelerStrategy for RaptorStrategy {
+    fn name(&self) -> &str {
+        self.strategy.name()
+    }
+
+    fn initialize(&mut self, ctx: &mut SimulationContext, pair: &Pair, _current_time: i32, result: &mut SimulationResult) -> bool {
+        let (min_journey, timing_det) = get_min_det_journey(ctx.raptor, pair.0, pair.1, pair.2);
+        result.algo_elapsed_ms.push(timing_det);
+        match min_journey {
+            Some(j) => {
+                self.journeys.insert(*pair, j);
+                true
+            },
+            None => false
+        }
+    }
+
+    fn replan(&mut self, ctx: &mut SimulationContext, pair: &Pair, current_time: i32, log: &mut Vec<LogEntry>, result: &mut SimulationResult) {
+        let tt = &mut *ctx.tt;
+        let arrival_time = Simulation::get_arrival_time(log, pair.2, tt);
+        if current_time >= arrival_time
+            && result.actual_dest_arrival.is_none()
+            && self.online {
+            let stuck_at = log.last().map(|l| tt.connections[tt.order[l.conn_id]].to_idx).unwrap_or(pair.0);
+            let mut fixed_arrival_time = None;
+            if result.broken {
+                fixed_arrival_time = Simulation::fix_if_sitting_in_cancelled_trip(log, result, pair.2, tt);
+            }
+            if result.broken || !self.only_when_broken {
+                let time = fixed_arrival_time.unwrap_or(if result.broken { current_time } else { arrival_time });
+                let (min_journey, timing_det) = get_min_det_journey(ctx.raptor, stuck_at, pair.1, time);
+                if min_journey.is_some() {
+                    self.journeys.insert(*pair, min_journey.unwrap());
+                    result.broken = false;
+                    result.algo_elapsed_ms.push(timing_det);
+                }
+            }
+        }
+    }
+
+    fn alternatives(&self, ctx: &SimulationContext, pair: &Pair, current_stop_idx: usize) -> Vec<Alternative> {
+        let tt = &*ctx.tt;
+        let journey = &self.journeys[pair];
+        let mut next_leg = 0;
+        for l in &*journey.legs {
+            if l.from_location_idx == current_stop_idx {
+                if l.is_footpath {
+                    next_leg += 1;
+                }
+                break;
+            }
+            next_leg += 1;
+        }
+        if next_leg >= journey.legs.len() {
+            println!("failed to find journey continuation (platform change?): {:?} current_stop: {} {:?}", journey, current_stop_idx, tt.stations[current_stop_idx]);
+            return vec![];
+        }
+        let departure_idx = resolve_connection_idx(journey, next_leg, false, &tt.transport_and_day_to_connection_id, &tt.order);
+        let arrival_idx = resolve_connection_idx(journey, next_leg, true, &tt.transport_and_day_to_connection_id, &tt.order);
+        vec![Alternative{
+            from_conn_idx: departure_idx,
+            to_conn_idx: arrival_idx,
+            proj_dest_arr: types::minutes(journey.dest_time as types::Mtime, ctx.conf.ticks_per_minute) as types::MFloat
+        }]
+    }
+
+    fn discard(&mut self, pair: &Pair) {
+        self.journeys.remove(pair);
+    }
+
+    fn clear(&mut self) {
+        self.journeys.clear();
+    }
+}
+
+struct StochActions {
+    station_labels: Vec<Vec<stost::query::ConnectionLabel>>,
+    connection_pairs: HashMap<i32, i32>,
+    connection_pairs_reverse: HashMap<usize, usize>,
+    relevant_stations: HashMap<usize, types::MFloat>
+}
+
+// Stochastic traveler following the best destination arrival distributions of topocsa,
+// optionally requeried on arrival at each stop (adaptive_online) and restricted to relevant stations.
+pub struct StochStrategy {
+    strategy: Strategy,
+    adaptive: bool,
+    relevant: bool,
+    with_distr: bool,
+    actions: HashMap<Pair, StochActions>
+}
+
+impl StochStrategy {
+    pub fn new(strategy: Strategy, adaptive: bool, relevant: bool, with_distr: bool) -> StochStrategy {
+        StochStrategy {
+            strategy,
+            adaptive,
+            relevant,
+            with_distr,
+            actions: HashMap::new()
+        }
+    }
+}
+
+fn extend_alternatives_by_station_labels(stoch_actions: &StochActions, stop_idx: usize, alternatives: &mut Vec<Alternative>, tt: &GtfsTimetable) {
+    let station_labels = stoch_actions.station_labels.get(stop_idx);
+    if station_labels.is_none() {
+        return;
+    }
+    alternatives.extend(station_labels.unwrap().iter().filter_map(|l| {
+        if l.destination_arrival.mean == 0.0 {
+            panic!("weirdly 0");
+        }
+        if l.destination_arrival.feasible_probability < 0.5 {
+            return None // TODO properly use transfer strategy?
+        }
+        Some(Alternative{
+            from_conn_idx: tt.order[l.connection_id],
+            to_conn_idx: if stoch_actions.connection_pairs_reverse.is_empty() {
+                tt.order[l.connection_id]
+            } else {
+                tt.order[stoch_actions.connection_pairs_reverse[&l.connection_id]]
+            },
+            proj_dest_arr: l.destination_arrival.mean
+        })
+    }));
+}
+
+// Departures at the current stop (or its station group or footpath targets) in order of their expected destination arrival.
+fn station_label_alternatives(stoch_actions: &StochActions, ctx: &SimulationContext, current_stop_idx: usize) -> Vec<Alternative> {
+    let tt = &*ctx.tt;
+    let mut alternatives: Vec<Alternative> = vec![];
+    if let Some(contr) = ctx.contr {
+        let stop_idx = contr.stop_to_group[current_stop_idx];
+        extend_alternatives_by_station_labels(stoch_actions, stop_idx, &mut alternatives, tt);
+    } else {
+        let footpaths = &tt.stations[current_stop_idx].footpaths;
+        for i in 0..footpaths.len()+1 {
+            let stop_idx = if i == footpaths.len() { current_stop_idx } else { footpaths[i].target_location_idx };
+            extend_alternatives_by_station_labels(stoch_actions, stop_idx, &mut alternatives, tt);
+        }
+    }
+    alternatives.sort_unstable_by(|a, b| a.proj_dest_arr.partial_cmp(&b.proj_dest_arr).unwrap());
+    alternatives
+}
+
+fn exist_alternatives(contr: &Option<StationContraction>, pair: &Pair, station_labels: &[Vec<stost::query::ConnectionLabel>]) -> bool {
+    station_labels.get(contr.as_ref().map(|contr| contr.stop_to_group[pair.0]).unwrap_or(pair.0)).is_some_and(|s| !s.is_empty())
+}
+
+impl TravelerStrategy for StochStrategy {
+    fn name(&self) -> &str {
+        self.strategy.name()
+    }
+
+    fn initialize(&mut self, ctx: &mut SimulationContext, pair: &Pair, current_time: i32, result: &mut SimulationResult) -> bool {
+        let tt = &mut *ctx.tt;
+        let mut env = Simulation::new_env(ctx.store, tt, ctx.contr, ctx.conf, false, current_time, true, true);
+        Simulation::preprocess_if_necessary(&mut env, ctx.timing_preprocessing);
+        let query = Query {
+            origin_idx: pair.0,
+            destination_idx: pair.1,
+            start_time: pair.2,
+            max_time: pair.2+ctx.conf.minutes(ctx.conf.query_window)
+        };
+        let start = Instant::now();
+        let stoch = env.query(query);
+        let mut timing_stoch = start.elapsed().as_millis();
+        let exist_alternatives = exist_alternatives(ctx.contr, pair, &stoch);
+        if exist_alternatives {
+            let relevant_stations = if self.relevant {
+                let start = Instant::now();
+                let mut relevant_stations = env.relevant_stations(query, &stoch);
+                timing_stoch += start.elapsed().as_millis();
+                if let (Some(det), _) = get_min_det_journey(ctx.raptor, pair.0, pair.1, pair.2) {
+                    for l in &*det.legs {
+                        relevant_stations.insert(l.from_location_idx, 1000.0);
+                        relevant_stations.insert(l.to_location_idx, 1000.0);
+                    }
+                }
+                relevant_stations
+            } else {
+                HashMap::new()
+            };
+            self.actions.insert(*pair, StochActions{
+                station_labels: stoch,
+                connection_pairs: HashMap::new(),
+                connection_pairs_reverse: HashMap::new(),
+                relevant_stations: relevant_stations
+            });
+        }
+        result.preprocessing_elapsed_ms = *ctx.timing_preprocessing;
+        result.algo_elapsed_ms.push(timing_stoch);
+        exist_alternatives
+    }
+
+    fn replan(&mut self, ctx: &mut SimulationContext, pair: &Pair, current_time: i32, log: &mut Vec<LogEntry>, result: &mut SimulationResult) {
+        let tt = &mut *ctx.tt;
+        let arrival_time = Simulation::get_arrival_time(log, pair.2, tt);
+        if current_time >= arrival_time
+            && result.actual_dest_arrival.is_none()
+            && self.adaptive {
+            let mut fixed_arrival_time = None;
+            let mut stuck_at = None;
+            if result.broken {
+                fixed_arrival_time = Simulation::fix_if_sitting_in_cancelled_trip(log, result, pair.2, tt);
+                stuck_at = Some(log.last().map(|l| tt.connections[tt.order[l.conn_id]].to_idx).unwrap_or(pair.0));
+            }
+            let stuck_footpaths: Vec<usize> = stuck_at.iter().flat_map(|sidx| tt.stations[*sidx].footpaths.iter().map(|f| f.target_location_idx)).collect();
+            let mut env = Simulation::new_env(ctx.store, tt, ctx.contr, ctx.conf, false, fixed_arrival_time.unwrap_or(current_time), !self.with_distr, false);
+            Simulation::preprocess_if_necessary(&mut env, ctx.timing_preprocessing);
+            let query = Query {
+                origin_idx: pair.0,
+                destination_idx: pair.1,
+                start_time: pair.2,
+                max_time: pair.2+ctx.conf.minutes(ctx.conf.query_window)
+            };
+            let start = Instant::now();
+            if self.relevant {
+                self.actions.entry(*pair).and_modify(|a| {
+                    if let Some(sidx) = stuck_at {
+                        a.relevant_stations.insert(sidx, 1000.0);
+                        for target_location_idx in &stuck_footpaths {
+                            a.relevant_stations.insert(*target_location_idx, 1000.0);
+                        }
+                    }
+                    a.connection_pairs = env.relevant_connection_pairs(query, &a.relevant_stations, 1000);
+                    a.connection_pairs_reverse = a.connection_pairs.iter().map(|(arr,dep)| (*dep as usize, *arr as usize)).collect();
+                });
+            }
+            let stoch = env.pair_query(query, &self.actions[pair].connection_pairs);
+            let timing_stoch = start.elapsed().as_millis();
+            self.actions.get_mut(pair).unwrap().station_labels = stoch;
+            result.algo_elapsed_ms.push(timing_stoch);
+        }
+    }
+
+    fn alternatives(&self, ctx: &SimulationContext, pair: &Pair, current_stop_idx: usize) -> Vec<Alternative> {
+        station_label_alternatives(&self.actions[pair], ctx, current_stop_idx)
+    }
+
+    fn finish_step(&mut self, pair: &Pair) {
+        if self.adaptive {
+            self.actions.entry(*pair).and_modify(|a| {
+                a.station_labels.clear();
+                a.station_labels.shrink_to_fit();
+                a.connection_pairs.clear();
+                a.connection_pairs.shrink_to_fit();
+                a.connection_pairs_reverse.clear();
+                a.connection_pairs_reverse.shrink_to_fit();
+            });
+        }
+    }
+
+    fn discard(&mut self, pair: &Pair) {
+        self.actions.remove(pair);
+    }
+
+    fn clear(&mut self) {
+        self.actions.clear();
+    }
+}
+
+// Traveler following the decision graph of the initial CSA MEAT query, optionally with full
+// destination arrival histograms instead of expected arrival times only (csameat_with_distr).
+pub struct CsameatStrategy {
+    strategy: Strategy,
+    with_distr: bool,
+    actions: HashMap<Pair, StochActions>
+}
+
+impl CsameatStrategy {
+    pub fn new(strategy: Strategy, with_distr: bool) -> CsameatStrategy {
+        CsameatStrategy {
+            strategy,
+            with_distr,
+            actions: HashMap::new()
+        }
+    }
+}
+
+impl TravelerStrategy for CsameatStrategy {
+    fn name(&self) -> &str {
+        self.strategy.name()
+    }
+
+    fn initialize(&mut self, ctx: &mut SimulationContext, pair: &Pair, current_time: i32, result: &mut SimulationResult) -> bool {
+        let tt = &mut *ctx.tt;
+        let mut env = Simulation::new_env(ctx.store, tt, ctx.contr, ctx.conf, true, current_time, !self.with_distr, true);
+        Simulation::preprocess_if_necessary(&mut env, ctx.timing_preprocessing);
+        let query = Query {
+            origin_idx: pair.0,
+            destination_idx: pair.1,
+            start_time: pair.2,
+            max_time: pair.2+ctx.conf.minutes(ctx.conf.query_window)
+        };
+        let start = Instant::now();
+        let station_labels = env.query(query);
+        let mut timing = start.elapsed().as_millis();
+        let exist_alternatives = exist_alternatives(ctx.contr, pair, &station_labels);
+        if exist_alternatives {
+            let start = Instant::now();
+            let connection_pairs_reverse = env.relevant_connection_pairs(query, &HashMap::new(), 0);
+            timing += start.elapsed().as_millis();
+            self.actions.insert(*pair, StochActions{
+                station_labels,
+                connection_pairs: connection_pairs_reverse.iter().map(|(dep,arr)| (*arr, *dep)).collect(),
+                connection_pairs_reverse: connection_pairs_reverse.into_iter().map(|(dep,arr)| (dep as usize, arr as usize)).collect(),
+                relevant_stations: HashMap::new()
+            });
+        }
+        result.preprocessing_elapsed_ms = *ctx.timing_preprocessing;
+        result.algo_elapsed_ms.push(timing);
+        exist_alternatives
+    }
+
+    fn replan(&mut self, _ctx: &mut SimulationContext, _pair: &Pair, _current_time: i32, _log: &mut Vec<LogEntry>, _result: &mut SimulationResult) {}
+
+    fn csameat(&self) -> bool {
+        true
+    }
+
+    fn alternatives(&self, ctx: &SimulationContext, pair: &Pair, current_stop_idx: usize) -> Vec<Alternative> {
+        station_label_alternatives(&self.actions[pair], ctx, current_stop_idx)
+    }
+
+    fn discard(&mut self, pair: &Pair) {
+        self.actions.remove(pair);
+    }
+
+    fn clear(&mut self) {
+        self.actions.clear();
+    }
+}
@@ -25,7 +25,13 @@ impl Route {
 	}
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Footpath {
+	pub target_location_idx: usize,
+	pub duration: u16
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Station {
 	pub id: String,
 	pub name: String,
@@ -35,7 +41,7 @@ pub struct Station {
 	pub lon: f64,
 	pub transfer_time: u16,
 	pub parent_idx: usize,
-	pub footpaths: Vec<motis_nigiri::Footpath>
+	pub footpaths: Vec<Footpath>
 }
 
 impl<'a> Station {
@@ -102,14 +108,16 @@ impl<'a> Connection {
 				delay: from_delay,
 				in_out_allowed: !cancelled,
 				scheduled_track: "".to_string(),
-				projected_track: "".to_string()
+				projected_track: "".to_string(),
+				min_delay: None
 			},
 			arrival: StopInfo {
 				scheduled: to_scheduled,
 				delay: to_delay,
 				in_out_allowed: !cancelled,
 				scheduled_track: "".to_string(),
-				projected_track: "".to_string()
+				projected_track: "".to_string(),
+				min_delay: None
 			},
 			message: "".to_string(),
 			product_type: product_type,
@@ -160,6 +168,14 @@ impl<'a> Connection {
             }
         }
     }
+
+	// Only ever raises the lower bound.
+	pub fn update_min_delay(&mut self, is_departure: bool, min_delay: i16) {
+		let stop_info = if is_departure { &mut self.departure } else { &mut self.arrival };
+		if stop_info.min_delay.is_none_or(|d| d < min_delay) {
+			stop_info.min_delay = Some(min_delay);
+		}
+	}
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -168,12 +184,15 @@ pub struct StopInfo {
 	pub delay: Option<i16>,
 	pub in_out_allowed: bool,
 	pub scheduled_track: String,
-	pub projected_track: String
+	pub projected_track: String,
+	// lower bound of the delay from evidence like vehicle positions, the distributions are conditioned on it
+	#[serde(default)]
+	pub min_delay: Option<i16>
 }
 
 impl StopInfo {
 	pub fn new(scheduled: types::Mtime, delay: Option<i16>) -> StopInfo {
-		StopInfo { scheduled: scheduled, delay: delay, in_out_allowed: true, scheduled_track: "".to_owned(), projected_track: "".to_owned() }
+		StopInfo { scheduled: scheduled, delay: delay, in_out_allowed: true, scheduled_track: "".to_owned(), projected_track: "".to_owned(), min_delay: None }
 	}
 
 	#[inline(always)]
@@ -13,10 +13,30 @@ pub struct Distribution {
     #[serde(default)]
     pub feasible_probability: types::MFloat,
     #[serde(default)]
-    pub relevance: Cell<types::MFloat>
+    pub relevance: Cell<types::MFloat>,
+    #[serde(default = "default_bucket_width")]
+    pub bucket_width: types::Mtime
 }
 
 const EMPTY_HISTOGRAM: Vec<types::MFloat> = vec![];
+const LANES: usize = 8;
+
+fn default_bucket_width() -> types::Mtime {
+    1
+}
+
+#[inline(always)]
+fn sum(xs: &[types::MFloat]) -> types::MFloat {
+    let mut acc = [0.0; LANES];
+    let chunks = xs.chunks_exact(LANES);
+    let remainder = chunks.remainder();
+    for chunk in chunks {
+        for l in 0..LANES {
+            acc[l] += chunk[l];
+        }
+    }
+    acc.iter().sum::<types::MFloat>()+remainder.iter().sum::<types::MFloat>()
+}
 
 impl Distribution {
 
@@ -30,7 +50,7 @@ impl Distribution {
     }
 
     pub fn end(&self) -> types::Mtime {
-        self.start+self.histogram.len() as types::Mtime
+        self.start+self.histogram.len() as types::Mtime*self.bucket_width
     }
 
     #[inline(always)]
@@ -40,7 +60,8 @@ impl Distribution {
             start: start,
             mean: 0.,
             feasible_probability: 0.0,
-            relevance: Cell::new(0.0)
+            relevance: Cell::new(0.0),
+            bucket_width: 1
         }
     }
 
@@ -53,24 +74,35 @@ impl Distribution {
             start: start,
             mean:  start as types::MFloat+((width-1) as types::MFloat/2.0),
             feasible_probability: 1.0,
-            relevance: Cell::new(0.0)
+            relevance: Cell::new(0.0),
+            bucket_width: 1
         }
     }
 
     pub fn mean(&self) -> types::MFloat {
         let mut mean = 0.0;
+        let bucket_center = (self.bucket_width-1) as types::MFloat/2.0;
         for i in 0..self.histogram.len() {
-            mean += (self.start as types::MFloat+i as types::MFloat)*self.histogram[i];
+            mean += (self.start as types::MFloat+(i as types::Mtime*self.bucket_width) as types::MFloat+bucket_center)*self.histogram[i];
         }
         mean as types::MFloat
     }
 
+    pub fn variance(&self) -> types::MFloat {
+        let mean = self.mean();
+        let bucket_center = (self.bucket_width-1) as types::MFloat/2.0;
+        self.histogram.iter().enumerate().map(|(i, p)| {
+            let x = (self.start+i as types::Mtime*self.bucket_width) as types::MFloat+bucket_center;
+            (x-mean)*(x-mean)*p
+        }).sum()
+    }
+
     pub fn quantile(&self, q: types::MFloat) -> types::Mtime {
         let mut cum = 0.0;
         for i in 0..self.histogram.len() {
             cum += self.histogram[i];
             if cum >= q {
-                return self.start+i as types::Mtime;
+                return self.start+i as types::Mtime*self.bucket_width;
             }
         }
         return self.end();
@@ -98,7 +130,7 @@ impl Distribution {
                 if self.histogram[i] > epsilon {
                     if !found {
                         offset = i;
-                        self.start += i as i32;
+                        self.start += i as i32*self.bucket_width;
                         found = true;
                     }
                     last = i;
@@ -128,26 +160,28 @@ impl Distribution {
         }
         if !self.exists() {
             self.start = other.start;
+            self.bucket_width = other.bucket_width;
         }
-        let self_start = self.start;
-        let other_start = other.start;
-        let start = cmp::min(self_start, other_start);
-        let end = cmp::max(self_start+self.histogram.len() as i32, other_start+other.histogram.len() as i32);
-        let self_offset = (self_start-start) as usize;
-        let other_offset = (other_start-start) as usize;
-        let new_len = (end-start) as usize;
-        let mut h = vec![0.; new_len];
-
-        for i in 0..new_len {
-            if i >= self_offset && i-self_offset < self.histogram.len() {
-                h[i] += self.histogram[i-self_offset];
-            }
-            if i >= other_offset && i-other_offset < other.histogram.len() {
-                h[i] += other.histogram[i-other_offset]*weight as types::MFloat;
-            }
+        assert_eq!(self.bucket_width, other.bucket_width, "cannot add distributions of different bucket widths");
+        assert_eq!((self.start-other.start)%self.bucket_width, 0, "cannot add distributions with unaligned buckets");
+        let w = self.bucket_width;
+        let self_len = self.histogram.len();
+        let start = cmp::min(self.start, other.start);
+        let end = cmp::max(self.end(), other.end());
+        let self_offset = ((self.start-start)/w) as usize;
+        let other_offset = ((other.start-start)/w) as usize;
+        let new_len = ((end-start)/w) as usize;
+        if new_len > self_len {
+            self.histogram.resize(new_len, 0.);
+        }
+        if self_offset > 0 {
+            self.histogram.copy_within(0..self_len, self_offset);
+            self.histogram[..self_offset].fill(0.);
+        }
+        for (h, o) in self.histogram[other_offset..other_offset+other.histogram.len()].iter_mut().zip(other.histogram.iter()) {
+            *h += o*weight;
         }
-        self.histogram = h;
-		self.start = start as types::Mtime;
+		self.start = start;
         self.mean += other.mean*weight;
     }
 
@@ -157,11 +191,19 @@ impl Distribution {
             start: self.start+start,
             mean: self.mean+start as types::MFloat,
             feasible_probability: self.feasible_probability,
-            relevance: Cell::new(self.relevance.get())
+            relevance: Cell::new(self.relevance.get()),
+            bucket_width: self.bucket_width
         }
     }
 
     pub fn before_probability(&self, other: &Distribution, offset: i32) -> types::MFloat {
+        // probability is assumed to be spread evenly within coarse buckets
+        if self.bucket_width != other.bucket_width {
+            return self.mixed_before_probability(other, offset);
+        }
+        if self.bucket_width != 1 {
+            return self.coarse_before_probability(other, offset);
+        }
         let self_len = self.histogram.len() as i32;
         let other_len = other.histogram.len() as i32;
         let diff = other.start-self.start-offset;
@@ -171,21 +213,85 @@ impl Distribution {
         if self_len < diff {
             return 1.0;
         }
-        let mut cumulative = 0.0;
-        let until_other_start = std::cmp::min(diff, self_len);
-        for i in 0..until_other_start {
-            cumulative += self.histogram[i as usize];
+        // j: index into other, i = diff+j: index into self
+        let first_j = cmp::max(0, -diff) as usize;
+        let first_i = cmp::max(0, diff) as usize;
+        let overlap_end_j = (self_len-diff).clamp(first_j as i32, other_len) as usize;
+        let mut cumulative = sum(&self.histogram[..first_i]);
+        let mut p = 0.0;
+        for (o, s) in other.histogram[first_j..overlap_end_j].iter().zip(self.histogram[first_i..].iter()) {
+            cumulative += s;
+            p += cumulative*o;
+        }
+        p += cumulative*sum(&other.histogram[overlap_end_j..]);
+        if p > 1.0 {
+            return 1.0
+        }
+        p
+    }
+
+    fn coarse_before_probability(&self, other: &Distribution, offset: i32) -> types::MFloat {
+        let w = self.bucket_width;
+        let diff = other.start-self.start-offset;
+        let q = diff.div_euclid(w);
+        let r = diff.rem_euclid(w);
+        // bucket m of other is shifted by r against bucket q+m of self and overlaps with bucket q+m+1
+        let w2 = (2*w*w) as types::MFloat;
+        let same = 1.0-((w-r-1)*(w-r)) as types::MFloat/w2;
+        let next = (r*(r+1)) as types::MFloat/w2;
+        let self_len = self.histogram.len() as i32;
+        let other_len = other.histogram.len() as i32;
+        if q+other_len < 0 || self_len == 0 {
+            return 0.0;
         }
+        // m: index into other, k = q+m: index into self
+        let first_m = cmp::max(0, -q) as usize;
+        let first_k = q.clamp(0, self_len) as usize;
+        let overlap_end_m = (self_len-q).clamp(first_m as i32, other_len) as usize;
+        let mut cumulative = sum(&self.histogram[..first_k]);
         let mut p = 0.0;
-        for j in 0..other_len {
-            let i = diff+j;
-            if i < 0 {
-                continue
-            }
-            if i < self_len {
-                cumulative += self.histogram[i as usize];
+        if first_m > 0 {
+            p += other.histogram[first_m-1]*self.histogram[0]*next;
+        }
+        let overlap = &other.histogram[first_m..overlap_end_m];
+        let mut p_same = 0.0;
+        let mut p_next = 0.0;
+        for (o, here) in overlap.iter().zip(self.histogram[first_k..].iter()) {
+            p += o*cumulative;
+            p_same += o*here;
+            cumulative += here;
+        }
+        for (o, after) in overlap.iter().zip(self.histogram.get(first_k+1..).unwrap_or(&[]).iter()) {
+            p_next += o*after;
+        }
+        p += p_same*same+p_next*next+cumulative*sum(&other.histogram[overlap_end_m..]);
+        if p > 1.0 {
+            return 1.0
+        }
+        p
+    }
+
+    fn mixed_before_probability(&self, other: &Distribution, offset: i32) -> types::MFloat {
+        let wa = self.bucket_width;
+        let wb = other.bucket_width;
+        let mut k = 0;
+        let mut cumulative = 0.0;
+        let mut p = 0.0;
+        for (m, o) in other.histogram.iter().enumerate() {
+            let mut q = 0.0;
+            for t in 0..wb {
+                let x = other.start+m as i32*wb+t-offset;
+                while k < self.histogram.len() && self.start+(k as i32+1)*wa <= x+1 {
+                    cumulative += self.histogram[k];
+                    k += 1;
+                }
+                q += cumulative;
+                let bucket_start = self.start+k as i32*wa;
+                if k < self.histogram.len() && x >= bucket_start {
+                    q += self.histogram[k]*(x-bucket_start+1) as types::MFloat/wa as types::MFloat;
+                }
             }
-            p += cumulative*other.histogram[j as usize];
+            p += o*q/wb as types::MFloat;
         }
         if p > 1.0 {
             return 1.0
@@ -228,7 +334,8 @@ impl Distribution {
             start: latest_sample_delays[0].0.start as i32,
             mean:  mean as types::MFloat,
             feasible_probability: feasibility as types::MFloat,
-            relevance: Cell::new(0.0)
+            relevance: Cell::new(0.0),
+            bucket_width: 1
         };
         assert_float_absolute_eq!(mean as types::MFloat, d.mean());
         d
@@ -257,9 +364,175 @@ impl Distribution {
         }
     }
 
+    // Conditioned on not being earlier than t, i.e. the mass before t is dropped and the rest renormalized.
+    // If there is no mass left, all of it is at t.
+    pub fn at_least(&self, t: types::Mtime) -> Distribution {
+        assert_eq!(self.bucket_width, 1, "only distributions with a bucket width of 1 are supported");
+        if t <= self.start {
+            return self.clone();
+        }
+        let from = cmp::min((t-self.start) as usize, self.histogram.len());
+        let rest = sum(&self.histogram[from..]);
+        if rest <= 0.0 {
+            let mut d = Distribution::uniform(t, 1);
+            d.feasible_probability = self.feasible_probability;
+            return d;
+        }
+        let mut d = Distribution{
+            histogram: self.histogram[from..].iter().map(|p| p/rest).collect(),
+            start: self.start+from as types::Mtime,
+            mean: 0.0,
+            feasible_probability: self.feasible_probability,
+            relevance: Cell::new(self.relevance.get()),
+            bucket_width: 1
+        };
+        d.mean = d.mean();
+        d
+    }
+
     pub fn update_relevance(&self, relevance: types::MFloat) {
         self.relevance.set(self.relevance.get().max(relevance));
     }
+
+    pub fn coarsen(&self, bucket_width: types::Mtime) -> Distribution {
+        assert_eq!(self.bucket_width, 1, "can only coarsen distributions with a bucket width of 1");
+        let start = self.start.div_euclid(bucket_width)*bucket_width;
+        let mut h = vec![0.; ((self.end()-start+bucket_width-1)/bucket_width) as usize];
+        for (i, p) in self.histogram.iter().enumerate() {
+            h[((self.start+i as types::Mtime-start)/bucket_width) as usize] += p;
+        }
+        Distribution{
+            histogram: h,
+            start: if self.histogram.is_empty() { self.start } else { start },
+            mean: self.mean,
+            feasible_probability: self.feasible_probability,
+            relevance: Cell::new(self.relevance.get()),
+            bucket_width
+        }
+    }
+
+    pub fn refine(&self) -> Distribution {
+        let w = self.bucket_width as usize;
+        let mut h = Vec::with_capacity(self.histogram.len()*w);
+        for p in &self.histogram {
+            h.extend(std::iter::repeat_n(p/w as types::MFloat, w));
+        }
+        Distribution{
+            histogram: h,
+            start: self.start,
+            mean: self.mean,
+            feasible_probability: self.feasible_probability,
+            relevance: Cell::new(self.relevance.get()),
+            bucket_width: 1
+        }
+    }
+
+    #[inline(always)]
+    fn density(&self, t: types::Mtime) -> types::MFloat {
+        if t < self.start {
+            return 0.0;
+        }
+        *self.histogram.get(((t-self.start)/self.bucket_width) as usize).unwrap_or(&0.0)
+    }
+
+    pub fn cdf(&self, t: types::Mtime) -> types::MFloat {
+        if t < self.start {
+            return 0.0;
+        }
+        let until = cmp::min(((t-self.start)/self.bucket_width+1) as usize, self.histogram.len());
+        sum(&self.histogram[..until])
+    }
+
+    pub fn survival(&self, t: types::Mtime) -> types::MFloat {
+        1.0-self.cdf(t)
+    }
+
+    // mid PIT, i.e. for discrete distributions half of the probability of t is counted
+    pub fn pit(&self, t: types::Mtime) -> types::MFloat {
+        let total = sum(&self.histogram);
+        (self.cdf(t-1)+self.cdf(t))/2.0/total
+    }
+
+    pub fn crps(&self, t: types::Mtime) -> types::MFloat {
+        let total = sum(&self.histogram);
+        let mut cum = 0.0;
+        let mut crps = 0.0;
+        for x in cmp::min(self.start, t)..cmp::max(self.end(), t+1) {
+            if x >= self.start && x < self.end() && (x-self.start)%self.bucket_width == 0 {
+                cum += self.histogram[((x-self.start)/self.bucket_width) as usize];
+            }
+            let step = if x >= t { 1.0 } else { 0.0 };
+            crps += (cum/total-step)*(cum/total-step);
+        }
+        crps
+    }
+
+    pub fn log_score(&self, t: types::Mtime) -> types::MFloat {
+        if t < self.start || t >= self.end() {
+            return types::MFloat::INFINITY;
+        }
+        let total = sum(&self.histogram);
+        -(self.histogram[((t-self.start)/self.bucket_width) as usize]/total/self.bucket_width as types::MFloat).ln()
+    }
+
+    pub fn convolve(&self, other: &Distribution) -> Distribution {
+        if self.histogram.is_empty() || other.histogram.is_empty() {
+            return Distribution::empty(self.start+other.start);
+        }
+        assert!(self.bucket_width == 1 && other.bucket_width == 1, "only distributions with a bucket width of 1 are supported");
+        let mut h = vec![0.; self.histogram.len()+other.histogram.len()-1];
+        for i in 0..self.histogram.len() {
+            for j in 0..other.histogram.len() {
+                h[i+j] += self.histogram[i]*other.histogram[j];
+            }
+        }
+        let mut d = Distribution{
+            histogram: h,
+            start: self.start+other.start,
+            mean: 0.0,
+            feasible_probability: self.feasible_probability*other.feasible_probability,
+            relevance: Cell::new(0.0),
+            bucket_width: 1
+        };
+        d.mean = d.mean();
+        d
+    }
+
+    pub fn max(&self, other: &Distribution) -> Distribution {
+        self.combine_independent(other, cmp::max(self.start, other.start), cmp::max(self.end(), other.end()), |a, b| a*b)
+    }
+
+    pub fn min(&self, other: &Distribution) -> Distribution {
+        self.combine_independent(other, cmp::min(self.start, other.start), cmp::min(self.end(), other.end()), |a, b| 1.0-(1.0-a)*(1.0-b))
+    }
+
+    fn combine_independent<F: Fn(types::MFloat, types::MFloat) -> types::MFloat>(&self, other: &Distribution, start: types::Mtime, end: types::Mtime, combine_cdfs: F) -> Distribution {
+        if self.histogram.is_empty() || other.histogram.is_empty() {
+            return Distribution::empty(start);
+        }
+        assert!(self.bucket_width == 1 && other.bucket_width == 1, "only distributions with a bucket width of 1 are supported");
+        let mut h = Vec::with_capacity((end-start) as usize);
+        let mut cum_self = self.cdf(start-1);
+        let mut cum_other = other.cdf(start-1);
+        let mut last = combine_cdfs(cum_self, cum_other);
+        for t in start..end {
+            cum_self += self.density(t);
+            cum_other += other.density(t);
+            let cum = combine_cdfs(cum_self, cum_other);
+            h.push(cum-last);
+            last = cum;
+        }
+        let mut d = Distribution{
+            histogram: h,
+            start,
+            mean: 0.0,
+            feasible_probability: self.feasible_probability*other.feasible_probability,
+            relevance: Cell::new(0.0),
+            bucket_width: 1
+        };
+        d.mean = d.mean();
+        d
+    }
 }
 
 
@@ -398,6 +671,23 @@ mod tests {
         assert_eq!(a.histogram[1], 0.5);
     }
 
+    #[test]
+    fn at_least() {
+        let mut a = Distribution::uniform(5, 4);
+        a.feasible_probability = 0.5;
+        let b = a.at_least(7);
+        assert_eq!(b.start, 7);
+        assert_eq!(b.histogram, vec![0.5, 0.5]);
+        assert_eq!(b.mean, 7.5);
+        assert_eq!(b.feasible_probability, 0.5);
+        assert_eq!(a.at_least(3).histogram, a.histogram);
+        let c = a.at_least(12);
+        assert_eq!(c.start, 12);
+        assert_eq!(c.histogram, vec![1.0]);
+        assert_eq!(c.mean, 12.0);
+        assert_eq!(c.feasible_probability, 0.5);
+    }
+
     #[test]
     fn normalize_with_histogram() {
         let mut a = Distribution::uniform(5, 3);
@@ -550,4 +840,223 @@ mod tests {
         assert_float_relative_eq!(a.histogram[6], 0.125);
         assert_float_relative_eq!(a.histogram[7], 0.125);
     }
-}
\ No newline at end of file
+
+    fn random_distribution(rng: &mut rand::rngs::StdRng) -> Distribution {
+        use rand::Rng;
+        let mut d = Distribution::uniform(rng.gen_range(-20..20), rng.gen_range(1..30));
+        for p in d.histogram.iter_mut() {
+            *p = rng.gen_range(0.0..1.0);
+        }
+        d.normalize();
+        d.mean = d.mean();
+        d
+    }
+
+    #[test]
+    fn variance_uniform() {
+        assert_float_absolute_eq!(Distribution::uniform(2, 4).variance(), 1.25);
+        assert_float_absolute_eq!(Distribution::uniform(-3, 1).variance(), 0.0);
+    }
+
+    #[test]
+    fn cdf_survival() {
+        let a = Distribution::uniform(2, 4);
+        assert_eq!(a.cdf(1), 0.0);
+        assert_eq!(a.cdf(2), 0.25);
+        assert_eq!(a.cdf(4), 0.75);
+        assert_eq!(a.cdf(10), 1.0);
+        assert_eq!(a.survival(3), 0.5);
+    }
+
+    #[test]
+    fn scores() {
+        let a = Distribution::uniform(0, 4);
+        assert_eq!(a.pit(-1), 0.0);
+        assert_eq!(a.pit(1), 0.375);
+        assert_eq!(a.pit(5), 1.0);
+        assert_eq!(Distribution::uniform(5, 1).crps(5), 0.0);
+        assert_eq!(Distribution::uniform(0, 2).crps(0), 0.25);
+        assert_eq!(Distribution::uniform(0, 1).crps(2), 2.0);
+        assert_float_absolute_eq!(a.log_score(2), (4.0 as types::MFloat).ln());
+        assert_eq!(a.log_score(4), types::MFloat::INFINITY);
+        let coarse = a.coarsen(2);
+        assert_float_absolute_eq!(coarse.log_score(3), a.log_score(3));
+        assert_float_absolute_eq!(coarse.crps(2), a.crps(2)+0.125);
+    }
+
+    #[test]
+    fn convolve_uniform() {
+        let a = Distribution::uniform(2, 2);
+        let b = Distribution::uniform(-1, 2);
+        let c = a.convolve(&b);
+        assert_eq!(c.start, 1);
+        assert_eq!(c.histogram, vec![0.25, 0.5, 0.25]);
+        assert_eq!(c.mean, 2.0);
+        assert_eq!(c.feasible_probability, 1.0);
+    }
+
+    #[test]
+    fn convolve_empty() {
+        let c = Distribution::uniform(2, 2).convolve(&Distribution::empty(3));
+        assert!(!c.exists());
+    }
+
+    #[test]
+    fn max_min_uniform() {
+        let a = Distribution::uniform(0, 2);
+        let b = Distribution::uniform(0, 2);
+        let max = a.max(&b);
+        assert_eq!(max.start, 0);
+        assert_eq!(max.histogram, vec![0.25, 0.75]);
+        assert_eq!(max.mean, 0.75);
+        let min = a.min(&b);
+        assert_eq!(min.start, 0);
+        assert_eq!(min.histogram, vec![0.75, 0.25]);
+        let shifted = a.max(&b.shift(1));
+        assert_eq!(shifted.start, 1);
+        assert_eq!(shifted.histogram, vec![0.5, 0.5]);
+    }
+
+    #[test]
+    fn max_min_apart() {
+        let a = Distribution::uniform(0, 2);
+        let b = Distribution::uniform(5, 4);
+        assert_eq!(a.max(&b).histogram, b.histogram);
+        assert_eq!(a.max(&b).start, 5);
+        assert_eq!(a.min(&b).histogram, a.histogram);
+        assert_eq!(a.min(&b).start, 0);
+    }
+
+    #[test]
+    fn arithmetic_mass_conservation() {
+        use rand::SeedableRng;
+        let mut rng = rand::rngs::StdRng::seed_from_u64(42);
+        for _ in 0..200 {
+            let a = random_distribution(&mut rng);
+            let b = random_distribution(&mut rng);
+            a.assert();
+            b.assert();
+            let sum = a.convolve(&b);
+            sum.assert();
+            assert_float_absolute_eq!(sum.mean, a.mean+b.mean, 1e-3);
+            let max = a.max(&b);
+            max.assert();
+            assert!(max.mean >= a.mean.max(b.mean)-1e-3);
+            let min = a.min(&b);
+            min.assert();
+            assert!(min.mean <= a.mean.min(b.mean)+1e-3);
+            assert_float_absolute_eq!(min.mean+max.mean, a.mean+b.mean, 1e-3);
+            for t in a.start-1..=a.end() {
+                assert_float_absolute_eq!(a.cdf(t)+a.survival(t), 1.0);
+                assert_float_absolute_eq!(max.cdf(t), a.cdf(t)*b.cdf(t), 1e-3);
+            }
+            assert_float_absolute_eq!(a.cdf(a.end()), 1.0, 1e-3);
+        }
+    }
+
+    #[test]
+    fn add_prepend_in_place() {
+        let mut a = Distribution::uniform(5, 2);
+        a.add(&Distribution::uniform(2, 2), 1.0);
+        assert_eq!(a.start, 2);
+        assert_eq!(a.histogram, vec![0.5, 0.5, 0.0, 0.5, 0.5]);
+        a.add(&Distribution::uniform(3, 6), 0.0);
+        assert_eq!(a.start, 2);
+        assert_eq!(a.histogram, vec![0.5, 0.5, 0.0, 0.5, 0.5, 0.0, 0.0]);
+    }
+
+    fn scalar_before_probability(a: &Distribution, b: &Distribution, offset: i32) -> types::MFloat {
+        let mut p = 0.0;
+        for i in 0..a.histogram.len() {
+            for j in 0..b.histogram.len() {
+                if a.start+i as i32+offset <= b.start+j as i32 {
+                    p += a.histogram[i]*b.histogram[j];
+                }
+            }
+        }
+        p
+    }
+
+    #[test]
+    fn before_probability_matches_scalar() {
+        use rand::{Rng, SeedableRng};
+        let mut rng = rand::rngs::StdRng::seed_from_u64(7);
+        for _ in 0..200 {
+            let a = random_distribution(&mut rng);
+            let b = random_distribution(&mut rng);
+            let offset = rng.gen_range(-10..10);
+            assert_float_absolute_eq!(a.before_probability(&b, offset), scalar_before_probability(&a, &b, offset), 1e-4);
+        }
+    }
+
+    #[test]
+    fn before_probability_coarse_matches_refined() {
+        use rand::{Rng, SeedableRng};
+        let mut rng = rand::rngs::StdRng::seed_from_u64(11);
+        for _ in 0..200 {
+            let wa = rng.gen_range(1..6);
+            let wb = if rng.gen_bool(0.5) { wa } else { rng.gen_range(1..6) };
+            let a = random_distribution(&mut rng).coarsen(wa);
+            let b = random_distribution(&mut rng).coarsen(wb);
+            let offset = rng.gen_range(-10..10);
+            assert_float_absolute_eq!(a.before_probability(&b, offset), scalar_before_probability(&a.refine(), &b.refine(), offset), 1e-4);
+        }
+    }
+
+    #[test]
+    fn coarsen_refine() {
+        let a = Distribution::uniform(3, 4);
+        let c = a.coarsen(5);
+        assert_eq!(c.start, 0);
+        assert_eq!(c.bucket_width, 5);
+        assert_eq!(c.histogram, vec![0.5, 0.5]);
+        assert_eq!(c.end(), 10);
+        assert_eq!(c.mean, 4.5);
+        assert_eq!(c.mean(), 4.5);
+        assert_eq!(c.quantile(0.75), 5);
+        let r = c.refine();
+        assert_eq!(r.start, 0);
+        assert_eq!(r.bucket_width, 1);
+        assert_eq!(r.histogram.len(), 10);
+        r.assert();
+    }
+
+    #[test]
+    fn coarsen_negative() {
+        let c = Distribution::uniform(-3, 2).coarsen(2);
+        assert_eq!(c.start, -4);
+        assert_eq!(c.histogram, vec![0.5, 0.5]);
+    }
+
+    #[test]
+    fn add_coarse() {
+        let mut a = Distribution::empty(0);
+        a.add(&Distribution::uniform(10, 5).coarsen(5), 0.5);
+        a.add(&Distribution::uniform(0, 5).coarsen(5), 0.5);
+        assert_eq!(a.bucket_width, 5);
+        assert_eq!(a.start, 0);
+        assert_eq!(a.histogram, vec![0.5, 0.0, 0.5]);
+        assert_eq!(a.end(), 15);
+        assert_eq!(a.mean, 7.0);
+    }
+
+    #[test]
+    fn before_coarse() {
+        // uniform within the coarse buckets, so coarsening loses nothing
+        let a = Distribution::uniform(4, 4);
+        let b = Distribution::uniform(6, 4);
+        for offset in -3..8 {
+            assert_float_absolute_eq!(a.coarsen(2).before_probability(&b, offset), a.before_probability(&b, offset), 1e-6);
+            assert_float_absolute_eq!(a.coarsen(2).before_probability(&b.coarsen(2), offset), a.before_probability(&b, offset), 1e-6);
+        }
+        // spread evenly within the bucket [4, 8)
+        assert_float_absolute_eq!(Distribution::uniform(5, 1).before_probability(&Distribution::uniform(4, 2).coarsen(4), 0), 0.75, 1e-6);
+    }
+
+    #[test]
+    #[should_panic]
+    fn add_mixed_widths() {
+        let mut a = Distribution::uniform(0, 5);
+        a.add(&Distribution::uniform(10, 5).coarsen(5), 0.5);
+    }
+}
@@ -1,4 +1,5 @@
 use std::cmp::Ordering;
+use std::collections::BTreeMap;
 use std::collections::HashMap;
 use std::ops::Range;
 use std::fs::File;
@@ -10,7 +11,8 @@ use itertools::Itertools;
 use crate::distribution;
 use crate::connection;
 use crate::types;
-use crate::walking::WALKING_PRODUCT_TYPE;
+use crate::online_correction::{OnlineCorrection, OnlineCorrectionConfig};
+use crate::walking::{WalkingProfile, MAX_WALKING_MINUTES, WALKING_PRODUCT_TYPE};
 
 const PRODUCT_TYPES_NUM: i16 = 13;
 
@@ -22,7 +24,7 @@ struct DelayKey {
     is_departure: bool
 }
 
-#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, Debug)]
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, Debug, Clone)]
 struct ReachabilityKey {
     from_product_type: i16,
     to_product_type: i16,
@@ -34,7 +36,153 @@ struct ReachabilityKey {
     from_is_departure: bool
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum BucketColumns {
+    Range(String),
+    Bounds(String, String)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CsvSchema {
+    pub product_type: String,
+    pub is_departure: String,
+    pub prior_ttl: BucketColumns,
+    pub prior_delay: BucketColumns,
+    pub latest_sample_delay: BucketColumns,
+    pub sample_count: String,
+    #[serde(default = "default_true_values")]
+    pub true_values: Vec<String>,
+    #[serde(default = "default_delimiter")]
+    pub delimiter: char
+}
+
+fn default_true_values() -> Vec<String> {
+    vec!["True".to_string(), "true".to_string(), "t".to_string(), "1".to_string()]
+}
+
+fn default_delimiter() -> char {
+    ','
+}
+
+impl Default for CsvSchema {
+    fn default() -> Self {
+        CsvSchema {
+            product_type: "product_type_id".to_string(),
+            is_departure: "is_departure".to_string(),
+            prior_ttl: BucketColumns::Range("prior_ttl_bucket".to_string()),
+            prior_delay: BucketColumns::Range("prior_delay_bucket".to_string()),
+            latest_sample_delay: BucketColumns::Range("latest_sample_delay_bucket".to_string()),
+            sample_count: "sample_count".to_string(),
+            true_values: default_true_values(),
+            delimiter: default_delimiter()
+        }
+    }
+}
+
+enum BucketIdx {
+    Range(usize),
+    Bounds(usize, usize)
+}
+
+struct ResolvedColumns {
+    product_type: usize,
+    is_departure: usize,
+    prior_ttl: BucketIdx,
+    prior_delay: BucketIdx,
+    latest_sample_delay: BucketIdx,
+    sample_count: usize
+}
+
+impl CsvSchema {
+    fn resolve(&self, headers: &csv::StringRecord) -> Result<ResolvedColumns, String> {
+        let bucket_names = |b: &BucketColumns| match b {
+            BucketColumns::Range(name) => vec![name.clone()],
+            BucketColumns::Bounds(lower, upper) => vec![lower.clone(), upper.clone()]
+        };
+        let names = [vec![self.product_type.clone(), self.is_departure.clone()], bucket_names(&self.prior_ttl), bucket_names(&self.prior_delay), bucket_names(&self.latest_sample_delay), vec![self.sample_count.clone()]].concat();
+        let missing: Vec<String> = names.into_iter().filter(|name| !headers.iter().any(|h| h == name)).collect();
+        if !missing.is_empty() {
+            return Err(format!("columns {} not found in {:?}", missing.join(", "), headers));
+        }
+        let idx = |name: &str| headers.iter().position(|h| h == name).unwrap();
+        let bucket_idx = |b: &BucketColumns| match b {
+            BucketColumns::Range(name) => BucketIdx::Range(idx(name)),
+            BucketColumns::Bounds(lower, upper) => BucketIdx::Bounds(idx(lower), idx(upper))
+        };
+        Ok(ResolvedColumns {
+            product_type: idx(&self.product_type),
+            is_departure: idx(&self.is_departure),
+            prior_ttl: bucket_idx(&self.prior_ttl),
+            prior_delay: bucket_idx(&self.prior_delay),
+            latest_sample_delay: bucket_idx(&self.latest_sample_delay),
+            sample_count: idx(&self.sample_count)
+        })
+    }
+}
+
+impl ResolvedColumns {
+    fn parse_bucket(record: &csv::StringRecord, idx: &BucketIdx, ticks_per_minute: types::Mtime) -> Result<Range<i16>, String> {
+        let bucket = match idx {
+            BucketIdx::Range(i) => Store::parse_bucket(&record[*i])?,
+            BucketIdx::Bounds(lower, upper) => {
+                let bound = |i: usize| match record[i].trim() {
+                    "" | "NULL" => Ok(None),
+                    v => v.parse().map(Some).map_err(|_| format!("invalid bucket bound {}", v))
+                };
+                Store::bucket_from_bounds(bound(*lower)?, bound(*upper)?)
+            }
+        };
+        Store::to_ticks(bucket, ticks_per_minute)
+    }
+
+    fn parse(&self, record: &csv::StringRecord, schema: &CsvSchema, ticks_per_minute: types::Mtime) -> Result<(DelayKey, Range<i16>, i32), String> {
+        let product_type = record[self.product_type].trim().parse().map_err(|_| format!("invalid product type {}", &record[self.product_type]))?;
+        let sample_count = record[self.sample_count].trim().parse().map_err(|_| format!("invalid sample count {}", &record[self.sample_count]))?;
+        let prior_delay = Self::parse_bucket(record, &self.prior_delay, ticks_per_minute)?;
+        let prior_ttl = Self::parse_bucket(record, &self.prior_ttl, ticks_per_minute)?;
+        Ok((DelayKey {
+            product_type,
+            prior_delay: (prior_delay.start, prior_delay.end),
+            prior_ttl: (prior_ttl.start, prior_ttl.end),
+            is_departure: schema.true_values.iter().any(|t| t == record[self.is_departure].trim())
+        }, Self::parse_bucket(record, &self.latest_sample_delay, ticks_per_minute)?, sample_count))
+    }
+}
+
+// Buckets in minutes as parsed from the CSV, i.e. NULL is 0..0 and open buckets are empty ranges.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistogramRow {
+    pub product_type: i16,
+    pub is_departure: bool,
+    pub prior_ttl: Range<i16>,
+    pub prior_delay: Range<i16>,
+    pub latest_sample_delay: Range<i16>,
+    pub sample_count: i32
+}
+
+#[derive(Debug, Default)]
+pub struct ImportReport {
+    pub rows: usize,
+    pub distributions: usize,
+    pub skipped: usize,
+    pub duplicates: usize,
+    pub unsorted: bool,
+    pub problems: Vec<String>
+}
+
+impl ImportReport {
+    pub fn print(&self) {
+        println!("imported {} distributions from {} rows, skipped {} with too few samples, {} duplicates, unsorted: {}", self.distributions, self.rows, self.skipped, self.duplicates, self.unsorted);
+        for p in self.problems.iter().take(10) {
+            println!("{}", p);
+        }
+        if self.problems.len() > 10 {
+            println!("... {} more problems", self.problems.len()-10);
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Store {
     delay: FxHashMap<DelayKey, distribution::Distribution>,
     delay_buckets: Vec<(i16, i16)>,
@@ -45,6 +193,7 @@ pub struct Store {
     hot_ttl_buckets_num: usize,
     ttl_lower: i16,
     reachability: FxHashMap<ReachabilityKey, types::MFloat>,
+    #[serde(skip)]
     hot_reachability: Vec<types::MFloat>,
     hot_reachability_factors: [usize; 5],
     pub min_delay_diff: i16,
@@ -53,11 +202,25 @@ pub struct Store {
     delay_range_size: usize,
     hits: usize,
     hot_hits: usize,
-    misses: usize
+    misses: usize,
+    #[serde(default)]
+    walking_profile: Option<WalkingProfile>,
+    #[serde(default)]
+    walking_distributions: Vec<distribution::Distribution>,
+    #[serde(skip)]
+    online_correction: Option<OnlineCorrection>,
+    #[serde(default = "types::one_tick_per_minute")]
+    ticks_per_minute: types::Mtime
 }
 
 impl Store {
     pub fn new() -> Store {
+        Self::with_ticks_per_minute(types::one_tick_per_minute())
+    }
+
+    // All times of the store, including those of the distributions loaded later, are in ticks of this resolution.
+    pub fn with_ticks_per_minute(ticks_per_minute: types::Mtime) -> Store {
+        types::assert_ticks_per_minute(ticks_per_minute);
         let mut s = Store{
             delay: FxHashMap::default(),
             delay_buckets: vec![],
@@ -76,12 +239,20 @@ impl Store {
             delay_range_size: 0,
             hits: 0,
             hot_hits: 0,
-            misses: 0
+            misses: 0,
+            walking_profile: None,
+            walking_distributions: vec![],
+            online_correction: None,
+            ticks_per_minute
         };
         s.insert_fallback_distributions();
         s
     }
 
+    pub fn ticks_per_minute(&self) -> types::Mtime {
+        self.ticks_per_minute
+    }
+
     pub fn print_stats(&self) {
         println!("store: min_delay_diff: {} epsilon_min_delay_diff: {} ttl_lower: {} reachability entries: {} hits: {} hot_hits: {} misses: {} delaykeys: {}", self.min_delay_diff, self.min_epsilon_delay_diff, self.ttl_lower, self.reachability.len(), self.hits, self.hot_hits, self.misses, self.delay.len());
     }
@@ -168,25 +339,29 @@ impl Store {
         Some(self.raw_delay_distribution_by_key(delay_key))
     }
 
-    fn parse_bucket(bucket: &str) -> Range<i16> {
+    fn parse_bucket(bucket: &str) -> Result<Range<i16>, String> {
+        let bucket = bucket.trim();
         if bucket == "NULL" {
-            return 0..0;
+            return Ok(0..0);
+        }
+        let cleaned = bucket.trim_start_matches(['[', '(']).trim_end_matches([']', ')']);
+        let parts: Vec<&str> = cleaned.split(',').map(|p| p.trim()).collect();
+        if parts.len() != 2 {
+            return Err(format!("invalid bucket {}", bucket));
         }
-        let cleaned = bucket.replace('[', "").replace(')', "");
-        let parts: Vec<&str> = cleaned.split(',').collect();
-        let start = parts[0].parse();
-        let end = parts[1].parse();
-        start.clone().unwrap_or(end.clone().unwrap_or(0))..end.unwrap_or(start.unwrap_or(0))
+        let bound = |v: &str| if v.is_empty() { Ok(None) } else { v.parse().map(Some).map_err(|_| format!("invalid bucket {}", bucket)) };
+        Ok(Self::bucket_from_bounds(bound(parts[0])?, bound(parts[1])?))
+    }
+
+    fn bucket_from_bounds(start: Option<i16>, end: Option<i16>) -> Range<i16> {
+        start.unwrap_or(end.unwrap_or(0))..end.unwrap_or(start.unwrap_or(0))
     }
 
-    fn make_delay_key(record: &csv::StringRecord, keys: &HashMap<&str, usize>) -> DelayKey {
-        let prior_delay_bucket = Self::parse_bucket(record.get(keys["prior_delay_bucket"]).unwrap());
-        let prior_ttl_bucket = Self::parse_bucket(record.get(keys["prior_ttl_bucket"]).unwrap());
-        DelayKey {
-            product_type: record.get(keys["product_type_id"]).unwrap().parse().unwrap(),
-            prior_delay: (prior_delay_bucket.start, prior_delay_bucket.end),
-            prior_ttl: (prior_ttl_bucket.start, prior_ttl_bucket.end),
-            is_departure: record.get(keys["is_departure"]).unwrap() == "True"
+    fn to_ticks(bucket: Range<i16>, ticks_per_minute: types::Mtime) -> Result<Range<i16>, String> {
+        let ticks = ticks_per_minute as i16;
+        match (bucket.start.checked_mul(ticks), bucket.end.checked_mul(ticks)) {
+            (Some(start), Some(end)) => Ok(start..end),
+            _ => Err(format!("bucket {:?} out of range for time resolution", bucket))
         }
     }
 
@@ -207,41 +382,112 @@ impl Store {
     }
 
     pub fn load_distributions(&mut self, file_path: &str) {
-        let key_array: [(&str, usize); 6] = [
-            ("product_type_id", 0),
-            ("is_departure",1),
-            ("prior_ttl_bucket",2),
-            ("prior_delay_bucket",3),
-            ("latest_sample_delay_bucket",4),
-            ("sample_count",5)
-        ];
-        let keys = HashMap::from(key_array);
+        let report = self.load_distributions_with_schema(file_path, &CsvSchema::default());
+        report.print();
+    }
+
+    pub fn load_distributions_with_schema(&mut self, file_path: &str, schema: &CsvSchema) -> ImportReport {
         let file = File::open(file_path).unwrap();
-        let mut rdr = csv::Reader::from_reader(file);
-        let mut current_delay_key: Option<DelayKey> = None;
-        let mut latest_sample_delays: Vec<(Range<i16>, i32)> = vec![];
+        let mut rdr = csv::ReaderBuilder::new().delimiter(schema.delimiter as u8).from_reader(file);
+        let headers = rdr.headers().unwrap().clone();
+        let mut report = ImportReport::default();
+        let columns = match schema.resolve(&headers) {
+            Ok(columns) => columns,
+            Err(e) => {
+                report.problems.push(e);
+                return report;
+            }
+        };
+        let mut groups: BTreeMap<DelayKey, Vec<(Range<i16>, i32)>> = BTreeMap::new();
+        let mut last_key: Option<DelayKey> = None;
+        for (i, result) in rdr.records().enumerate() {
+            let line = i+2;
+            report.rows += 1;
+            let row = result.map_err(|e| e.to_string()).and_then(|record| columns.parse(&record, schema, self.ticks_per_minute));
+            match row {
+                Ok((delay_key, latest_sample_delay, sample_count)) => Self::group_row(&mut groups, &mut last_key, &mut report, line, delay_key, latest_sample_delay, sample_count),
+                Err(e) => report.problems.push(format!("line {}: {}", line, e))
+            }
+        }
+        self.insert_groups(groups, &mut report);
+        report
+    }
+
+    pub fn load_histogram(&mut self, rows: &[HistogramRow]) -> ImportReport {
+        let mut report = ImportReport::default();
+        let mut groups: BTreeMap<DelayKey, Vec<(Range<i16>, i32)>> = BTreeMap::new();
+        let mut last_key: Option<DelayKey> = None;
+        for (i, row) in rows.iter().enumerate() {
+            report.rows += 1;
+            let buckets = Self::to_ticks(row.prior_delay.clone(), self.ticks_per_minute).and_then(|d| Ok((d, Self::to_ticks(row.prior_ttl.clone(), self.ticks_per_minute)?, Self::to_ticks(row.latest_sample_delay.clone(), self.ticks_per_minute)?)));
+            let (prior_delay, prior_ttl, latest_sample_delay) = match buckets {
+                Ok(b) => b,
+                Err(e) => {
+                    report.problems.push(format!("line {}: {}", i+2, e));
+                    continue;
+                }
+            };
+            let delay_key = DelayKey {
+                product_type: row.product_type,
+                prior_delay: (prior_delay.start, prior_delay.end),
+                prior_ttl: (prior_ttl.start, prior_ttl.end),
+                is_departure: row.is_departure
+            };
+            Self::group_row(&mut groups, &mut last_key, &mut report, i+2, delay_key, latest_sample_delay, row.sample_count);
+        }
+        self.insert_groups(groups, &mut report);
+        report
+    }
+
+    fn group_row(groups: &mut BTreeMap<DelayKey, Vec<(Range<i16>, i32)>>, last_key: &mut Option<DelayKey>, report: &mut ImportReport, line: usize, delay_key: DelayKey, latest_sample_delay: Range<i16>, sample_count: i32) {
+        if sample_count < 0 {
+            report.problems.push(format!("line {}: negative sample count {}", line, sample_count));
+            return;
+        }
+        for (name, bucket) in [("prior delay", delay_key.prior_delay), ("prior ttl", delay_key.prior_ttl), ("latest sample delay", (latest_sample_delay.start, latest_sample_delay.end))] {
+            if bucket.0 > bucket.1 {
+                report.problems.push(format!("line {}: {} bucket {:?} starts after its end", line, name, bucket));
+                return;
+            }
+        }
+        if last_key.as_ref() != Some(&delay_key) && groups.contains_key(&delay_key) {
+            report.unsorted = true;
+        }
+        let buckets = groups.entry(delay_key.clone()).or_default();
+        if buckets.iter().try_fold(sample_count, |acc, b| acc.checked_add(b.1)).is_none() {
+            report.problems.push(format!("line {}: sample counts of {:?} overflow", line, delay_key));
+            return;
+        }
+        match buckets.iter_mut().find(|b| b.0 == latest_sample_delay) {
+            Some(b) => {
+                report.duplicates += 1;
+                report.problems.push(format!("line {}: duplicate key {:?} {:?}, summing sample counts", line, delay_key, latest_sample_delay));
+                b.1 += sample_count;
+            },
+            None => buckets.push((latest_sample_delay, sample_count))
+        }
+        *last_key = Some(delay_key);
+    }
+
+    fn insert_groups(&mut self, groups: BTreeMap<DelayKey, Vec<(Range<i16>, i32)>>, report: &mut ImportReport) {
         let mut min_max_delay = (0,0);
         let mut epsilon_min_max_delay = (0,0);
-        let mut total_feasible_sample_count = 0;
-        for result in rdr.records() {
-            let record = result.unwrap();
-            let delay_key = Self::make_delay_key(&record, &keys);
-            if current_delay_key.is_some() && delay_key != *current_delay_key.as_ref().unwrap() {
-                let dist = self.insert_distribution_from_buckets(current_delay_key.unwrap(), latest_sample_delays, total_feasible_sample_count);
-                if dist.is_some() {
-                    Self::update_min_max_delay(dist.unwrap(), &mut min_max_delay, &mut epsilon_min_max_delay);
-                }
-                current_delay_key = None;
-                latest_sample_delays = vec![];
-                total_feasible_sample_count = 0;
+        for (delay_key, mut latest_sample_delays) in groups.into_iter() {
+            // cancelled (0..0) last, as ordered by the Postgres export
+            latest_sample_delays.sort_by_key(|b| (b.0 == (0..0), b.0.start, b.0.end));
+            let feasible_buckets: Vec<&Range<i16>> = latest_sample_delays.iter().map(|b| &b.0).filter(|b| b.start != b.end).collect();
+            if let Some(overlap) = feasible_buckets.windows(2).find(|w| w[1].start < w[0].end) {
+                report.problems.push(format!("{:?}: overlapping buckets {:?} and {:?} would distort the probability mass, skipping", delay_key, overlap[0], overlap[1]));
+                continue;
             }
-            current_delay_key.replace(delay_key);
-            let latest_sample_delay = Self::parse_bucket(record.get(keys["latest_sample_delay_bucket"]).unwrap());
-            let sample_count = record.get(keys["sample_count"]).unwrap().parse().unwrap();
-            if latest_sample_delay.start != latest_sample_delay.end {
-                total_feasible_sample_count += sample_count;
+            let total_feasible_sample_count = latest_sample_delays.iter().filter(|b| b.0.start != b.0.end).map(|b| b.1).sum();
+            match self.insert_distribution_from_buckets(delay_key, latest_sample_delays, total_feasible_sample_count) {
+                Some(dist) => {
+                    Self::update_min_max_delay(dist, &mut min_max_delay, &mut epsilon_min_max_delay);
+                    report.distributions += 1;
+                },
+                None => report.skipped += 1
             }
-            latest_sample_delays.push((latest_sample_delay, sample_count));
         }
         self.min_delay_diff = min_max_delay.0-min_max_delay.1;
         self.max_delay = min_max_delay.1;
@@ -250,6 +496,18 @@ impl Store {
         self.create_hot_reachability();
     }
 
+    pub fn save_snapshot(&self, path: &str) {
+        let buf = rmp_serde::to_vec(self).unwrap();
+        std::fs::write(path, buf).expect("error writing store snapshot");
+    }
+
+    pub fn load_snapshot(path: &str) -> Store {
+        let buf = std::fs::read(path).unwrap();
+        let mut store: Store = rmp_serde::from_slice(&buf).unwrap();
+        store.create_hot_reachability();
+        store
+    }
+
     fn create_hot_reachability(&mut self) {
         let map: HashMap<(i16, i16), i16> = HashMap::from_iter(self.ttl_buckets.iter().unique().map(|t| *t).sorted_by(|a,b| {
             if *a == (0,0) {
@@ -277,7 +535,7 @@ impl Store {
             prior_delay: (0,0),
             prior_ttl: (0,0),
             is_departure: true
-        }, distribution::Distribution::uniform(0, 1)); //TODO?
+        }, distribution::Distribution::uniform(0, 1));
         self.insert_delay_key(DelayKey {
             product_type: WALKING_PRODUCT_TYPE,
             prior_delay: (0,0),
@@ -318,15 +576,73 @@ impl Store {
         }
     }
 
+    // final delay distribution without any realtime information
+    pub fn prior_delay_distribution(&self, is_departure: bool, product_type: i16) -> distribution::Distribution {
+        self.raw_delay_distribution((0,0), is_departure, product_type, (0,0)).clone()
+    }
+
     pub fn delay_distribution(&self, stop_info: &connection::StopInfo, is_departure: bool, product_type: i16, now: types::Mtime) -> distribution::Distribution {
         let ttl = self.ttl_bucket(stop_info.projected()-now);
-        self.raw_delay_distribution(self.delay_bucket(stop_info.delay, ttl), is_departure, product_type, ttl).shift(stop_info.projected())
+        let d = self.raw_delay_distribution(self.delay_bucket(stop_info.delay, ttl), is_departure, product_type, ttl).shift(stop_info.projected());
+        let d = match &self.online_correction {
+            Some(o) => o.apply(d, product_type),
+            None => d
+        };
+        match stop_info.min_delay {
+            Some(min_delay) => d.at_least(stop_info.scheduled+min_delay as types::Mtime),
+            None => d
+        }
     }
 
     #[inline]
     pub fn delay_distribution_mean(&self, stop_info: &connection::StopInfo, is_departure: bool, product_type: i16, now: types::Mtime) -> types::MFloat {
+        if stop_info.min_delay.is_some() {
+            return self.delay_distribution(stop_info, is_departure, product_type, now).mean;
+        }
         let ttl = self.ttl_bucket(stop_info.projected()-now);
-        self.raw_delay_distribution(self.delay_bucket(stop_info.delay, ttl), is_departure, product_type, ttl).mean+stop_info.projected() as types::MFloat
+        self.raw_delay_distribution(self.delay_bucket(stop_info.delay, ttl), is_departure, product_type, ttl).mean+stop_info.projected() as types::MFloat+self.online_shift(product_type)
+    }
+
+    #[inline(always)]
+    fn online_shift(&self, product_type: i16) -> types::MFloat {
+        match &self.online_correction {
+            Some(o) => o.shift(product_type),
+            None => 0.0
+        }
+    }
+
+    pub fn enable_online_correction(&mut self, config: OnlineCorrectionConfig) {
+        self.online_correction = Some(OnlineCorrection::new(config, self.ticks_per_minute));
+    }
+
+    pub fn online_correction(&self) -> Option<&OnlineCorrection> {
+        self.online_correction.as_ref()
+    }
+
+    // Compares the prediction of the historical distributions with the realized event once it has been observed.
+    pub fn observe_update(&mut self, c: &connection::Connection, is_departure: bool, now: types::Mtime) {
+        let min_lead = match &self.online_correction {
+            Some(o) => types::minutes(o.config.min_lead, self.ticks_per_minute),
+            None => return
+        };
+        let stop_info = if is_departure { &c.departure } else { &c.arrival };
+        let key = (c.id, is_departure);
+        let projected = stop_info.projected();
+        let prediction = if stop_info.in_out_allowed && projected-now >= min_lead {
+            let ttl = self.ttl_bucket(projected-now);
+            let d = self.raw_delay_distribution(self.delay_bucket(stop_info.delay, ttl), is_departure, c.product_type, ttl);
+            Some((d.mean+projected as types::MFloat, d.variance()))
+        } else {
+            None
+        };
+        let o = self.online_correction.as_mut().unwrap();
+        if !stop_info.in_out_allowed {
+            o.discard(key);
+        } else if projected <= now {
+            o.observe(key, projected, now);
+        } else if let Some((mean, variance)) = prediction {
+            o.predict(key, c.product_type, mean, variance, now);
+        }
     }
 
     #[inline(always)]
@@ -368,9 +684,43 @@ impl Store {
         && key.to_product_type < PRODUCT_TYPES_NUM
     }
 
+    // The online correction is applied like in OnlineCorrection::apply, blending the two neighbouring integer shifts.
     #[inline]
     pub fn before_probability(&mut self, from: &connection::StopInfo, from_product_type: i16, from_is_departure: bool, to: &connection::StopInfo, to_product_type: i16, transfer_time: i32, now: types::Mtime) -> types::MFloat {
-        let diff = (to.projected()-from.projected()-transfer_time) as i16;
+        if from.min_delay.is_some() || to.min_delay.is_some() {
+            return self.bounded_before_probability(from, from_product_type, from_is_departure, to, to_product_type, transfer_time, now);
+        }
+        let diff = to.projected()-from.projected()-transfer_time;
+        let shift = self.online_shift(to_product_type)-self.online_shift(from_product_type);
+        if shift == 0.0 {
+            return self.shifted_before_probability(from, from_product_type, from_is_departure, to, to_product_type, diff, now);
+        }
+        let lower = shift.floor();
+        let fraction = shift-lower;
+        let p = self.shifted_before_probability(from, from_product_type, from_is_departure, to, to_product_type, diff+lower as i32, now);
+        if fraction == 0.0 {
+            return p;
+        }
+        p*(1.0-fraction)+self.shifted_before_probability(from, from_product_type, from_is_departure, to, to_product_type, diff+lower as i32+1, now)*fraction
+    }
+
+    // Not cached, since the conditioned distributions depend on the lower bounds.
+    fn bounded_before_probability(&self, from: &connection::StopInfo, from_product_type: i16, from_is_departure: bool, to: &connection::StopInfo, to_product_type: i16, transfer_time: i32, now: types::Mtime) -> types::MFloat {
+        if !from_is_departure && (!from.in_out_allowed || !to.in_out_allowed) {
+            return 0.0;
+        }
+        let a = self.delay_distribution(from, from_is_departure, from_product_type, now);
+        let d = self.delay_distribution(to, true, to_product_type, now);
+        let mut p = a.before_probability(&d, transfer_time);
+        if !from_is_departure {
+            p *= d.feasible_probability;
+        }
+        p
+    }
+
+    #[inline(always)]
+    fn shifted_before_probability(&mut self, from: &connection::StopInfo, from_product_type: i16, from_is_departure: bool, to: &connection::StopInfo, to_product_type: i16, diff: i32, now: types::Mtime) -> types::MFloat {
+        let diff = diff as i16;
         if diff < self.min_delay_diff {
             return 0.0;
         } else if !from_is_departure && (!from.in_out_allowed || !to.in_out_allowed) {
@@ -429,11 +779,73 @@ impl Store {
     pub fn between_probability_conn(&mut self, c: &connection::Connection, lower: types::Mtime, upper: types::Mtime, now: types::Mtime) -> types::MFloat {
         let ttl = self.ttl_bucket(c.arrival.projected()-now);
         let d = self.raw_delay_distribution(self.delay_bucket(c.arrival.delay, ttl), false, c.product_type, ttl);
-        let mut cum = 0.0;
-        for i in std::cmp::max(0, lower-d.start)..std::cmp::min(upper-d.start, d.histogram.len() as i32) {
-            cum += d.histogram[i as usize];
+        let cum = |lower: types::Mtime, upper: types::Mtime, shift: types::Mtime| {
+            let mut cum = 0.0;
+            for i in std::cmp::max(0, lower-shift-d.start)..std::cmp::min(upper-shift-d.start, d.histogram.len() as i32) {
+                cum += d.histogram[i as usize];
+            }
+            cum
+        };
+        let shift = self.online_shift(c.product_type);
+        let lower_shift = shift.floor();
+        let fraction = shift-lower_shift;
+        let shifted_cum = |lower: types::Mtime, upper: types::Mtime| {
+            if fraction == 0.0 {
+                return cum(lower, upper, lower_shift as types::Mtime);
+            }
+            cum(lower, upper, lower_shift as types::Mtime)*(1.0-fraction)+cum(lower, upper, lower_shift as types::Mtime+1)*fraction
+        };
+        // condition on the lower bound the same way Distribution::at_least does, relative to the projected arrival
+        if let Some(min_delay) = c.arrival.min_delay {
+            let cut = c.arrival.scheduled+min_delay as types::Mtime-c.arrival.projected();
+            if cut > d.start+lower_shift as types::Mtime {
+                let rest = shifted_cum(cut, types::Mtime::MAX/2);
+                if rest <= 0.0 {
+                    return if lower <= cut && cut < upper { 1.0 } else { 0.0 };
+                }
+                return shifted_cum(std::cmp::max(lower, cut), upper)/rest;
+            }
+        }
+        shifted_cum(lower, upper)
+    }
+
+    pub fn set_walking_profile(&mut self, profile: WalkingProfile) {
+        self.walking_distributions = (0..=MAX_WALKING_MINUTES*self.ticks_per_minute as u16).map(|d| profile.duration_distribution(d, self.ticks_per_minute)).collect();
+        self.walking_profile = Some(profile);
+    }
+
+    pub fn walking_profile(&self) -> Option<&WalkingProfile> {
+        self.walking_profile.as_ref()
+    }
+
+    pub fn walking_distribution(&self, duration: u16) -> distribution::Distribution {
+        match self.walking_distributions.get(duration as usize) {
+            Some(d) => d.clone(),
+            None => match self.walking_profile {
+                Some(p) => p.duration_distribution(duration, self.ticks_per_minute),
+                None => distribution::Distribution::uniform(duration as types::Mtime, 1)
+            }
+        }
+    }
+
+    pub fn footpath_before_probability(&mut self, from: &connection::StopInfo, from_product_type: i16, to: &connection::StopInfo, to_product_type: i16, duration: u16, now: types::Mtime) -> types::MFloat {
+        if self.walking_profile.is_none() {
+            return self.before_probability(from, from_product_type, false, to, to_product_type, duration as i32, now);
+        }
+        if !from.in_out_allowed || !to.in_out_allowed {
+            return 0.0;
+        }
+        let a = self.footpath_arrival_distribution(from, from_product_type, duration, now);
+        let d = self.delay_distribution(to, true, to_product_type, now);
+        a.before_probability(&d, 0)*d.feasible_probability
+    }
+
+    pub fn footpath_arrival_distribution(&self, arrival: &connection::StopInfo, product_type: i16, duration: u16, now: types::Mtime) -> distribution::Distribution {
+        let d = self.delay_distribution(arrival, false, product_type, now);
+        if self.walking_profile.is_none() {
+            return d.shift(duration as types::Mtime);
         }
-        cum
+        d.convolve(&self.walking_distribution(duration))
     }
 
     pub fn nonnegative(&mut self) {
@@ -489,6 +901,22 @@ mod tests {
         assert_eq!(d.histogram.len(), 3);
     }
 
+    #[test]
+    fn distribution_with_min_delay() {
+        let mut s = setup();
+        let mut stop_info = connection::StopInfo::new(55, Some(7));
+        stop_info.min_delay = Some(6);
+        let d = s.delay_distribution(&stop_info, true, 1, 21);
+        assert_eq!(d.start, 61);
+        assert_eq!(d.mean, 61.5);
+        assert_eq!(d.histogram.len(), 2);
+        assert_eq!(s.delay_distribution_mean(&stop_info, true, 1, 21), 61.5);
+        let to = connection::StopInfo::new(55, Some(7));
+        let p = s.before_probability(&stop_info, 1, true, &to, 1, 0, 21);
+        assert_float_absolute_eq!(p, d.before_probability(&s.delay_distribution(&to, true, 1, 21), 0), 1e-4);
+        assert!(p < s.before_probability(&to, 1, true, &to, 1, 0, 21));
+    }
+
     #[test]
     fn distribution_with_nonexistant_delay() {
         let s = setup();
@@ -518,31 +946,45 @@ mod tests {
 
     #[test]
     fn parse_bucket_normal() {
-        let s = Store::parse_bucket("[5,10)");
+        let s = Store::parse_bucket("[5,10)").unwrap();
         assert_eq!(s, 5..10);
     }
 
     #[test]
     fn parse_bucket_open_right() {
-        let s = Store::parse_bucket("[5,)");
+        let s = Store::parse_bucket("[5,)").unwrap();
         assert_eq!(s, 5..5);
     }
 
     #[test]
     fn parse_bucket_open_left() {
-        let s = Store::parse_bucket("(,10)");
+        let s = Store::parse_bucket("(,10)").unwrap();
         assert_eq!(s, 10..10);
     }
 
     #[test]
     fn parse_bucket_empty() {
-        let s = Store::parse_bucket("(0,0)");
+        let s = Store::parse_bucket("(0,0)").unwrap();
         assert_eq!(s, 0..0);
     }
 
+    #[test]
+    fn parse_bucket_brackets() {
+        assert_eq!(Store::parse_bucket("(5,10]").unwrap(), 5..10);
+        assert_eq!(Store::parse_bucket("[5, 10]").unwrap(), 5..10);
+        assert_eq!(Store::parse_bucket("(-5,)").unwrap(), -5..-5);
+    }
+
+    #[test]
+    fn parse_bucket_invalid() {
+        assert!(Store::parse_bucket("5").is_err());
+        assert!(Store::parse_bucket("[5,10,15)").is_err());
+        assert!(Store::parse_bucket("[a,10)").is_err());
+    }
+
     #[test]
     fn parse_bucket_null() {
-        let s = Store::parse_bucket("NULL");
+        let s = Store::parse_bucket("NULL").unwrap();
         assert_eq!(s, 0..0);
     }
 
@@ -559,5 +1001,200 @@ mod tests {
         assert_float_absolute_eq!(d.mean, 133.21875);
         assert_eq!(d.histogram.len(), 136);
     }
-   
-}
\ No newline at end of file
+
+    fn write_csv(name: &str, content: &str) -> String {
+        let path = std::env::temp_dir().join(name);
+        std::fs::write(&path, content).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn load_distributions_schema_bounds() {
+        let path = write_csv("stost_schema_bounds.csv", "dep;product;ttl_from;ttl_to;delay_from;delay_to;latest_from;latest_to;n\n\
+            yes;1;;;;;2;4;60\n\
+            no;1;;;;;0;1;100\n\
+            yes;1;;;;;0;2;40\n\
+            yes;1;;;;;;;25\n\
+            yes;1;;;;;2;4;0\n");
+        let schema = CsvSchema {
+            product_type: "product".to_string(),
+            is_departure: "dep".to_string(),
+            prior_ttl: BucketColumns::Bounds("ttl_from".to_string(), "ttl_to".to_string()),
+            prior_delay: BucketColumns::Bounds("delay_from".to_string(), "delay_to".to_string()),
+            latest_sample_delay: BucketColumns::Bounds("latest_from".to_string(), "latest_to".to_string()),
+            sample_count: "n".to_string(),
+            true_values: vec!["yes".to_string()],
+            delimiter: ';'
+        };
+        let mut s = Store::new();
+        let report = s.load_distributions_with_schema(&path, &schema);
+        assert_eq!(report.rows, 5);
+        assert_eq!(report.distributions, 2);
+        assert!(report.unsorted);
+        assert_eq!(report.duplicates, 1);
+        let d = s.delay_distribution(&connection::StopInfo::new(0, None), true, 1, 0);
+        assert_eq!(d.start, 0);
+        assert_eq!(d.histogram.len(), 4);
+        assert_float_absolute_eq!(d.histogram[0], 0.2);
+        assert_float_absolute_eq!(d.histogram[3], 0.3);
+        assert_float_absolute_eq!(d.feasible_probability, 0.8);
+    }
+
+    #[test]
+    fn load_distributions_problems() {
+        let path = write_csv("stost_schema_problems.csv", "product_type_id,is_departure,prior_ttl_bucket,prior_delay_bucket,latest_sample_delay_bucket,sample_count\n\
+            1,True,NULL,NULL,\"[0,2)\",100\n\
+            1,True,NULL,NULL,\"[1,3)\",100\n\
+            x,True,NULL,NULL,\"[1,3)\",100\n\
+            2,True,NULL,NULL,\"[1,3)\",-1\n\
+            3,True,NULL,NULL,\"[1,3)\",99\n");
+        let mut s = Store::new();
+        let report = s.load_distributions_with_schema(&path, &CsvSchema::default());
+        assert_eq!(report.distributions, 0);
+        assert_eq!(report.skipped, 1);
+        assert!(!report.unsorted);
+        assert_eq!(report.problems.len(), 3);
+        assert!(report.problems[0].starts_with("line 4"));
+        assert!(report.problems[1].starts_with("line 5"));
+        assert!(report.problems[2].contains("overlapping"));
+    }
+
+    #[test]
+    fn load_distributions_invalid_buckets() {
+        let path = write_csv("stost_schema_invalid.csv", "product_type_id,is_departure,prior_ttl_bucket,prior_delay_bucket,latest_sample_delay_bucket,sample_count\n\
+            1,True,NULL,NULL,\"[0,2)\",100\n\
+            1,True,NULL,NULL,\"[5,3)\",100\n\
+            2,True,NULL,\"[4,2)\",\"[0,2)\",100\n\
+            3,True,NULL,NULL,\"[0,2)\",2147483647\n\
+            3,True,NULL,NULL,\"[2,4)\",1\n\
+            4,True,NULL,5,\"[0,2)\",100\n");
+        let mut s = Store::new();
+        let report = s.load_distributions_with_schema(&path, &CsvSchema::default());
+        assert_eq!(report.distributions, 2);
+        assert_eq!(report.problems.len(), 4);
+        assert!(report.problems[0].starts_with("line 3") && report.problems[0].contains("starts after its end"));
+        assert!(report.problems[1].starts_with("line 4"));
+        assert!(report.problems[2].contains("overflow"));
+        assert_eq!(report.problems[3], "line 7: invalid bucket 5");
+        assert_float_absolute_eq!(s.delay_distribution(&connection::StopInfo::new(0, None), true, 1, 0).histogram[1], 0.5);
+    }
+
+    #[test]
+    fn load_distributions_missing_columns() {
+        let path = write_csv("stost_schema_missing.csv", "product_type_id,is_departure,delay_bucket,sample_count\n1,True,\"[0,2)\",100\n");
+        let mut s = Store::new();
+        let report = s.load_distributions_with_schema(&path, &CsvSchema::default());
+        assert_eq!(report.rows, 0);
+        assert_eq!(report.problems.len(), 1);
+        assert!(report.problems[0].contains("prior_ttl_bucket, prior_delay_bucket, latest_sample_delay_bucket"));
+    }
+
+    #[test]
+    fn walking_profile_distribution() {
+        let default = WalkingProfile::default().duration_distribution(10, 1);
+        let wheelchair = WalkingProfile::named("wheelchair").unwrap().duration_distribution(10, 1);
+        default.assert();
+        wheelchair.assert();
+        assert!(default.mean >= 10.0 && default.mean < 11.0);
+        assert!(wheelchair.mean > default.mean);
+        assert!(wheelchair.histogram.len() > default.histogram.len());
+        assert_eq!(WalkingProfile::default().duration_distribution(0, 1).start, 0);
+    }
+
+    #[test]
+    fn walking_profile_config() {
+        let named: crate::walking::WalkingProfileConfig = serde_json::from_str("\"wheelchair\"").unwrap();
+        assert_eq!(named.profile(), WalkingProfile::named("wheelchair"));
+        let custom: crate::walking::WalkingProfileConfig = serde_json::from_str("{\"metres_per_second\": 1.1, \"relative_spread\": 0.25, \"min_spread_minutes\": 1.0, \"overhead_minutes\": 0.5}").unwrap();
+        assert_eq!(custom.profile().unwrap().metres_per_second, 1.1);
+        assert_eq!(custom.profile().unwrap().overhead_minutes, 0.5);
+        let unknown: crate::walking::WalkingProfileConfig = serde_json::from_str("\"running\"").unwrap();
+        assert!(unknown.profile().is_none());
+    }
+
+    #[test]
+    fn footpath_before_probability_without_profile() {
+        let mut s = Store::new();
+        let arr = connection::StopInfo::new(10, None);
+        let dep = connection::StopInfo::new(20, None);
+        assert_float_absolute_eq!(s.footpath_before_probability(&arr, 1, &dep, 1, 10, 0), s.before_probability(&arr, 1, false, &dep, 1, 10, 0));
+        assert_float_absolute_eq!(s.footpath_before_probability(&arr, 1, &dep, 1, 10, 0), 1.0);
+    }
+
+    #[test]
+    fn footpath_before_probability_with_profile() {
+        let mut s = Store::new();
+        s.set_walking_profile(WalkingProfile::named("slow").unwrap());
+        let arr = connection::StopInfo::new(10, None);
+        let p = s.footpath_before_probability(&arr, 1, &connection::StopInfo::new(20, None), 1, 6, 0);
+        assert!(p > 0.0 && p < 1.0);
+        let p_later = s.footpath_before_probability(&arr, 1, &connection::StopInfo::new(25, None), 1, 6, 0);
+        assert!(p_later > p);
+
+        // the convolved arrival agrees with weighting each walking duration
+        let mut s = setup();
+        s.set_walking_profile(WalkingProfile::named("slow").unwrap());
+        let arr = connection::StopInfo::new(55, Some(7));
+        let dep = connection::StopInfo::new(65, Some(0));
+        let walking = s.walking_distribution(6);
+        let expected: types::MFloat = walking.histogram.iter().enumerate().map(|(i, w)| w*s.before_probability(&arr, 1, false, &dep, 2, walking.start+i as i32, 0)).sum();
+        assert_float_absolute_eq!(s.footpath_before_probability(&arr, 1, &dep, 2, 6, 0), expected, 1e-4);
+    }
+
+    #[test]
+    fn online_correction() {
+        let mut s = setup();
+        let stop_info = connection::StopInfo::new(55, Some(7));
+        s.observe_update(&connection::Connection::new(0, 0, 1, 0, false, 0, 55, Some(7), 1, 60, Some(7)), true, 21);
+        assert!(s.online_correction().is_none());
+        s.enable_online_correction(OnlineCorrectionConfig::default());
+        let mut c = connection::Connection::new(0, 0, 1, 0, false, 0, 55, Some(7), 1, 60, Some(7));
+        s.observe_update(&c, true, 21);
+        c.update(true, None, None, Some(12));
+        s.observe_update(&c, true, 70);
+        assert_eq!(s.online_correction().unwrap().observations(1), 1);
+        assert_float_absolute_eq!(s.delay_distribution_mean(&stop_info, true, 1, 21), 65.8, 1e-4);
+        let d = s.delay_distribution(&stop_info, true, 1, 21);
+        d.assert();
+        assert_float_absolute_eq!(d.mean, 65.8, 1e-4);
+        assert_float_absolute_eq!(d.mean(), 65.8, 1e-4);
+        assert_eq!(s.delay_distribution(&stop_info, true, 2, 21).mean, 62.0);
+    }
+
+    #[test]
+    fn online_correction_consistent() {
+        let mut s = setup();
+        s.enable_online_correction(OnlineCorrectionConfig::default());
+        let mut c = connection::Connection::new(0, 0, 1, 0, false, 0, 55, Some(7), 1, 60, Some(7));
+        s.observe_update(&c, true, 21);
+        c.update(true, None, None, Some(12));
+        s.observe_update(&c, true, 70);
+        let shift = s.online_correction().unwrap().shift(1);
+        assert!(shift.fract() != 0.0);
+        // the arrival of c as blended by delay_distribution
+        let arrival = connection::Connection::new(1, 0, 1, 0, false, 0, 45, Some(7), 1, 55, Some(7));
+        let d = s.delay_distribution(&arrival.arrival, false, 1, 21);
+        for (lower, upper) in [(55, 60), (60, 65), (63, 64), (0, 100)] {
+            let expected: types::MFloat = (lower..upper).map(|t| d.histogram.get((t-d.start) as usize).copied().unwrap_or(0.0)).sum();
+            assert_float_absolute_eq!(s.between_probability_conn(&arrival, lower, upper, 21), expected, 1e-4);
+        }
+        // against an uncorrected product type the departure has to be earlier than the blended arrival
+        let dep = connection::StopInfo::new(65, Some(0));
+        let p = s.before_probability(&arrival.arrival, 1, false, &dep, 2, 0, 21);
+        let to = s.delay_distribution(&dep, true, 2, 21);
+        assert_float_absolute_eq!(p, d.before_probability(&to, 0)*to.feasible_probability, 1e-4);
+    }
+
+    #[test]
+    fn footpath_arrival_distribution() {
+        let mut s = Store::new();
+        let arr = connection::StopInfo::new(10, None);
+        assert_float_absolute_eq!(s.footpath_arrival_distribution(&arr, 1, 5, 0).mean, 15.0);
+        s.set_walking_profile(WalkingProfile::named("luggage").unwrap());
+        let d = s.footpath_arrival_distribution(&arr, 1, 5, 0);
+        d.assert();
+        assert_float_absolute_eq!(d.feasible_probability, 1.0);
+        assert!(d.mean > 15.0);
+        assert_eq!(s.footpath_arrival_distribution(&arr, 1, MAX_WALKING_MINUTES+20, 0).start, s.walking_distribution(MAX_WALKING_MINUTES+20).start+10);
+    }
+}
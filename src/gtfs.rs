@@ -1,33 +1,53 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+#[cfg(feature = "nigiri")]
 use chrono;
+#[cfg(feature = "nigiri")]
 use motis_nigiri::Timetable;
-use crate::connection::{self, Route};
+use crate::connection;
+use crate::types;
 use rustc_hash::FxHashSet;
 use serde::{Serialize, Deserialize};
 use rand::Rng;
+use rand::distributions::{Distribution, WeightedIndex};
+use crate::walking;
 
-#[derive(Serialize, Deserialize, Debug)]
+#[cfg(feature = "native-gtfs")]
+pub mod native;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct GtfsTimetable {
     pub stations: Vec<connection::Station>,
     pub connections: Vec<connection::Connection>,
     pub cut: FxHashSet<(usize, usize)>,
     pub order: Vec<usize>,
-    pub transport_and_day_to_connection_id: HashMap<(usize, u16), usize>
+    pub transport_and_day_to_connection_id: HashMap<(usize, u16), usize>,
+    #[serde(default = "types::one_tick_per_minute")]
+    pub ticks_per_minute: types::Mtime,
+    // number of connections without the final footpaths csameat appends for decision graphs, set by the first csameat environment
+    #[serde(default)]
+    pub base_len: Option<usize>
 }
 
 impl GtfsTimetable {
     pub fn new() -> GtfsTimetable {
+        Self::with_ticks_per_minute(types::one_tick_per_minute())
+    }
+
+    pub fn with_ticks_per_minute(ticks_per_minute: types::Mtime) -> GtfsTimetable {
+        types::assert_ticks_per_minute(ticks_per_minute);
         GtfsTimetable {
             stations: vec![],
             connections: vec![],
             cut: FxHashSet::default(),
             order: vec![],
-            transport_and_day_to_connection_id: HashMap::new()
+            transport_and_day_to_connection_id: HashMap::new(),
+            ticks_per_minute,
+            base_len: None
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct StationContraction {
     pub stop_to_group: Vec<usize>,
     pub stop_to_group_idx: Vec<usize>,
@@ -42,30 +62,34 @@ impl StationContraction {
     }
 }
 
+#[cfg(feature = "nigiri")]
 pub fn load_timetable<'a, 'b>(gtfs_path: &str, start_date: chrono::NaiveDate, end_date: chrono::NaiveDate) -> Timetable {
     Timetable::load_linking_stops(gtfs_path, start_date, end_date, 0)
 }
 
-pub fn retrieve<'a, 'b>(t: &Timetable, stations: &'a mut Vec<connection::Station>, routes: &'a mut Vec<connection::Route>, connections: &'b mut Vec<connection::Connection>) -> HashMap<(usize, u16), usize> {
+#[cfg(feature = "nigiri")]
+pub fn retrieve<'a, 'b>(t: &Timetable, stations: &'a mut Vec<connection::Station>, routes: &'a mut Vec<connection::Route>, connections: &'b mut Vec<connection::Connection>, ticks_per_minute: types::Mtime) -> HashMap<(usize, u16), usize> {
     let gtfs_locations = t.get_locations();
-    for mut l in gtfs_locations {
-        let mut station = connection::Station {
+    for l in gtfs_locations {
+        let station = connection::Station {
             id: l.id.to_string(), 
             name: l.name.to_string(),
             arrivals: vec![],
             departures: vec![],
             lat: l.lat,
             lon: l.lon,
-            transfer_time: l.transfer_time,
+            transfer_time: l.transfer_time*ticks_per_minute as u16,
             parent_idx: l.parent_idx,
-            footpaths: vec![]
+            footpaths: l.footpaths.iter().map(|f| connection::Footpath {
+                target_location_idx: f.target_location_idx,
+                duration: f.duration*ticks_per_minute as u16
+            }).collect()
         };
-        station.footpaths.append(&mut l.footpaths);
         stations.push(station);
     }
     let gtfs_routes = t.get_routes();
     for r in gtfs_routes {
-        let mut route = Route::new(r.route_idx.to_string(), "".to_string(), r.clasz as i16);
+        let mut route = connection::Route::new(r.route_idx.to_string(), "".to_string(), r.clasz as i16);
         route.direction = r.stops.last().map(|s| stations[s.location_idx].name.clone()).unwrap_or_default(); // TODO proper directions from GTFS
         routes.push(route);
     }
@@ -81,8 +105,8 @@ pub fn retrieve<'a, 'b>(t: &Timetable, stations: &'a mut Vec<connection::Station
         let to_idx = c.to_idx.try_into().unwrap();
         let mut conn = connection::Connection::new(
             id, c.route_idx.try_into().unwrap(), route.product_type, c.trip_id.try_into().unwrap(), false,
-            from_idx, c.departure.try_into().unwrap(), None,
-            to_idx, c.arrival.try_into().unwrap(), None
+            from_idx, types::minutes(c.departure.try_into().unwrap(), ticks_per_minute), None,
+            to_idx, types::minutes(c.arrival.try_into().unwrap(), ticks_per_minute), None
         );
         conn.departure.in_out_allowed = c.in_allowed;
         conn.arrival.in_out_allowed = c.out_allowed;
@@ -138,19 +162,65 @@ pub fn get_station_contraction(stations: &[connection::Station]) -> StationContr
     contr
 }
 
+#[cfg(feature = "nigiri")]
 fn to_connecion_id(e: &motis_nigiri::EventChange, transport_and_day_to_connection_id: &HashMap<(usize, u16), usize>) -> usize {
     let dep_offset = if e.is_departure { 0 } else { 1 };
     let initial_connection_of_transport = transport_and_day_to_connection_id[&(e.transport_idx, e.day_idx)];
     initial_connection_of_transport-dep_offset+e.stop_idx as usize
 }
 
-pub fn load_realtime<F: FnMut(usize, bool, Option<usize>, Option<bool>, Option<i16>)>(gtfsrt_path: &str, t: &Timetable, transport_and_day_to_connection_id: &HashMap<(usize, u16), usize>, mut callback: F) {
-    t.update_with_rt(gtfsrt_path, |e| callback(to_connecion_id(&e, transport_and_day_to_connection_id), e.is_departure, e.location_idx, e.in_out_allowed, e.delay));
+#[cfg(feature = "nigiri")]
+pub fn load_realtime<F: FnMut(usize, bool, Option<usize>, Option<bool>, Option<i16>)>(gtfsrt_path: &str, t: &Timetable, transport_and_day_to_connection_id: &HashMap<(usize, u16), usize>, ticks_per_minute: types::Mtime, mut callback: F) {
+    t.update_with_rt(gtfsrt_path, |e| callback(to_connecion_id(&e, transport_and_day_to_connection_id), e.is_departure, e.location_idx, e.in_out_allowed, e.delay.map(|d| d*ticks_per_minute as i16)));
+}
+
+// The GTFS-RT of a nigiri timetable as parsed by gtfs::native, i.e. with cancellations, skipped stops, vehicle positions and
+// alerts, on the nigiri connections of the trips matched by native::match_connections. ADDED trips are not supported.
+#[cfg(all(feature = "nigiri", feature = "native-gtfs"))]
+pub struct NativeRealtime {
+    timetable: native::Timetable,
+    transport_and_day_to_connection_id: HashMap<(usize, u16), usize>,
+    reference_ts: i64
+}
+
+#[cfg(all(feature = "nigiri", feature = "native-gtfs"))]
+impl NativeRealtime {
+    // The native timetable should cover the service days of the nigiri timetable, including the day before for trips running after midnight.
+    pub fn new(gtfs_path: &str, start_date: chrono::NaiveDate, end_date: chrono::NaiveDate, t: &Timetable, tt: &GtfsTimetable) -> NativeRealtime {
+        let timetable = native::load_timetable(gtfs_path, start_date, end_date, tt.ticks_per_minute);
+        let reference_ts = t.get_start_day_ts();
+        NativeRealtime {
+            transport_and_day_to_connection_id: native::match_connections(&timetable, &tt.stations, &tt.connections, &tt.transport_and_day_to_connection_id, reference_ts),
+            timetable,
+            reference_ts
+        }
+    }
+
+    // Like load_realtime, but nigiri only updates its own state for RAPTOR, while the callback gets the events of native::apply_realtime.
+    pub fn load_realtime<F: FnMut(usize, bool, Option<usize>, Option<bool>, Option<i16>)>(&self, gtfsrt_path: &str, t: &Timetable, callback: F) {
+        t.update_with_rt(gtfsrt_path, |_| {});
+        let bytes = std::fs::read(gtfsrt_path).unwrap();
+        native::apply_realtime(&bytes, &self.timetable, &self.transport_and_day_to_connection_id, callback);
+    }
+
+    // The lower bounds of native::apply_vehicle_positions, for Queriable::update_min_delay.
+    pub fn load_vehicle_positions<F: FnMut(usize, bool, i16)>(&self, gtfsrt_path: &str, callback: F) -> usize {
+        let bytes = std::fs::read(gtfsrt_path).unwrap();
+        native::apply_vehicle_positions(&bytes, &self.timetable, &self.transport_and_day_to_connection_id, callback)
+    }
+
+    // nigiri routes are not GTFS routes, so alerts on whole routes are set on the connections of their trips.
+    pub fn apply_alerts(&self, gtfsrt_path: &str, stations: &[connection::Station], connections: &mut [connection::Connection]) -> usize {
+        let bytes = std::fs::read(gtfsrt_path).unwrap();
+        native::apply_alerts(&bytes, &self.timetable, &self.transport_and_day_to_connection_id, stations, None, connections, self.reference_ts)
+    }
 }
 
-pub fn load_gtfs_cache(cache_path: &str) -> GtfsTimetable {
+pub fn load_gtfs_cache(cache_path: &str, ticks_per_minute: types::Mtime) -> GtfsTimetable {
     let buf = std::fs::read(cache_path).unwrap();
-    rmp_serde::from_slice(&buf).unwrap()
+    let tt: GtfsTimetable = rmp_serde::from_slice(&buf).unwrap();
+    assert_eq!(tt.ticks_per_minute, ticks_per_minute, "timetable cache was saved with a different time resolution");
+    tt
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -158,12 +228,21 @@ pub struct OriginDestinationSample {
     pub from_idx: usize,
     pub from_id: String,
     pub to_idx: usize,
-    pub to_id: String
+    pub to_id: String,
+    #[serde(default)]
+    pub stratum: String
 }
 
-fn get_rand_conn_idx(connection_count: usize) -> usize {
-    let mut rng = rand::thread_rng();
-    rng.gen_range(0..connection_count)
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct SamplingConfig {
+    pub samples: usize,
+    // (min_lat, min_lon, max_lat, max_lon)
+    pub bbox: Option<(f64, f64, f64, f64)>,
+    // upper bounds of the straight-line distance bands in km, the last band is open
+    pub distance_bands: Vec<f64>,
+    // upper bounds of the importance bands in departures of the less served stop of a pair, the last band is open
+    pub importance_bands: Vec<usize>
 }
 
 fn in_bbox(lon: f64, lat: f64, bbox: Option<(f64, f64, f64, f64)>) -> bool {
@@ -173,34 +252,111 @@ fn in_bbox(lon: f64, lat: f64, bbox: Option<(f64, f64, f64, f64)>) -> bool {
     return true;
 }
 
-fn get_rand_stop_idx(connections: &[connection::Connection], stations: &[connection::Station], from: bool, bbox: Option<(f64, f64, f64, f64)>) -> usize {
-    loop {
-        let c = &connections[get_rand_conn_idx(connections.len())];
-        let s_idx = if from {c.from_idx} else {c.to_idx};
-        let s = &stations[s_idx];
-        if in_bbox(s.lon, s.lat, bbox) {
-            return s_idx;
+fn get_rand_stop_idx<R: Rng>(connections: &[connection::Connection], stations: &[connection::Station], from: bool, bbox: Option<(f64, f64, f64, f64)>, rng: &mut R) -> Option<usize> {
+    let c = &connections[rng.gen_range(0..connections.len())];
+    let s_idx = if from {c.from_idx} else {c.to_idx};
+    let s = &stations[s_idx];
+    if in_bbox(s.lon, s.lat, bbox) {
+        Some(s_idx)
+    } else {
+        None
+    }
+}
+
+fn band<T: PartialOrd + std::fmt::Display>(bands: &[T], value: T, unit: &str) -> String {
+    match bands.iter().position(|b| value < *b) {
+        Some(0) => format!("0-{}{}", bands[0], unit),
+        Some(i) => format!("{}-{}{}", bands[i-1], bands[i], unit),
+        None => format!("{}+{}", bands[bands.len()-1], unit)
+    }
+}
+
+fn stratum(conf: &SamplingConfig, stations: &[connection::Station], departures: &[usize], from: usize, to: usize) -> String {
+    let mut s = vec![];
+    if !conf.distance_bands.is_empty() {
+        s.push(band(&conf.distance_bands, walking::geodist_meters(&stations[from], &stations[to])/1000.0, "km"));
+    }
+    if !conf.importance_bands.is_empty() {
+        s.push(band(&conf.importance_bands, departures[from].min(departures[to]), "dep"));
+    }
+    s.join("/")
+}
+
+// Loads a demand matrix as CSV with the columns from_id,to_id,weight. Unknown stops are skipped.
+pub fn load_demand(path: &str, stations: &[connection::Station]) -> Vec<(usize, usize, f64)> {
+    let ids: HashMap<&str, usize> = stations.iter().enumerate().map(|(i, s)| (s.id.as_str(), i)).collect();
+    let mut rdr = csv::Reader::from_path(path).expect("demand file not readable");
+    let mut demand = vec![];
+    for record in rdr.deserialize::<(String, String, f64)>() {
+        let (from_id, to_id, weight) = record.expect("demand file malformed");
+        if let (Some(from), Some(to)) = (ids.get(from_id.as_str()), ids.get(to_id.as_str())) {
+            if weight > 0.0 {
+                demand.push((*from, *to, weight));
+            }
         }
     }
+    demand
 }
 
-pub fn create_simulation_samples(gtfs_path: &str, start_date: chrono::NaiveDate, end_date: chrono::NaiveDate, bbox: Option<(f64, f64, f64, f64)>) -> Vec<OriginDestinationSample> {
-    let t = load_timetable(gtfs_path, start_date, end_date);
-    let mut tt = GtfsTimetable {
-        stations: vec![],
-        connections: vec![],
-        cut: FxHashSet::default(),
-        order: vec![],
-        transport_and_day_to_connection_id: HashMap::new()
-    };
-    let mut routes = vec![];
-    retrieve(&t, &mut tt.stations, &mut routes, &mut tt.connections);
-    let sample_count = 10000;
+// Draws at most this many candidate pairs per requested sample, which also bounds the calls of reachable.
+const CANDIDATES_PER_SAMPLE: usize = 20;
+
+// Draws origins and destinations proportionally to the number of departures at a stop, or to the demand if given,
+// with an equal share for each stratum of distance and importance bands. Pairs of the same stop and pairs that are
+// not reachable are rejected. The samples are accepted round-robin across the strata, so that any prefix is stratified
+// as well and the share of strata that are empty or run out of reachable candidates goes to the others.
+// Returns the samples and the number of samples that could not be found.
+pub fn sample_origin_destinations<R: Rng, F: FnMut(usize, usize) -> bool>(connections: &[connection::Connection], stations: &[connection::Station], conf: &SamplingConfig, demand: Option<&[(usize, usize, f64)]>, mut reachable: F, rng: &mut R) -> (Vec<OriginDestinationSample>, usize) {
+    let mut departures = vec![0; stations.len()];
+    for c in connections {
+        departures[c.from_idx] += 1;
+    }
+    let demand: Option<Vec<&(usize, usize, f64)>> = demand.map(|d| d.iter().filter(|(from, to, _)| in_bbox(stations[*from].lon, stations[*from].lat, conf.bbox) && in_bbox(stations[*to].lon, stations[*to].lat, conf.bbox)).collect());
+    let weights = demand.as_ref().map(|d| WeightedIndex::new(d.iter().map(|(_, _, w)| *w)).expect("no demand within bbox"));
+    let mut candidates: BTreeMap<String, VecDeque<(usize, usize)>> = BTreeMap::new();
+    for _ in 0..conf.samples*CANDIDATES_PER_SAMPLE {
+        let (origin, destination) = match (&demand, &weights) {
+            (Some(d), Some(w)) => {
+                let e = d[w.sample(rng)];
+                (e.0, e.1)
+            },
+            _ => match (get_rand_stop_idx(connections, stations, true, conf.bbox, rng), get_rand_stop_idx(connections, stations, true, conf.bbox, rng)) {
+                (Some(origin), Some(destination)) => (origin, destination),
+                _ => continue
+            }
+        };
+        if origin != destination {
+            candidates.entry(stratum(conf, stations, &departures, origin, destination)).or_default().push_back((origin, destination));
+        }
+    }
     let mut samples = vec![];
-    for _i in 0..sample_count {
-        let origin = get_rand_stop_idx(&tt.connections, &tt.stations, true, bbox);
-        let destination = get_rand_stop_idx(&tt.connections, &tt.stations, true, bbox);
-        samples.push(OriginDestinationSample {from_idx: origin, from_id: tt.stations[origin].id.clone(), to_idx: destination, to_id: tt.stations[destination].id.clone()});
+    while samples.len() < conf.samples && !candidates.is_empty() {
+        candidates.retain(|stratum, c| {
+            if samples.len() >= conf.samples {
+                return true;
+            }
+            while let Some((origin, destination)) = c.pop_front() {
+                if reachable(origin, destination) {
+                    samples.push(OriginDestinationSample {from_idx: origin, from_id: stations[origin].id.clone(), to_idx: destination, to_id: stations[destination].id.clone(), stratum: stratum.clone()});
+                    return true;
+                }
+            }
+            false
+        });
     }
-    samples
+    let shortfall = conf.samples-samples.len();
+    (samples, shortfall)
+}
+
+// reachable_at: if given, only pairs with a RAPTOR journey departing at this time (minutes since the start of the timetable)
+#[cfg(feature = "nigiri")]
+pub fn create_simulation_samples<R: Rng>(gtfs_path: &str, start_date: chrono::NaiveDate, end_date: chrono::NaiveDate, conf: &SamplingConfig, demand_path: Option<&str>, reachable_at: Option<i32>, rng: &mut R) -> (Vec<OriginDestinationSample>, usize) {
+    let t = load_timetable(gtfs_path, start_date, end_date);
+    let mut tt = GtfsTimetable::new();
+    let mut routes = vec![];
+    retrieve(&t, &mut tt.stations, &mut routes, &mut tt.connections, tt.ticks_per_minute);
+    let demand = demand_path.map(|p| load_demand(p, &tt.stations));
+    sample_origin_destinations(&tt.connections, &tt.stations, conf, demand.as_deref(), |from, to| {
+        reachable_at.map(|time| !t.get_journeys(from, to, time, false).journeys.is_empty()).unwrap_or(true)
+    }, rng)
 }
\ No newline at end of file
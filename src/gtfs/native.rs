@@ -0,0 +1,1081 @@
+use std::collections::HashMap;
+
+use chrono::{Datelike, NaiveDate, Offset, TimeZone, Weekday};
+use chrono_tz::Tz;
+use indexmap::IndexMap;
+use quick_protobuf::{BytesReader, MessageRead};
+use serde::Deserialize;
+
+use crate::connection;
+use crate::types;
+use crate::walking::WALKING_MSG;
+use crate::wire::gtfs_realtime;
+use crate::wire::gtfs_realtime::mod_TripDescriptor::ScheduleRelationship as TripRelationship;
+use crate::wire::gtfs_realtime::mod_TripUpdate::mod_StopTimeUpdate::ScheduleRelationship as StopRelationship;
+use crate::wire::gtfs_realtime::mod_VehiclePosition::VehicleStopStatus;
+
+// Minutes, as used by nigiri if transfers.txt does not specify a transfer time.
+const DEFAULT_TRANSFER_TIME: u16 = 2;
+
+#[derive(Deserialize)]
+struct AgencyRecord {
+    agency_timezone: String
+}
+
+#[derive(Deserialize)]
+struct StopRecord {
+    stop_id: String,
+    stop_name: Option<String>,
+    stop_lat: Option<f64>,
+    stop_lon: Option<f64>,
+    location_type: Option<u8>,
+    parent_station: Option<String>
+}
+
+#[derive(Deserialize)]
+struct RouteRecord {
+    route_id: String,
+    route_short_name: Option<String>,
+    route_long_name: Option<String>,
+    route_type: i32
+}
+
+#[derive(Deserialize)]
+struct TripRecord {
+    route_id: String,
+    service_id: String,
+    trip_id: String,
+    trip_headsign: Option<String>
+}
+
+#[derive(Deserialize)]
+struct StopTimeRecord {
+    trip_id: String,
+    arrival_time: Option<String>,
+    departure_time: Option<String>,
+    stop_id: String,
+    stop_sequence: u32,
+    pickup_type: Option<u8>,
+    drop_off_type: Option<u8>
+}
+
+#[derive(Deserialize)]
+struct CalendarRecord {
+    service_id: String,
+    monday: u8,
+    tuesday: u8,
+    wednesday: u8,
+    thursday: u8,
+    friday: u8,
+    saturday: u8,
+    sunday: u8,
+    start_date: String,
+    end_date: String
+}
+
+#[derive(Deserialize)]
+struct CalendarDateRecord {
+    service_id: String,
+    date: String,
+    exception_type: u8
+}
+
+#[derive(Deserialize)]
+struct TransferRecord {
+    from_stop_id: String,
+    to_stop_id: String,
+    transfer_type: Option<u8>,
+    min_transfer_time: Option<u32>
+}
+
+struct StopTime {
+    stop_idx: usize,
+    stop_sequence: u32,
+    // seconds after midnight of the service day
+    arrival: i32,
+    departure: i32,
+    in_allowed: bool,
+    out_allowed: bool
+}
+
+struct Trip {
+    route_idx: usize,
+    stop_times: Vec<StopTime>,
+    days: Vec<u16>
+}
+
+// A GTFS feed loaded without nigiri. Trips are identified by their index in trips.txt (transport_idx)
+// and service days by their offset from start_date (day_idx), so that the mapping returned by retrieve
+// has the same meaning as the one of gtfs::retrieve.
+pub struct Timetable {
+    stations: Vec<connection::Station>,
+    routes: Vec<connection::Route>,
+    trips: Vec<Trip>,
+    trip_ids: HashMap<String, usize>,
+    start_date: NaiveDate,
+    timezone: Option<Tz>,
+    utc_offset: i32,
+    ticks_per_minute: types::Mtime
+}
+
+fn read_csv<T: for<'de> Deserialize<'de>>(gtfs_path: &str, file: &str, required: bool) -> Vec<T> {
+    let path = std::path::Path::new(gtfs_path).join(file);
+    if !required && !path.exists() {
+        return vec![];
+    }
+    let mut rdr = csv::Reader::from_path(&path).unwrap_or_else(|e| panic!("{} not readable: {}", path.display(), e));
+    rdr.deserialize().map(|r| r.unwrap_or_else(|e| panic!("{} malformed: {}", path.display(), e))).collect()
+}
+
+fn parse_date(date: &str) -> NaiveDate {
+    NaiveDate::parse_from_str(date.trim(), "%Y%m%d").unwrap_or_else(|_| panic!("invalid date {}", date))
+}
+
+// HH:MM:SS, hours may exceed 24
+fn parse_time(time: &str) -> i32 {
+    let parts: Vec<i32> = time.trim().split(':').map(|p| p.parse().unwrap_or_else(|_| panic!("invalid time {}", time))).collect();
+    assert_eq!(parts.len(), 3, "invalid time {}", time);
+    parts[0]*3600+parts[1]*60+parts[2]
+}
+
+// Same classes as nigiri's clasz, which the delay distributions are keyed by.
+fn to_product_type(route_type: i32) -> i16 {
+    match route_type {
+        1100..=1199 => 0,
+        101 => 1,
+        100 | 102 | 103 | 104 => 2,
+        200..=299 => 3,
+        105 => 4,
+        106 => 6,
+        0 | 5 | 900..=999 => 9,
+        109 | 400 | 403 | 404 => 7,
+        1 | 401 | 402 => 8,
+        2 | 107 | 108 | 110..=199 => 6,
+        3 | 11 | 700..=899 => 10,
+        4 | 1000..=1099 | 1200..=1299 => 11,
+        _ => 12
+    }
+}
+
+fn ticks_ceil(seconds: u32, ticks_per_minute: types::Mtime) -> u16 {
+    let spt = types::seconds_per_tick(ticks_per_minute) as u32;
+    seconds.div_ceil(spt) as u16
+}
+
+pub fn load_timetable(gtfs_path: &str, start_date: NaiveDate, end_date: NaiveDate, ticks_per_minute: types::Mtime) -> Timetable {
+    types::assert_ticks_per_minute(ticks_per_minute);
+    let mut stop_records: Vec<StopRecord> = read_csv::<StopRecord>(gtfs_path, "stops.txt", true).into_iter()
+        .filter(|s| s.location_type.unwrap_or(0) <= 1).collect();
+    // parent stations last, since a parent_idx of 0 means no parent
+    stop_records.sort_by_key(|s| s.location_type.unwrap_or(0));
+    let stop_idx: HashMap<String, usize> = stop_records.iter().enumerate().map(|(i, s)| (s.stop_id.clone(), i)).collect();
+    let mut stations: Vec<connection::Station> = stop_records.iter().map(|s| {
+        let mut station = connection::Station::new(s.stop_id.clone(), s.stop_name.clone().unwrap_or_default(), vec![]);
+        station.lat = s.stop_lat.unwrap_or(0.);
+        station.lon = s.stop_lon.unwrap_or(0.);
+        station.transfer_time = types::minutes(DEFAULT_TRANSFER_TIME as types::Mtime, ticks_per_minute) as u16;
+        station.parent_idx = s.parent_station.as_ref().and_then(|p| stop_idx.get(p)).copied().unwrap_or(0);
+        station
+    }).collect();
+
+    for t in read_csv::<TransferRecord>(gtfs_path, "transfers.txt", false) {
+        let (from_idx, to_idx) = match (stop_idx.get(&t.from_stop_id), stop_idx.get(&t.to_stop_id)) {
+            (Some(from), Some(to)) => (*from, *to),
+            _ => continue
+        };
+        if t.transfer_type.unwrap_or(0) > 2 {
+            continue;
+        }
+        let duration = t.min_transfer_time.map(|s| ticks_ceil(s, ticks_per_minute)).unwrap_or(types::minutes(DEFAULT_TRANSFER_TIME as types::Mtime, ticks_per_minute) as u16);
+        if from_idx == to_idx {
+            stations[from_idx].transfer_time = duration;
+        } else if !stations[from_idx].footpaths.iter().any(|f| f.target_location_idx == to_idx) {
+            stations[from_idx].footpaths.push(connection::Footpath { target_location_idx: to_idx, duration });
+        }
+    }
+    // stops of the same parent station without explicit transfers
+    let mut children: HashMap<&str, Vec<usize>> = HashMap::new();
+    for (i, s) in stop_records.iter().enumerate() {
+        if let Some(p) = s.parent_station.as_deref().filter(|p| !p.is_empty()) {
+            children.entry(p).or_default().push(i);
+        }
+    }
+    for siblings in children.values() {
+        for from_idx in siblings {
+            for to_idx in siblings {
+                if from_idx != to_idx && !stations[*from_idx].footpaths.iter().any(|f| f.target_location_idx == *to_idx) {
+                    let duration = stations[*from_idx].transfer_time;
+                    stations[*from_idx].footpaths.push(connection::Footpath { target_location_idx: *to_idx, duration });
+                }
+            }
+        }
+    }
+
+    let route_records = read_csv::<RouteRecord>(gtfs_path, "routes.txt", true);
+    let route_idx: HashMap<String, usize> = route_records.iter().enumerate().map(|(i, r)| (r.route_id.clone(), i)).collect();
+    let mut routes: Vec<connection::Route> = route_records.iter().map(|r| {
+        let name = r.route_short_name.clone().filter(|n| !n.is_empty()).or(r.route_long_name.clone()).unwrap_or_default();
+        connection::Route::new(r.route_id.clone(), name, to_product_type(r.route_type))
+    }).collect();
+
+    // all agencies of a feed have the same timezone
+    let timezone = read_csv::<AgencyRecord>(gtfs_path, "agency.txt", false).first()
+        .map(|a| a.agency_timezone.trim().parse::<Tz>().unwrap_or_else(|_| panic!("invalid agency_timezone {}", a.agency_timezone)));
+    let days = service_days(gtfs_path, start_date, end_date);
+    let trip_records = read_csv::<TripRecord>(gtfs_path, "trips.txt", true);
+    let trip_ids: HashMap<String, usize> = trip_records.iter().enumerate().map(|(i, t)| (t.trip_id.clone(), i)).collect();
+    let mut stop_times: Vec<Vec<StopTimeRecord>> = trip_records.iter().map(|_| vec![]).collect();
+    for st in read_csv::<StopTimeRecord>(gtfs_path, "stop_times.txt", true) {
+        match trip_ids.get(&st.trip_id) {
+            Some(i) => stop_times[*i].push(st),
+            None => println!("stop time of unknown trip {}", st.trip_id)
+        }
+    }
+    let mut trips = vec![];
+    let mut skipped = 0;
+    for (record, mut records) in trip_records.iter().zip(stop_times) {
+        let route_idx = route_idx[&record.route_id];
+        records.sort_by_key(|st| st.stop_sequence);
+        let trip_stop_times = match to_stop_times(&records, &stop_idx) {
+            Some(st) => st,
+            None => {
+                skipped += 1;
+                vec![]
+            }
+        };
+        if routes[route_idx].direction.is_empty() {
+            routes[route_idx].direction = record.trip_headsign.clone().filter(|h| !h.is_empty())
+                .or(trip_stop_times.last().map(|st| stations[st.stop_idx].name.clone())).unwrap_or_default();
+        }
+        trips.push(Trip {
+            route_idx,
+            days: if trip_stop_times.is_empty() { vec![] } else { days.get(&record.service_id).cloned().unwrap_or_default() },
+            stop_times: trip_stop_times
+        });
+    }
+    println!("Loaded {} stops, {} routes, {} trips, skipped {} trips with invalid stop times", stations.len(), routes.len(), trips.len(), skipped);
+    Timetable {
+        stations,
+        routes,
+        trips,
+        trip_ids,
+        start_date,
+        timezone,
+        utc_offset: 0,
+        ticks_per_minute
+    }
+}
+
+// Stop times sorted by stop_sequence, with missing times interpolated between timepoints. None if the trip has fewer than two
+// stops, unknown stops or times that go backwards.
+fn to_stop_times(records: &[StopTimeRecord], stop_idx: &HashMap<String, usize>) -> Option<Vec<StopTime>> {
+    if records.len() < 2 {
+        return None;
+    }
+    let mut times: Vec<(Option<i32>, Option<i32>)> = records.iter().map(|st| {
+        let parse = |t: &Option<String>| t.as_deref().filter(|t| !t.trim().is_empty()).map(parse_time);
+        let (arrival, departure) = (parse(&st.arrival_time), parse(&st.departure_time));
+        (arrival.or(departure), departure.or(arrival))
+    }).collect();
+    let mut last_timepoint: Option<usize> = None;
+    for i in 0..times.len() {
+        if times[i].0.is_none() {
+            continue;
+        }
+        if let Some(last) = last_timepoint {
+            let (from, to) = (times[last].1.unwrap(), times[i].0.unwrap());
+            for (j, time) in times.iter_mut().enumerate().take(i).skip(last+1) {
+                let t = from+(to-from)*(j-last) as i32/(i-last) as i32;
+                *time = (Some(t), Some(t));
+            }
+        } else if i > 0 {
+            return None;
+        }
+        last_timepoint = Some(i);
+    }
+    if last_timepoint != Some(times.len()-1) {
+        return None;
+    }
+    let mut stop_times = vec![];
+    for (st, (arrival, departure)) in records.iter().zip(times) {
+        let (arrival, departure) = (arrival.unwrap(), departure.unwrap());
+        if departure < arrival || stop_times.last().is_some_and(|prev: &StopTime| arrival < prev.departure) {
+            return None;
+        }
+        stop_times.push(StopTime {
+            stop_idx: *stop_idx.get(&st.stop_id)?,
+            stop_sequence: st.stop_sequence,
+            arrival,
+            departure,
+            in_allowed: st.pickup_type.unwrap_or(0) != 1,
+            out_allowed: st.drop_off_type.unwrap_or(0) != 1
+        });
+    }
+    Some(stop_times)
+}
+
+// Days (offsets from start_date, end_date exclusive) on which each service operates.
+fn service_days(gtfs_path: &str, start_date: NaiveDate, end_date: NaiveDate) -> HashMap<String, Vec<u16>> {
+    let day_count = (end_date-start_date).num_days().max(0) as usize;
+    let mut active: HashMap<String, Vec<bool>> = HashMap::new();
+    for c in read_csv::<CalendarRecord>(gtfs_path, "calendar.txt", false) {
+        let (from, to) = (parse_date(&c.start_date), parse_date(&c.end_date));
+        let days = (0..day_count).map(|d| {
+            let date = start_date+chrono::Days::new(d as u64);
+            let weekday = match date.weekday() {
+                Weekday::Mon => c.monday,
+                Weekday::Tue => c.tuesday,
+                Weekday::Wed => c.wednesday,
+                Weekday::Thu => c.thursday,
+                Weekday::Fri => c.friday,
+                Weekday::Sat => c.saturday,
+                Weekday::Sun => c.sunday
+            };
+            weekday == 1 && from <= date && date <= to
+        }).collect();
+        active.insert(c.service_id, days);
+    }
+    for cd in read_csv::<CalendarDateRecord>(gtfs_path, "calendar_dates.txt", false) {
+        let d = (parse_date(&cd.date)-start_date).num_days();
+        let days = active.entry(cd.service_id).or_insert_with(|| vec![false; day_count]);
+        if d >= 0 && (d as usize) < day_count {
+            days[d as usize] = cd.exception_type == 1;
+        }
+    }
+    active.into_iter().map(|(service_id, days)| (service_id, days.iter().enumerate().filter(|d| *d.1).map(|d| d.0 as u16).collect())).collect()
+}
+
+impl Timetable {
+    // Replaces the timezone of agency.txt by a fixed offset (without daylight saving changes), e.g. for feeds without
+    // agency.txt. Has to be set before retrieve.
+    pub fn set_utc_offset(&mut self, seconds: i32) {
+        self.timezone = None;
+        self.utc_offset = seconds;
+    }
+
+    // GTFS times are relative to noon minus 12h of the service day in the agency timezone, i.e. to midnight except on days
+    // with daylight saving changes. This is the UTC offset of that reference, in seconds.
+    fn day_utc_offset(&self, day_idx: i32) -> i32 {
+        match self.timezone {
+            Some(tz) => {
+                let noon = (self.start_date+chrono::Duration::days(day_idx as i64)).and_hms_opt(12, 0, 0).unwrap();
+                tz.offset_from_local_datetime(&noon).earliest().map_or(self.utc_offset, |o| o.fix().local_minus_utc())
+            },
+            None => self.utc_offset
+        }
+    }
+
+    // Unix timestamp of midnight UTC of start_date, which connection times are relative to.
+    pub fn get_start_day_ts(&self) -> i64 {
+        self.start_date.and_hms_opt(0, 0, 0).unwrap().and_utc().timestamp()
+    }
+
+    fn to_mtime(&self, day_idx: u16, seconds: i32) -> types::Mtime {
+        (day_idx as i32*86400+seconds-self.day_utc_offset(day_idx as i32)).div_euclid(types::seconds_per_tick(self.ticks_per_minute))
+    }
+
+    fn scheduled_ts(&self, day_idx: u16, seconds: i32) -> i64 {
+        self.get_start_day_ts()+(day_idx as i32*86400+seconds-self.day_utc_offset(day_idx as i32)) as i64
+    }
+}
+
+pub fn retrieve(t: &Timetable, stations: &mut Vec<connection::Station>, routes: &mut Vec<connection::Route>, connections: &mut Vec<connection::Connection>) -> HashMap<(usize, u16), usize> {
+    for s in &t.stations {
+        let mut station = s.clone_metadata();
+        station.footpaths = s.footpaths.clone();
+        stations.push(station);
+    }
+    for r in &t.routes {
+        let mut route = connection::Route::new(r.id.clone(), r.name.clone(), r.product_type);
+        route.direction = r.direction.clone();
+        routes.push(route);
+    }
+    let mut transport_and_day_to_connection_id = HashMap::new();
+    for (transport_idx, trip) in t.trips.iter().enumerate() {
+        let product_type = routes[trip.route_idx].product_type;
+        for day_idx in &trip.days {
+            transport_and_day_to_connection_id.insert((transport_idx, *day_idx), connections.len());
+            for pair in trip.stop_times.windows(2) {
+                let id = connections.len();
+                let mut c = connection::Connection::new(
+                    id, trip.route_idx, product_type, transport_idx as i32, false,
+                    pair[0].stop_idx, t.to_mtime(*day_idx, pair[0].departure), None,
+                    pair[1].stop_idx, t.to_mtime(*day_idx, pair[1].arrival), None
+                );
+                c.departure.in_out_allowed = pair[0].in_allowed;
+                c.arrival.in_out_allowed = pair[1].out_allowed;
+                stations[pair[0].stop_idx].departures.push(id);
+                stations[pair[1].stop_idx].arrivals.push(id);
+                connections.push(c);
+            }
+        }
+    }
+    for station in stations {
+        station.departures.sort_unstable_by(|a,b| connections[*a].departure.projected().cmp(&connections[*b].departure.projected()));
+    }
+    transport_and_day_to_connection_id
+}
+
+// Maps the trips of this timetable to the first connections of the same trips in connections retrieved by another loader,
+// e.g. gtfs::retrieve with nigiri, so that the GTFS-RT functions of this module can be applied to them. Their
+// transport_and_day_to_connection_id provides the first connections of the trips there, reference_ts the timestamp of
+// their time 0. Trips are matched by their first two stops and their first departure and checked by their last stop.
+pub fn match_connections(t: &Timetable, stations: &[connection::Station], connections: &[connection::Connection], transport_and_day_to_connection_id: &HashMap<(usize, u16), usize>, reference_ts: i64) -> HashMap<(usize, u16), usize> {
+    let mut candidates: HashMap<(&str, &str, types::Mtime), Vec<usize>> = HashMap::new();
+    for id in transport_and_day_to_connection_id.values() {
+        let c = &connections[*id];
+        candidates.entry((&stations[c.from_idx].id, &stations[c.to_idx].id, c.departure.scheduled)).or_default().push(*id);
+    }
+    let mut mapping = HashMap::new();
+    let mut unmatched = 0;
+    for (transport_idx, trip) in t.trips.iter().enumerate() {
+        let stop_times = &trip.stop_times;
+        if stop_times.len() < 2 {
+            continue;
+        }
+        let last_stop_id = &t.stations[stop_times[stop_times.len()-1].stop_idx].id;
+        for day_idx in &trip.days {
+            let departure = (t.scheduled_ts(*day_idx, stop_times[0].departure)-reference_ts).div_euclid(types::seconds_per_tick(t.ticks_per_minute) as i64) as types::Mtime;
+            let key = (t.stations[stop_times[0].stop_idx].id.as_str(), t.stations[stop_times[1].stop_idx].id.as_str(), departure);
+            let ids = candidates.get_mut(&key);
+            let matched = ids.as_ref().and_then(|ids| ids.iter().position(|id| connections.get(id+stop_times.len()-2).is_some_and(|last|
+                last.trip_id == connections[*id].trip_id && stations[last.to_idx].id == *last_stop_id
+            )));
+            match (ids, matched) {
+                (Some(ids), Some(i)) => {
+                    mapping.insert((transport_idx, *day_idx), ids.swap_remove(i));
+                },
+                _ => unmatched += 1
+            }
+        }
+    }
+    println!("Matched {} trips, {} unmatched", mapping.len(), unmatched);
+    mapping
+}
+
+pub fn load_realtime<F: FnMut(usize, bool, Option<usize>, Option<bool>, Option<i16>)>(gtfsrt_path: &str, t: &Timetable, transport_and_day_to_connection_id: &HashMap<(usize, u16), usize>, callback: F) {
+    let bytes = std::fs::read(gtfsrt_path).unwrap();
+    apply_realtime(&bytes, t, transport_and_day_to_connection_id, callback);
+}
+
+fn read_feed(bytes: &[u8]) -> gtfs_realtime::FeedMessage<'_> {
+    let mut reader = BytesReader::from_bytes(bytes);
+    gtfs_realtime::FeedMessage::from_reader(&mut reader, bytes).expect("Cannot read GTFS-RT feed")
+}
+
+// Delays in seconds, None if unknown.
+#[derive(Clone, Default)]
+struct StopEvents {
+    arrival_delay: Option<i32>,
+    departure_delay: Option<i32>,
+    arrival_allowed: Option<bool>,
+    departure_allowed: Option<bool>
+}
+
+struct TripEvents {
+    initial_connection_id: usize,
+    stops: Vec<StopEvents>
+}
+
+impl TripEvents {
+    fn emit<F: FnMut(usize, bool, Option<usize>, Option<bool>, Option<i16>)>(&self, ticks_per_minute: types::Mtime, callback: &mut F) {
+        for (i, e) in self.stops.iter().enumerate() {
+            if i > 0 && (e.arrival_delay.is_some() || e.arrival_allowed.is_some()) {
+                callback(self.initial_connection_id+i-1, false, None, e.arrival_allowed, e.arrival_delay.map(|d| to_delay_ticks(d, ticks_per_minute)));
+            }
+            if i < self.stops.len()-1 && (e.departure_delay.is_some() || e.departure_allowed.is_some()) {
+                callback(self.initial_connection_id+i, true, None, e.departure_allowed, e.departure_delay.map(|d| to_delay_ticks(d, ticks_per_minute)));
+            }
+        }
+    }
+}
+
+// Emits TripUpdates with the same callback signature as gtfs::load_realtime, i.e. for Queriable::update.
+// Delays are propagated along the trip until the next stop time update, a missing departure delay is taken from the arrival.
+// CANCELED trips and SKIPPED stops are emitted as in_out_allowed=false. ADDED trips are only matched if they have been
+// merged into the timetable by add_realtime_trips before retrieve. VehiclePositions are left to apply_vehicle_positions.
+pub fn apply_realtime<F: FnMut(usize, bool, Option<usize>, Option<bool>, Option<i16>)>(bytes: &[u8], t: &Timetable, transport_and_day_to_connection_id: &HashMap<(usize, u16), usize>, mut callback: F) {
+    let feed = read_feed(bytes);
+    let mut trips: IndexMap<(usize, u16), TripEvents> = IndexMap::new();
+    let (mut updates, mut cancelled, mut unmatched) = (0, 0, 0);
+    for update in feed.entity.iter().filter_map(|e| e.trip_update.as_ref()) {
+        match t.match_trip(&update.trip, feed.header.timestamp, transport_and_day_to_connection_id) {
+            Some((transport_idx, day_idx, initial_connection_id)) => {
+                let trip_events = trip_events(&mut trips, t, transport_idx, day_idx, initial_connection_id);
+                if update.trip.schedule_relationship == Some(TripRelationship::CANCELED) {
+                    for e in &mut trip_events.stops {
+                        e.arrival_allowed = Some(false);
+                        e.departure_allowed = Some(false);
+                    }
+                    cancelled += 1;
+                } else {
+                    t.apply_trip_update(transport_idx, day_idx, update, trip_events);
+                    updates += 1;
+                }
+            },
+            None => unmatched += 1
+        }
+    }
+    for trip_events in trips.values() {
+        trip_events.emit(t.ticks_per_minute, &mut callback);
+    }
+    println!("Applied {} trip updates, {} cancellations, {} unmatched", updates, cancelled, unmatched);
+}
+
+// Emits the lower bounds of the delays in ticks that follow from VehiclePositions, for Queriable::update_min_delay.
+// They are evidence rather than observed delays: a vehicle that has not arrived at (IN_TRANSIT_TO, INCOMING_AT) or departed
+// from (STOPPED_AT) its current stop at the time of the position is at least that late there and at all later stops.
+// Returns the number of applied positions.
+pub fn apply_vehicle_positions<F: FnMut(usize, bool, i16)>(bytes: &[u8], t: &Timetable, transport_and_day_to_connection_id: &HashMap<(usize, u16), usize>, mut callback: F) -> usize {
+    let feed = read_feed(bytes);
+    let (mut positions, mut unmatched) = (0, 0);
+    for vehicle in feed.entity.iter().filter_map(|e| e.vehicle.as_ref()) {
+        let matched = vehicle.trip.as_ref().and_then(|trip| t.match_trip(trip, feed.header.timestamp, transport_and_day_to_connection_id));
+        match matched {
+            Some((transport_idx, day_idx, initial_connection_id)) => {
+                if t.apply_vehicle_position(transport_idx, day_idx, initial_connection_id, vehicle, feed.header.timestamp, &mut callback) {
+                    positions += 1;
+                }
+            },
+            None => unmatched += 1
+        }
+    }
+    println!("Applied {} vehicle positions, {} unmatched", positions, unmatched);
+    positions
+}
+
+fn trip_events<'a>(trips: &'a mut IndexMap<(usize, u16), TripEvents>, t: &Timetable, transport_idx: usize, day_idx: u16, initial_connection_id: usize) -> &'a mut TripEvents {
+    trips.entry((transport_idx, day_idx)).or_insert_with(|| TripEvents {
+        initial_connection_id,
+        stops: vec![StopEvents::default(); t.trips[transport_idx].stop_times.len()]
+    })
+}
+
+fn to_delay_ticks(seconds: i32, ticks_per_minute: types::Mtime) -> i16 {
+    (seconds as f32/types::seconds_per_tick(ticks_per_minute) as f32).round() as i16
+}
+
+// Appends the trips with schedule relationship ADDED, which are not part of the static timetable, to their route_id on the
+// service day of their start_date (or of their first event). The absolute times of their stop time updates become the schedule.
+// Has to be called before retrieve. Returns the number of added trips.
+pub fn add_realtime_trips(bytes: &[u8], t: &mut Timetable) -> usize {
+    let feed = read_feed(bytes);
+    let mut added = vec![];
+    let mut incomplete = 0;
+    for update in feed.entity.iter().filter_map(|e| e.trip_update.as_ref()) {
+        if update.trip.schedule_relationship != Some(TripRelationship::ADDED) {
+            continue;
+        }
+        match update.trip.trip_id.as_ref().filter(|id| !t.trip_ids.contains_key(id.as_ref())) {
+            Some(trip_id) => match t.to_added_trip(update, feed.header.timestamp) {
+                Some(trip) => added.push((trip_id.to_string(), trip)),
+                None => incomplete += 1
+            },
+            None => continue
+        }
+    }
+    let count = added.len();
+    for (trip_id, trip) in added {
+        t.trip_ids.insert(trip_id, t.trips.len());
+        t.trips.push(trip);
+    }
+    println!("Added {} realtime trips, skipped {} incomplete", count, incomplete);
+    count
+}
+
+// Sets the texts of ServiceAlerts as Route.message for alerts on whole routes and as Connection.message for alerts on trips or
+// stops (optionally restricted to a route). Connections departing outside of all active periods are left out. Has to be called
+// after retrieve, on the stations, routes and connections it has filled, or on those of another loader with the mapping of
+// match_connections and their reference_ts. Without routes, e.g. since those of the other loader are not GTFS routes, alerts
+// on whole routes are set on the connections of their trips instead. Returns the number of applied alerts.
+pub fn apply_alerts(bytes: &[u8], t: &Timetable, transport_and_day_to_connection_id: &HashMap<(usize, u16), usize>, stations: &[connection::Station], mut routes: Option<&mut [connection::Route]>, connections: &mut [connection::Connection], reference_ts: i64) -> usize {
+    let feed = read_feed(bytes);
+    let mut applied = 0;
+    let trip_connections = |transport_idx: usize, initial_connection_id: usize| initial_connection_id..initial_connection_id+t.trips[transport_idx].stop_times.len()-1;
+    for alert in feed.entity.iter().filter_map(|e| e.alert.as_ref()) {
+        let text = match alert_text(alert) {
+            Some(text) => text,
+            None => continue
+        };
+        let is_active = |ts: i64| alert.active_period.is_empty() || alert.active_period.iter().any(|p|
+            p.start.is_none_or(|start| start as i64 <= ts) && p.end.is_none_or(|end| ts <= end as i64)
+        );
+        for selector in &alert.informed_entity {
+            let route_idx = selector.route_id.as_ref().and_then(|id| t.routes.iter().position(|r| r.id == *id));
+            if selector.route_id.is_some() && route_idx.is_none() {
+                continue;
+            }
+            let stop_id = selector.stop_id.as_deref();
+            if stop_id.is_some_and(|id| !t.stations.iter().any(|s| s.id == id)) {
+                continue;
+            }
+            let selected: Vec<usize> = match (&selector.trip, route_idx) {
+                (Some(trip), _) => match t.match_trip(trip, feed.header.timestamp, transport_and_day_to_connection_id) {
+                    Some((transport_idx, _, initial_connection_id)) if route_idx.is_none_or(|r| t.trips[transport_idx].route_idx == r) => trip_connections(transport_idx, initial_connection_id).collect(),
+                    _ => continue
+                },
+                (None, Some(route_idx)) => {
+                    let route = routes.as_deref_mut().and_then(|routes| routes.iter_mut().find(|r| r.id == t.routes[route_idx].id));
+                    if let Some(route) = route.filter(|_| stop_id.is_none()) {
+                        if feed.header.timestamp.is_none_or(|ts| is_active(ts as i64)) {
+                            append_message(&mut route.message, &text);
+                            applied += 1;
+                        }
+                        continue;
+                    }
+                    transport_and_day_to_connection_id.iter()
+                        .filter(|((transport_idx, _), _)| t.trips[*transport_idx].route_idx == route_idx)
+                        .flat_map(|((transport_idx, _), initial_connection_id)| trip_connections(*transport_idx, *initial_connection_id))
+                        .collect()
+                },
+                (None, None) if stop_id.is_some() => (0..connections.len()).collect(),
+                (None, None) => continue
+            };
+            for id in selected {
+                let c = &mut connections[id];
+                // walking connections are recognized by their message
+                if c.message == WALKING_MSG || stop_id.is_some_and(|s| stations[c.from_idx].id != s && stations[c.to_idx].id != s) {
+                    continue;
+                }
+                if is_active(reference_ts+c.departure.scheduled as i64*types::seconds_per_tick(t.ticks_per_minute) as i64) {
+                    append_message(&mut c.message, &text);
+                    applied += 1;
+                }
+            }
+        }
+    }
+    println!("Applied {} alerts", applied);
+    applied
+}
+
+fn alert_text(alert: &gtfs_realtime::Alert) -> Option<String> {
+    let first = |s: &Option<gtfs_realtime::TranslatedString>| s.as_ref().and_then(|s| s.translation.first()).map(|t| t.text.to_string()).filter(|t| !t.is_empty());
+    first(&alert.header_text).or(first(&alert.description_text))
+}
+
+fn append_message(message: &mut String, text: &str) {
+    if message.is_empty() {
+        *message = text.to_string();
+    } else if !message.split('\n').any(|m| m == text) {
+        message.push('\n');
+        message.push_str(text);
+    }
+}
+
+impl Timetable {
+    // Without a start_date, the service day on which the trip runs closest to the feed timestamp.
+    fn day_idx(&self, transport_idx: usize, start_date: Option<&str>, feed_ts: Option<u64>) -> Option<u16> {
+        let trip = &self.trips[transport_idx];
+        match start_date {
+            Some(date) => {
+                let d = (NaiveDate::parse_from_str(date, "%Y%m%d").ok()?-self.start_date).num_days();
+                u16::try_from(d).ok().filter(|d| trip.days.contains(d))
+            },
+            None => {
+                let feed_ts = feed_ts? as i64;
+                let first_departure = trip.stop_times.first()?.departure;
+                trip.days.iter().min_by_key(|d| (self.scheduled_ts(**d, first_departure)-feed_ts).abs()).copied()
+            }
+        }
+    }
+
+    // transport_idx, day_idx and the id of the first connection of the trip
+    fn match_trip(&self, trip: &gtfs_realtime::TripDescriptor, feed_ts: Option<u64>, transport_and_day_to_connection_id: &HashMap<(usize, u16), usize>) -> Option<(usize, u16, usize)> {
+        let transport_idx = *self.trip_ids.get(trip.trip_id.as_ref()?.as_ref())?;
+        let day_idx = self.day_idx(transport_idx, trip.start_date.as_deref(), feed_ts)?;
+        transport_and_day_to_connection_id.get(&(transport_idx, day_idx)).map(|id| (transport_idx, day_idx, *id))
+    }
+
+    fn stop_position(&self, transport_idx: usize, stop_sequence: Option<u32>, stop_id: Option<&str>) -> Option<usize> {
+        self.trips[transport_idx].stop_times.iter().position(|st| match stop_sequence {
+            Some(seq) => seq == st.stop_sequence,
+            None => stop_id.is_some_and(|id| self.stations[st.stop_idx].id == id)
+        })
+    }
+
+    fn apply_trip_update(&self, transport_idx: usize, day_idx: u16, update: &gtfs_realtime::TripUpdate, events: &mut TripEvents) {
+        let stop_times = &self.trips[transport_idx].stop_times;
+        let mut stop_updates = vec![None; stop_times.len()];
+        for u in &update.stop_time_update {
+            if let Some(i) = self.stop_position(transport_idx, u.stop_sequence, u.stop_id.as_deref()) {
+                stop_updates[i] = Some(u);
+            }
+        }
+        let event_delay = |event: &Option<gtfs_realtime::mod_TripUpdate::StopTimeEvent>, scheduled: i32| event.as_ref().and_then(|e|
+            e.delay.or(e.time.map(|time| (time-self.scheduled_ts(day_idx, scheduled)) as i32))
+        );
+        let mut delay = update.delay;
+        for ((st, stop_update), e) in stop_times.iter().zip(stop_updates).zip(events.stops.iter_mut()) {
+            match stop_update.and_then(|u| u.schedule_relationship) {
+                Some(StopRelationship::SKIPPED) => {
+                    e.arrival_allowed = Some(false);
+                    e.departure_allowed = Some(false);
+                    continue;
+                },
+                Some(StopRelationship::NO_DATA) => {
+                    delay = None;
+                    continue;
+                },
+                _ => {}
+            }
+            let arrival_delay = stop_update.and_then(|u| event_delay(&u.arrival, st.arrival)).or(delay);
+            let departure_delay = stop_update.and_then(|u| event_delay(&u.departure, st.departure)).or(arrival_delay);
+            delay = departure_delay;
+            e.arrival_delay = arrival_delay;
+            e.departure_delay = departure_delay;
+        }
+    }
+
+    fn apply_vehicle_position<F: FnMut(usize, bool, i16)>(&self, transport_idx: usize, day_idx: u16, initial_connection_id: usize, vehicle: &gtfs_realtime::VehiclePosition, feed_ts: Option<u64>, callback: &mut F) -> bool {
+        let (ts, current) = match (vehicle.timestamp.or(feed_ts), self.stop_position(transport_idx, vehicle.current_stop_sequence, vehicle.stop_id.as_deref())) {
+            (Some(ts), Some(current)) => (ts as i64, current),
+            _ => return false
+        };
+        let stopped = vehicle.current_status == Some(VehicleStopStatus::STOPPED_AT);
+        let stop_times = &self.trips[transport_idx].stop_times;
+        let st = &stop_times[current];
+        let lower_bound = (ts-self.scheduled_ts(day_idx, if stopped { st.departure } else { st.arrival })) as i32;
+        // rounded down to stay a lower bound
+        let min_delay = lower_bound.div_euclid(types::seconds_per_tick(self.ticks_per_minute)) as i16;
+        for i in current..stop_times.len() {
+            if i > 0 && (i > current || !stopped) {
+                callback(initial_connection_id+i-1, false, min_delay);
+            }
+            if i < stop_times.len()-1 {
+                callback(initial_connection_id+i, true, min_delay);
+            }
+        }
+        true
+    }
+
+    fn to_added_trip(&self, update: &gtfs_realtime::TripUpdate, feed_ts: Option<u64>) -> Option<Trip> {
+        let route_id = update.trip.route_id.as_ref()?;
+        let route_idx = self.routes.iter().position(|r| r.id == *route_id)?;
+        let mut times = vec![];
+        for (i, u) in update.stop_time_update.iter().enumerate() {
+            let stop_id = u.stop_id.as_ref()?;
+            let stop_idx = self.stations.iter().position(|s| s.id == *stop_id)?;
+            let (arrival, departure) = (u.arrival.as_ref().and_then(|e| e.time), u.departure.as_ref().and_then(|e| e.time));
+            times.push((stop_idx, u.stop_sequence.unwrap_or(i as u32), arrival.or(departure)?, departure.or(arrival)?));
+        }
+        let first_departure = times.first()?.3;
+        let day_idx = match update.trip.start_date.as_deref() {
+            Some(date) => (NaiveDate::parse_from_str(date, "%Y%m%d").ok()?-self.start_date).num_days(),
+            None => (first_departure.min(feed_ts.map_or(first_departure, |ts| ts as i64))-self.scheduled_ts(0, 0)).div_euclid(86400)
+        };
+        let day_idx = u16::try_from(day_idx).ok()?;
+        let mut stop_times: Vec<StopTime> = vec![];
+        for (stop_idx, stop_sequence, arrival, departure) in times {
+            let (arrival, departure) = ((arrival-self.scheduled_ts(day_idx, 0)) as i32, (departure-self.scheduled_ts(day_idx, 0)) as i32);
+            if departure < arrival || stop_times.last().is_some_and(|prev| arrival < prev.departure) {
+                return None;
+            }
+            stop_times.push(StopTime { stop_idx, stop_sequence, arrival, departure, in_allowed: true, out_allowed: true });
+        }
+        if stop_times.len() < 2 {
+            return None;
+        }
+        Some(Trip { route_idx, stop_times, days: vec![day_idx] })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use quick_protobuf::{MessageWrite, Writer};
+    use std::borrow::Cow;
+
+    fn day(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    #[test]
+    fn load_fixture() {
+        let t = load_timetable("./tests/fixtures/gtfs_minimal_swiss/", day(2024, 1, 1), day(2024, 1, 8), 1);
+        let mut stations = vec![];
+        let mut routes = vec![];
+        let mut connections = vec![];
+        let mapping = retrieve(&t, &mut stations, &mut routes, &mut connections);
+        assert_eq!(stations.len(), 50);
+        assert_eq!(routes.len(), 4);
+        // Mon, Tue, Thu, Fri, Sat
+        let trip = t.trip_ids["7.TA.1-1-A-j19-1.7.H"];
+        assert_eq!(t.trips[trip].days, vec![0, 1, 3, 4, 5]);
+        let c = &connections[mapping[&(trip, 1)]];
+        assert_eq!(stations[c.from_idx].id, "8503000:0:41/42");
+        // 01:00 in Europe/Berlin
+        assert_eq!(c.departure.scheduled, types::minutes(24*60, 1));
+        assert_eq!(routes[c.route_idx].product_type, 7);
+        // going backwards in time
+        assert!(t.trips[t.trip_ids["210.TA.92-702-j24-1.9.R"]].days.is_empty());
+        // pickup_type 1
+        let c = &connections[mapping[&(t.trip_ids["22.TA.1-1-A-j19-1.22.R"], 0)]];
+        assert!(!c.departure.in_out_allowed);
+        let hb = &stations[c.from_idx];
+        assert_eq!(hb.transfer_time, 2);
+        let zurich = stations.iter().find(|s| s.id == "8503000:0:41/42").unwrap();
+        assert_eq!(zurich.footpaths.len(), 2);
+        assert!(zurich.footpaths.iter().all(|f| f.duration == 7));
+    }
+
+    #[test]
+    fn match_connections_of_other_timetable() {
+        let t = load_timetable("./tests/fixtures/gtfs_minimal_swiss/", day(2024, 1, 1), day(2024, 1, 8), 1);
+        let (mut stations, mut routes, mut connections) = (vec![], vec![], vec![]);
+        let mapping = retrieve(&t, &mut stations, &mut routes, &mut connections);
+        // starting a day later, so that ids, days and times differ
+        let other = load_timetable("./tests/fixtures/gtfs_minimal_swiss/", day(2024, 1, 2), day(2024, 1, 8), 1);
+        let (mut other_stations, mut other_routes, mut other_connections) = (vec![], vec![], vec![]);
+        let other_mapping = retrieve(&other, &mut other_stations, &mut other_routes, &mut other_connections);
+        let matched = match_connections(&t, &other_stations, &other_connections, &other_mapping, other.get_start_day_ts());
+        assert_eq!(matched.len(), other_mapping.len());
+        for ((transport_idx, day_idx), id) in &mapping {
+            assert_eq!(matched.get(&(*transport_idx, *day_idx)), other_mapping.get(&(*transport_idx, day_idx.wrapping_sub(1))));
+            if let Some(other_id) = matched.get(&(*transport_idx, *day_idx)) {
+                assert_eq!(connections[*id].departure.scheduled, other_connections[*other_id].departure.scheduled+types::minutes(1440, 1));
+            }
+        }
+    }
+
+    #[test]
+    fn daylight_saving_time() {
+        // CEST starts on 2024-03-31
+        let mut t = load_timetable("./tests/fixtures/gtfs_minimal_swiss/", day(2024, 3, 30), day(2024, 4, 1), 1);
+        assert_eq!(t.day_utc_offset(0), 3600);
+        assert_eq!(t.day_utc_offset(1), 7200);
+        assert_eq!(t.to_mtime(0, 12*3600), types::minutes(11*60, 1));
+        assert_eq!(t.to_mtime(1, 12*3600), types::minutes(24*60+10*60, 1));
+        assert_eq!(t.scheduled_ts(1, 0), t.get_start_day_ts()+86400-7200);
+        t.set_utc_offset(0);
+        assert_eq!(t.to_mtime(1, 12*3600), types::minutes(24*60+12*60, 1));
+    }
+
+    #[test]
+    fn interpolate_stop_times() {
+        let record = |stop_id: &str, stop_sequence: u32, time: Option<&str>| StopTimeRecord {
+            trip_id: "t".to_string(),
+            arrival_time: time.map(|t| t.to_string()),
+            departure_time: time.map(|t| t.to_string()),
+            stop_id: stop_id.to_string(),
+            stop_sequence,
+            pickup_type: None,
+            drop_off_type: Some(1)
+        };
+        let stop_idx: HashMap<String, usize> = [("a".to_string(), 0), ("b".to_string(), 1), ("c".to_string(), 2)].into();
+        let stop_times = to_stop_times(&[record("a", 1, Some("24:00:00")), record("b", 2, None), record("c", 3, Some("24:10:00"))], &stop_idx).unwrap();
+        assert_eq!(stop_times.iter().map(|st| st.arrival).collect::<Vec<i32>>(), vec![86400, 86700, 87000]);
+        assert!(!stop_times[2].out_allowed);
+        assert!(to_stop_times(&[record("a", 1, Some("10:00:00")), record("b", 2, Some("09:00:00"))], &stop_idx).is_none());
+        assert!(to_stop_times(&[record("a", 1, None), record("b", 2, Some("09:00:00"))], &stop_idx).is_none());
+    }
+
+    #[test]
+    fn trip_updates() {
+        let t = load_timetable("./tests/fixtures/gtfs_minimal_swiss/", day(2024, 1, 1), day(2024, 1, 8), 1);
+        let mut stations = vec![];
+        let mut routes = vec![];
+        let mut connections = vec![];
+        let mapping = retrieve(&t, &mut stations, &mut routes, &mut connections);
+        let trip = t.trip_ids["7.TA.1-1-A-j19-1.7.H"];
+        let stop_update = |stop_sequence: u32, arrival_delay: Option<i32>, departure_time: Option<i64>| gtfs_realtime::mod_TripUpdate::StopTimeUpdate {
+            stop_sequence: Some(stop_sequence),
+            arrival: arrival_delay.map(|d| gtfs_realtime::mod_TripUpdate::StopTimeEvent { delay: Some(d), ..Default::default() }),
+            departure: departure_time.map(|time| gtfs_realtime::mod_TripUpdate::StopTimeEvent { time: Some(time), ..Default::default() }),
+            ..Default::default()
+        };
+        let feed = gtfs_realtime::FeedMessage {
+            header: gtfs_realtime::FeedHeader { gtfs_realtime_version: Cow::Borrowed("2.0"), ..Default::default() },
+            entity: vec![gtfs_realtime::FeedEntity {
+                id: Cow::Borrowed("1"),
+                trip_update: Some(gtfs_realtime::TripUpdate {
+                    trip: gtfs_realtime::TripDescriptor {
+                        trip_id: Some(Cow::Borrowed("7.TA.1-1-A-j19-1.7.H")),
+                        start_date: Some(Cow::Borrowed("20240102")),
+                        ..Default::default()
+                    },
+                    stop_time_update: vec![
+                        stop_update(2, Some(120), None),
+                        stop_update(5, None, Some(t.scheduled_ts(1, t.trips[trip].stop_times[4].departure)+300))
+                    ],
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }]
+        };
+        let mut bytes = vec![];
+        feed.write_message(&mut Writer::new(&mut bytes)).unwrap();
+
+        let mut events = vec![];
+        apply_realtime(&bytes, &t, &mapping, |connection_id, is_departure, _location_idx, _in_out_allowed, delay| events.push((connection_id, is_departure, delay.unwrap())));
+        let initial = mapping[&(trip, 1)];
+        assert_eq!(events[0], (initial, false, 2));
+        assert_eq!(events[1], (initial+1, true, 2));
+        assert_eq!(events[6], (initial+3, false, 2));
+        assert_eq!(events[7], (initial+4, true, 5));
+        assert_eq!(events.last().unwrap(), &(initial+16, false, 5));
+        assert_eq!(events.len(), 33);
+    }
+
+    fn encode(entity: Vec<gtfs_realtime::FeedEntity>) -> Vec<u8> {
+        let feed = gtfs_realtime::FeedMessage {
+            header: gtfs_realtime::FeedHeader { gtfs_realtime_version: Cow::Borrowed("2.0"), ..Default::default() },
+            entity
+        };
+        let mut bytes = vec![];
+        feed.write_message(&mut Writer::new(&mut bytes)).unwrap();
+        bytes
+    }
+
+    fn trip<'a>(trip_id: &'a str, start_date: &'a str) -> gtfs_realtime::TripDescriptor<'a> {
+        gtfs_realtime::TripDescriptor {
+            trip_id: Some(Cow::Borrowed(trip_id)),
+            start_date: Some(Cow::Borrowed(start_date)),
+            ..Default::default()
+        }
+    }
+
+    fn trip_update_entity<'a>(trip: gtfs_realtime::TripDescriptor<'a>, stop_time_update: Vec<gtfs_realtime::mod_TripUpdate::StopTimeUpdate<'a>>) -> gtfs_realtime::FeedEntity<'a> {
+        gtfs_realtime::FeedEntity {
+            trip_update: Some(gtfs_realtime::TripUpdate { trip, stop_time_update, ..Default::default() }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn schedule_relationships() {
+        let mut t = load_timetable("./tests/fixtures/gtfs_minimal_swiss/", day(2024, 1, 1), day(2024, 1, 8), 1);
+        let event = |time: i64| Some(gtfs_realtime::mod_TripUpdate::StopTimeEvent { time: Some(time), ..Default::default() });
+        let added_stop = |stop_id: &'static str, time: i64| gtfs_realtime::mod_TripUpdate::StopTimeUpdate {
+            stop_id: Some(Cow::Borrowed(stop_id)),
+            arrival: event(time),
+            departure: event(time),
+            ..Default::default()
+        };
+        let added_start = t.scheduled_ts(1, 3*3600);
+        let bytes = encode(vec![
+            trip_update_entity(gtfs_realtime::TripDescriptor { schedule_relationship: Some(TripRelationship::CANCELED), ..trip("7.TA.1-1-A-j19-1.7.H", "20240102") }, vec![]),
+            trip_update_entity(trip("7.TA.1-1-A-j19-1.7.H", "20240101"), vec![
+                gtfs_realtime::mod_TripUpdate::StopTimeUpdate { stop_sequence: Some(2), arrival: Some(gtfs_realtime::mod_TripUpdate::StopTimeEvent { delay: Some(60), ..Default::default() }), ..Default::default() },
+                gtfs_realtime::mod_TripUpdate::StopTimeUpdate { stop_sequence: Some(3), schedule_relationship: Some(StopRelationship::SKIPPED), ..Default::default() }
+            ]),
+            trip_update_entity(gtfs_realtime::TripDescriptor { schedule_relationship: Some(TripRelationship::ADDED), route_id: Some(Cow::Borrowed("1-1-A-j19-1")), ..trip("added", "20240102") }, vec![
+                added_stop("8503000:0:41/42", added_start),
+                added_stop("8503020:0:4", added_start+180)
+            ])
+        ]);
+        assert_eq!(add_realtime_trips(&bytes, &mut t), 1);
+        let mut stations = vec![];
+        let mut routes = vec![];
+        let mut connections = vec![];
+        let mapping = retrieve(&t, &mut stations, &mut routes, &mut connections);
+        let added = mapping[&(t.trip_ids["added"], 1)];
+        assert_eq!(stations[connections[added].from_idx].id, "8503000:0:41/42");
+        assert_eq!(connections[added].departure.scheduled, types::minutes(24*60+120, 1));
+        assert_eq!(connections[added].arrival.scheduled, types::minutes(24*60+123, 1));
+
+        let mut events = vec![];
+        apply_realtime(&bytes, &t, &mapping, |connection_id, is_departure, _location_idx, in_out_allowed, delay| events.push((connection_id, is_departure, in_out_allowed, delay)));
+        let trip = t.trip_ids["7.TA.1-1-A-j19-1.7.H"];
+        let cancelled = mapping[&(trip, 1)];
+        let cancelled_events: Vec<_> = events.iter().filter(|e| (cancelled..cancelled+17).contains(&e.0)).collect();
+        assert_eq!(cancelled_events.len(), 34);
+        assert!(cancelled_events.iter().all(|e| e.2 == Some(false) && e.3.is_none()));
+        let initial = mapping[&(trip, 0)];
+        assert!(events.contains(&(initial, false, None, Some(1))));
+        assert!(events.contains(&(initial+1, false, Some(false), None)));
+        assert!(events.contains(&(initial+2, true, Some(false), None)));
+        assert!(events.contains(&(initial+2, false, None, Some(1))));
+        assert!(events.contains(&(added, true, None, Some(0))));
+        assert!(events.contains(&(added, false, None, Some(0))));
+    }
+
+    #[test]
+    fn vehicle_positions() {
+        let t = load_timetable("./tests/fixtures/gtfs_minimal_swiss/", day(2024, 1, 1), day(2024, 1, 8), 1);
+        let mut stations = vec![];
+        let mut routes = vec![];
+        let mut connections = vec![];
+        let mapping = retrieve(&t, &mut stations, &mut routes, &mut connections);
+        let trip_idx = t.trip_ids["7.TA.1-1-A-j19-1.7.H"];
+        let stop_times = &t.trips[trip_idx].stop_times;
+        let vehicle_entity = |start_date: &'static str, stop_sequence: u32, current_status: VehicleStopStatus, timestamp: i64| gtfs_realtime::FeedEntity {
+            vehicle: Some(gtfs_realtime::VehiclePosition {
+                trip: Some(trip("7.TA.1-1-A-j19-1.7.H", start_date)),
+                current_stop_sequence: Some(stop_sequence),
+                current_status: Some(current_status),
+                timestamp: Some(timestamp as u64),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let bytes = encode(vec![
+            trip_update_entity(trip("7.TA.1-1-A-j19-1.7.H", "20240102"), vec![
+                gtfs_realtime::mod_TripUpdate::StopTimeUpdate { stop_sequence: Some(2), arrival: Some(gtfs_realtime::mod_TripUpdate::StopTimeEvent { delay: Some(120), ..Default::default() }), ..Default::default() }
+            ]),
+            // five minutes late, not yet at the fifth stop
+            vehicle_entity("20240102", 5, VehicleStopStatus::IN_TRANSIT_TO, t.scheduled_ts(1, stop_times[4].arrival)+300),
+            // early
+            vehicle_entity("20240104", 2, VehicleStopStatus::STOPPED_AT, t.scheduled_ts(3, stop_times[1].departure)-60)
+        ]);
+        let mut events = vec![];
+        apply_realtime(&bytes, &t, &mapping, |connection_id, is_departure, _location_idx, _in_out_allowed, delay| events.push((connection_id, is_departure, delay.unwrap())));
+        let initial = mapping[&(trip_idx, 1)];
+        // the positions do not change the reported delays
+        assert!(events.contains(&(initial+3, false, 2)));
+        assert!(events.iter().all(|e| e.2 == 2));
+        let mut bounds = vec![];
+        assert_eq!(apply_vehicle_positions(&bytes, &t, &mapping, |connection_id, is_departure, min_delay| bounds.push((connection_id, is_departure, min_delay))), 2);
+        assert!(!bounds.contains(&(initial+2, false, 5)));
+        assert!(bounds.contains(&(initial+3, false, 5)));
+        assert!(bounds.contains(&(initial+4, true, 5)));
+        assert_eq!(bounds.iter().rfind(|b| b.2 == 5).unwrap(), &(initial+16, false, 5));
+        let early = mapping[&(trip_idx, 3)];
+        assert!(!bounds.contains(&(early, false, -1)));
+        assert!(bounds.contains(&(early+1, true, -1)));
+        assert!(bounds.contains(&(early+1, false, -1)));
+        assert_eq!(bounds.len(), 27+32);
+    }
+
+    #[test]
+    fn alerts() {
+        let t = load_timetable("./tests/fixtures/gtfs_minimal_swiss/", day(2024, 1, 1), day(2024, 1, 8), 1);
+        let mut stations = vec![];
+        let mut routes = vec![];
+        let mut connections = vec![];
+        let mapping = retrieve(&t, &mut stations, &mut routes, &mut connections);
+        let alert_entity = |text: &'static str, informed_entity: gtfs_realtime::EntitySelector<'static>, active_period: Vec<gtfs_realtime::TimeRange>| gtfs_realtime::FeedEntity {
+            alert: Some(gtfs_realtime::Alert {
+                active_period,
+                informed_entity: vec![informed_entity],
+                header_text: Some(gtfs_realtime::TranslatedString {
+                    translation: vec![gtfs_realtime::mod_TranslatedString::Translation { text: Cow::Borrowed(text), ..Default::default() }]
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let bytes = encode(vec![
+            alert_entity("Construction works", gtfs_realtime::EntitySelector { route_id: Some(Cow::Borrowed("1-1-j19-1")), ..Default::default() }, vec![]),
+            alert_entity("Delays", gtfs_realtime::EntitySelector { trip: Some(trip("7.TA.1-1-A-j19-1.7.H", "20240102")), ..Default::default() }, vec![]),
+            alert_entity("Platform change", gtfs_realtime::EntitySelector { route_id: Some(Cow::Borrowed("1-1-A-j19-1")), stop_id: Some(Cow::Borrowed("8503020:0:4")), ..Default::default() }, vec![]),
+            alert_entity("Closed", gtfs_realtime::EntitySelector { stop_id: Some(Cow::Borrowed("8503000:0:41/42")), ..Default::default() },
+                vec![gtfs_realtime::TimeRange { end: Some(t.get_start_day_ts() as u64-1), ..Default::default() }])
+        ]);
+        apply_alerts(&bytes, &t, &mapping, &stations, Some(&mut routes), &mut connections, t.get_start_day_ts());
+        assert_eq!(routes[1].message, "Construction works");
+        assert_eq!(routes[0].message, "");
+        let trip_idx = t.trip_ids["7.TA.1-1-A-j19-1.7.H"];
+        let initial = mapping[&(trip_idx, 1)];
+        assert_eq!(connections[initial].message, "Delays\nPlatform change");
+        assert_eq!(connections[initial+1].message, "Delays\nPlatform change");
+        assert_eq!(connections[initial+2].message, "Delays");
+        assert_eq!(connections[mapping[&(trip_idx, 0)]].message, "Platform change");
+        assert!(connections.iter().all(|c| !c.message.contains("Closed")));
+        // without routes, on the connections of the trips of the route
+        let route_idx = routes.iter().position(|r| r.id == "1-1-j19-1").unwrap();
+        assert!(connections.iter().all(|c| !c.message.contains("Construction works")));
+        apply_alerts(&bytes, &t, &mapping, &stations, None, &mut connections, t.get_start_day_ts());
+        assert!(connections.iter().all(|c| c.message.contains("Construction works") == (c.route_idx == route_idx)));
+        assert_eq!(connections[initial].message, "Delays\nPlatform change");
+    }
+}
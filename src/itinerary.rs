@@ -0,0 +1,409 @@
+#[cfg(feature = "nigiri")]
+use std::collections::HashMap;
+
+use rustc_hash::FxHashSet;
+
+use crate::connection;
+use crate::distribution;
+use crate::distribution_store;
+use crate::gtfs::StationContraction;
+use crate::query::topocsa;
+use crate::query::{Queriable, Query};
+use crate::types;
+
+#[derive(Debug, Clone)]
+pub struct Leg {
+    pub departure_idx: usize,
+    pub arrival_idx: usize,
+    pub footpath_duration: Option<u16>,
+    pub transfer_probability: types::MFloat,
+    pub fallback_idx: Option<usize>,
+    pub fallback_arrival: Option<distribution::Distribution>
+}
+
+// A leg given by its trip (trip_id and route_idx, as trip ids may only be unique per route) and boarding and alighting stop.
+#[derive(Debug, Clone, Copy)]
+pub struct TripLeg {
+    pub trip_id: i32,
+    pub route_idx: usize,
+    pub from_idx: usize,
+    pub to_idx: usize
+}
+
+// A fixed sequence of legs, each from the departure to the arrival connection (indices into the connections) of one trip.
+// footpath_duration is the walk before boarding the leg, None for a transfer at the same stop.
+#[derive(Debug, Clone)]
+pub struct Itinerary {
+    pub legs: Vec<Leg>,
+    pub final_footpath_duration: Option<u16>,
+    pub destination_arrival: distribution::Distribution
+}
+
+impl Itinerary {
+    #[cfg(feature = "nigiri")]
+    pub fn from_journey(journey: &motis_nigiri::Journey, transport_and_day_to_connection_id: &HashMap<(usize, u16), usize>, order: &[usize], ticks_per_minute: types::Mtime) -> Itinerary {
+        let mut legs = vec![];
+        let mut walk = None;
+        for l in &journey.legs {
+            if l.is_footpath {
+                walk = Some(walk.unwrap_or(0) + l.duration*ticks_per_minute as u16);
+                continue;
+            }
+            let initial_connection_of_transport = transport_and_day_to_connection_id[&(l.transport_idx, l.day_idx)];
+            legs.push(new_leg(
+                order[initial_connection_of_transport + l.from_stop_idx as usize],
+                order[initial_connection_of_transport + l.to_stop_idx as usize - 1],
+                walk.take()
+            ));
+        }
+        let mut itinerary = Itinerary::new(legs);
+        itinerary.final_footpath_duration = walk;
+        itinerary
+    }
+
+    // Consecutive connections of the same trip are merged into one leg.
+    pub fn from_connection_ids(connection_ids: &[usize], connections: &[connection::Connection], stations: &[connection::Station], order: &[usize]) -> Result<Itinerary, String> {
+        let mut legs: Vec<Leg> = vec![];
+        for id in connection_ids {
+            let idx = order[*id];
+            let c = &connections[idx];
+            if let Some(last) = legs.last_mut() {
+                let prev = &connections[last.arrival_idx];
+                if prev.trip_id == c.trip_id && prev.route_idx == c.route_idx && prev.to_idx == c.from_idx {
+                    last.arrival_idx = idx;
+                    continue;
+                }
+            }
+            let footpath_duration = match legs.last() {
+                Some(l) => footpath_duration(stations, connections[l.arrival_idx].to_idx, c.from_idx)?,
+                None => None
+            };
+            legs.push(new_leg(idx, idx, footpath_duration));
+        }
+        Ok(Itinerary::new(legs))
+    }
+
+    pub fn from_trip_legs(trip_legs: &[TripLeg], connections: &[connection::Connection], stations: &[connection::Station], order: &[usize]) -> Result<Itinerary, String> {
+        let mut legs: Vec<Leg> = vec![];
+        for l in trip_legs {
+            let on_trip = |c: &&connection::Connection| c.trip_id == l.trip_id && c.route_idx == l.route_idx;
+            let dep = stations[l.from_idx].departures.iter().map(|id| &connections[order[*id]]).find(on_trip)
+                .ok_or_else(|| format!("trip {} of route {} does not depart at {}", l.trip_id, l.route_idx, stations[l.from_idx].id))?;
+            let arr = stations[l.to_idx].arrivals.iter().map(|id| &connections[order[*id]]).filter(on_trip)
+                .filter(|c| c.arrival.scheduled >= dep.departure.scheduled)
+                .min_by_key(|c| c.arrival.scheduled)
+                .ok_or_else(|| format!("trip {} of route {} does not arrive at {}", l.trip_id, l.route_idx, stations[l.to_idx].id))?;
+            let footpath_duration = match legs.last() {
+                Some(prev) => footpath_duration(stations, connections[prev.arrival_idx].to_idx, l.from_idx)?,
+                None => None
+            };
+            legs.push(new_leg(order[dep.id], order[arr.id], footpath_duration));
+        }
+        Ok(Itinerary::new(legs))
+    }
+
+    fn new(legs: Vec<Leg>) -> Itinerary {
+        Itinerary {
+            legs,
+            final_footpath_duration: None,
+            destination_arrival: distribution::Distribution::empty(0)
+        }
+    }
+
+    // The arrival distribution is propagated leg by leg: The traveler is ready for a leg at the arrival of the previous one
+    // (or at start_time) plus the transfer time or walk, and catches it with the probability that it departs afterwards,
+    // assuming independent delays. Given that it was caught, the leg departs later than unconditionally expected,
+    // which shifts its arrival accordingly. A missed leg is continued with its fallback (see find_fallbacks),
+    // whose destination arrival is mixed in with the probability of missing the leg.
+    // The feasible_probability of the destination arrival is the probability of reaching the destination this way.
+    pub fn evaluate(&mut self, store: &mut distribution_store::Store, connections: &[connection::Connection], stations: &[connection::Station], start_time: types::Mtime, now: types::Mtime) {
+        let mut destination_arrival = distribution::Distribution::empty(0);
+        let mut reached = 1.0;
+        let mut arrival = distribution::Distribution::uniform(start_time, 1);
+        for i in 0..self.legs.len() {
+            let dep = &connections[self.legs[i].departure_idx];
+            let ready = match self.legs[i].footpath_duration {
+                Some(duration) => arrival.convolve(&store.walking_distribution(duration)),
+                None if i == 0 => arrival,
+                None => arrival.shift(stations[dep.from_idx].transfer_time as types::Mtime)
+            };
+            let departure = store.delay_distribution(&dep.departure, true, dep.product_type, now);
+            let (p, caught_departure_mean) = if dep.departure.in_out_allowed { catch(&ready, &departure) } else { (0.0, 0.0) };
+            self.legs[i].transfer_probability = p;
+            if let Some(fallback) = &self.legs[i].fallback_arrival {
+                destination_arrival.add(fallback, reached*(1.0-p)*fallback.feasible_probability);
+            }
+            reached *= p;
+            let arr = &connections[self.legs[i].arrival_idx];
+            let shift = if p > 0.0 { (caught_departure_mean-departure.mean()).round() as types::Mtime } else { 0 };
+            arrival = store.delay_distribution(&arr.arrival, false, arr.product_type, now).shift(shift);
+        }
+        if self.legs.is_empty() || !connections[self.legs.last().unwrap().arrival_idx].arrival.in_out_allowed {
+            reached = 0.0;
+        }
+        if reached > 0.0 {
+            let arrival = match self.final_footpath_duration {
+                Some(duration) => arrival.convolve(&store.walking_distribution(duration)),
+                None => arrival
+            };
+            destination_arrival.add(&arrival, reached);
+        }
+        destination_arrival.feasible_probability = destination_arrival.histogram.iter().sum();
+        destination_arrival.normalize();
+        self.destination_arrival = destination_arrival;
+    }
+
+    // The fallback when missing a leg is the best departure of a query from the stop where the previous leg arrives
+    // (or the origin) at the time of the missed departure, with the transfers of the station contraction.
+    // The timetable must already be preprocessed, as the legs refer to positions in connections.
+    pub fn find_fallbacks(&mut self, store: &mut distribution_store::Store, connections: &mut Vec<connection::Connection>, stations: &[connection::Station], cut: &mut FxHashSet<(usize, usize)>, order: &mut Vec<usize>, contraction: &StationContraction, destination_idx: usize, max_time: types::Mtime, now: types::Mtime) {
+        for i in 0..self.legs.len() {
+            let missed = &connections[self.legs[i].departure_idx];
+            let missed_trip = (missed.trip_id, missed.route_idx);
+            let stop_idx = if i == 0 { missed.from_idx } else { connections[self.legs[i-1].arrival_idx].to_idx };
+            let query = Query {
+                origin_idx: stop_idx,
+                destination_idx,
+                start_time: missed.departure.projected(),
+                max_time
+            };
+            let station_labels = {
+                let mut env = topocsa::Environment::new(store, connections, stations, cut, order, now, 0.01, 0.001, false, false);
+                env.set_station_contraction(contraction).unwrap();
+                env.query(query)
+            };
+            let best = station_labels[contraction.stop_to_group[stop_idx]].iter().filter(|l| {
+                let c = &connections[order[l.connection_id]];
+                (c.trip_id, c.route_idx) != missed_trip && c.departure.in_out_allowed && l.destination_arrival.feasible_probability > 0.0
+                    && c.departure.projected() >= query.start_time + contraction.get_transfer_time(stop_idx, c.from_idx) as types::Mtime
+            }).min_by(|a, b| a.destination_arrival.mean.partial_cmp(&b.destination_arrival.mean).unwrap());
+            self.legs[i].fallback_idx = best.map(|l| order[l.connection_id]);
+            self.legs[i].fallback_arrival = best.map(|l| l.destination_arrival.clone());
+        }
+    }
+}
+
+// Probability that a departure is caught by a traveler ready at the given time, and its expected time if caught.
+fn catch(ready: &distribution::Distribution, departure: &distribution::Distribution) -> (types::MFloat, types::MFloat) {
+    let mut p = 0.0;
+    let mut mean = 0.0;
+    for (i, d) in departure.histogram.iter().enumerate() {
+        let t = departure.start+i as types::Mtime*departure.bucket_width;
+        let q = d*ready.cdf(t);
+        p += q;
+        mean += q*t as types::MFloat;
+    }
+    if p == 0.0 {
+        return (0.0, 0.0);
+    }
+    (p*departure.feasible_probability, mean/p)
+}
+
+fn new_leg(departure_idx: usize, arrival_idx: usize, footpath_duration: Option<u16>) -> Leg {
+    Leg {
+        departure_idx,
+        arrival_idx,
+        footpath_duration,
+        transfer_probability: 0.0,
+        fallback_idx: None,
+        fallback_arrival: None
+    }
+}
+
+fn footpath_duration(stations: &[connection::Station], from_idx: usize, to_idx: usize) -> Result<Option<u16>, String> {
+    if from_idx == to_idx {
+        return Ok(None);
+    }
+    match stations[from_idx].footpaths.iter().find(|f| f.target_location_idx == to_idx) {
+        Some(f) => Ok(Some(f.duration)),
+        None => Err(format!("no footpath from {} to {}", stations[from_idx].id, stations[to_idx].id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gtfs;
+
+    #[test]
+    fn transfer_probabilities() {
+        let mut store = distribution_store::Store::new();
+        store.insert_from_distribution(0..0, 0..0, false, 1, distribution::Distribution::uniform(0, 4));
+        store.insert_from_distribution(0..0, 0..0, true, 1, distribution::Distribution::uniform(0, 1));
+        let stations: Vec<connection::Station> = (0..3).map(|i| connection::Station::new(i.to_string(), format!("station{}", i), vec![])).collect();
+        let connections = vec![
+            connection::Connection::new(0, 0, 1, 0, false, 0, 10, None, 1, 20, None),
+            connection::Connection::new(1, 1, 1, 0, false, 1, 23, None, 2, 30, None)
+        ];
+        let mut itinerary = Itinerary {
+            legs: vec![
+                new_leg(0, 0, None),
+                new_leg(1, 1, None)
+            ],
+            final_footpath_duration: Some(2),
+            destination_arrival: distribution::Distribution::empty(0)
+        };
+        itinerary.evaluate(&mut store, &connections, &stations, 0, 0);
+        assert_eq!(itinerary.legs[0].transfer_probability, 1.0);
+        // arrival delays 0..=3 with one minute to transfer, departure delay 0
+        assert_eq!(itinerary.legs[1].transfer_probability, 0.75);
+        assert_eq!(itinerary.destination_arrival.start, 32);
+        assert_eq!(itinerary.destination_arrival.mean, 33.5);
+        assert_eq!(itinerary.destination_arrival.feasible_probability, 0.75);
+    }
+
+    #[test]
+    fn first_leg_probability() {
+        let mut store = distribution_store::Store::new();
+        store.insert_from_distribution(0..0, 0..0, true, 1, distribution::Distribution::uniform(-1, 2));
+        let stations: Vec<connection::Station> = (0..2).map(|i| connection::Station::new(i.to_string(), format!("station{}", i), vec![])).collect();
+        let connections = vec![
+            connection::Connection::new(0, 0, 1, 0, false, 0, 10, None, 1, 20, None)
+        ];
+        let mut itinerary = Itinerary::new(vec![new_leg(0, 0, None)]);
+        itinerary.evaluate(&mut store, &connections, &stations, 9, 0);
+        assert_eq!(itinerary.legs[0].transfer_probability, 1.0);
+        // departing one minute early misses a traveler arriving at 10
+        itinerary.evaluate(&mut store, &connections, &stations, 10, 0);
+        assert_eq!(itinerary.legs[0].transfer_probability, 0.5);
+        assert_eq!(itinerary.destination_arrival.feasible_probability, 0.5);
+        itinerary.legs[0].footpath_duration = Some(2);
+        itinerary.evaluate(&mut store, &connections, &stations, 7, 0);
+        assert_eq!(itinerary.legs[0].transfer_probability, 1.0);
+        itinerary.evaluate(&mut store, &connections, &stations, 9, 0);
+        assert_eq!(itinerary.legs[0].transfer_probability, 0.0);
+    }
+
+    #[cfg(feature = "nigiri")]
+    #[test]
+    fn from_nigiri_journey() {
+        let leg = |transport_idx: usize, from_stop_idx: u16, to_stop_idx: u16, is_footpath: bool, duration: u16| motis_nigiri::Leg {
+            from_location_idx: 0,
+            to_location_idx: 0,
+            from_stop_idx,
+            to_stop_idx,
+            transport_idx,
+            day_idx: 1,
+            is_footpath,
+            duration,
+            dep_time: 0,
+            arr_time: 0
+        };
+        let journey = motis_nigiri::Journey {
+            legs: vec![
+                leg(0, 0, 0, true, 2),
+                leg(0, 0, 2, false, 0),
+                leg(0, 0, 0, true, 3),
+                leg(1, 1, 3, false, 0),
+                leg(0, 0, 0, true, 4)
+            ],
+            start_time: 0,
+            dest_time: 0,
+            transfers: 1
+        };
+        // transport 0 has the connections 0 and 1, transport 1 the connections 2, 3 and 4
+        let transport_and_day_to_connection_id = HashMap::from([((0, 1), 0), ((1, 1), 2)]);
+        let order = vec![4, 3, 2, 1, 0];
+        let itinerary = Itinerary::from_journey(&journey, &transport_and_day_to_connection_id, &order, 2);
+        let tpm = 2;
+        assert_eq!(itinerary.legs.iter().map(|l| (l.departure_idx, l.arrival_idx, l.footpath_duration)).collect::<Vec<_>>(), vec![(4, 3, Some(2*tpm)), (1, 0, Some(3*tpm))]);
+        assert_eq!(itinerary.final_footpath_duration, Some(4*tpm));
+    }
+
+    #[test]
+    fn arrival_conditioned_on_transfer() {
+        let mut store = distribution_store::Store::new();
+        store.insert_from_distribution(0..0, 0..0, true, 1, distribution::Distribution::uniform(0, 4));
+        let stations: Vec<connection::Station> = (0..2).map(|i| connection::Station::new(i.to_string(), format!("station{}", i), vec![])).collect();
+        let connections = vec![
+            connection::Connection::new(0, 0, 1, 0, false, 0, 10, None, 1, 20, None)
+        ];
+        let mut itinerary = Itinerary::new(vec![new_leg(0, 0, None)]);
+        itinerary.evaluate(&mut store, &connections, &stations, 12, 0);
+        assert_eq!(itinerary.legs[0].transfer_probability, 0.5);
+        // caught only when departing at 12 or 13, i.e. one minute later than expected
+        assert_eq!(itinerary.destination_arrival.mean, 21.0);
+        assert_eq!(itinerary.destination_arrival.feasible_probability, 0.5);
+    }
+
+    #[test]
+    fn fallbacks_mixed_in() {
+        let mut store = distribution_store::Store::new();
+        store.insert_from_distribution(0..0, 0..0, false, 1, distribution::Distribution::uniform(0, 4));
+        let stations: Vec<connection::Station> = (0..3).map(|i| connection::Station::new(i.to_string(), format!("station{}", i), vec![])).collect();
+        let connections = vec![
+            connection::Connection::new(0, 0, 1, 0, false, 0, 10, None, 1, 20, None),
+            connection::Connection::new(1, 1, 1, 0, false, 1, 23, None, 2, 30, None)
+        ];
+        let mut itinerary = Itinerary::new(vec![new_leg(0, 0, None), new_leg(1, 1, None)]);
+        let mut fallback_arrival = distribution::Distribution::uniform(50, 1);
+        fallback_arrival.feasible_probability = 0.8;
+        itinerary.legs[1].fallback_arrival = Some(fallback_arrival);
+        itinerary.evaluate(&mut store, &connections, &stations, 0, 0);
+        assert_eq!(itinerary.legs[1].transfer_probability, 0.75);
+        // 0.75 arrive at 30..=33, 0.25*0.8 with the fallback at 50
+        assert_float_absolute_eq!(itinerary.destination_arrival.feasible_probability, 0.95, 1e-6);
+        assert_float_absolute_eq!(itinerary.destination_arrival.mean, (0.75*31.5+0.2*50.0)/0.95, 1e-4);
+        assert_float_absolute_eq!(itinerary.destination_arrival.histogram.iter().sum::<types::MFloat>(), 1.0, 1e-6);
+    }
+
+    #[test]
+    fn resolve_and_fallbacks() {
+        let mut store = distribution_store::Store::new();
+        let mut stations: Vec<connection::Station> = (0..4).map(|i| connection::Station::new(i.to_string(), format!("station{}", i), vec![])).collect();
+        let mut connections = vec![
+            connection::Connection::new(0, 0, 1, 0, false, 0, 10, None, 1, 20, None),
+            connection::Connection::new(1, 0, 1, 0, false, 1, 21, None, 2, 25, None),
+            connection::Connection::new(2, 1, 1, 0, false, 2, 27, None, 3, 40, None),
+            connection::Connection::new(3, 1, 1, 1, false, 2, 30, None, 3, 45, None),
+            connection::Connection::new(4, 1, 1, 2, false, 2, 35, None, 3, 42, None),
+            connection::Connection::new(5, 0, 1, 1, false, 0, 15, None, 1, 22, None),
+            connection::Connection::new(6, 0, 1, 1, false, 1, 23, None, 2, 28, None)
+        ];
+        for c in &connections {
+            stations[c.from_idx].departures.push(c.id);
+            stations[c.to_idx].arrivals.push(c.id);
+        }
+        let contraction = gtfs::get_station_contraction(&stations);
+        let mut cut = FxHashSet::default();
+        let mut order = vec![];
+        {
+            let mut env = topocsa::Environment::new(&mut store, &mut connections, &stations, &mut cut, &mut order, 0, 0.01, 0.001, false, false);
+            env.set_station_contraction(&contraction).unwrap();
+            env.preprocess();
+        }
+        let legs = |itinerary: &Itinerary| itinerary.legs.iter().map(|l| (connections[l.departure_idx].id, connections[l.arrival_idx].id)).collect::<Vec<_>>();
+        let by_ids = Itinerary::from_connection_ids(&[0, 1, 2], &connections, &stations, &order).unwrap();
+        assert_eq!(legs(&by_ids), vec![(0, 1), (2, 2)]);
+        let mut by_trips = Itinerary::from_trip_legs(&[
+            TripLeg { trip_id: 0, route_idx: 0, from_idx: 0, to_idx: 2 },
+            TripLeg { trip_id: 0, route_idx: 1, from_idx: 2, to_idx: 3 }
+        ], &connections, &stations, &order).unwrap();
+        assert_eq!(legs(&by_trips), vec![(0, 1), (2, 2)]);
+        by_trips.find_fallbacks(&mut store, &mut connections, &stations, &mut cut, &mut order, &contraction, 3, 100, 0);
+        let fallbacks: Vec<Option<usize>> = by_trips.legs.iter().map(|l| l.fallback_idx.map(|idx| connections[idx].id)).collect();
+        // the later connection 4 arrives before connection 3, also when continuing with trip 1 of route 0
+        assert_eq!(fallbacks, vec![Some(5), Some(4)]);
+        assert_eq!(by_trips.legs[0].fallback_arrival.as_ref().unwrap().mean, 42.0);
+        assert_eq!(by_trips.legs[1].fallback_arrival.as_ref().unwrap().mean, 42.0);
+    }
+
+    #[test]
+    fn invalid_legs() {
+        let mut stations: Vec<connection::Station> = (0..3).map(|i| connection::Station::new(i.to_string(), format!("station{}", i), vec![])).collect();
+        let connections = vec![
+            connection::Connection::new(0, 0, 1, 0, false, 0, 10, None, 1, 20, None),
+            connection::Connection::new(1, 1, 1, 0, false, 2, 25, None, 0, 30, None)
+        ];
+        for c in &connections {
+            stations[c.from_idx].departures.push(c.id);
+            stations[c.to_idx].arrivals.push(c.id);
+        }
+        let order: Vec<usize> = (0..connections.len()).collect();
+        let leg = |route_idx: usize, from_idx: usize, to_idx: usize| TripLeg { trip_id: 0, route_idx, from_idx, to_idx };
+        assert_eq!(Itinerary::from_trip_legs(&[leg(0, 1, 0)], &connections, &stations, &order).unwrap_err(), "trip 0 of route 0 does not depart at 1");
+        assert_eq!(Itinerary::from_trip_legs(&[leg(0, 0, 2)], &connections, &stations, &order).unwrap_err(), "trip 0 of route 0 does not arrive at 2");
+        assert_eq!(Itinerary::from_trip_legs(&[leg(0, 0, 1), leg(1, 2, 0)], &connections, &stations, &order).unwrap_err(), "no footpath from 1 to 2");
+        assert!(Itinerary::from_connection_ids(&[0, 1], &connections, &stations, &order).is_err());
+    }
+}
@@ -0,0 +1,347 @@
+use std::collections::BTreeMap;
+#[cfg(feature = "nigiri")]
+use std::fs;
+use std::ops::Range;
+#[cfg(feature = "nigiri")]
+use std::time::UNIX_EPOCH;
+#[cfg(feature = "nigiri")]
+use glob::glob;
+use rustc_hash::FxHashMap;
+#[cfg(feature = "nigiri")]
+use rustc_hash::FxHashSet;
+use serde::{Serialize, Deserialize};
+
+use crate::connection;
+use crate::distribution_store::{HistogramRow, ImportReport, Store};
+#[cfg(feature = "nigiri")]
+use crate::gtfs;
+use crate::types;
+
+// (lower, upper) in minutes, None for open ends
+type Bucket = (Option<i16>, Option<i16>);
+type HistogramKey = (i16, bool, Option<Bucket>, Option<Bucket>);
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct LearningConfig {
+    pub prior_ttl_edges: Vec<i16>,
+    pub prior_delay_edges: Vec<i16>,
+    pub latest_sample_delay_edges: Vec<i16>,
+    // the latest sample only counts as final if it was taken at most this many minutes before the event
+    pub max_final_ttl: i16,
+    // minutes after the projected event time after which no more samples are expected
+    pub horizon: i16
+}
+
+impl Default for LearningConfig {
+    fn default() -> Self {
+        LearningConfig {
+            prior_ttl_edges: vec![-20, -15, -10, -5, 0, 5, 10, 15, 20, 25, 30, 45, 60, 75, 90, 120, 150, 180, 240, 300, 360],
+            prior_delay_edges: [-20, -15].into_iter().chain(-10..=11).chain([16, 21, 26, 31, 46, 61, 76, 91]).collect(),
+            latest_sample_delay_edges: [-90, -75, -60, -45, -30, -25, -20, -15].into_iter().chain(-10..=11).chain([16, 21, 26, 31, 46, 61, 76, 91]).collect(),
+            max_final_ttl: 0,
+            horizon: 60
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ArchiveConfig {
+    pub gtfs_path: String,
+    pub gtfsrt_glob: String,
+    pub start_date: Vec<i32>,
+    pub num_days: i32,
+    #[serde(default)]
+    pub learning: LearningConfig
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Sample {
+    ttl: i16,
+    delay: Option<i16>,
+    cancelled: bool
+}
+
+#[derive(Debug)]
+struct Event {
+    product_type: i16,
+    projected: types::Mtime,
+    samples: Vec<Sample>
+}
+
+pub struct DelayLearner {
+    config: LearningConfig,
+    events: FxHashMap<(usize, bool), Event>,
+    histogram: BTreeMap<HistogramKey, BTreeMap<Option<Bucket>, i32>>,
+    ticks_per_minute: types::Mtime,
+    pub finished: usize,
+    pub unfinished: usize
+}
+
+fn bucket(edges: &[i16], value: i16) -> Bucket {
+    let i = edges.partition_point(|e| *e <= value);
+    (if i == 0 { None } else { Some(edges[i-1]) }, edges.get(i).copied())
+}
+
+fn to_minutes(ticks: types::Mtime, ticks_per_minute: types::Mtime) -> i16 {
+    ticks.div_euclid(ticks_per_minute) as i16
+}
+
+fn format_bucket(bucket: &Option<Bucket>) -> String {
+    match bucket {
+        None => "NULL".to_string(),
+        Some((None, None)) => "(,)".to_string(),
+        Some((None, Some(upper))) => format!("(,{})", upper),
+        Some((Some(lower), None)) => format!("[{},)", lower),
+        Some((Some(lower), Some(upper))) => format!("[{},{})", lower, upper)
+    }
+}
+
+fn to_range(bucket: &Option<Bucket>) -> Range<i16> {
+    match bucket {
+        None => 0..0,
+        Some((lower, upper)) => lower.unwrap_or(upper.unwrap_or(0))..upper.unwrap_or(lower.unwrap_or(0))
+    }
+}
+
+impl DelayLearner {
+    pub fn new(config: LearningConfig, ticks_per_minute: types::Mtime) -> DelayLearner {
+        DelayLearner {
+            config,
+            events: FxHashMap::default(),
+            histogram: BTreeMap::new(),
+            ticks_per_minute,
+            finished: 0,
+            unfinished: 0
+        }
+    }
+
+    // Records the current realtime state of the departure or arrival of c as seen at now.
+    // Within a prior TTL bucket, only the latest sample of an event is kept, so that an event
+    // counts once per bucket no matter how many feeds repeat it.
+    pub fn observe(&mut self, c: &connection::Connection, is_departure: bool, cancelled: bool, now: types::Mtime) {
+        let stop_info = if is_departure { &c.departure } else { &c.arrival };
+        let event = self.events.entry((c.id, is_departure)).or_insert_with(|| Event {
+            product_type: c.product_type,
+            projected: stop_info.projected(),
+            samples: vec![]
+        });
+        event.projected = stop_info.projected();
+        let sample = Sample {
+            ttl: to_minutes(stop_info.projected()-now, self.ticks_per_minute),
+            delay: stop_info.delay.map(|d| to_minutes(d as types::Mtime, self.ticks_per_minute)),
+            cancelled
+        };
+        let edges = &self.config.prior_ttl_edges;
+        match event.samples.last_mut() {
+            Some(last) if bucket(edges, last.ttl) == bucket(edges, sample.ttl) => *last = sample,
+            _ => event.samples.push(sample)
+        }
+    }
+
+    pub fn finalize_before(&mut self, now: types::Mtime) {
+        let horizon = types::minutes(self.config.horizon as types::Mtime, self.ticks_per_minute);
+        let done: Vec<(usize, bool)> = self.events.iter().filter(|(_, e)| e.projected+horizon < now).map(|(k, _)| *k).collect();
+        for key in done {
+            let event = self.events.remove(&key).unwrap();
+            self.finalize(key.1, event);
+        }
+    }
+
+    pub fn finish(&mut self) {
+        let events = std::mem::take(&mut self.events);
+        for (key, event) in events {
+            self.finalize(key.1, event);
+        }
+    }
+
+    fn finalize(&mut self, is_departure: bool, event: Event) {
+        let last = *event.samples.last().unwrap();
+        if last.ttl > self.config.max_final_ttl || !last.cancelled && last.delay.is_none() {
+            self.unfinished += 1;
+            return;
+        }
+        let latest = |prior_delay: Option<i16>| if last.cancelled {
+            None
+        } else {
+            Some(bucket(&self.config.latest_sample_delay_edges, last.delay.unwrap()-prior_delay.unwrap_or(0)))
+        };
+        let mut counts: Vec<(HistogramKey, Option<Bucket>)> = event.samples.iter().map(|s| (
+            (event.product_type, is_departure, Some(bucket(&self.config.prior_ttl_edges, s.ttl)), s.delay.map(|d| bucket(&self.config.prior_delay_edges, d))),
+            latest(s.delay)
+        )).collect();
+        counts.push(((event.product_type, is_departure, None, None), latest(None)));
+        for (key, latest_sample_delay) in counts {
+            *self.histogram.entry(key).or_default().entry(latest_sample_delay).or_insert(0) += 1;
+        }
+        self.finished += 1;
+    }
+
+    pub fn rows(&self) -> Vec<HistogramRow> {
+        self.histogram.iter().flat_map(|(key, latest)| latest.iter().map(|(latest_sample_delay, sample_count)| HistogramRow {
+            product_type: key.0,
+            is_departure: key.1,
+            prior_ttl: to_range(&key.2),
+            prior_delay: to_range(&key.3),
+            latest_sample_delay: to_range(latest_sample_delay),
+            sample_count: *sample_count
+        })).collect()
+    }
+
+    pub fn load_into(&self, store: &mut Store) -> ImportReport {
+        store.load_histogram(&self.rows())
+    }
+
+    pub fn write_csv(&self, path: &str) {
+        let mut wtr = csv::Writer::from_path(path).unwrap();
+        wtr.write_record(["product_type_id", "is_departure", "prior_ttl_bucket", "prior_delay_bucket", "latest_sample_delay_bucket", "sample_count"]).unwrap();
+        for (key, latest) in &self.histogram {
+            for (latest_sample_delay, sample_count) in latest {
+                wtr.write_record([
+                    key.0.to_string(),
+                    if key.1 { "True" } else { "False" }.to_string(),
+                    format_bucket(&key.2),
+                    format_bucket(&key.3),
+                    format_bucket(latest_sample_delay),
+                    sample_count.to_string()
+                ]).unwrap();
+            }
+        }
+        wtr.flush().unwrap();
+    }
+}
+
+pub fn load_archive_config(path: &str) -> ArchiveConfig {
+    let buf = std::fs::read(path).unwrap();
+    serde_json::from_slice(&buf).unwrap()
+}
+
+// Replays the GTFS-RT files of the glob in order, using their mtime as the observation time, as the simulation does.
+#[cfg(feature = "nigiri")]
+pub fn learn_from_archive(conf: &ArchiveConfig) -> DelayLearner {
+    let start_date = chrono::NaiveDate::from_ymd_opt(conf.start_date[0], conf.start_date[1] as u32, conf.start_date[2] as u32).unwrap();
+    let t = gtfs::load_timetable(&conf.gtfs_path, start_date, start_date+chrono::Days::new(conf.num_days as u64+1));
+    let reference_ts = t.get_start_day_ts() as u64;
+    let mut stations = vec![];
+    let mut routes = vec![];
+    let mut connections = vec![];
+    let ticks_per_minute = types::one_tick_per_minute();
+    let mapping = gtfs::retrieve(&t, &mut stations, &mut routes, &mut connections, ticks_per_minute);
+    let mut learner = DelayLearner::new(conf.learning.clone(), ticks_per_minute);
+    for f in glob(&conf.gtfsrt_glob).expect("Failed to read glob pattern") {
+        let path = f.unwrap();
+        let mtime = fs::metadata(&path).unwrap().modified().unwrap().duration_since(UNIX_EPOCH).unwrap().as_secs();
+        if mtime < reference_ts {
+            continue;
+        }
+        let now = ((mtime-reference_ts)/types::seconds_per_tick(ticks_per_minute) as u64) as types::Mtime;
+        println!("Loading GTFSRT {}", path.display());
+        // an event may be updated several times per feed, observe it once in its latest state
+        let mut touched: FxHashSet<(usize, bool)> = FxHashSet::default();
+        gtfs::load_realtime(path.to_str().unwrap(), &t, &mapping, ticks_per_minute, |connection_id: usize, is_departure: bool, location_idx: Option<usize>, in_out_allowed: Option<bool>, delay: Option<i16>| {
+            connections[connection_id].update(is_departure, location_idx, in_out_allowed, delay);
+            touched.insert((connection_id, is_departure));
+        });
+        for (connection_id, is_departure) in touched {
+            let c = &connections[connection_id];
+            let stop_info = if is_departure { &c.departure } else { &c.arrival };
+            learner.observe(c, is_departure, !stop_info.in_out_allowed, now);
+        }
+        learner.finalize_before(now);
+    }
+    learner.finish();
+    println!("learned from {} events, {} without final sample", learner.finished, learner.unfinished);
+    learner
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::{Connection, StopInfo};
+
+    fn observe_trip(learner: &mut DelayLearner, id: usize, delays: &[(types::Mtime, i16)], cancelled: bool) {
+        let mut c = Connection::new(id, 0, 1, 0, false, 0, 100, None, 1, 110, None);
+        for (now, delay) in delays {
+            c.update(true, None, None, Some(*delay));
+            learner.observe(&c, true, cancelled, *now);
+        }
+    }
+
+    #[test]
+    fn bucket_edges() {
+        let edges = [0, 5, 10];
+        assert_eq!(bucket(&edges, -1), (None, Some(0)));
+        assert_eq!(bucket(&edges, 0), (Some(0), Some(5)));
+        assert_eq!(bucket(&edges, 9), (Some(5), Some(10)));
+        assert_eq!(bucket(&edges, 10), (Some(10), None));
+        assert_eq!(format_bucket(&Some((None, Some(0)))), "(,0)");
+        assert_eq!(format_bucket(&Some((Some(10), None))), "[10,)");
+        assert_eq!(format_bucket(&None), "NULL");
+    }
+
+    #[test]
+    fn final_delay_relative_to_prior() {
+        let mut learner = DelayLearner::new(LearningConfig::default(), 1);
+        observe_trip(&mut learner, 0, &[(70, 2), (106, 5)], false);
+        learner.finish();
+        assert_eq!(learner.finished, 1);
+        let rows = learner.rows();
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[0], HistogramRow { product_type: 1, is_departure: true, prior_ttl: 0..0, prior_delay: 0..0, latest_sample_delay: 5..6, sample_count: 1 });
+        assert_eq!(rows[1], HistogramRow { product_type: 1, is_departure: true, prior_ttl: -5..0, prior_delay: 5..6, latest_sample_delay: 0..1, sample_count: 1 });
+        assert_eq!(rows[2], HistogramRow { product_type: 1, is_departure: true, prior_ttl: 30..45, prior_delay: 2..3, latest_sample_delay: 3..4, sample_count: 1 });
+    }
+
+    #[test]
+    fn repeated_feeds_count_once() {
+        let mut learner = DelayLearner::new(LearningConfig::default(), 1);
+        // the first four feeds fall into the same prior TTL bucket [30,45)
+        observe_trip(&mut learner, 0, &[(60, 1), (62, 1), (65, 2), (70, 2), (106, 5), (107, 5)], false);
+        learner.finish();
+        assert_eq!(learner.finished, 1);
+        let rows = learner.rows();
+        assert!(rows.iter().all(|r| r.sample_count == 1));
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[2], HistogramRow { product_type: 1, is_departure: true, prior_ttl: 30..45, prior_delay: 2..3, latest_sample_delay: 3..4, sample_count: 1 });
+    }
+
+    #[test]
+    fn unfinished_and_cancelled() {
+        let mut learner = DelayLearner::new(LearningConfig::default(), 1);
+        observe_trip(&mut learner, 0, &[(70, 2)], false);
+        learner.finalize_before(100);
+        assert_eq!(learner.finished+learner.unfinished, 0);
+        learner.finalize_before(200);
+        assert_eq!(learner.unfinished, 1);
+        observe_trip(&mut learner, 1, &[(110, 0)], true);
+        learner.finish();
+        assert_eq!(learner.finished, 1);
+        assert!(learner.rows().iter().all(|r| r.latest_sample_delay == (0..0)));
+    }
+
+    #[test]
+    fn learned_store() {
+        let mut learner = DelayLearner::new(LearningConfig::default(), 1);
+        for i in 0..200 {
+            observe_trip(&mut learner, i, &[(80, 1), (103, (i%3) as i16)], false);
+        }
+        learner.finish();
+        let path = std::env::temp_dir().join("stost_learned.csv");
+        learner.write_csv(path.to_str().unwrap());
+        let mut from_csv = Store::new();
+        let report = from_csv.load_distributions_with_schema(path.to_str().unwrap(), &Default::default());
+        assert_eq!(report.problems.len(), 0);
+        let mut from_rows = Store::new();
+        assert_eq!(learner.load_into(&mut from_rows).distributions, report.distributions);
+        for store in [&from_csv, &from_rows] {
+            let d = store.delay_distribution(&StopInfo::new(100, Some(1)), true, 1, 80);
+            assert_eq!(d.start, 100);
+            assert_eq!(d.histogram.len(), 3);
+            assert_float_absolute_eq!(d.mean, 101.0, 0.01);
+        }
+        let snapshot = std::env::temp_dir().join("stost_learned.snapshot");
+        from_rows.save_snapshot(snapshot.to_str().unwrap());
+        let loaded = Store::load_snapshot(snapshot.to_str().unwrap());
+        assert_float_absolute_eq!(loaded.delay_distribution(&StopInfo::new(100, None), true, 1, 0).mean, 101.0, 0.01);
+    }
+}
@@ -6,6 +6,10 @@ pub mod query;
 pub mod wire;
 pub mod gtfs;
 pub mod walking;
+pub mod learning;
+pub mod online_correction;
+pub mod synthetic;
+pub mod itinerary;
 
 #[macro_use]
 extern crate assert_float_eq;
@@ -0,0 +1,203 @@
+use rustc_hash::FxHashMap;
+use serde::{Serialize, Deserialize};
+
+use crate::distribution::Distribution;
+use crate::types;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct OnlineCorrectionConfig {
+    // variance of the prior belief that the historical distributions are unbiased today, in minutes^2
+    pub prior_variance: types::MFloat,
+    // predictions made less than this many minutes before the event are not compared
+    pub min_lead: types::Mtime,
+    pub max_shift: types::Mtime,
+    // the correction is reset at this minute of the local service day, after the night services,
+    // with local time being utc_offset minutes ahead of the UTC midnight that times are relative to
+    pub service_day_start: types::Mtime,
+    pub utc_offset: types::Mtime
+}
+
+impl Default for OnlineCorrectionConfig {
+    fn default() -> Self {
+        OnlineCorrectionConfig {
+            prior_variance: 4.0,
+            min_lead: 10,
+            max_shift: 15,
+            service_day_start: 180,
+            utc_offset: 0
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Estimate {
+    precision: types::MFloat,
+    weighted_residuals: types::MFloat,
+    observations: usize
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Prediction {
+    product_type: i16,
+    mean: types::MFloat,
+    variance: types::MFloat
+}
+
+// Normal posterior of today's shift per product type against the historical distributions, reset every day.
+#[derive(Debug, Clone)]
+pub struct OnlineCorrection {
+    pub config: OnlineCorrectionConfig,
+    ticks_per_minute: types::Mtime,
+    day: Option<types::Mtime>,
+    estimates: FxHashMap<i16, Estimate>,
+    predictions: FxHashMap<(usize, bool), Prediction>
+}
+
+impl OnlineCorrection {
+    pub fn new(config: OnlineCorrectionConfig, ticks_per_minute: types::Mtime) -> OnlineCorrection {
+        OnlineCorrection {
+            config,
+            ticks_per_minute,
+            day: None,
+            estimates: FxHashMap::default(),
+            predictions: FxHashMap::default()
+        }
+    }
+
+    fn reset_if_new_day(&mut self, now: types::Mtime) {
+        let boundary = types::minutes(self.config.service_day_start-self.config.utc_offset, self.ticks_per_minute);
+        let day = (now-boundary).div_euclid(types::minutes(1440, self.ticks_per_minute));
+        if self.day != Some(day) {
+            self.day = Some(day);
+            self.estimates.clear();
+            self.predictions.clear();
+        }
+    }
+
+    fn prior_precision(&self) -> types::MFloat {
+        let ticks = self.ticks_per_minute as types::MFloat;
+        1.0/(self.config.prior_variance*ticks*ticks)
+    }
+
+    pub fn predict(&mut self, key: (usize, bool), product_type: i16, mean: types::MFloat, variance: types::MFloat, now: types::Mtime) {
+        self.reset_if_new_day(now);
+        self.predictions.insert(key, Prediction { product_type, mean, variance: variance.max(1.0) });
+    }
+
+    pub fn discard(&mut self, key: (usize, bool)) {
+        self.predictions.remove(&key);
+    }
+
+    pub fn observe(&mut self, key: (usize, bool), actual: types::Mtime, now: types::Mtime) {
+        self.reset_if_new_day(now);
+        if let Some(p) = self.predictions.remove(&key) {
+            let e = self.estimates.entry(p.product_type).or_default();
+            e.precision += 1.0/p.variance;
+            e.weighted_residuals += (actual as types::MFloat-p.mean)/p.variance;
+            e.observations += 1;
+        }
+    }
+
+    pub fn observations(&self, product_type: i16) -> usize {
+        self.estimates.get(&product_type).map(|e| e.observations).unwrap_or(0)
+    }
+
+    pub fn weight(&self, product_type: i16) -> types::MFloat {
+        match self.estimates.get(&product_type) {
+            Some(e) => e.precision/(self.prior_precision()+e.precision),
+            None => 0.0
+        }
+    }
+
+    // posterior mean of the shift in ticks
+    pub fn shift(&self, product_type: i16) -> types::MFloat {
+        match self.estimates.get(&product_type) {
+            Some(e) => {
+                let max_shift = types::minutes(self.config.max_shift, self.ticks_per_minute) as types::MFloat;
+                (e.weighted_residuals/(self.prior_precision()+e.precision)).clamp(-max_shift, max_shift)
+            },
+            None => 0.0
+        }
+    }
+
+    // Blends the two neighbouring integer shifts so that the mean moves by exactly shift().
+    pub fn apply(&self, d: Distribution, product_type: i16) -> Distribution {
+        let shift = self.shift(product_type);
+        if shift == 0.0 || !d.exists() {
+            return d;
+        }
+        let lower = shift.floor();
+        let fraction = shift-lower;
+        if fraction == 0.0 {
+            return d.shift(lower as types::Mtime);
+        }
+        let mut blended = Distribution::empty(0);
+        blended.add(&d.shift(lower as types::Mtime), 1.0-fraction);
+        blended.add(&d.shift(lower as types::Mtime+1), fraction);
+        blended.feasible_probability = d.feasible_probability;
+        blended
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn posterior_shift() {
+        let mut o = OnlineCorrection::new(OnlineCorrectionConfig::default(), 1);
+        assert_eq!(o.shift(1), 0.0);
+        for i in 0..4 {
+            o.predict((i, true), 1, 100.0, 4.0, 50);
+            o.observe((i, true), 104, 110);
+        }
+        o.observe((9, true), 200, 110);
+        assert_eq!(o.observations(1), 4);
+        assert_float_absolute_eq!(o.weight(1), 0.8);
+        assert_float_absolute_eq!(o.shift(1), 3.2);
+        assert_eq!(o.shift(2), 0.0);
+    }
+
+    #[test]
+    fn clamped_and_reset() {
+        let mut o = OnlineCorrection::new(OnlineCorrectionConfig::default(), 1);
+        o.predict((0, true), 1, 100.0, 1.0, 50);
+        o.observe((0, true), 160, 110);
+        assert_float_absolute_eq!(o.shift(1), 15.0);
+        o.predict((1, true), 1, 100.0, 1.0, 1500);
+        assert_eq!(o.shift(1), 0.0);
+    }
+
+    #[test]
+    fn reset_at_local_service_day_start() {
+        let config = OnlineCorrectionConfig { utc_offset: 60, ..Default::default() };
+        let mut o = OnlineCorrection::new(config, 2);
+        o.predict((0, true), 1, 1300.0, 4.0, 2*600);
+        o.observe((0, true), 1310, 2*660);
+        let shift = o.shift(1);
+        assert!(shift > 0.0);
+        // 01:30 local of the next day still belongs to the service day
+        o.predict((1, true), 1, 3000.0, 4.0, 2*(1440+30));
+        assert_eq!(o.shift(1), shift);
+        // 02:59 local, 03:00 local
+        o.predict((2, true), 1, 3000.0, 4.0, 2*(1440+119)+1);
+        assert_eq!(o.shift(1), shift);
+        o.predict((3, true), 1, 3000.0, 4.0, 2*(1440+120));
+        assert_eq!(o.shift(1), 0.0);
+    }
+
+    #[test]
+    fn apply_fractional() {
+        let mut o = OnlineCorrection::new(OnlineCorrectionConfig::default(), 1);
+        o.predict((0, true), 1, 100.0, 4.0, 50);
+        o.observe((0, true), 105, 110);
+        let d = o.apply(Distribution::uniform(100, 2), 1);
+        assert_float_absolute_eq!(o.shift(1), 2.5);
+        assert_eq!(d.start, 102);
+        assert_eq!(d.histogram.len(), 3);
+        assert_float_absolute_eq!(d.mean, 103.0);
+        assert_float_absolute_eq!(d.mean(), 103.0);
+        d.assert();
+    }
+}
@@ -2,12 +2,13 @@ use std::cell::RefCell;
 use std::cmp::Ordering;
 use std::collections::HashMap;
 use std::time::Instant;
-use rustc_hash::FxHashSet;
+use rustc_hash::{FxHashMap, FxHashSet};
 use crate::distribution;
 use crate::distribution_store;
 use crate::connection;
-use crate::gtfs::StationContraction;
+use crate::gtfs::{GtfsTimetable, StationContraction};
 use crate::types;
+use crate::walking::{WALKING_MSG, WALKING_PRODUCT_TYPE};
 use super::Queriable;
 use super::ConnectionLabel;
 use super::Query;
@@ -20,10 +21,11 @@ pub struct Environment<'a> {
     now: types::Mtime,
     order: &'a mut Vec<usize>,
     contraction: Option<&'a StationContraction>,
-    number_of_trips: &'a mut FxHashSet<(usize, usize)>,
     connection_pairs_idx_reverse: Vec<usize>,
     connection_pairs: HashMap<i32, i32>,
-    max_dc: types::Mtime
+    max_dc: types::Mtime,
+    mean_only: bool,
+    base_len: usize
 }
 
 impl PartialEq for ConnectionLabel {
@@ -50,8 +52,9 @@ impl PartialOrd for ConnectionLabel {
 
 impl<'a> Queriable<'a> for Environment<'a> {
 
-    fn set_station_contraction(&mut self, contr: &'a StationContraction) {
+    fn set_station_contraction(&mut self, contr: &'a StationContraction) -> Result<(), String> {
         self.contraction = Some(contr);
+        Ok(())
     }
 
     fn preprocess(&mut self) {
@@ -81,16 +84,23 @@ impl<'a> Queriable<'a> for Environment<'a> {
     fn update(&mut self, connection_id: usize, is_departure: bool, location_idx: Option<usize>, in_out_allowed: Option<bool>, delay: Option<i16>) {
         let c = &mut self.connections[self.order[connection_id]];
         c.update(is_departure, location_idx, in_out_allowed, delay);
+        self.store.borrow_mut().observe_update(c, is_departure, self.now);
+    }
+
+    fn update_min_delay(&mut self, connection_id: usize, is_departure: bool, min_delay: i16) {
+        self.connections[self.order[connection_id]].update_min_delay(is_departure, min_delay);
     }
 
 }
 
 impl<'a> Environment<'a> {
     
-    pub fn new(store: &'a mut distribution_store::Store, connections: &'a mut Vec<connection::Connection>, stations: &'a [connection::Station], cut: &'a mut FxHashSet<(usize, usize)>, order: &'a mut Vec<usize>, now: types::Mtime) -> Environment<'a> {
+    pub fn new(store: &'a mut distribution_store::Store, connections: &'a mut Vec<connection::Connection>, stations: &'a [connection::Station], _cut: &'a mut FxHashSet<(usize, usize)>, order: &'a mut Vec<usize>, now: types::Mtime) -> Environment<'a> {
         if order.is_empty() {
             order.extend(0..connections.len());
         }
+        let base_len = connections.len();
+        let ticks_per_minute = store.ticks_per_minute();
         Environment {
             store: RefCell::new(store),
             connections: connections,
@@ -98,30 +108,193 @@ impl<'a> Environment<'a> {
             now,
             order,
             contraction: None,
-            number_of_trips: cut, // hacky reusing of variable
             connection_pairs_idx_reverse: vec![],
             connection_pairs: HashMap::new(),
-            max_dc: 90
+            max_dc: types::minutes(90, ticks_per_minute),
+            mean_only: true,
+            base_len
         }
     }
 
+    // The final footpaths appended to a timetable by get_decision_graph are dropped by later environments on the same timetable.
+    pub fn from_timetable(store: &'a mut distribution_store::Store, tt: &'a mut GtfsTimetable, now: types::Mtime) -> Environment<'a> {
+        let base_len = *tt.base_len.get_or_insert(tt.connections.len());
+        let mut env = Self::new(store, &mut tt.connections, &tt.stations, &mut tt.cut, &mut tt.order, now);
+        env.base_len = base_len;
+        env
+    }
+
+    // Maximum delay of an arriving connection that is considered for transfers.
+    pub fn set_max_dc(&mut self, max_dc: types::Mtime) {
+        self.max_dc = max_dc;
+    }
+
+    // Whether only expected arrival times are computed or full destination arrival distributions.
+    pub fn set_mean_only(&mut self, mean_only: bool) {
+        self.mean_only = mean_only;
+    }
+
     fn do_preprocess(&mut self) {
+        self.drop_final_footpaths();
         println!("Start preprocessing...");
         self.connections.sort_unstable_by(|a, b|
             b.departure.projected().cmp(&a.departure.projected()).then(b.id.cmp(&a.id))
         );
-        let mut number_of_trips = 0;
         for c in self.connections.iter().enumerate() {
             self.order[c.1.id] = c.0;
-            if number_of_trips <= c.1.trip_id as usize {
-                number_of_trips = (c.1.trip_id+1) as usize;
-            }
         }
-        self.number_of_trips.clear();
-        self.number_of_trips.insert((number_of_trips, 0));
         println!("Done preprocessing.");
     }
 
+    fn group(&self, stop_idx: usize) -> usize {
+        match self.contraction {
+            Some(contr) => contr.stop_to_group[stop_idx],
+            None => stop_idx
+        }
+    }
+
+    // Footpaths to the destination materialized by a previous decision graph.
+    fn drop_final_footpaths(&mut self) {
+        if self.connections.len() > self.base_len {
+            let base_len = self.base_len;
+            self.connections.retain(|c| c.id < base_len);
+            self.order.truncate(base_len);
+        }
+    }
+
+    // Walk from the stop where arr alights to the destination, if the destination is only reachable by a footpath.
+    fn final_footpath(&self, arr: &connection::Connection, destination: usize, id: usize) -> Option<(connection::Connection, distribution::Distribution)> {
+        if self.contraction.is_some() || arr.to_idx == destination || !arr.arrival.in_out_allowed {
+            return None;
+        }
+        let footpath = self.stations[arr.to_idx].footpaths.iter().find(|f| f.target_location_idx == destination)?;
+        let destination_arrival = self.reduce(self.store.borrow().footpath_arrival_distribution(&arr.arrival, arr.product_type, footpath.duration, self.now));
+        let duration = {
+            let store = self.store.borrow();
+            store.walking_profile().map_or(footpath.duration, |p| p.expected_duration(footpath.duration, store.ticks_per_minute()))
+        };
+        let mut walk = connection::Connection::new(
+            id,
+            id,
+            WALKING_PRODUCT_TYPE,
+            arr.id as i32,
+            false,
+            arr.to_idx,
+            arr.arrival.projected(),
+            None,
+            destination,
+            arr.arrival.projected()+duration as types::Mtime,
+            None
+        );
+        walk.message = WALKING_MSG.to_owned();
+        destination_arrival.relevance.set(1.0);
+        if !self.mean_only {
+            walk.destination_arrival.replace(Some(destination_arrival.clone()));
+        }
+        Some((walk, destination_arrival))
+    }
+
+    // Label indices at which a traveler arriving at stop_idx can depart.
+    fn transfer_stops(&self, stop_idx: usize) -> impl Iterator<Item = usize> + '_ {
+        let (first, footpaths) = match self.contraction {
+            Some(contr) => (contr.stop_to_group[stop_idx], &[][..]),
+            None => (stop_idx, &self.stations[stop_idx].footpaths[..])
+        };
+        std::iter::once(first).chain(footpaths.iter().map(|f| f.target_location_idx))
+    }
+
+    fn transfer_time(&self, from_idx: usize, to_idx: usize) -> i32 {
+        match self.contraction {
+            Some(contr) => contr.get_transfer_time(from_idx, to_idx) as i32,
+            None if from_idx == to_idx => self.stations[from_idx].transfer_time as i32,
+            None => self.stations[from_idx].footpaths.iter().find(|f| f.target_location_idx == to_idx).unwrap().duration as i32
+        }
+    }
+
+    fn reduce(&self, d: distribution::Distribution) -> distribution::Distribution {
+        if !self.mean_only {
+            return d;
+        }
+        let mut reduced = distribution::Distribution::empty(0);
+        reduced.mean = d.mean;
+        reduced
+    }
+
+    fn destination_arrival(&self, c: &connection::Connection, destination: usize, dest_contr: usize) -> Option<distribution::Distribution> {
+        if !c.arrival.in_out_allowed {
+            return None;
+        }
+        match self.contraction {
+            Some(contr) if contr.stop_to_group[c.to_idx] == dest_contr => {
+                let new_distribution = self.store.borrow().delay_distribution(&c.arrival, false, c.product_type, self.now);
+                if c.to_idx != destination {
+                    return Some(new_distribution.shift(contr.get_transfer_time(c.to_idx, destination) as i32));
+                }
+                Some(new_distribution)
+            },
+            Some(_) => None,
+            None if c.to_idx == destination => Some(self.store.borrow().delay_distribution(&c.arrival, false, c.product_type, self.now)),
+            None => self.stations[c.to_idx].footpaths.iter().find(|f| f.target_location_idx == destination).map(|f| 
+                self.store.borrow().footpath_arrival_distribution(&c.arrival, c.product_type, f.duration, self.now)
+            )
+        }
+    }
+
+    // Destination arrival when arriving with c and departing with one of the labels at stop_idx,
+    // mixed by the probability of each label being the first one reachable.
+    fn transfer_arrival(&self, c: &connection::Connection, stop_idx: usize, station_labels: &[Vec<ConnectionLabel>]) -> Option<distribution::Distribution> {
+        let mut cum = distribution::Distribution::empty(0);
+        // the first reachable label also catches all early arrivals
+        let mut last_latest_arrival = types::Mtime::MIN/2;
+        let mut mass = 0.0;
+        // under a walking profile, the footpath duration is folded into the arrival distribution as in topocsa
+        let walk = match self.store.borrow().walking_profile() {
+            Some(_) if self.contraction.is_none() && stop_idx != c.to_idx => {
+                let duration = self.transfer_time(c.to_idx, stop_idx) as u16;
+                let store = self.store.borrow();
+                Some((store.footpath_arrival_distribution(&c.arrival, c.product_type, duration, self.now), store.walking_distribution(duration).end()))
+            },
+            _ => None
+        };
+        for dep_label in station_labels[stop_idx].iter().rev() {
+            if (dep_label.departure_mean as i32) < c.arrival.projected() {
+                continue;
+            }
+            if dep_label.destination_arrival.mean == types::MFloat::MAX {
+                break;
+            }
+            if !c.arrival.in_out_allowed {
+                break;
+            }
+            let (m, latest_arrival, transfer_time) = match &walk {
+                Some((arrival, longest_walk)) => {
+                    let latest_arrival = dep_label.departure_mean as i32 - c.arrival.projected();
+                    let m = if latest_arrival > last_latest_arrival {
+                        arrival.cdf(c.arrival.projected()+latest_arrival)-arrival.cdf(c.arrival.projected()+last_latest_arrival)
+                    } else {
+                        0.0
+                    };
+                    (m, latest_arrival, *longest_walk)
+                },
+                None => {
+                    let transfer_time = self.transfer_time(c.to_idx, self.connections[self.order[dep_label.connection_id]].from_idx);
+                    let latest_arrival = dep_label.departure_mean as i32 - c.arrival.projected() - transfer_time;
+                    (self.store.borrow_mut().between_probability_conn(c, last_latest_arrival+1, latest_arrival+1, self.now), latest_arrival, transfer_time)
+                }
+            };
+            if m > 0.0 {
+                cum.add_with(&dep_label.destination_arrival, m, self.mean_only);
+            }
+            mass += m;
+            last_latest_arrival = std::cmp::max(latest_arrival, last_latest_arrival);
+            if dep_label.departure_mean as i32 > c.arrival.projected() + transfer_time + self.max_dc {
+                assert_float_absolute_eq!(mass, 1.0, 1e-3);
+                return Some(cum);
+            }
+        }
+        None
+    }
+
     fn dominates(&self, q: &ConnectionLabel, p: &ConnectionLabel) -> bool {
         if q.destination_arrival.mean < p.destination_arrival.mean {
             return true;
@@ -132,11 +305,20 @@ impl<'a> Environment<'a> {
         return false;
     }
 
+    // Without a StationContraction, labels are kept per stop and a traveler arriving at a stop may transfer there or
+    // walk any of its footpaths, choosing the option with the earliest expected destination arrival.
     pub fn full_query(&mut self, _origin: usize, destination: usize, start_time: types::Mtime, max_time: types::Mtime) -> Vec<Vec<ConnectionLabel>> {
-        let contr = self.contraction.unwrap();
+        self.drop_final_footpaths();
         self.connection_pairs_idx_reverse = vec![self.connections.len(); self.connections.len()];
         let mut station_labels: Vec<Vec<ConnectionLabel>> = (0..self.stations.len()).map(|_i| Vec::new()).collect();
-        let mut trip_labels: Vec<(types::MFloat, usize)> = vec![(types::MFloat::MAX, 0); self.number_of_trips.iter().last().unwrap().0]; 
+        // trip ids are only unique per route in timetables received via the API
+        let mut trip_labels: FxHashMap<(i32, usize), (types::MFloat, usize, distribution::Distribution)> = FxHashMap::default();
+        if !self.mean_only {
+            for c in self.connections.iter() {
+                c.destination_arrival.replace(None);
+            }
+        }
+        let dest_contr = self.group(destination);
         for i in 0..self.connections.len() {
             let c = &self.connections[i];
 
@@ -146,63 +328,36 @@ impl<'a> Environment<'a> {
             if c.departure.projected() >= max_time {
                 continue;
             }
-            let stop_idx = contr.stop_to_group[c.to_idx];
-            let dest_contr = contr.stop_to_group[destination];
-            let mut t1 = types::MFloat::MAX;
-            if stop_idx == dest_contr && c.arrival.in_out_allowed {
-                let mut new_distribution = self.store.borrow().delay_distribution(&c.arrival, false, c.product_type, self.now);
-                if c.to_idx != destination {
-                    let contr = self.contraction.unwrap();
-                    new_distribution = new_distribution.shift(contr.get_transfer_time(c.to_idx, destination) as i32);
-                }
-                t1 = new_distribution.mean;
-            }
-            let t2 = trip_labels[c.trip_id as usize].0;
-            let mut t3 = types::MFloat::MAX;
-            let mut cum = 0.0;
-            let mut last_latest_arrival = -1;
-            let mut mass = 0.0;
-            for dep_label in station_labels[stop_idx].iter().rev() {
-                if (dep_label.departure_mean as i32) < c.arrival.projected() {
-                    continue;
-                }
-                if dep_label.destination_arrival.mean == types::MFloat::MAX {
-                    break;
-                }
-                if !c.arrival.in_out_allowed {
-                    break;
-                }
-                let transfer_time = contr.get_transfer_time(c.to_idx, self.connections[self.order[dep_label.connection_id]].from_idx) as i32;
-                let latest_arrival = dep_label.departure_mean as i32 - c.arrival.projected() - transfer_time;
-                
-                let m = self.store.borrow_mut().between_probability_conn(c, last_latest_arrival+1, latest_arrival+1, self.now);
-                cum += dep_label.destination_arrival.mean * m;
-                mass += m;
-                last_latest_arrival = std::cmp::max(latest_arrival, last_latest_arrival);
-                if dep_label.departure_mean as i32 > c.arrival.projected() + transfer_time + self.max_dc {
-                    t3 = cum;
-                    break;
-                }
-            }
-            if t3 != types::MFloat::MAX {
-                assert_float_absolute_eq!(mass, 1.0, 1e-3);
-            }            
-            let tc = t1.min(t2).min(t3);            
-            if tc != types::MFloat::MAX {
-                if tc < trip_labels[c.trip_id as usize].0 {
-                    trip_labels[c.trip_id as usize] = (tc, i);
-                    self.connection_pairs_idx_reverse[i] = i;
-                } else {
-                    self.connection_pairs_idx_reverse[i] = trip_labels[c.trip_id as usize].1;
+            let t1 = self.destination_arrival(c, destination, dest_contr).map(|d| self.reduce(d));
+            let t3 = self.transfer_stops(c.to_idx)
+                .filter_map(|stop_idx| self.transfer_arrival(c, stop_idx, &station_labels))
+                .min_by(|a, b| a.mean.partial_cmp(&b.mean).unwrap());
+            let trip_key = (c.trip_id, c.route_idx);
+            let trip_label = trip_labels.get(&trip_key);
+            let trip_mean = trip_label.map_or(types::MFloat::MAX, |l| l.0);
+            let tc = [t1, t3].into_iter().flatten().filter(|d| d.mean < trip_mean).min_by(|a, b| a.mean.partial_cmp(&b.mean).unwrap());
+            if tc.is_some() || trip_label.is_some() {
+                let mut distr = match tc {
+                    Some(distr) => {
+                        trip_labels.insert(trip_key, (distr.mean, i, distr.clone()));
+                        self.connection_pairs_idx_reverse[i] = i;
+                        distr
+                    },
+                    None => {
+                        let trip_label = trip_label.unwrap();
+                        self.connection_pairs_idx_reverse[i] = trip_label.1;
+                        trip_label.2.clone()
+                    }
+                };
+                if !self.mean_only {
+                    c.destination_arrival.replace(Some(distr.clone()));
                 }
                 if !c.departure.in_out_allowed {
                     continue;
                 }
-                let departure_station_idx = contr.stop_to_group[c.from_idx];
+                let departure_station_idx = self.group(c.from_idx);
                 let departures = station_labels.get_mut(departure_station_idx).unwrap();
                 let q = &departures.last();
-                let mut distr = distribution::Distribution::empty(0);
-                distr.mean = tc;
                 distr.feasible_probability = 1.0;
                 let mut p = ConnectionLabel{
                     connection_id: c.id,
@@ -228,48 +383,71 @@ impl<'a> Environment<'a> {
     }
 
     pub fn get_decision_graph(&mut self, origin: usize, destination: usize, start_time: types::Mtime, station_labels: &Vec<Vec<ConnectionLabel>>) -> Vec<Vec<ConnectionLabel>> {
-        let contr = self.contraction.unwrap();
         let mut decision_graph: Vec<Vec<ConnectionLabel>> = (0..self.stations.len()).map(|_i| Vec::new()).collect();
         
         let mut priority_queue = std::collections::BinaryHeap::new();
-        let origin_contr = contr.stop_to_group[origin];
 
-        let anchor = station_labels[origin_contr].iter().rev().filter(|l| {
+        let anchor = self.transfer_stops(origin).filter_map(|stop_idx| station_labels[stop_idx].iter().rev().find(|l| {
             let from_idx = self.connections[self.order[l.connection_id]].from_idx;
-            let init_transfer_time = if from_idx == origin { 0 } else { contr.get_transfer_time(origin, from_idx) as i32 };
+            let init_transfer_time = if from_idx == origin { 0 } else { self.transfer_time(origin, from_idx) };
             start_time + init_transfer_time <= l.departure_mean as i32
-        }).next();
+        })).min_by(|a, b| a.destination_arrival.mean.partial_cmp(&b.destination_arrival.mean).unwrap());
         if anchor.is_none() {
             return decision_graph;
         }
         priority_queue.push(anchor.unwrap());
+        let dest_contr = self.group(destination);
+        let mut final_footpaths: Vec<(connection::Connection, distribution::Distribution)> = vec![];
+        let mut walked = FxHashSet::default();
 
         while !priority_queue.is_empty() {
             let p = priority_queue.pop().unwrap();
             let c = &self.connections[self.order[p.connection_id]];
             let arr = &self.connections[self.connection_pairs_idx_reverse[self.order[p.connection_id]] as usize];
             assert_eq!(c.trip_id, arr.trip_id);
-            let stop_idx = contr.stop_to_group[arr.to_idx];
-            let dest_contr = contr.stop_to_group[destination];
+            let stop_idx = self.group(arr.to_idx);
             
             let other = self.connection_pairs.insert(c.id as i32, arr.id as i32);
             assert_eq!(other.unwrap_or(arr.id as i32), arr.id as i32);
-            let existing_deps = decision_graph.get_mut(contr.stop_to_group[c.from_idx]).unwrap();
+            let existing_deps = decision_graph.get_mut(self.group(c.from_idx)).unwrap();
             if !existing_deps.last().is_some_and(|l| p.connection_id == l.connection_id) {
                 existing_deps.push(p.clone());
+                if !self.mean_only {
+                    c.destination_arrival.borrow().as_ref().unwrap().relevance.set(1.0);
+                }
+                if walked.insert(arr.id) {
+                    if let Some(walk) = self.final_footpath(arr, destination, self.connections.len()+final_footpaths.len()) {
+                        final_footpaths.push(walk);
+                    }
+                }
 
                 if dest_contr != stop_idx {
-                    for next_p in station_labels[stop_idx].iter().rev() {
-                        if next_p.departure_mean as i32 >= arr.arrival.projected() && next_p.departure_mean != f32::MAX {
-                            priority_queue.push(next_p);
-                        }
-                        if next_p.departure_mean as i32 > arr.arrival.projected() + self.max_dc {
-                            break;
+                    for transfer_stop_idx in self.transfer_stops(arr.to_idx) {
+                        for next_p in station_labels[transfer_stop_idx].iter().rev() {
+                            if next_p.departure_mean as i32 >= arr.arrival.projected() && next_p.departure_mean != f32::MAX {
+                                priority_queue.push(next_p);
+                            }
+                            if next_p.departure_mean as i32 > arr.arrival.projected() + self.max_dc {
+                                break;
+                            }
                         }
                     }
                 }
             }
         }
+        for (walk, destination_arrival) in final_footpaths {
+            let deps = &mut decision_graph[walk.from_idx];
+            deps.push(ConnectionLabel {
+                connection_id: walk.id,
+                destination_arrival,
+                prob_after: 1.0,
+                departure_mean: walk.departure.projected() as types::MFloat
+            });
+            deps.sort_by(|a, b| a.departure_mean.partial_cmp(&b.departure_mean).unwrap());
+            self.connection_pairs.insert(walk.id as i32, walk.id as i32);
+            self.order.push(self.connections.len());
+            self.connections.push(walk);
+        }
         decision_graph
     }
 }
@@ -11,12 +11,38 @@ use crate::connection;
 use crate::types;
 
 use rustc_hash::FxHashSet;
+use serde::{Deserialize, Serialize};
 
 pub fn query<'a>(store: &'a mut distribution_store::Store, connections: &mut Vec<connection::Connection>, stations: &[connection::Station], origin: usize, destination: usize, start_time: types::Mtime, max_time: types::Mtime, now: types::Mtime) {
     let mut cut = FxHashSet::default();    
     topocsa::prepare_and_query(store, connections, stations, &mut cut, origin, destination, start_time, max_time, now, 0.0, false);
 }
 
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Algorithm {
+    #[default]
+    Topocsa,
+    Csameat
+}
+
+impl Algorithm {
+    pub fn from_name(name: &str) -> Option<Algorithm> {
+        match name {
+            "topocsa" => Some(Algorithm::Topocsa),
+            "csameat" => Some(Algorithm::Csameat),
+            _ => None
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Algorithm::Topocsa => "topocsa",
+            Algorithm::Csameat => "csameat"
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct Query {
     pub origin_idx: usize,
@@ -34,11 +60,13 @@ pub struct ConnectionLabel {
 }
 
 pub trait Queriable<'a> {
-    fn set_station_contraction(&mut self, contr: &'a StationContraction);
+    fn set_station_contraction(&mut self, contr: &'a StationContraction) -> Result<(), String>;
     fn preprocess(&mut self);
     fn query(&mut self, query: Query) -> Vec<Vec<ConnectionLabel>>;
     fn pair_query(&mut self, query: Query, connection_pairs: &HashMap<i32, i32>) -> Vec<Vec<ConnectionLabel>>;    
     fn relevant_stations(&mut self, query: Query, station_labels: &[Vec<ConnectionLabel>]) -> HashMap<usize, types::MFloat>;
     fn relevant_connection_pairs(&mut self, query: Query, weights_by_station_idx: &HashMap<usize, types::MFloat>, max_station_count: usize) -> HashMap<i32, i32>;
     fn update(&mut self, connection_id: usize, is_departure: bool, location_idx: Option<usize>, in_out_allowed: Option<bool>, delay: Option<i16>);
+    // Evidence that the delay is at least min_delay, which is not an observed delay and thus not fed to the online correction.
+    fn update_min_delay(&mut self, connection_id: usize, is_departure: bool, min_delay: i16);
 }
\ No newline at end of file
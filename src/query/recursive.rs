@@ -1,187 +1,305 @@
-
+use std::cell::RefCell;
 use std::collections::HashMap;
-use std::collections::HashSet;
+use std::time::Instant;
 
-use indexmap::IndexMap;
+use rustc_hash::FxHashSet;
 
+use crate::connection;
 use crate::distribution;
 use crate::distribution_store;
-use crate::connection;
+use crate::gtfs::StationContraction;
 use crate::types;
+use super::ConnectionLabel;
+use super::Queriable;
+use super::Query;
 
-pub fn query<'a, 'b>(store: &'b mut distribution_store::Store, connections: &mut Vec<connection::Connection>, stations: &[connection::Station], _origin: &'a connection::Station, destination: &'a connection::Station, _start_time: types::Mtime, _max_time: types::Mtime, now: types::Mtime, cut: HashSet<(usize, usize)> ) {
-    let mut q = Query {
-        store: store,
-        destination: destination,
-        now: now,
-        trace: IndexMap::new(),
-        visited: HashMap::new(),
-        cycles_found: 0,
-        cycles_cut: 0,
-        cycles_cut_direct: 0,
-        connections: 0,
-        cut: HashSet::new()
-    };
-    //for dep in &*origin.departures.borrow() {
-    for dep in connections.iter() {
-        q.recursive(dep.id, connections, stations);
-    }
-    println!("cut: {}", q.cut.len());
-    if cut.len() > 0 {
-        assert_eq!(q.cut.len(), cut.len());
-    }
-    q.store.clear_reachability();
+// Reference implementation of the recurrence behind topocsa (no station contraction, epsilons of 0, no domination),
+// written from its definition: the destination arrival of a connection is the mixture of the destination arrivals of
+// its continuations (departures at its arrival stop and footpaths), tried in ascending order of their means, each weighted
+// by the probability of catching it after having missed the better ones. Destination arrivals are computed on demand by
+// an iterative depth-first search, which cuts the cycles it finds at the transfer with the lowest predicted transfer time.
+#[derive(Debug)]
+pub struct Environment<'a> {
+    store: RefCell<&'a mut distribution_store::Store>,
+    connections: &'a mut Vec<connection::Connection>,
+    stations: &'a [connection::Station],
+    now: types::Mtime,
+    cut: FxHashSet<(usize, usize)>,
+    order: &'a mut Vec<usize>
 }
 
-struct Query<'a> {
-    store: &'a mut distribution_store::Store,
-    destination: &'a connection::Station,
-    now: types::Mtime,
-    trace: IndexMap<usize, f32>,
-    visited: HashMap<usize, usize>,
-    cycles_found: i32,
-    cycles_cut: i32,
-    cycles_cut_direct: i32,
-    connections: i32,
-    cut: HashSet<(usize, usize)>,
+struct Continuation<'c> {
+    destination_arrival: distribution::Distribution,
+    departure: &'c connection::StopInfo,
+    product_type: i16,
+    reachable: types::MFloat
+}
+
+struct Frame {
+    idx: usize,
+    successors: Vec<usize>,
+    next: usize
+}
+
+impl<'a> Queriable<'a> for Environment<'a> {
+    fn set_station_contraction(&mut self, _contr: &'a StationContraction) -> Result<(), String> {
+        Err("station contraction is not supported by the recursive query".to_owned())
+    }
+
+    fn preprocess(&mut self) {
+        // cycles are cut during the query
+    }
+
+    fn query(&mut self, q: Query) -> Vec<Vec<ConnectionLabel>> {
+        let start_ts = Instant::now();
+        let station_labels = self.full_query(q);
+        println!("recursive elapsed: {}", start_ts.elapsed().as_millis());
+        station_labels
+    }
+
+    fn pair_query(&mut self, q: Query, _connection_pairs: &HashMap<i32, i32>) -> Vec<Vec<ConnectionLabel>> {
+        self.query(q)
+    }
+
+    fn relevant_stations(&mut self, _q: Query, _station_labels: &[Vec<ConnectionLabel>]) -> HashMap<usize, types::MFloat> {
+        HashMap::new()
+    }
+
+    fn relevant_connection_pairs(&mut self, _q: Query, _weights_by_station_idx: &HashMap<usize, types::MFloat>, _max_stop_count: usize) -> HashMap<i32, i32> {
+        HashMap::new()
+    }
+
+    fn update(&mut self, connection_id: usize, is_departure: bool, location_idx: Option<usize>, in_out_allowed: Option<bool>, delay: Option<i16>) {
+        let c = &mut self.connections[self.order[connection_id]];
+        c.update(is_departure, location_idx, in_out_allowed, delay);
+        self.store.borrow_mut().observe_update(c, is_departure, self.now);
+    }
+
+    fn update_min_delay(&mut self, connection_id: usize, is_departure: bool, min_delay: i16) {
+        self.connections[self.order[connection_id]].update_min_delay(is_departure, min_delay);
+    }
 }
 
-impl<'a, 'b> Query<'a> {
-    fn recursive(&mut self, c_id: usize, connections: &[connection::Connection], stations: &[connection::Station]) -> Option<usize> {
-        let c = connections.get(c_id).unwrap();
-        if c.destination_arrival.borrow().is_some() {
-            return None;
+impl<'a> Environment<'a> {
+
+    pub fn new(store: &'a mut distribution_store::Store, connections: &'a mut Vec<connection::Connection>, stations: &'a [connection::Station], order: &'a mut Vec<usize>, now: types::Mtime) -> Environment<'a> {
+        if order.is_empty() {
+            order.extend(0..connections.len());
         }
-        
-        self.trace.insert(c_id, 1.0);
-        for dep_id in &stations[c.to_idx].departures {
-            let dep = connections.get(*dep_id).unwrap();
-            if self.cut.contains(&(c_id, *dep_id)) {
-                continue;
+        Environment {
+            store: RefCell::new(store),
+            connections,
+            stations,
+            now,
+            cut: FxHashSet::default(),
+            order
+        }
+    }
+
+    // The transfers cut by the last query, as pairs of connection ids.
+    pub fn cut(&self) -> &FxHashSet<(usize, usize)> {
+        &self.cut
+    }
+
+    fn full_query(&mut self, q: Query) -> Vec<Vec<ConnectionLabel>> {
+        self.cut.clear();
+        let max_delay = self.store.borrow().max_delay as types::Mtime;
+        let in_window: Vec<bool> = self.connections.iter().map(|c| c.departure.projected()+max_delay >= q.start_time && c.departure.projected() < q.max_time).collect();
+        let mut results: Vec<Option<distribution::Distribution>> = vec![None; self.connections.len()];
+        // 0: unvisited, 1: on the stack, 2: destination arrival computed
+        let mut visited = vec![0u8; self.connections.len()];
+        for anchor_idx in 0..self.connections.len() {
+            if in_window[anchor_idx] && visited[anchor_idx] == 0 {
+                self.dfs(anchor_idx, q, &in_window, &mut visited, &mut results);
             }
-            if dep.destination_arrival.borrow().is_some() {
-                continue;
+        }
+        for (c, result) in self.connections.iter().zip(results.iter()) {
+            if result.is_some() {
+                c.destination_arrival.replace(result.clone());
             }
-            let p = self.store.reachable_probability_conn(c, dep, self.now);
-            if p <= 0.0 {
+        }
+        let mut station_labels: Vec<Vec<ConnectionLabel>> = (0..self.stations.len()).map(|station_idx| {
+            self.labels(station_idx, &results).into_iter().map(|idx| ConnectionLabel {
+                connection_id: self.connections[idx].id,
+                destination_arrival: results[idx].clone().unwrap(),
+                prob_after: 1.0,
+                departure_mean: 0.0
+            }).collect()
+        }).collect();
+        // same order as topocsa, i.e. the best label last
+        for labels in station_labels.iter_mut() {
+            labels.reverse();
+        }
+        station_labels
+    }
+
+    fn dfs(&mut self, anchor_idx: usize, q: Query, in_window: &[bool], visited: &mut [u8], results: &mut [Option<distribution::Distribution>]) {
+        let mut stack = vec![self.frame(anchor_idx, q, in_window)];
+        visited[anchor_idx] = 1;
+        while let Some(frame) = stack.last_mut() {
+            if frame.next < frame.successors.len() {
+                let dep_idx = frame.successors[frame.next];
+                frame.next += 1;
+                if self.cut.contains(&(self.connections[frame.idx].id, self.connections[dep_idx].id)) {
+                    continue;
+                }
+                match visited[dep_idx] {
+                    0 => {
+                        visited[dep_idx] = 1;
+                        stack.push(self.frame(dep_idx, q, in_window));
+                    },
+                    1 => self.cut_cycle(dep_idx, &mut stack, visited),
+                    _ => ()
+                }
                 continue;
             }
-            let idx = self.trace.get_index_of(dep_id);
-            if idx.is_some() {
-                self.cycles_found += 1;
-
-                let transfer_time = dep.departure.projected()-c.arrival.projected();
-                let mut min_transfer = transfer_time;
-                let mut min_i = self.trace.len();
-                let start = idx.unwrap()+1 as usize;
-                for i in start..self.trace.len() {
-                    let test = self.trace.get_index(i).unwrap();
-                    let t = connections.get(*test.0).unwrap().departure.projected()-connections.get(*self.trace.get_index(i-1).unwrap().0).unwrap().arrival.projected();
-                    if t < min_transfer {
-                        min_transfer = t;
-                        min_i = i;
+            let idx = frame.idx;
+            results[idx] = Some(self.destination_arrival(idx, q, results));
+            visited[idx] = 2;
+            stack.pop();
+        }
+    }
+
+    fn frame(&self, idx: usize, q: Query, in_window: &[bool]) -> Frame {
+        let c = &self.connections[idx];
+        let mut successors = vec![];
+        if c.to_idx != q.destination_idx {
+            let footpaths = self.stations[c.to_idx].footpaths.iter().filter(|f| f.target_location_idx != q.destination_idx).map(|f| (f.target_location_idx, f.duration as i32, true));
+            for (stop_idx, transfer_time, via_footpath) in std::iter::once((c.to_idx, self.stations[c.to_idx].transfer_time as i32, false)).chain(footpaths) {
+                for id in &self.stations[stop_idx].departures {
+                    let dep_idx = self.order[*id];
+                    let dep = &self.connections[dep_idx];
+                    if !in_window[dep_idx] || self.cut.contains(&(c.id, dep.id)) {
+                        continue;
+                    }
+                    let reachable = if !via_footpath && c.is_consecutive(dep) {
+                        1.0
+                    } else if via_footpath {
+                        self.store.borrow_mut().footpath_before_probability(&c.arrival, c.product_type, &dep.departure, dep.product_type, transfer_time as u16, self.now)
+                    } else {
+                        self.store.borrow_mut().before_probability(&c.arrival, c.product_type, false, &dep.departure, dep.product_type, transfer_time, self.now)
+                    };
+                    if reachable > 0.0 {
+                        successors.push(dep_idx);
                     }
                 }
-                if min_transfer > 0 {
-                    panic!("cutting positive transfer {:?} {:?} {} {}", dep.departure, dep.route_idx, min_transfer, transfer_time);
-                }
-                if min_i == self.trace.len() {
-                    self.cycles_cut += 1;
-                    self.cycles_cut_direct += 1;
-                    self.cut.insert((c_id, *dep_id));
-                    continue;
-                }
-                let min_id = *self.trace.get_index(min_i).unwrap().0;
-                self.cut.insert((*self.trace.get_index(min_i-1).unwrap().0, min_id));
-
-                assert_eq!(self.trace.pop().unwrap().0, c_id);
-                return Some(min_id);
             }
         }
-        for dep_id in stations[c.to_idx].departures.iter().rev() {
-            let dep = connections.get(*dep_id).unwrap();
-            if self.cut.contains(&(c_id, *dep_id)) {
+        Frame { idx, successors, next: 0 }
+    }
+
+    // Same rule as the topocsa preprocessing: cut the transfer with the lowest predicted transfer time on the cycle.
+    fn cut_cycle(&mut self, dep_idx: usize, stack: &mut Vec<Frame>, visited: &mut [u8]) {
+        let c = &self.connections[stack.last().unwrap().idx];
+        let dep = &self.connections[dep_idx];
+        let mut min_transfer = if c.is_consecutive(dep) { 1 } else { dep.departure.projected()-c.arrival.projected() };
+        let mut min_i = stack.len();
+        let mut i = stack.len();
+        while stack[i-1].idx != dep_idx {
+            i -= 1;
+            let a = &self.connections[stack[i-1].idx];
+            let b = &self.connections[stack[i].idx];
+            if a.is_consecutive(b) {
                 continue;
             }
-            if dep.destination_arrival.borrow().is_some() {
-                continue;
+            let predicted_transfer_time = b.departure.projected()-a.arrival.projected();
+            if predicted_transfer_time < min_transfer {
+                min_transfer = predicted_transfer_time;
+                min_i = i;
             }
-            let p = self.store.reachable_probability_conn(c, dep, self.now);
-            if p <= 0.0 {
-                continue;
+        }
+        if min_i == stack.len() {
+            self.cut.insert((c.id, dep.id));
+            return;
+        }
+        self.cut.insert((self.connections[stack[min_i-1].idx].id, self.connections[stack[min_i].idx].id));
+        for frame in stack.drain(min_i..) {
+            visited[frame.idx] = 0;
+        }
+    }
+
+    // Departures with a destination arrival at the station, in the order they are considered (ascending mean,
+    // on equal means later departures first).
+    fn labels(&self, station_idx: usize, results: &[Option<distribution::Distribution>]) -> Vec<usize> {
+        let mut labels: Vec<usize> = self.stations[station_idx].departures.iter().map(|id| self.order[*id])
+            .filter(|idx| results[*idx].as_ref().is_some_and(|d| d.feasible_probability > 1e-3)).collect();
+        labels.sort_by(|a, b| {
+            let (ca, cb) = (&self.connections[*a], &self.connections[*b]);
+            results[*a].as_ref().unwrap().mean.partial_cmp(&results[*b].as_ref().unwrap().mean).unwrap()
+                .then(cb.departure.projected().cmp(&ca.departure.projected()))
+                .then(cb.id.cmp(&ca.id))
+        });
+        labels
+    }
+
+    fn destination_arrival(&self, idx: usize, q: Query, results: &[Option<distribution::Distribution>]) -> distribution::Distribution {
+        let c = &self.connections[idx];
+        if c.to_idx == q.destination_idx {
+            if !c.arrival.in_out_allowed {
+                return distribution::Distribution::empty(c.arrival.scheduled);
             }
-            let cycle_found = self.recursive(*dep_id, connections, stations);
-            if cycle_found.is_some() {
-                if *dep_id == cycle_found.unwrap() {
-                    self.cycles_cut += 1;
+            return self.store.borrow().delay_distribution(&c.arrival, false, c.product_type, self.now);
+        }
+        let mut continuations = self.departures(c.to_idx, c, self.stations[c.to_idx].transfer_time as i32, false, results);
+        for f in &self.stations[c.to_idx].footpaths {
+            let destination_arrival = if f.target_location_idx == q.destination_idx {
+                if !c.arrival.in_out_allowed {
                     continue;
                 }
-                assert_eq!(self.trace.pop().unwrap().0, c_id);
-                return cycle_found;
+                self.store.borrow().footpath_arrival_distribution(&c.arrival, c.product_type, f.duration, self.now)
+            } else {
+                self.mix(self.departures(f.target_location_idx, c, f.duration as i32, true, results), 0)
+            };
+            if destination_arrival.feasible_probability > 0.0 {
+                // walking is not a transfer, but competes with the departures at the arrival stop
+                continuations.push(Continuation { destination_arrival, departure: &c.arrival, product_type: c.product_type, reachable: 1.0 });
             }
         }
-        assert_eq!(self.trace.pop().unwrap().0, c_id);
-        if !self.visited.contains_key(&c.to_idx) {
-            println!("finished iterating {} {} len: {} cycles: {} cut: {} direct: {} conns: {} reachs: {}", stations[c.to_idx].name, stations[c.to_idx].id, self.visited.len(), self.cycles_found, self.cut.len(), self.cycles_cut_direct, self.connections, self.store.reachability_len());
-            self.visited.insert(c.to_idx, self.visited.len());
-        }
-        if !c.departure.in_out_allowed && !c.arrival.in_out_allowed {
-            c.destination_arrival.replace(Some(distribution::Distribution::empty(c.arrival.scheduled)));
-            self.connections += 1;
-            return None;
-        } else if stations[c.to_idx].id == self.destination.id {
-            c.destination_arrival.replace(Some(self.store.delay_distribution(&c.arrival, false, c.product_type, self.now)));
-            self.connections += 1;
-            return None;
-        }
-        
-        let mut departures_by_arrival: Vec<&usize> = stations[c.to_idx].departures.iter().collect();
-        departures_by_arrival.sort_by(|a, b| connections.get(**a).unwrap().destination_arrival.borrow().as_ref().map(|da| da.mean).unwrap_or(0.0).partial_cmp(
-            &connections.get(**b).unwrap().destination_arrival.borrow().as_ref().map(|da| da.mean).unwrap_or(0.0)).unwrap());
-
-        let mut remaining_probability = 1.0;
-        let mut new_distribution = distribution::Distribution::empty(c.arrival.scheduled);
-        let mut last_departure: Option<distribution::Distribution> = None;
-        for dep_id in &departures_by_arrival {
-            let dep = connections.get(**dep_id).unwrap();
-            let dest = dep.destination_arrival.borrow();
-            if self.cut.contains(&(c.id, dep.id)) {
-                continue;
+        // stable, i.e. on equal means departures before footpaths
+        continuations.sort_by(|a, b| a.destination_arrival.mean.partial_cmp(&b.destination_arrival.mean).unwrap());
+        self.mix(continuations, c.arrival.scheduled)
+    }
+
+    // The departures at the stop that c can transfer to, in the order they are considered, with their reachability.
+    fn departures(&self, stop_idx: usize, c: &connection::Connection, transfer_time: i32, via_footpath: bool, results: &[Option<distribution::Distribution>]) -> Vec<Continuation<'_>> {
+        self.labels(stop_idx, results).into_iter().filter(|dep_idx| !self.cut.contains(&(c.id, self.connections[*dep_idx].id))).map(|dep_idx| {
+            let dep = &self.connections[dep_idx];
+            let reachable = if via_footpath {
+                self.store.borrow_mut().footpath_before_probability(&c.arrival, c.product_type, &dep.departure, dep.product_type, transfer_time as u16, self.now)
+            } else if c.is_consecutive(dep) {
+                1.0
+            } else {
+                self.store.borrow_mut().before_probability(&c.arrival, c.product_type, false, &dep.departure, dep.product_type, transfer_time, self.now)
+            };
+            Continuation { destination_arrival: results[dep_idx].clone().unwrap(), departure: &dep.departure, product_type: dep.product_type, reachable }
+        }).collect()
+    }
+
+    // Each continuation is taken if it is reachable, feasible and, for the departures, not departed before the previously
+    // considered one (which is missed), so it is taken with the probability of all these and of none of the better ones being taken.
+    fn mix(&self, continuations: Vec<Continuation>, start: types::Mtime) -> distribution::Distribution {
+        let mut mixture = distribution::Distribution::empty(start);
+        let mut none_taken = 1.0;
+        let mut previous: Option<&Continuation> = None;
+        for continuation in &continuations {
+            let mut p = continuation.destination_arrival.feasible_probability*continuation.reachable;
+            if let Some(previous) = previous {
+                p *= self.store.borrow_mut().before_probability(previous.departure, previous.product_type, true, continuation.departure, continuation.product_type, 1, self.now);
             }
-            let mut p = dest.as_ref().map(|da| da.feasible_probability).unwrap_or(0.0);   
             if p <= 0.0 {
                 continue;
             }
-            if expect_float_absolute_eq!(dest.as_ref().unwrap().mean, 0.0, 1e-3).is_ok() {
-                panic!("mean 0 with high feasibility");
-            }
-            assert_float_absolute_eq!(dest.as_ref().unwrap().mean, dest.as_ref().unwrap().mean(), 1e-3);
-
-            let dep_dist = self.store.delay_distribution(&dep.departure, true, dep.product_type, self.now);
-            /*if last_departure.is_some() && dep_dist.mean < last_departure.as_ref().unwrap().mean {
-                continue;
-            }*/
-            if last_departure.is_some() {
-                p *= last_departure.as_ref().unwrap().before_probability(&dep_dist, 1);
-            }  
-            if p > 0.0 && (c.trip_id != dep.trip_id || c.route_idx != dep.route_idx) {
-                p *= self.store.reachable_probability_conn(c, dep, self.now);
-            }
-            if p > 0.0 {
-                new_distribution.add(dest.as_ref().unwrap(), (p*remaining_probability).clamp(0.0,1.0));
-                remaining_probability = ((1.0-p).clamp(0.0,1.0)*remaining_probability).clamp(0.0,1.0);
-                last_departure = Some(dep_dist);
-                if remaining_probability <= 0.0 {
-                    break;
-                }
+            mixture.add_with(&continuation.destination_arrival, p*none_taken, false);
+            none_taken *= (1.0-p).clamp(0.0, 1.0);
+            previous = Some(continuation);
+            if none_taken <= 0.0 {
+                break;
             }
         }
-        new_distribution.feasible_probability = (1.0-remaining_probability).clamp(0.0,1.0);
-        if new_distribution.feasible_probability < 1.0 {
-            new_distribution.normalize();
+        mixture.feasible_probability = (1.0-none_taken).clamp(0.0, 1.0);
+        if mixture.feasible_probability < 1.0 {
+            mixture.normalize_with(false, 0.0);
         }
-        c.destination_arrival.replace(Some(new_distribution));
-        self.connections += 1;
-        None
+        mixture
     }
-}
\ No newline at end of file
+}
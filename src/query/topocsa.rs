@@ -31,7 +31,8 @@ pub struct Environment<'a> {
     domination: bool,
     cut: &'a mut FxHashSet<(usize, usize)>,
     order: &'a mut Vec<usize>,
-    contraction: Option<&'a StationContraction>
+    contraction: Option<&'a StationContraction>,
+    destination_bucket_width: types::Mtime
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -61,8 +62,9 @@ struct CsaInstrumentation {
 
 impl<'a> Queriable<'a> for Environment<'a> {
 
-    fn set_station_contraction(&mut self, contr: &'a StationContraction) {
+    fn set_station_contraction(&mut self, contr: &'a StationContraction) -> Result<(), String> {
         self.contraction = Some(contr);
+        Ok(())
     }
 
     fn preprocess(&mut self) {
@@ -92,6 +94,11 @@ impl<'a> Queriable<'a> for Environment<'a> {
     fn update(&mut self, connection_id: usize, is_departure: bool, location_idx: Option<usize>, in_out_allowed: Option<bool>, delay: Option<i16>) {
         let c = &mut self.connections[self.order[connection_id]];
         c.update(is_departure, location_idx, in_out_allowed, delay);
+        self.store.borrow_mut().observe_update(c, is_departure, self.now);
+    }
+
+    fn update_min_delay(&mut self, connection_id: usize, is_departure: bool, min_delay: i16) {
+        self.connections[self.order[connection_id]].update_min_delay(is_departure, min_delay);
     }
 
 }
@@ -113,10 +120,23 @@ impl<'a> Environment<'a> {
             domination,
             cut,
             order,
-            contraction: None
+            contraction: None,
+            destination_bucket_width: 1
         }
     }
 
+    pub fn set_destination_bucket_width(&mut self, bucket_width: types::Mtime) {
+        self.destination_bucket_width = bucket_width;
+    }
+
+    #[inline]
+    fn coarsen_destination_arrival(&self, distribution: distribution::Distribution) -> distribution::Distribution {
+        if self.destination_bucket_width > 1 && !self.mean_only {
+            return distribution.coarsen(self.destination_bucket_width);
+        }
+        distribution
+    }
+
     fn dfs(&mut self, anchor_idx: usize, topo_idx: &mut usize, labels: &mut Vec<DfsConnectionLabel>, visited: &mut Vec<i16>, stops_completed_up: &mut Vec<usize>, instr: &mut Instrumentation) {
         let mut stack: Vec<usize> = Vec::with_capacity(1000);
         stack.push(anchor_idx);
@@ -174,7 +194,11 @@ impl<'a> Environment<'a> {
                     let is_continuing = c_label.footpath_i == footpaths.len() && c.is_consecutive(dep);
                     if !is_continuing {
                         let transfer_time = if c_label.footpath_i == footpaths.len() { self.stations[stop_idx].transfer_time } else { footpaths[c_label.footpath_i].duration } as i32;
-                        let reachable = self.store.borrow_mut().before_probability(&c.arrival, c.product_type, false, &dep.departure, dep.product_type, transfer_time, self.now);
+                        let reachable = if c_label.footpath_i == footpaths.len() {
+                            self.store.borrow_mut().before_probability(&c.arrival, c.product_type, false, &dep.departure, dep.product_type, transfer_time, self.now)
+                        } else {
+                            self.store.borrow_mut().footpath_before_probability(&c.arrival, c.product_type, &dep.departure, dep.product_type, transfer_time as u16, self.now)
+                        };
                         if reachable <= self.epsilon_reachable {
                             if reachable == 0.0 {
                                 let diff = (dep.departure.projected()-c.arrival.projected()-transfer_time) as i16;
@@ -348,7 +372,7 @@ impl<'a> Environment<'a> {
                     let contr = self.contraction.unwrap();
                     new_distribution = new_distribution.shift(contr.get_transfer_time(c.to_idx, q.destination_idx) as i32);
                 }
-                new_distribution
+                self.coarsen_destination_arrival(new_distribution)
             } else {
                 let mut new_distribution = distribution::Distribution::empty(c.arrival.scheduled);
                 if self.contraction.is_none() {
@@ -384,9 +408,9 @@ impl<'a> Environment<'a> {
                     }
                     continue;
                 }
-                footpath_dest_arr = self.store.borrow().delay_distribution(&c.arrival, false, c.product_type, self.now).shift(f.1.duration as i32);
+                footpath_dest_arr = self.coarsen_destination_arrival(self.store.borrow().footpath_arrival_distribution(&c.arrival, c.product_type, f.1.duration, self.now));
             } else {
-                self.calculate_destination_arrival(f.1.target_location_idx, i, -1, 0, c.product_type, &c.arrival, f.1.duration as i32, station_labels, &empty_vec, materialized_footpaths, &mut footpath_dest_arr, instr);   
+                self.calculate_destination_arrival(f.1.target_location_idx, i, -1, 0, c.product_type, &c.arrival, f.1.duration as i32, true, station_labels, &empty_vec, materialized_footpaths, &mut footpath_dest_arr, instr);   
             }
             if footpath_dest_arr.feasible_probability > 0.0 {
                 footpath_distributions.push((f.0, footpath_dest_arr));
@@ -394,11 +418,11 @@ impl<'a> Environment<'a> {
         }
         // TODO domination in case of strict domination
         footpath_distributions.sort_unstable_by(|a, b| a.1.mean.partial_cmp(&b.1.mean).unwrap());
-        self.calculate_destination_arrival(stop_idx, i, c.trip_id, c.route_idx, c.product_type, &c.arrival, self.stations[stop_idx].transfer_time as i32, station_labels, &footpath_distributions, materialized_footpaths, new_distribution, instr);
+        self.calculate_destination_arrival(stop_idx, i, c.trip_id, c.route_idx, c.product_type, &c.arrival, self.stations[stop_idx].transfer_time as i32, false, station_labels, &footpath_distributions, materialized_footpaths, new_distribution, instr);
     }
     
     #[inline]
-    fn calculate_destination_arrival<'c>(&'c self, station_idx: usize, c_idx: usize, from_trip_id: i32, from_route_idx: usize, from_product_type: i16, from_arrival: &connection::StopInfo, transfer_time: i32, station_labels: &[Vec<ConnectionLabel>], footpath_distributions: &[(usize, distribution::Distribution)], materialized_footpaths: &mut Vec<Connection>, new_distribution: &mut distribution::Distribution, instr: &mut CsaInstrumentation) {
+    fn calculate_destination_arrival<'c>(&'c self, station_idx: usize, c_idx: usize, from_trip_id: i32, from_route_idx: usize, from_product_type: i16, from_arrival: &connection::StopInfo, transfer_time: i32, via_footpath: bool, station_labels: &[Vec<ConnectionLabel>], footpath_distributions: &[(usize, distribution::Distribution)], materialized_footpaths: &mut Vec<Connection>, new_distribution: &mut distribution::Distribution, instr: &mut CsaInstrumentation) {
         let mut remaining_probability = 1.0;
         let mut last_departure: Option<&connection::StopInfo> = None;
         let mut last_product_type: i16 = 0;
@@ -451,7 +475,11 @@ impl<'a> Environment<'a> {
                 p *= self.store.borrow_mut().before_probability(last_departure.unwrap(), last_product_type, true, departure.unwrap(), departure_product_type, 1, self.now);
             }
             if p > 0.0 && !is_continuing {
-                p *= self.store.borrow_mut().before_probability(from_arrival, from_product_type, false, departure.unwrap(), departure_product_type, transfer_time, self.now);
+                p *= if via_footpath {
+                    self.store.borrow_mut().footpath_before_probability(from_arrival, from_product_type, departure.unwrap(), departure_product_type, transfer_time as u16, self.now)
+                } else {
+                    self.store.borrow_mut().before_probability(from_arrival, from_product_type, false, departure.unwrap(), departure_product_type, transfer_time, self.now)
+                };
             }
             if p > 0.0 {
                 let p_taking = p*remaining_probability;
@@ -477,6 +505,10 @@ impl<'a> Environment<'a> {
             let footpath_idx = footpath_distributions[footpaths_i-1].0;
             let footpath = &self.stations[station_idx].footpaths[footpath_idx];
             let id = self.connections.len()+materialized_footpaths.len();
+            let duration = {
+                let store = self.store.borrow();
+                store.walking_profile().map_or(footpath.duration, |p| p.expected_duration(footpath.duration, store.ticks_per_minute()))
+            };
             let mut c = Connection::new(
                 id,
                 id,
@@ -487,7 +519,7 @@ impl<'a> Environment<'a> {
                 departure.unwrap().projected(),
                 None,
                 footpath.target_location_idx,
-                departure.unwrap().projected()+footpath.duration as types::Mtime,
+                departure.unwrap().projected()+duration as types::Mtime,
                 None
             );
             c.destination_arrival.replace(Some(footpath_distributions[footpaths_i-1].1.clone()));
@@ -546,9 +578,18 @@ impl<'a> Environment<'a> {
         if new_distribution.feasible_probability > self.epsilon_feasible && new_distribution.feasible_probability > 1e-3 {                
             let mut j = departures.len() as i32-1;
             while j >= 0 {
-                if new_distribution.mean < departures[j as usize].destination_arrival.mean {
+                let label = &departures[j as usize];
+                if new_distribution.mean < label.destination_arrival.mean {
                     break;
                 }
+                // on equal means, later departures are considered first, independent of the topological order, which
+                // depends on the order of the input connections and is not known to the recursive oracle
+                if new_distribution.mean == label.destination_arrival.mean {
+                    let label_conn = &self.connections[self.order[label.connection_id]];
+                    if (label_conn.departure.projected(), label_conn.id) < (departure_conn.departure.projected(), departure_conn.id) {
+                        break;
+                    }
+                }
                 j -= 1;
             }
             let mut prob_after = 1.0;
@@ -606,6 +647,7 @@ impl<'a> Environment<'a> {
         if self.connections.is_empty() {
             return weights_by_station_idx;
         }
+        let ticks_per_minute = self.store.borrow().ticks_per_minute();
         while !stack.is_empty() {
             let conn_with_prob = stack.pop().unwrap();
             let c = &self.connections[conn_with_prob.0];
@@ -674,7 +716,7 @@ impl<'a> Environment<'a> {
                     transfer_time = contr.get_transfer_time(c.to_idx, dep.from_idx) as i32;
                 }
                 // TODO magic^10
-                if !self.domination && initial && last_departure.is_some() && dep.departure.projected()-transfer_time+10 < last_departure.unwrap().projected()-last_transfer_time { 
+                if !self.domination && initial && last_departure.is_some() && dep.departure.projected()-transfer_time+types::minutes(10, ticks_per_minute) < last_departure.unwrap().projected()-last_transfer_time { 
                     continue;
                 }
                 if !initial && self.cut.contains(&(c.id, dep.id)) {
@@ -0,0 +1,244 @@
+use rand::Rng;
+use serde::{Serialize, Deserialize};
+
+use crate::connection;
+use crate::distribution::Distribution;
+use crate::distribution_store::Store;
+use crate::types;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct SyntheticDelayConfig {
+    // minutes before the scheduled event at which its final delay is published
+    pub reveal_lead: types::Mtime,
+    // minutes before the scheduled event at which intermediate predictions are published, each drawn
+    // from the Store's distribution given the previous prediction
+    pub prediction_leads: Vec<types::Mtime>,
+    // minutes between two synthetic realtime updates
+    pub interval: types::Mtime,
+    // misspecification of the ground truth against the Store: delay*scale+shift (minutes)
+    pub scale: types::MFloat,
+    pub shift: types::MFloat,
+    pub cancellations: bool
+}
+
+impl Default for SyntheticDelayConfig {
+    fn default() -> Self {
+        SyntheticDelayConfig {
+            reveal_lead: 30,
+            prediction_leads: vec![120, 60],
+            interval: 5,
+            scale: 1.0,
+            shift: 0.0,
+            cancellations: true
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+struct Event {
+    reveal_at: types::Mtime,
+    connection_id: usize,
+    is_departure: bool,
+    delay: i16,
+    cancelled: bool
+}
+
+// Ground truth delays sampled from the Store's prior distributions, published as a realtime stream.
+pub struct SyntheticDelays {
+    pub config: SyntheticDelayConfig,
+    events: Vec<Event>,
+    next_event: usize
+}
+
+impl SyntheticDelays {
+    pub fn new(config: SyntheticDelayConfig) -> SyntheticDelays {
+        SyntheticDelays {
+            config,
+            events: vec![],
+            next_event: 0
+        }
+    }
+
+    // Draws an absolute time from d, None if the event is cancelled.
+    fn draw<R: Rng>(d: &Distribution, cancellable: bool, rng: &mut R) -> Option<types::Mtime> {
+        if cancellable && rng.gen::<types::MFloat>() >= d.feasible_probability {
+            return None;
+        }
+        let total: types::MFloat = d.histogram.iter().sum();
+        let mut u = rng.gen::<types::MFloat>()*total;
+        let mut time = d.start;
+        for (i, p) in d.histogram.iter().enumerate() {
+            time = d.start+i as types::Mtime*d.bucket_width;
+            if u < *p {
+                break;
+            }
+            u -= p;
+        }
+        Some(time)
+    }
+
+    // Leads in ticks from the earliest prediction to the final delay.
+    fn leads(&self, ticks_per_minute: types::Mtime) -> Vec<types::Mtime> {
+        let mut leads: Vec<types::Mtime> = self.config.prediction_leads.iter().filter(|l| **l > self.config.reveal_lead).map(|l| types::minutes(*l, ticks_per_minute)).collect();
+        leads.sort_unstable_by(|a, b| b.cmp(a));
+        leads.dedup();
+        leads.push(types::minutes(self.config.reveal_lead, ticks_per_minute));
+        leads
+    }
+
+    // Predicted delays of a stop event at each lead, the last one being the (distorted) ground truth,
+    // or None if the event turns out to be cancelled.
+    fn sample_predictions<R: Rng>(&self, stop: &connection::StopInfo, is_departure: bool, product_type: i16, leads: &[types::Mtime], store: &Store, rng: &mut R) -> Option<Vec<i16>> {
+        let mut delays: Vec<i16> = vec![];
+        for (i, lead) in leads.iter().enumerate() {
+            let last = i == leads.len()-1;
+            let prediction = connection::StopInfo::new(stop.scheduled, delays.last().copied());
+            let d = store.delay_distribution(&prediction, is_departure, product_type, stop.scheduled-lead);
+            let delay = (Self::draw(&d, last && self.config.cancellations, rng)?-stop.scheduled) as types::MFloat;
+            let delay = if last {
+                delay*self.config.scale+self.config.shift*store.ticks_per_minute() as types::MFloat
+            } else {
+                delay
+            };
+            delays.push(delay.round() as i16);
+        }
+        Some(delays)
+    }
+
+    // Replaces the ground truth for a newly loaded timetable. Every predicted delay is propagated along
+    // its trip so that no vehicle departs before it arrived, and a cancellation cancels the remainder
+    // of the trip as soon as the earliest prediction of the cancelled event is due.
+    pub fn sample<R: Rng>(&mut self, connections: &[connection::Connection], store: &Store, rng: &mut R) {
+        let mut by_id: Vec<&connection::Connection> = connections.iter().collect();
+        by_id.sort_unstable_by_key(|c| c.id);
+        let leads = self.leads(store.ticks_per_minute());
+        self.events.clear();
+        self.next_event = 0;
+        let mut prev: Option<(&connection::Connection, Vec<i16>)> = None;
+        let mut cancelled_at: Option<types::Mtime> = None;
+        for c in by_id {
+            let consecutive = prev.as_ref().is_some_and(|(p, _)| p.is_consecutive(c));
+            if !consecutive {
+                cancelled_at = None;
+            }
+            let predictions = match cancelled_at {
+                Some(_) => None,
+                None => self.sample_predictions(&c.departure, true, c.product_type, &leads, store, rng)
+                    .zip(self.sample_predictions(&c.arrival, false, c.product_type, &leads, store, rng))
+            };
+            let Some((mut dep_delays, mut arr_delays)) = predictions else {
+                let at = *cancelled_at.get_or_insert(c.departure.scheduled-leads[0]);
+                self.events.push(Event { reveal_at: at, connection_id: c.id, is_departure: true, delay: 0, cancelled: true });
+                self.events.push(Event { reveal_at: at, connection_id: c.id, is_departure: false, delay: 0, cancelled: true });
+                prev = Some((c, vec![]));
+                continue;
+            };
+            for i in 0..leads.len() {
+                if let Some((p, p_arr_delays)) = &prev {
+                    if consecutive && !p_arr_delays.is_empty() {
+                        dep_delays[i] = dep_delays[i].max((p.arrival.scheduled+p_arr_delays[i] as types::Mtime-c.departure.scheduled) as i16);
+                    }
+                }
+                arr_delays[i] = arr_delays[i].max((c.departure.scheduled+dep_delays[i] as types::Mtime-c.arrival.scheduled) as i16);
+                self.events.push(Event { reveal_at: c.departure.scheduled-leads[i], connection_id: c.id, is_departure: true, delay: dep_delays[i], cancelled: false });
+                self.events.push(Event { reveal_at: c.arrival.scheduled-leads[i], connection_id: c.id, is_departure: false, delay: arr_delays[i], cancelled: false });
+            }
+            prev = Some((c, arr_delays));
+        }
+        self.events.sort_by_key(|e| e.reveal_at);
+    }
+
+    // Publishes all events revealed until now, with the same callback signature as gtfs::load_realtime.
+    pub fn emit<F: FnMut(usize, bool, Option<usize>, Option<bool>, Option<i16>)>(&mut self, now: types::Mtime, mut callback: F) -> usize {
+        let start = self.next_event;
+        while self.next_event < self.events.len() && self.events[self.next_event].reveal_at <= now {
+            let e = self.events[self.next_event];
+            callback(e.connection_id, e.is_departure, None, if e.cancelled { Some(false) } else { None }, Some(e.delay));
+            self.next_event += 1;
+        }
+        self.next_event-start
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+
+    fn store(d: Distribution) -> Store {
+        let mut s = Store::new();
+        s.insert_from_distribution(0..0, 0..0, true, 1, d.clone());
+        s.insert_from_distribution(0..0, 0..0, false, 1, d);
+        s
+    }
+
+    #[test]
+    fn propagated_along_trip() {
+        let s = store(Distribution::uniform(0, 10));
+        let connections = [
+            connection::Connection::new(0, 0, 1, 0, false, 0, 100, None, 1, 110, None),
+            connection::Connection::new(1, 0, 1, 0, false, 1, 111, None, 2, 120, None)
+        ];
+        let mut synthetic = SyntheticDelays::new(SyntheticDelayConfig { cancellations: false, prediction_leads: vec![], ..Default::default() });
+        let mut rng = rand::rngs::StdRng::seed_from_u64(1);
+        for _ in 0..50 {
+            synthetic.sample(&connections, &s, &mut rng);
+            let mut delays = [[0; 2]; 2];
+            assert_eq!(synthetic.emit(1000, |id, is_departure, _, _, delay| delays[id][is_departure as usize] = delay.unwrap() as types::Mtime), 4);
+            assert!(delays.iter().all(|d| d[1] >= 0 && d[1] < 10));
+            assert!(100+delays[0][1] <= 110+delays[0][0]);
+            assert!(110+delays[0][0] <= 111+delays[1][1]);
+            assert!(111+delays[1][1] <= 120+delays[1][0]);
+        }
+    }
+
+    #[test]
+    fn revealed_before_event() {
+        let s = store(Distribution::uniform(2, 1));
+        let connections = [connection::Connection::new(0, 0, 1, 0, false, 0, 100, None, 1, 110, None)];
+        let mut synthetic = SyntheticDelays::new(SyntheticDelayConfig { scale: 2.0, shift: 1.0, prediction_leads: vec![], ..Default::default() });
+        synthetic.sample(&connections, &s, &mut rand::rngs::StdRng::seed_from_u64(1));
+        let mut updates = vec![];
+        assert_eq!(synthetic.emit(70, |_, is_departure, _, _, delay| updates.push((is_departure, delay))), 1);
+        assert_eq!(updates, vec![(true, Some(5))]);
+        assert_eq!(synthetic.emit(80, |_, is_departure, _, _, delay| updates.push((is_departure, delay))), 1);
+        assert_eq!(updates[1], (false, Some(5)));
+        assert_eq!(synthetic.emit(1000, |_, _, _, _, _| {}), 0);
+    }
+
+    #[test]
+    fn predictions_follow_store() {
+        let mut s = store(Distribution::uniform(0, 1));
+        s.insert_from_distribution(0..0, 60..120, true, 1, Distribution::uniform(10, 1));
+        s.insert_from_distribution(5..15, 30..60, true, 1, Distribution::uniform(20, 1));
+        let connections = [connection::Connection::new(0, 0, 1, 0, false, 0, 100, None, 1, 110, None)];
+        let mut synthetic = SyntheticDelays::new(SyntheticDelayConfig { prediction_leads: vec![90], ..Default::default() });
+        synthetic.sample(&connections, &s, &mut rand::rngs::StdRng::seed_from_u64(1));
+        let mut updates = vec![];
+        synthetic.emit(1000, |_, is_departure, _, _, delay| if is_departure { updates.push(delay.unwrap()) });
+        // 10 minutes predicted 90 minutes before departure, and relative to that prediction another 20 minutes
+        assert_eq!(updates, vec![10, 30]);
+    }
+
+    #[test]
+    fn cancellation_propagated_along_trip() {
+        let mut cancelled = Distribution::uniform(0, 1);
+        cancelled.feasible_probability = 0.0;
+        let mut s = store(Distribution::uniform(0, 1));
+        s.insert_from_distribution(0..0, 0..0, true, 2, cancelled.clone());
+        s.insert_from_distribution(0..0, 0..0, false, 2, cancelled);
+        let connections = [
+            connection::Connection::new(0, 0, 2, 0, false, 0, 100, None, 1, 110, None),
+            connection::Connection::new(1, 0, 1, 0, false, 1, 200, None, 2, 210, None),
+            connection::Connection::new(2, 1, 1, 1, false, 1, 200, None, 2, 210, None)
+        ];
+        let mut synthetic = SyntheticDelays::new(SyntheticDelayConfig { prediction_leads: vec![60], ..Default::default() });
+        synthetic.sample(&connections, &s, &mut rand::rngs::StdRng::seed_from_u64(1));
+        let mut updates = vec![];
+        synthetic.emit(1000, |id, _, _, in_out_allowed, _| updates.push((id, in_out_allowed == Some(false))));
+        // the remainder of trip 0 is cancelled 60 minutes before its first cancelled departure, trip 1 runs
+        assert_eq!(synthetic.events.iter().filter(|e| e.cancelled).map(|e| (e.connection_id, e.reveal_at)).collect::<Vec<_>>(), vec![(0, 40), (0, 40), (1, 40), (1, 40)]);
+        assert_eq!(updates.iter().filter(|u| u.0 == 2 && !u.1).count(), 4);
+    }
+}
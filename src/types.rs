@@ -1,2 +1,21 @@
 pub type Mtime = i32;
-pub type MFloat = f32;
\ No newline at end of file
+pub type MFloat = f32;
+
+// Resolution of data saved before it was recorded.
+pub fn one_tick_per_minute() -> Mtime {
+    1
+}
+
+pub fn assert_ticks_per_minute(ticks_per_minute: Mtime) {
+    assert!(ticks_per_minute > 0 && 60 % ticks_per_minute == 0, "ticks per minute must divide 60");
+}
+
+#[inline(always)]
+pub fn seconds_per_tick(ticks_per_minute: Mtime) -> Mtime {
+    60/ticks_per_minute
+}
+
+#[inline(always)]
+pub fn minutes(m: Mtime, ticks_per_minute: Mtime) -> Mtime {
+    m*ticks_per_minute
+}
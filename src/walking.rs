@@ -1,9 +1,10 @@
 use crate::{
-    connection::{Connection, Station, StopInfo}, distribution_store, gtfs::{sort_station_departures_asc, GtfsTimetable, StationContraction}, query::{topocsa, ConnectionLabel, Queriable, Query}, types
+    connection::{Connection, Station, StopInfo}, distribution, distribution_store, gtfs::{sort_station_departures_asc, GtfsTimetable, StationContraction}, query::{topocsa, ConnectionLabel, Queriable, Query}, types
 };
-use motis_nigiri::Footpath;
+use crate::connection::Footpath;
 use rstar::RTree;
 use rustc_hash::FxHashSet;
+use serde::{Deserialize, Serialize};
 use std::{cell::RefCell, collections::HashMap};
 
 const WALKING_METRES_PER_SECOND: f64 = 1.5;
@@ -12,12 +13,92 @@ pub const WALKING_MSG: &str = "walking";
 pub const WALKING_PRODUCT_TYPE: i16 = 100;
 pub const WALKING_RELEVANCE_THRESH: f32 = 0.01;
 pub const WALKING_INITIAL_BUFFER_MINUTES: i32 = 3;
+pub const MAX_WALKING_MINUTES: u16 = (MAX_WALKING_METRES / WALKING_METRES_PER_SECOND / 60.0) as u16 + 1;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct WalkingProfile {
+    pub metres_per_second: f64,
+    pub relative_spread: f64,
+    pub min_spread_minutes: f64,
+    pub overhead_minutes: f64,
+}
+
+// Either the name of a preset or the parameters of a custom profile.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum WalkingProfileConfig {
+    Named(String),
+    Custom(WalkingProfile),
+}
+
+impl WalkingProfileConfig {
+    pub fn profile(&self) -> Option<WalkingProfile> {
+        match self {
+            WalkingProfileConfig::Named(name) => WalkingProfile::named(name),
+            WalkingProfileConfig::Custom(profile) => Some(*profile),
+        }
+    }
+}
+
+impl Default for WalkingProfile {
+    fn default() -> Self {
+        WalkingProfile::named("default").unwrap()
+    }
+}
+
+impl WalkingProfile {
+    pub fn named(name: &str) -> Option<WalkingProfile> {
+        let (metres_per_second, relative_spread, min_spread_minutes, overhead_minutes) = match name {
+            "default" => (WALKING_METRES_PER_SECOND, 0.2, 1.0, 0.0),
+            "slow" => (1.0, 0.3, 1.0, 0.0),
+            "luggage" => (1.2, 0.3, 1.0, 1.0),
+            "wheelchair" => (0.9, 0.4, 2.0, 2.0),
+            _ => return None,
+        };
+        Some(WalkingProfile {
+            metres_per_second,
+            relative_spread,
+            min_spread_minutes,
+            overhead_minutes,
+        })
+    }
+
+    fn expected_minutes(&self, nominal_duration: u16, ticks_per_minute: types::Mtime) -> f64 {
+        nominal_duration as f64 * WALKING_METRES_PER_SECOND / self.metres_per_second
+            + self.overhead_minutes * ticks_per_minute as f64
+    }
+
+    pub fn expected_duration(&self, nominal_duration: u16, ticks_per_minute: types::Mtime) -> u16 {
+        self.expected_minutes(nominal_duration, ticks_per_minute).round() as u16
+    }
+
+    // nominal_duration is based on WALKING_METRES_PER_SECOND, i.e. proportional to the distance.
+    // Walking times are right-skewed: getting lost or queueing at stairs takes longer than hurrying saves.
+    pub fn duration_distribution(&self, nominal_duration: u16, ticks_per_minute: types::Mtime) -> distribution::Distribution {
+        let expected = self.expected_minutes(nominal_duration, ticks_per_minute);
+        let spread = (expected * self.relative_spread).max(self.min_spread_minutes * ticks_per_minute as f64);
+        let lower = (expected - spread / 2.0).floor().max(0.0) as types::Mtime;
+        let upper = (expected + spread).ceil() as types::Mtime;
+        let mode = (expected.round() as types::Mtime).clamp(lower, upper);
+        let mut d = distribution::Distribution::uniform(lower, (upper - lower + 1) as usize);
+        for t in lower..=upper {
+            d.histogram[(t - lower) as usize] = if t <= mode {
+                (t - lower + 1) as types::MFloat / (mode - lower + 1) as types::MFloat
+            } else {
+                (upper - t + 1) as types::MFloat / (upper - mode + 1) as types::MFloat
+            };
+        }
+        d.normalize();
+        d.mean = d.mean();
+        d
+    }
+}
 
 pub fn geodist_meters_string(stop1: &Station, stop2: &Station) -> String {
     format!("{}m", geodist_meters(stop1, stop2).round())
 }
 
-fn geodist_meters(stop1: &Station, stop2: &Station) -> f64 {
+pub fn geodist_meters(stop1: &Station, stop2: &Station) -> f64 {
     lonlat_geodist_meters(stop1.lon, stop1.lat, stop2.lon, stop2.lat)
 }
 
@@ -29,25 +110,25 @@ fn lonlat_geodist_meters(stop1_lon: f64, stop1_lat: f64, stop2_lon: f64, stop2_l
     (x * x + y * y).sqrt() * r
 }
 
-fn walking_duration(dist: f64) -> u16 {
-    (dist / WALKING_METRES_PER_SECOND / 60.0).round() as u16
+fn walking_duration(dist: f64, ticks_per_minute: types::Mtime) -> u16 {
+    (dist / WALKING_METRES_PER_SECOND / types::seconds_per_tick(ticks_per_minute) as f64).round() as u16
 }
 
-pub fn shorten_footpaths(stations: &mut Vec<Station>) {
+pub fn shorten_footpaths(stations: &mut Vec<Station>, ticks_per_minute: types::Mtime) {
     for i in 0..stations.len() {
         for j in 0..stations[i].footpaths.len() {
             let dur = walking_duration(geodist_meters(
                 &stations[i],
                 &stations[stations[i].footpaths[j].target_location_idx],
-            ));
+            ), ticks_per_minute);
             stations[i].footpaths[j].duration =
-                std::cmp::min(std::cmp::max(dur, 1), stations[i].footpaths[j].duration);
+                std::cmp::min(std::cmp::max(dur, types::minutes(1, ticks_per_minute) as u16), stations[i].footpaths[j].duration);
         }
-        stations[i].transfer_time = 1;
+        stations[i].transfer_time = types::minutes(1, ticks_per_minute) as u16;
     }
 }
 
-pub fn create_quadratic_footpaths(stations: &mut Vec<Station>) {
+pub fn create_quadratic_footpaths(stations: &mut Vec<Station>, ticks_per_minute: types::Mtime) {
     let mut ctr = 0;
     for i in 0..stations.len() {
         for j in 0..stations.len() {
@@ -58,29 +139,32 @@ pub fn create_quadratic_footpaths(stations: &mut Vec<Station>) {
             if dist < MAX_WALKING_METRES {
                 stations[i].footpaths.push(Footpath {
                     target_location_idx: j,
-                    duration: walking_duration(dist),
+                    duration: walking_duration(dist, ticks_per_minute),
                 });
                 ctr += 1;
             }
         }
-        stations[i].transfer_time = 1;
+        stations[i].transfer_time = types::minutes(1, ticks_per_minute) as u16;
     }
     println!("Created {} footpaths", ctr);
 }
 
-pub fn create_materialized_initial_footpaths(origin_idx: usize, stations: &mut Vec<Station>, connections: &mut Vec<Connection>) {
+pub fn create_materialized_initial_footpaths(origin_idx: usize, stations: &mut Vec<Station>, connections: &mut Vec<Connection>, profile: Option<&WalkingProfile>, ticks_per_minute: types::Mtime) {
     let mut walking_connections = vec![];
 
     for i in 0..stations[origin_idx].footpaths.len() {
         let target_idx = stations[origin_idx].footpaths[i].target_location_idx;
-        let duration = stations[origin_idx].footpaths[i].duration;
+        let mut duration = stations[origin_idx].footpaths[i].duration;
+        if let Some(p) = profile {
+            duration = p.expected_duration(duration, ticks_per_minute);
+        }
         for j in 0..stations[target_idx].departures.len() {
             let id = connections.len() + walking_connections.len();
             let cid = stations[target_idx].departures[j];
             let c = &connections[cid];
-            let arrival = StopInfo::new(c.departure.projected()-WALKING_INITIAL_BUFFER_MINUTES, None);
+            let arrival = StopInfo::new(c.departure.projected()-types::minutes(WALKING_INITIAL_BUFFER_MINUTES, ticks_per_minute), None);
             let mut departure = arrival.clone();
-            departure.scheduled -= duration.max(1) as i32;
+            departure.scheduled -= duration.max(types::minutes(1, ticks_per_minute) as u16) as i32;
             if departure.projected() < 0 {
                 continue;
             }
@@ -121,7 +205,7 @@ pub fn update_footpath_relevance(origin_idx: usize, destination_idx: usize, orde
     }
 }
 
-pub fn create_materialized_quadratic_footpaths(stations: &mut Vec<Station>, connections: &mut Vec<Connection>) {
+pub fn create_materialized_quadratic_footpaths(stations: &mut Vec<Station>, connections: &mut Vec<Connection>, ticks_per_minute: types::Mtime) {
     let mut walking_connections = vec![];
     for s in 0..stations.len() {
         for c in connections.iter() {
@@ -136,6 +220,7 @@ pub fn create_materialized_quadratic_footpaths(stations: &mut Vec<Station>, conn
                     stations,
                     dist,
                     s,
+                    ticks_per_minute,
                 ));
             }
         }
@@ -212,6 +297,7 @@ pub fn create_relevant_timetable_with_extended_walking(
     weights_by_station_idx: &HashMap<usize, types::MFloat>,
     origin_idx: usize,
     destination_idx: usize,
+    ticks_per_minute: types::Mtime,
 ) -> (GtfsTimetable, usize, usize) {
     let origin_id = &stations[origin_idx].id;
     let destination_id = &stations[destination_idx].id;
@@ -268,7 +354,7 @@ pub fn create_relevant_timetable_with_extended_walking(
                     .footpaths
                     .push(Footpath {
                         target_location_idx: to_idx,
-                        duration: walking_duration(dist),
+                        duration: walking_duration(dist, ticks_per_minute),
                     });
             }
         }
@@ -287,6 +373,7 @@ pub fn create_relevant_timetable_with_extended_walking(
                     &mut new_stations,
                     dist,
                     to_idx,
+                    ticks_per_minute,
                 ));
             }
         }
@@ -301,6 +388,8 @@ pub fn create_relevant_timetable_with_extended_walking(
             cut: FxHashSet::default(),
             order: new_order,
             transport_and_day_to_connection_id: HashMap::new(),
+            ticks_per_minute,
+            base_len: None
         },
         new_stations_map[origin_id],
         new_stations_map[destination_id],
@@ -313,9 +402,10 @@ fn create_walking_connection(
     new_stations: &mut Vec<Station>,
     dist: f64,
     to_idx: usize,
+    ticks_per_minute: types::Mtime,
 ) -> Connection {
     let mut arrival = c.arrival.clone();
-    arrival.scheduled += walking_duration(dist) as i32;
+    arrival.scheduled += walking_duration(dist, ticks_per_minute) as i32;
     new_stations.get_mut(c.to_idx).unwrap().departures.push(id);
     new_stations.get_mut(to_idx).unwrap().arrivals.push(id);
     Connection {
@@ -332,7 +422,8 @@ fn create_walking_connection(
     }
 }
 
-pub fn query_with_extended_walking(store: &mut distribution_store::Store, tt: &mut GtfsTimetable, query: Query, now: types::Mtime, contraction: &StationContraction, _rtree: &RTree<StationLocation>) -> (GtfsTimetable, usize, usize, Vec<Vec<ConnectionLabel>>) {
+pub fn query_with_extended_walking(store: &mut distribution_store::Store, tt: &mut GtfsTimetable, query: Query, now: types::Mtime, contraction: &StationContraction, _rtree: &RTree<StationLocation>, destination_bucket_width: types::Mtime) -> (GtfsTimetable, usize, usize, Vec<Vec<ConnectionLabel>>) {
+    let ticks_per_minute = tt.ticks_per_minute;
     let mut env = topocsa::Environment::new(
         store,
         &mut tt.connections,
@@ -345,7 +436,7 @@ pub fn query_with_extended_walking(store: &mut distribution_store::Store, tt: &m
         true,
         true,
     );
-    env.set_station_contraction(contraction);
+    env.set_station_contraction(contraction).unwrap();
     println!("preprocessing...");
     env.preprocess();
     println!("start_time: {} now: {}", query.start_time, now);
@@ -354,7 +445,7 @@ pub fn query_with_extended_walking(store: &mut distribution_store::Store, tt: &m
     let weights_by_station_idx =
         env.get_relevant_stations(query.origin_idx, query.destination_idx, &station_labels, false);
     if weights_by_station_idx.is_empty() {
-        return (GtfsTimetable::new(), 0, 0, vec![])
+        return (GtfsTimetable::with_ticks_per_minute(ticks_per_minute), 0, 0, vec![])
     }
     println!("unextended: {}", weights_by_station_idx.len());
     /*relevant_stations_with_extended_walking(
@@ -372,7 +463,8 @@ pub fn query_with_extended_walking(store: &mut distribution_store::Store, tt: &m
         connection_pairs,
         &weights_by_station_idx,
         query.origin_idx,
-        query.destination_idx
+        query.destination_idx,
+        ticks_per_minute
     );
     let mut walking_tt = walking_timetable.0;
     println!("conns incl. walking: {} relstops: {} greatest footpath set: {}", walking_tt.connections.len(), walking_tt.stations.len(), walking_tt.stations.iter().map(|s|s.footpaths.len()).max().unwrap());
@@ -388,6 +480,7 @@ pub fn query_with_extended_walking(store: &mut distribution_store::Store, tt: &m
         false,
         false,
     );
+    rel_env.set_destination_bucket_width(destination_bucket_width);
     rel_env.preprocess();
     println!("querying walking tt...");
     let walking_query = Query {
@@ -0,0 +1,745 @@
+// Automatically generated rust module for 'gtfs_realtime.proto' file
+
+#![allow(non_snake_case)]
+#![allow(non_upper_case_globals)]
+#![allow(non_camel_case_types)]
+#![allow(unused_imports)]
+#![allow(unknown_lints)]
+#![allow(clippy::all)]
+#![cfg_attr(rustfmt, rustfmt_skip)]
+
+
+use std::borrow::Cow;
+use quick_protobuf::{MessageInfo, MessageRead, MessageWrite, BytesReader, Writer, WriterBackend, Result};
+use quick_protobuf::sizeofs::*;
+use super::*;
+
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct FeedMessage<'a> {
+    pub header: FeedHeader<'a>,
+    pub entity: Vec<FeedEntity<'a>>,
+}
+
+impl<'a> MessageRead<'a> for FeedMessage<'a> {
+    fn from_reader(r: &mut BytesReader, bytes: &'a [u8]) -> Result<Self> {
+        let mut msg = Self::default();
+        while !r.is_eof() {
+            match r.next_tag(bytes) {
+                Ok(10) => msg.header = r.read_message::<FeedHeader>(bytes)?,
+                Ok(18) => msg.entity.push(r.read_message::<FeedEntity>(bytes)?),
+                Ok(t) => { r.read_unknown(bytes, t)?; }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(msg)
+    }
+}
+
+impl<'a> MessageWrite for FeedMessage<'a> {
+    fn get_size(&self) -> usize {
+        0
+        + 1 + sizeof_len((&self.header).get_size())
+        + self.entity.iter().map(|s| 1 + sizeof_len((s).get_size())).sum::<usize>()
+    }
+
+    fn write_message<W: WriterBackend>(&self, w: &mut Writer<W>) -> Result<()> {
+        w.write_with_tag(10, |w| w.write_message(&self.header))?;
+        for s in &self.entity { w.write_with_tag(18, |w| w.write_message(s))?; }
+        Ok(())
+    }
+}
+
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct FeedHeader<'a> {
+    pub gtfs_realtime_version: Cow<'a, str>,
+    pub incrementality: Option<mod_FeedHeader::Incrementality>,
+    pub timestamp: Option<u64>,
+}
+
+impl<'a> MessageRead<'a> for FeedHeader<'a> {
+    fn from_reader(r: &mut BytesReader, bytes: &'a [u8]) -> Result<Self> {
+        let mut msg = Self::default();
+        while !r.is_eof() {
+            match r.next_tag(bytes) {
+                Ok(10) => msg.gtfs_realtime_version = r.read_string(bytes).map(Cow::Borrowed)?,
+                Ok(16) => msg.incrementality = Some(r.read_enum(bytes)?),
+                Ok(24) => msg.timestamp = Some(r.read_uint64(bytes)?),
+                Ok(t) => { r.read_unknown(bytes, t)?; }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(msg)
+    }
+}
+
+impl<'a> MessageWrite for FeedHeader<'a> {
+    fn get_size(&self) -> usize {
+        0
+        + 1 + sizeof_len((&self.gtfs_realtime_version).len())
+        + self.incrementality.as_ref().map_or(0, |m| 1 + sizeof_varint(*(m) as u64))
+        + self.timestamp.as_ref().map_or(0, |m| 1 + sizeof_varint(*(m) as u64))
+    }
+
+    fn write_message<W: WriterBackend>(&self, w: &mut Writer<W>) -> Result<()> {
+        w.write_with_tag(10, |w| w.write_string(&**&self.gtfs_realtime_version))?;
+        if let Some(ref s) = self.incrementality { w.write_with_tag(16, |w| w.write_enum(*s as i32))?; }
+        if let Some(ref s) = self.timestamp { w.write_with_tag(24, |w| w.write_uint64(*s))?; }
+        Ok(())
+    }
+}
+
+pub mod mod_FeedHeader {
+
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Incrementality {
+    FULL_DATASET = 0,
+    DIFFERENTIAL = 1,
+}
+
+impl Default for Incrementality {
+    fn default() -> Self {
+        Incrementality::FULL_DATASET
+    }
+}
+
+impl From<i32> for Incrementality {
+    fn from(i: i32) -> Self {
+        match i {
+            0 => Incrementality::FULL_DATASET,
+            1 => Incrementality::DIFFERENTIAL,
+            _ => Self::default(),
+        }
+    }
+}
+
+}
+
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct FeedEntity<'a> {
+    pub id: Cow<'a, str>,
+    pub is_deleted: Option<bool>,
+    pub trip_update: Option<TripUpdate<'a>>,
+    pub vehicle: Option<VehiclePosition<'a>>,
+    pub alert: Option<Alert<'a>>,
+}
+
+impl<'a> MessageRead<'a> for FeedEntity<'a> {
+    fn from_reader(r: &mut BytesReader, bytes: &'a [u8]) -> Result<Self> {
+        let mut msg = Self::default();
+        while !r.is_eof() {
+            match r.next_tag(bytes) {
+                Ok(10) => msg.id = r.read_string(bytes).map(Cow::Borrowed)?,
+                Ok(16) => msg.is_deleted = Some(r.read_bool(bytes)?),
+                Ok(26) => msg.trip_update = Some(r.read_message::<TripUpdate>(bytes)?),
+                Ok(34) => msg.vehicle = Some(r.read_message::<VehiclePosition>(bytes)?),
+                Ok(42) => msg.alert = Some(r.read_message::<Alert>(bytes)?),
+                Ok(t) => { r.read_unknown(bytes, t)?; }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(msg)
+    }
+}
+
+impl<'a> MessageWrite for FeedEntity<'a> {
+    fn get_size(&self) -> usize {
+        0
+        + 1 + sizeof_len((&self.id).len())
+        + self.is_deleted.as_ref().map_or(0, |m| 1 + sizeof_varint(*(m) as u64))
+        + self.trip_update.as_ref().map_or(0, |m| 1 + sizeof_len((m).get_size()))
+        + self.vehicle.as_ref().map_or(0, |m| 1 + sizeof_len((m).get_size()))
+        + self.alert.as_ref().map_or(0, |m| 1 + sizeof_len((m).get_size()))
+    }
+
+    fn write_message<W: WriterBackend>(&self, w: &mut Writer<W>) -> Result<()> {
+        w.write_with_tag(10, |w| w.write_string(&**&self.id))?;
+        if let Some(ref s) = self.is_deleted { w.write_with_tag(16, |w| w.write_bool(*s))?; }
+        if let Some(ref s) = self.trip_update { w.write_with_tag(26, |w| w.write_message(s))?; }
+        if let Some(ref s) = self.vehicle { w.write_with_tag(34, |w| w.write_message(s))?; }
+        if let Some(ref s) = self.alert { w.write_with_tag(42, |w| w.write_message(s))?; }
+        Ok(())
+    }
+}
+
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct TripUpdate<'a> {
+    pub trip: TripDescriptor<'a>,
+    pub stop_time_update: Vec<mod_TripUpdate::StopTimeUpdate<'a>>,
+    pub timestamp: Option<u64>,
+    pub delay: Option<i32>,
+}
+
+impl<'a> MessageRead<'a> for TripUpdate<'a> {
+    fn from_reader(r: &mut BytesReader, bytes: &'a [u8]) -> Result<Self> {
+        let mut msg = Self::default();
+        while !r.is_eof() {
+            match r.next_tag(bytes) {
+                Ok(10) => msg.trip = r.read_message::<TripDescriptor>(bytes)?,
+                Ok(18) => msg.stop_time_update.push(r.read_message::<mod_TripUpdate::StopTimeUpdate>(bytes)?),
+                Ok(32) => msg.timestamp = Some(r.read_uint64(bytes)?),
+                Ok(40) => msg.delay = Some(r.read_int32(bytes)?),
+                Ok(t) => { r.read_unknown(bytes, t)?; }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(msg)
+    }
+}
+
+impl<'a> MessageWrite for TripUpdate<'a> {
+    fn get_size(&self) -> usize {
+        0
+        + 1 + sizeof_len((&self.trip).get_size())
+        + self.stop_time_update.iter().map(|s| 1 + sizeof_len((s).get_size())).sum::<usize>()
+        + self.timestamp.as_ref().map_or(0, |m| 1 + sizeof_varint(*(m) as u64))
+        + self.delay.as_ref().map_or(0, |m| 1 + sizeof_varint(*(m) as u64))
+    }
+
+    fn write_message<W: WriterBackend>(&self, w: &mut Writer<W>) -> Result<()> {
+        w.write_with_tag(10, |w| w.write_message(&self.trip))?;
+        for s in &self.stop_time_update { w.write_with_tag(18, |w| w.write_message(s))?; }
+        if let Some(ref s) = self.timestamp { w.write_with_tag(32, |w| w.write_uint64(*s))?; }
+        if let Some(ref s) = self.delay { w.write_with_tag(40, |w| w.write_int32(*s))?; }
+        Ok(())
+    }
+}
+
+pub mod mod_TripUpdate {
+
+use std::borrow::Cow;
+use super::*;
+
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct StopTimeEvent {
+    pub delay: Option<i32>,
+    pub time: Option<i64>,
+    pub uncertainty: Option<i32>,
+}
+
+impl<'a> MessageRead<'a> for StopTimeEvent {
+    fn from_reader(r: &mut BytesReader, bytes: &'a [u8]) -> Result<Self> {
+        let mut msg = Self::default();
+        while !r.is_eof() {
+            match r.next_tag(bytes) {
+                Ok(8) => msg.delay = Some(r.read_int32(bytes)?),
+                Ok(16) => msg.time = Some(r.read_int64(bytes)?),
+                Ok(24) => msg.uncertainty = Some(r.read_int32(bytes)?),
+                Ok(t) => { r.read_unknown(bytes, t)?; }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(msg)
+    }
+}
+
+impl MessageWrite for StopTimeEvent {
+    fn get_size(&self) -> usize {
+        0
+        + self.delay.as_ref().map_or(0, |m| 1 + sizeof_varint(*(m) as u64))
+        + self.time.as_ref().map_or(0, |m| 1 + sizeof_varint(*(m) as u64))
+        + self.uncertainty.as_ref().map_or(0, |m| 1 + sizeof_varint(*(m) as u64))
+    }
+
+    fn write_message<W: WriterBackend>(&self, w: &mut Writer<W>) -> Result<()> {
+        if let Some(ref s) = self.delay { w.write_with_tag(8, |w| w.write_int32(*s))?; }
+        if let Some(ref s) = self.time { w.write_with_tag(16, |w| w.write_int64(*s))?; }
+        if let Some(ref s) = self.uncertainty { w.write_with_tag(24, |w| w.write_int32(*s))?; }
+        Ok(())
+    }
+}
+
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct StopTimeUpdate<'a> {
+    pub stop_sequence: Option<u32>,
+    pub stop_id: Option<Cow<'a, str>>,
+    pub arrival: Option<mod_TripUpdate::StopTimeEvent>,
+    pub departure: Option<mod_TripUpdate::StopTimeEvent>,
+    pub schedule_relationship: Option<mod_TripUpdate::mod_StopTimeUpdate::ScheduleRelationship>,
+}
+
+impl<'a> MessageRead<'a> for StopTimeUpdate<'a> {
+    fn from_reader(r: &mut BytesReader, bytes: &'a [u8]) -> Result<Self> {
+        let mut msg = Self::default();
+        while !r.is_eof() {
+            match r.next_tag(bytes) {
+                Ok(8) => msg.stop_sequence = Some(r.read_uint32(bytes)?),
+                Ok(34) => msg.stop_id = Some(r.read_string(bytes).map(Cow::Borrowed)?),
+                Ok(18) => msg.arrival = Some(r.read_message::<mod_TripUpdate::StopTimeEvent>(bytes)?),
+                Ok(26) => msg.departure = Some(r.read_message::<mod_TripUpdate::StopTimeEvent>(bytes)?),
+                Ok(40) => msg.schedule_relationship = Some(r.read_enum(bytes)?),
+                Ok(t) => { r.read_unknown(bytes, t)?; }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(msg)
+    }
+}
+
+impl<'a> MessageWrite for StopTimeUpdate<'a> {
+    fn get_size(&self) -> usize {
+        0
+        + self.stop_sequence.as_ref().map_or(0, |m| 1 + sizeof_varint(*(m) as u64))
+        + self.stop_id.as_ref().map_or(0, |m| 1 + sizeof_len((m).len()))
+        + self.arrival.as_ref().map_or(0, |m| 1 + sizeof_len((m).get_size()))
+        + self.departure.as_ref().map_or(0, |m| 1 + sizeof_len((m).get_size()))
+        + self.schedule_relationship.as_ref().map_or(0, |m| 1 + sizeof_varint(*(m) as u64))
+    }
+
+    fn write_message<W: WriterBackend>(&self, w: &mut Writer<W>) -> Result<()> {
+        if let Some(ref s) = self.stop_sequence { w.write_with_tag(8, |w| w.write_uint32(*s))?; }
+        if let Some(ref s) = self.stop_id { w.write_with_tag(34, |w| w.write_string(&**s))?; }
+        if let Some(ref s) = self.arrival { w.write_with_tag(18, |w| w.write_message(s))?; }
+        if let Some(ref s) = self.departure { w.write_with_tag(26, |w| w.write_message(s))?; }
+        if let Some(ref s) = self.schedule_relationship { w.write_with_tag(40, |w| w.write_enum(*s as i32))?; }
+        Ok(())
+    }
+}
+
+pub mod mod_StopTimeUpdate {
+
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ScheduleRelationship {
+    SCHEDULED = 0,
+    SKIPPED = 1,
+    NO_DATA = 2,
+    UNSCHEDULED = 3,
+}
+
+impl Default for ScheduleRelationship {
+    fn default() -> Self {
+        ScheduleRelationship::SCHEDULED
+    }
+}
+
+impl From<i32> for ScheduleRelationship {
+    fn from(i: i32) -> Self {
+        match i {
+            0 => ScheduleRelationship::SCHEDULED,
+            1 => ScheduleRelationship::SKIPPED,
+            2 => ScheduleRelationship::NO_DATA,
+            3 => ScheduleRelationship::UNSCHEDULED,
+            _ => Self::default(),
+        }
+    }
+}
+
+}
+
+}
+
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct TripDescriptor<'a> {
+    pub trip_id: Option<Cow<'a, str>>,
+    pub route_id: Option<Cow<'a, str>>,
+    pub direction_id: Option<u32>,
+    pub start_time: Option<Cow<'a, str>>,
+    pub start_date: Option<Cow<'a, str>>,
+    pub schedule_relationship: Option<mod_TripDescriptor::ScheduleRelationship>,
+}
+
+impl<'a> MessageRead<'a> for TripDescriptor<'a> {
+    fn from_reader(r: &mut BytesReader, bytes: &'a [u8]) -> Result<Self> {
+        let mut msg = Self::default();
+        while !r.is_eof() {
+            match r.next_tag(bytes) {
+                Ok(10) => msg.trip_id = Some(r.read_string(bytes).map(Cow::Borrowed)?),
+                Ok(42) => msg.route_id = Some(r.read_string(bytes).map(Cow::Borrowed)?),
+                Ok(48) => msg.direction_id = Some(r.read_uint32(bytes)?),
+                Ok(18) => msg.start_time = Some(r.read_string(bytes).map(Cow::Borrowed)?),
+                Ok(26) => msg.start_date = Some(r.read_string(bytes).map(Cow::Borrowed)?),
+                Ok(32) => msg.schedule_relationship = Some(r.read_enum(bytes)?),
+                Ok(t) => { r.read_unknown(bytes, t)?; }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(msg)
+    }
+}
+
+impl<'a> MessageWrite for TripDescriptor<'a> {
+    fn get_size(&self) -> usize {
+        0
+        + self.trip_id.as_ref().map_or(0, |m| 1 + sizeof_len((m).len()))
+        + self.route_id.as_ref().map_or(0, |m| 1 + sizeof_len((m).len()))
+        + self.direction_id.as_ref().map_or(0, |m| 1 + sizeof_varint(*(m) as u64))
+        + self.start_time.as_ref().map_or(0, |m| 1 + sizeof_len((m).len()))
+        + self.start_date.as_ref().map_or(0, |m| 1 + sizeof_len((m).len()))
+        + self.schedule_relationship.as_ref().map_or(0, |m| 1 + sizeof_varint(*(m) as u64))
+    }
+
+    fn write_message<W: WriterBackend>(&self, w: &mut Writer<W>) -> Result<()> {
+        if let Some(ref s) = self.trip_id { w.write_with_tag(10, |w| w.write_string(&**s))?; }
+        if let Some(ref s) = self.route_id { w.write_with_tag(42, |w| w.write_string(&**s))?; }
+        if let Some(ref s) = self.direction_id { w.write_with_tag(48, |w| w.write_uint32(*s))?; }
+        if let Some(ref s) = self.start_time { w.write_with_tag(18, |w| w.write_string(&**s))?; }
+        if let Some(ref s) = self.start_date { w.write_with_tag(26, |w| w.write_string(&**s))?; }
+        if let Some(ref s) = self.schedule_relationship { w.write_with_tag(32, |w| w.write_enum(*s as i32))?; }
+        Ok(())
+    }
+}
+
+pub mod mod_TripDescriptor {
+
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ScheduleRelationship {
+    SCHEDULED = 0,
+    ADDED = 1,
+    UNSCHEDULED = 2,
+    CANCELED = 3,
+    REPLACEMENT = 5,
+    DUPLICATED = 6,
+    DELETED = 7,
+}
+
+impl Default for ScheduleRelationship {
+    fn default() -> Self {
+        ScheduleRelationship::SCHEDULED
+    }
+}
+
+impl From<i32> for ScheduleRelationship {
+    fn from(i: i32) -> Self {
+        match i {
+            0 => ScheduleRelationship::SCHEDULED,
+            1 => ScheduleRelationship::ADDED,
+            2 => ScheduleRelationship::UNSCHEDULED,
+            3 => ScheduleRelationship::CANCELED,
+            5 => ScheduleRelationship::REPLACEMENT,
+            6 => ScheduleRelationship::DUPLICATED,
+            7 => ScheduleRelationship::DELETED,
+            _ => Self::default(),
+        }
+    }
+}
+
+}
+
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct VehiclePosition<'a> {
+    pub trip: Option<TripDescriptor<'a>>,
+    pub position: Option<Position>,
+    pub current_stop_sequence: Option<u32>,
+    pub stop_id: Option<Cow<'a, str>>,
+    pub current_status: Option<mod_VehiclePosition::VehicleStopStatus>,
+    pub timestamp: Option<u64>,
+}
+
+impl<'a> MessageRead<'a> for VehiclePosition<'a> {
+    fn from_reader(r: &mut BytesReader, bytes: &'a [u8]) -> Result<Self> {
+        let mut msg = Self::default();
+        while !r.is_eof() {
+            match r.next_tag(bytes) {
+                Ok(10) => msg.trip = Some(r.read_message::<TripDescriptor>(bytes)?),
+                Ok(18) => msg.position = Some(r.read_message::<Position>(bytes)?),
+                Ok(24) => msg.current_stop_sequence = Some(r.read_uint32(bytes)?),
+                Ok(58) => msg.stop_id = Some(r.read_string(bytes).map(Cow::Borrowed)?),
+                Ok(32) => msg.current_status = Some(r.read_enum(bytes)?),
+                Ok(40) => msg.timestamp = Some(r.read_uint64(bytes)?),
+                Ok(t) => { r.read_unknown(bytes, t)?; }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(msg)
+    }
+}
+
+impl<'a> MessageWrite for VehiclePosition<'a> {
+    fn get_size(&self) -> usize {
+        0
+        + self.trip.as_ref().map_or(0, |m| 1 + sizeof_len((m).get_size()))
+        + self.position.as_ref().map_or(0, |m| 1 + sizeof_len((m).get_size()))
+        + self.current_stop_sequence.as_ref().map_or(0, |m| 1 + sizeof_varint(*(m) as u64))
+        + self.stop_id.as_ref().map_or(0, |m| 1 + sizeof_len((m).len()))
+        + self.current_status.as_ref().map_or(0, |m| 1 + sizeof_varint(*(m) as u64))
+        + self.timestamp.as_ref().map_or(0, |m| 1 + sizeof_varint(*(m) as u64))
+    }
+
+    fn write_message<W: WriterBackend>(&self, w: &mut Writer<W>) -> Result<()> {
+        if let Some(ref s) = self.trip { w.write_with_tag(10, |w| w.write_message(s))?; }
+        if let Some(ref s) = self.position { w.write_with_tag(18, |w| w.write_message(s))?; }
+        if let Some(ref s) = self.current_stop_sequence { w.write_with_tag(24, |w| w.write_uint32(*s))?; }
+        if let Some(ref s) = self.stop_id { w.write_with_tag(58, |w| w.write_string(&**s))?; }
+        if let Some(ref s) = self.current_status { w.write_with_tag(32, |w| w.write_enum(*s as i32))?; }
+        if let Some(ref s) = self.timestamp { w.write_with_tag(40, |w| w.write_uint64(*s))?; }
+        Ok(())
+    }
+}
+
+pub mod mod_VehiclePosition {
+
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum VehicleStopStatus {
+    INCOMING_AT = 0,
+    STOPPED_AT = 1,
+    IN_TRANSIT_TO = 2,
+}
+
+impl Default for VehicleStopStatus {
+    fn default() -> Self {
+        VehicleStopStatus::INCOMING_AT
+    }
+}
+
+impl From<i32> for VehicleStopStatus {
+    fn from(i: i32) -> Self {
+        match i {
+            0 => VehicleStopStatus::INCOMING_AT,
+            1 => VehicleStopStatus::STOPPED_AT,
+            2 => VehicleStopStatus::IN_TRANSIT_TO,
+            _ => Self::default(),
+        }
+    }
+}
+
+}
+
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct Position {
+    pub latitude: f32,
+    pub longitude: f32,
+}
+
+impl<'a> MessageRead<'a> for Position {
+    fn from_reader(r: &mut BytesReader, bytes: &'a [u8]) -> Result<Self> {
+        let mut msg = Self::default();
+        while !r.is_eof() {
+            match r.next_tag(bytes) {
+                Ok(13) => msg.latitude = r.read_float(bytes)?,
+                Ok(21) => msg.longitude = r.read_float(bytes)?,
+                Ok(t) => { r.read_unknown(bytes, t)?; }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(msg)
+    }
+}
+
+impl MessageWrite for Position {
+    fn get_size(&self) -> usize {
+        0
+        + 1 + 4
+        + 1 + 4
+    }
+
+    fn write_message<W: WriterBackend>(&self, w: &mut Writer<W>) -> Result<()> {
+        w.write_with_tag(13, |w| w.write_float(*&self.latitude))?;
+        w.write_with_tag(21, |w| w.write_float(*&self.longitude))?;
+        Ok(())
+    }
+}
+
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct Alert<'a> {
+    pub active_period: Vec<TimeRange>,
+    pub informed_entity: Vec<EntitySelector<'a>>,
+    pub header_text: Option<TranslatedString<'a>>,
+    pub description_text: Option<TranslatedString<'a>>,
+}
+
+impl<'a> MessageRead<'a> for Alert<'a> {
+    fn from_reader(r: &mut BytesReader, bytes: &'a [u8]) -> Result<Self> {
+        let mut msg = Self::default();
+        while !r.is_eof() {
+            match r.next_tag(bytes) {
+                Ok(10) => msg.active_period.push(r.read_message::<TimeRange>(bytes)?),
+                Ok(42) => msg.informed_entity.push(r.read_message::<EntitySelector>(bytes)?),
+                Ok(82) => msg.header_text = Some(r.read_message::<TranslatedString>(bytes)?),
+                Ok(90) => msg.description_text = Some(r.read_message::<TranslatedString>(bytes)?),
+                Ok(t) => { r.read_unknown(bytes, t)?; }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(msg)
+    }
+}
+
+impl<'a> MessageWrite for Alert<'a> {
+    fn get_size(&self) -> usize {
+        0
+        + self.active_period.iter().map(|s| 1 + sizeof_len((s).get_size())).sum::<usize>()
+        + self.informed_entity.iter().map(|s| 1 + sizeof_len((s).get_size())).sum::<usize>()
+        + self.header_text.as_ref().map_or(0, |m| 1 + sizeof_len((m).get_size()))
+        + self.description_text.as_ref().map_or(0, |m| 1 + sizeof_len((m).get_size()))
+    }
+
+    fn write_message<W: WriterBackend>(&self, w: &mut Writer<W>) -> Result<()> {
+        for s in &self.active_period { w.write_with_tag(10, |w| w.write_message(s))?; }
+        for s in &self.informed_entity { w.write_with_tag(42, |w| w.write_message(s))?; }
+        if let Some(ref s) = self.header_text { w.write_with_tag(82, |w| w.write_message(s))?; }
+        if let Some(ref s) = self.description_text { w.write_with_tag(90, |w| w.write_message(s))?; }
+        Ok(())
+    }
+}
+
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct TimeRange {
+    pub start: Option<u64>,
+    pub end: Option<u64>,
+}
+
+impl<'a> MessageRead<'a> for TimeRange {
+    fn from_reader(r: &mut BytesReader, bytes: &'a [u8]) -> Result<Self> {
+        let mut msg = Self::default();
+        while !r.is_eof() {
+            match r.next_tag(bytes) {
+                Ok(8) => msg.start = Some(r.read_uint64(bytes)?),
+                Ok(16) => msg.end = Some(r.read_uint64(bytes)?),
+                Ok(t) => { r.read_unknown(bytes, t)?; }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(msg)
+    }
+}
+
+impl MessageWrite for TimeRange {
+    fn get_size(&self) -> usize {
+        0
+        + self.start.as_ref().map_or(0, |m| 1 + sizeof_varint(*(m) as u64))
+        + self.end.as_ref().map_or(0, |m| 1 + sizeof_varint(*(m) as u64))
+    }
+
+    fn write_message<W: WriterBackend>(&self, w: &mut Writer<W>) -> Result<()> {
+        if let Some(ref s) = self.start { w.write_with_tag(8, |w| w.write_uint64(*s))?; }
+        if let Some(ref s) = self.end { w.write_with_tag(16, |w| w.write_uint64(*s))?; }
+        Ok(())
+    }
+}
+
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct EntitySelector<'a> {
+    pub agency_id: Option<Cow<'a, str>>,
+    pub route_id: Option<Cow<'a, str>>,
+    pub route_type: Option<i32>,
+    pub trip: Option<TripDescriptor<'a>>,
+    pub stop_id: Option<Cow<'a, str>>,
+}
+
+impl<'a> MessageRead<'a> for EntitySelector<'a> {
+    fn from_reader(r: &mut BytesReader, bytes: &'a [u8]) -> Result<Self> {
+        let mut msg = Self::default();
+        while !r.is_eof() {
+            match r.next_tag(bytes) {
+                Ok(10) => msg.agency_id = Some(r.read_string(bytes).map(Cow::Borrowed)?),
+                Ok(18) => msg.route_id = Some(r.read_string(bytes).map(Cow::Borrowed)?),
+                Ok(24) => msg.route_type = Some(r.read_int32(bytes)?),
+                Ok(34) => msg.trip = Some(r.read_message::<TripDescriptor>(bytes)?),
+                Ok(42) => msg.stop_id = Some(r.read_string(bytes).map(Cow::Borrowed)?),
+                Ok(t) => { r.read_unknown(bytes, t)?; }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(msg)
+    }
+}
+
+impl<'a> MessageWrite for EntitySelector<'a> {
+    fn get_size(&self) -> usize {
+        0
+        + self.agency_id.as_ref().map_or(0, |m| 1 + sizeof_len((m).len()))
+        + self.route_id.as_ref().map_or(0, |m| 1 + sizeof_len((m).len()))
+        + self.route_type.as_ref().map_or(0, |m| 1 + sizeof_varint(*(m) as u64))
+        + self.trip.as_ref().map_or(0, |m| 1 + sizeof_len((m).get_size()))
+        + self.stop_id.as_ref().map_or(0, |m| 1 + sizeof_len((m).len()))
+    }
+
+    fn write_message<W: WriterBackend>(&self, w: &mut Writer<W>) -> Result<()> {
+        if let Some(ref s) = self.agency_id { w.write_with_tag(10, |w| w.write_string(&**s))?; }
+        if let Some(ref s) = self.route_id { w.write_with_tag(18, |w| w.write_string(&**s))?; }
+        if let Some(ref s) = self.route_type { w.write_with_tag(24, |w| w.write_int32(*s))?; }
+        if let Some(ref s) = self.trip { w.write_with_tag(34, |w| w.write_message(s))?; }
+        if let Some(ref s) = self.stop_id { w.write_with_tag(42, |w| w.write_string(&**s))?; }
+        Ok(())
+    }
+}
+
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct TranslatedString<'a> {
+    pub translation: Vec<mod_TranslatedString::Translation<'a>>,
+}
+
+impl<'a> MessageRead<'a> for TranslatedString<'a> {
+    fn from_reader(r: &mut BytesReader, bytes: &'a [u8]) -> Result<Self> {
+        let mut msg = Self::default();
+        while !r.is_eof() {
+            match r.next_tag(bytes) {
+                Ok(10) => msg.translation.push(r.read_message::<mod_TranslatedString::Translation>(bytes)?),
+                Ok(t) => { r.read_unknown(bytes, t)?; }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(msg)
+    }
+}
+
+impl<'a> MessageWrite for TranslatedString<'a> {
+    fn get_size(&self) -> usize {
+        0
+        + self.translation.iter().map(|s| 1 + sizeof_len((s).get_size())).sum::<usize>()
+    }
+
+    fn write_message<W: WriterBackend>(&self, w: &mut Writer<W>) -> Result<()> {
+        for s in &self.translation { w.write_with_tag(10, |w| w.write_message(s))?; }
+        Ok(())
+    }
+}
+
+pub mod mod_TranslatedString {
+
+use std::borrow::Cow;
+use super::*;
+
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct Translation<'a> {
+    pub text: Cow<'a, str>,
+    pub language: Option<Cow<'a, str>>,
+}
+
+impl<'a> MessageRead<'a> for Translation<'a> {
+    fn from_reader(r: &mut BytesReader, bytes: &'a [u8]) -> Result<Self> {
+        let mut msg = Self::default();
+        while !r.is_eof() {
+            match r.next_tag(bytes) {
+                Ok(10) => msg.text = r.read_string(bytes).map(Cow::Borrowed)?,
+                Ok(18) => msg.language = Some(r.read_string(bytes).map(Cow::Borrowed)?),
+                Ok(t) => { r.read_unknown(bytes, t)?; }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(msg)
+    }
+}
+
+impl<'a> MessageWrite for Translation<'a> {
+    fn get_size(&self) -> usize {
+        0
+        + 1 + sizeof_len((&self.text).len())
+        + self.language.as_ref().map_or(0, |m| 1 + sizeof_len((m).len()))
+    }
+
+    fn write_message<W: WriterBackend>(&self, w: &mut Writer<W>) -> Result<()> {
+        w.write_with_tag(10, |w| w.write_string(&**&self.text))?;
+        if let Some(ref s) = self.language { w.write_with_tag(18, |w| w.write_string(&**s))?; }
+        Ok(())
+    }
+}
+
+}
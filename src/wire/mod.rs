@@ -1,3 +1,5 @@
 // Automatically generated mod.rs
 pub mod wire;
-pub mod serde;
\ No newline at end of file
+pub mod serde;
+#[cfg(feature = "native-gtfs")]
+pub mod gtfs_realtime;
@@ -17,6 +17,7 @@ use crate::walking::WALKING_PRODUCT_TYPE;
 use crate::wire::wire;
 use crate::connection;
 use crate::distribution;
+use crate::itinerary;
 
 pub struct QueryMetadata {
     pub start_ts: i64,
@@ -25,7 +26,17 @@ pub struct QueryMetadata {
     pub destination_id: String,
     pub destination_idx: usize,
     pub now: i64,
-    pub system: String
+    pub system: String,
+    pub algorithm: String,
+    pub itinerary: Vec<RequestedLeg>
+}
+
+// A leg of a fixed itinerary as given by the client, identifying the trip by its route and departure.
+pub struct RequestedLeg {
+    pub route_id: String,
+    pub from_id: String,
+    pub to_id: String,
+    pub departure_ts: i64
 }
 
 pub fn write_protobuf(bytes: &Vec<u8>, filepath: &str) {
@@ -40,15 +51,28 @@ pub fn read_protobuf(filepath: &str) -> Vec<u8> {
     std::fs::read(filepath).unwrap()
 }
 
-pub fn to_mtime(t: i64, reference: i64) -> types::Mtime {
-    ((t-reference) as f32/60.0).round() as types::Mtime
+pub fn to_mtime(t: i64, reference: i64, ticks_per_minute: types::Mtime) -> types::Mtime {
+    ((t-reference) as f32/types::seconds_per_tick(ticks_per_minute) as f32).round() as types::Mtime
+}
+
+pub fn from_mtime(mtime: types::Mtime, reference: i64, ticks_per_minute: types::Mtime) -> i64 {
+    (mtime*types::seconds_per_tick(ticks_per_minute)) as i64 + reference
+}
+
+fn to_delay(delay_minutes: i32, is_live: bool, ticks_per_minute: types::Mtime) -> Option<i16> {
+    if is_live { Some(types::minutes(delay_minutes, ticks_per_minute) as i16) } else { None }
 }
 
-pub fn from_mtime(mtime: types::Mtime, reference: i64) -> i64 {
-    (mtime*60) as i64 + reference
+fn from_delay(delay: Option<i16>, ticks_per_minute: types::Mtime) -> i32 {
+    (delay.unwrap_or(0) as f32/ticks_per_minute as f32).round() as i32
 }
 
-pub fn deserialize_protobuf<'a, 'b>(bytes: Vec<u8>, stations: &'a mut Vec<connection::Station>, routes: &'b mut Vec<connection::Route>, connections: &'b mut Vec<connection::Connection>, load_distributions: bool) -> QueryMetadata {
+fn to_minute_resolution(d: &distribution::Distribution, ticks_per_minute: types::Mtime) -> distribution::Distribution {
+    let refined = if d.bucket_width > 1 { d.refine() } else { d.clone() };
+    if ticks_per_minute > 1 && !refined.histogram.is_empty() { refined.coarsen(ticks_per_minute) } else { refined }
+}
+
+pub fn deserialize_protobuf<'a, 'b>(bytes: Vec<u8>, stations: &'a mut Vec<connection::Station>, routes: &'b mut Vec<connection::Route>, connections: &'b mut Vec<connection::Connection>, load_distributions: bool, ticks_per_minute: types::Mtime) -> QueryMetadata {
     let mut reader = BytesReader::from_bytes(&bytes);
     let request_message = wire::Message::from_reader(&mut reader, &bytes).expect("Cannot read Timetable");
         
@@ -63,7 +87,7 @@ pub fn deserialize_protobuf<'a, 'b>(bytes: Vec<u8>, stations: &'a mut Vec<connec
 			departures: vec![],
 			lat: s.lat as f64,
 			lon: s.lon as f64,
-			transfer_time: 1,
+			transfer_time: types::minutes(1, ticks_per_minute) as u16,
 			parent_idx: 0,
 			footpaths: vec![]
 		});
@@ -85,19 +109,20 @@ pub fn deserialize_protobuf<'a, 'b>(bytes: Vec<u8>, stations: &'a mut Vec<connec
                 let id = connections.len();
                 let mut nc = connection::Connection::new(
                     id, route_idx, r.product_type as i16, trip_id, c.cancelled,
-                    from_idx, to_mtime(c.departure.as_ref().unwrap().scheduled, timetable.start_time), if c.departure.as_ref().unwrap().is_live { Some(c.departure.as_ref().unwrap().delay_minutes as i16) } else { None },
-                    to_idx, to_mtime(c.arrival.as_ref().unwrap().scheduled, timetable.start_time), if c.arrival.as_ref().unwrap().is_live { Some(c.arrival.as_ref().unwrap().delay_minutes as i16) } else { None }
+                    from_idx, to_mtime(c.departure.as_ref().unwrap().scheduled, timetable.start_time, ticks_per_minute), to_delay(c.departure.as_ref().unwrap().delay_minutes, c.departure.as_ref().unwrap().is_live, ticks_per_minute),
+                    to_idx, to_mtime(c.arrival.as_ref().unwrap().scheduled, timetable.start_time, ticks_per_minute), to_delay(c.arrival.as_ref().unwrap().delay_minutes, c.arrival.as_ref().unwrap().is_live, ticks_per_minute)
                 );
                 if nc.product_type == WALKING_PRODUCT_TYPE {
                     nc.departure.in_out_allowed = false; //TODO tstp footpaths not reachable, using virtual footpaths instead
                 }
                 nc.destination_arrival.replace(if !load_distributions || c.destination_arrival.is_none() { None } else { let da = c.destination_arrival.as_ref().unwrap(); Some(distribution::Distribution {
                     histogram: da.histogram.to_vec().into_iter().map(|h| h as types::MFloat).collect(),
-                    start: to_mtime(da.start, timetable.start_time),
-                    mean: (da.mean as types::MFloat/60.0) - timetable.start_time as types::MFloat,
+                    start: to_mtime(da.start, timetable.start_time, ticks_per_minute),
+                    mean: (da.mean as types::MFloat/types::seconds_per_tick(ticks_per_minute) as types::MFloat) - timetable.start_time as types::MFloat,
                     feasible_probability: da.feasible_probability as types::MFloat,
-                    relevance: Cell::new(da.relevance as types::MFloat)
-                }) });
+                    relevance: Cell::new(da.relevance as types::MFloat),
+                    bucket_width: ticks_per_minute
+                }.refine()) });
                 connections.push(nc);
                 stations[from_idx].departures.push(id);
                 stations[to_idx].arrivals.push(id);
@@ -126,11 +151,92 @@ pub fn deserialize_protobuf<'a, 'b>(bytes: Vec<u8>, stations: &'a mut Vec<connec
         destination_id,
         destination_idx,
         now,
-        system: request_message.system.to_string()
+        system: request_message.system.to_string(),
+        algorithm: query.algorithm.to_string(),
+        itinerary: request_message.itinerary.as_ref().map(|j| j.legs.iter().map(|l| {
+            let c = l.connection.as_ref().expect("itinerary leg without connection");
+            RequestedLeg {
+                route_id: l.route_id.to_string(),
+                from_id: c.from_id.to_string(),
+                to_id: c.to_id.to_string(),
+                departure_ts: c.departure.as_ref().expect("itinerary leg without departure").scheduled
+            }
+        }).collect()).unwrap_or_default()
+    }
+}
+
+pub fn resolve_itinerary(requested: &[RequestedLeg], stations: &[connection::Station], routes: &[connection::Route], connections: &[connection::Connection], order: &[usize], start_ts: i64, ticks_per_minute: types::Mtime) -> Result<Vec<itinerary::TripLeg>, String> {
+    let station_idx = |id: &str| stations.iter().position(|s| s.id == id).ok_or_else(|| format!("unknown station {}", id));
+    requested.iter().map(|l| {
+        let from_idx = station_idx(&l.from_id)?;
+        let departure = to_mtime(l.departure_ts, start_ts, ticks_per_minute);
+        let c = stations[from_idx].departures.iter().map(|id| &connections[order[*id]])
+            .find(|c| c.departure.scheduled == departure && routes[c.route_idx].id == l.route_id)
+            .ok_or_else(|| format!("no departure of route {} at {} {}", l.route_id, l.from_id, l.departure_ts))?;
+        Ok(itinerary::TripLeg {
+            trip_id: c.trip_id,
+            route_idx: c.route_idx,
+            from_idx,
+            to_idx: station_idx(&l.to_id)?
+        })
+    }).collect()
+}
+
+fn to_wire_distribution(d: &distribution::Distribution, start_ts: i64, ticks_per_minute: types::Mtime) -> wire::Distribution<'static> {
+    let d = to_minute_resolution(d, ticks_per_minute);
+    wire::Distribution {
+        histogram: Cow::Owned(d.histogram.iter().map(|h| *h as f32).collect()),
+        start: if d.start == 0 { 0 } else { from_mtime(d.start, start_ts, ticks_per_minute) },
+        mean: (d.mean*types::seconds_per_tick(ticks_per_minute) as types::MFloat) as i64 + start_ts,
+        feasible_probability: d.feasible_probability as f32,
+        relevance: d.relevance.get() as f32
+    }
+}
+
+fn to_wire_stop_info(stop_info: &connection::StopInfo, start_ts: i64, ticks_per_minute: types::Mtime) -> wire::StopInfo<'static> {
+    wire::StopInfo{
+        scheduled: from_mtime(stop_info.scheduled, start_ts, ticks_per_minute),
+        delay_minutes: from_delay(stop_info.delay, ticks_per_minute),
+        is_live: stop_info.delay.is_some(),
+        scheduled_track: Cow::Borrowed(""),
+        projected_track: Cow::Borrowed("")
+    }
+}
+
+fn to_wire_leg_connection<'a>(dep: &connection::Connection, arr: &connection::Connection, stations: &'a [connection::Station], start_ts: i64, ticks_per_minute: types::Mtime) -> wire::Connection<'a> {
+    wire::Connection {
+        from_id: Cow::Borrowed(&stations[dep.from_idx].id),
+        to_id: Cow::Borrowed(&stations[arr.to_idx].id),
+        cancelled: !dep.departure.in_out_allowed || !arr.arrival.in_out_allowed,
+        departure: Some(to_wire_stop_info(&dep.departure, start_ts, ticks_per_minute)),
+        arrival: Some(to_wire_stop_info(&arr.arrival, start_ts, ticks_per_minute)),
+        message: Cow::Owned(if dep.message == WALKING_MSG { "".to_string() } else { dep.message.clone() }),
+        destination_arrival: None
+    }
+}
+
+pub fn to_wire_journey<'a>(itinerary: &itinerary::Itinerary, stations: &'a [connection::Station], routes: &'a [connection::Route], connections: &[connection::Connection], start_ts: i64, ticks_per_minute: types::Mtime) -> wire::Journey<'a> {
+    wire::Journey {
+        legs: itinerary.legs.iter().map(|l| {
+            let dep = &connections[l.departure_idx];
+            let fallback = l.fallback_idx.map(|idx| &connections[idx]);
+            wire::Leg {
+                connection: Some(to_wire_leg_connection(dep, &connections[l.arrival_idx], stations, start_ts, ticks_per_minute)),
+                route_id: Cow::Borrowed(&routes[dep.route_idx].id),
+                transfer_probability: l.transfer_probability,
+                fallback: fallback.map(|c| {
+                    let mut wire_connection = to_wire_leg_connection(c, c, stations, start_ts, ticks_per_minute);
+                    wire_connection.destination_arrival = l.fallback_arrival.as_ref().map(|da| to_wire_distribution(da, start_ts, ticks_per_minute));
+                    wire_connection
+                }),
+                fallback_route_id: fallback.map_or(Cow::Borrowed(""), |c| Cow::Borrowed(&routes[c.route_idx].id))
+            }
+        }).collect(),
+        destination_arrival: Some(to_wire_distribution(&itinerary.destination_arrival, start_ts, ticks_per_minute))
     }
 }
 
-pub fn serialize_protobuf(stations: &[connection::Station], routes: &[connection::Route], connections: &[connection::Connection], _contraction: Option<&StationContraction>, metadata: &QueryMetadata) -> Vec<u8> {
+pub fn serialize_protobuf(stations: &[connection::Station], routes: &[connection::Route], connections: &[connection::Connection], _contraction: Option<&StationContraction>, metadata: &QueryMetadata, journeys: Vec<wire::Journey>, itinerary: Option<wire::Journey>, ticks_per_minute: types::Mtime) -> Vec<u8> {
     let mut wire_stations: Vec<wire::Station> = Vec::new();
     let mut trips: IndexMap<(i32, usize), Vec<(usize, wire::Connection)>> = IndexMap::new();
     for s in stations.iter().enumerate() {
@@ -173,28 +279,10 @@ pub fn serialize_protobuf(stations: &[connection::Station], routes: &[connection
             from_id: Cow::Borrowed(&stations.get(c.from_idx).unwrap().id),
             to_id: Cow::Borrowed(&stations.get(c.to_idx).unwrap().id),
             cancelled: false, // TODO
-            departure: Some(wire::StopInfo{
-                scheduled: from_mtime(c.departure.scheduled, metadata.start_ts),
-                delay_minutes: c.departure.delay.unwrap_or(0) as i32,
-                is_live: c.departure.delay.is_some(),
-                scheduled_track: Cow::Borrowed(""),
-                projected_track: Cow::Borrowed("")
-            }),
-            arrival: Some(wire::StopInfo{
-                scheduled: from_mtime(c.arrival.scheduled, metadata.start_ts),
-                delay_minutes: c.arrival.delay.unwrap_or(0) as i32,
-                is_live: c.arrival.delay.is_some(),
-                scheduled_track: Cow::Borrowed(""),
-                projected_track: Cow::Borrowed("")
-            }),
-            message: Cow::Borrowed(""),
-            destination_arrival: if da.is_none() || da.as_ref().unwrap().mean == 0.0 { None } else { let da = da.as_ref().unwrap(); Some(wire::Distribution {
-                histogram: Cow::Owned(da.histogram.iter().map(|h| *h as f32).collect()),
-                start: if da.start == 0 { 0 } else { from_mtime(da.start, metadata.start_ts) },
-                mean: (da.mean*60.0) as i64 + metadata.start_ts,
-                feasible_probability: da.feasible_probability as f32,
-                relevance: da.relevance.get() as f32
-            }) }
+            departure: Some(to_wire_stop_info(&c.departure, metadata.start_ts, ticks_per_minute)),
+            arrival: Some(to_wire_stop_info(&c.arrival, metadata.start_ts, ticks_per_minute)),
+            message: Cow::Borrowed(if c.message == WALKING_MSG { "" } else { &c.message }),
+            destination_arrival: if da.is_none() || da.as_ref().unwrap().mean == 0.0 { None } else { Some(to_wire_distribution(da.as_ref().unwrap(), metadata.start_ts, ticks_per_minute)) }
         }));
     }
     for (key, mut connections) in trips.into_iter() {
@@ -212,9 +300,12 @@ pub fn serialize_protobuf(stations: &[connection::Station], routes: &[connection
         query: Some(wire::Query{
             origin: Cow::Borrowed(&stations[metadata.origin_idx].id),
             destination: Cow::Borrowed(&stations[metadata.destination_idx].id),
-            now: 0
+            now: 0,
+            algorithm: Cow::Borrowed(&metadata.algorithm)
         }),
-        system: Cow::Borrowed("")
+        system: Cow::Borrowed(""),
+        journeys,
+        itinerary
     };
     let mut bytes = Vec::new();
     let mut writer = Writer::new(&mut bytes);
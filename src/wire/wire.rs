@@ -344,6 +344,7 @@ pub struct Query<'a> {
     pub origin: Cow<'a, str>,
     pub destination: Cow<'a, str>,
     pub now: i64,
+    pub algorithm: Cow<'a, str>,
 }
 
 impl<'a> MessageRead<'a> for Query<'a> {
@@ -354,6 +355,7 @@ impl<'a> MessageRead<'a> for Query<'a> {
                 Ok(10) => msg.origin = r.read_string(bytes).map(Cow::Borrowed)?,
                 Ok(18) => msg.destination = r.read_string(bytes).map(Cow::Borrowed)?,
                 Ok(24) => msg.now = r.read_int64(bytes)?,
+                Ok(34) => msg.algorithm = r.read_string(bytes).map(Cow::Borrowed)?,
                 Ok(t) => { r.read_unknown(bytes, t)?; }
                 Err(e) => return Err(e),
             }
@@ -368,12 +370,98 @@ impl<'a> MessageWrite for Query<'a> {
         + if self.origin == "" { 0 } else { 1 + sizeof_len((&self.origin).len()) }
         + if self.destination == "" { 0 } else { 1 + sizeof_len((&self.destination).len()) }
         + if self.now == 0i64 { 0 } else { 1 + sizeof_varint(*(&self.now) as u64) }
+        + if self.algorithm == "" { 0 } else { 1 + sizeof_len((&self.algorithm).len()) }
     }
 
     fn write_message<W: WriterBackend>(&self, w: &mut Writer<W>) -> Result<()> {
         if self.origin != "" { w.write_with_tag(10, |w| w.write_string(&**&self.origin))?; }
         if self.destination != "" { w.write_with_tag(18, |w| w.write_string(&**&self.destination))?; }
         if self.now != 0i64 { w.write_with_tag(24, |w| w.write_int64(*&self.now))?; }
+        if self.algorithm != "" { w.write_with_tag(34, |w| w.write_string(&**&self.algorithm))?; }
+        Ok(())
+    }
+}
+
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct Leg<'a> {
+    pub connection: Option<Connection<'a>>,
+    pub route_id: Cow<'a, str>,
+    pub transfer_probability: f32,
+    pub fallback: Option<Connection<'a>>,
+    pub fallback_route_id: Cow<'a, str>,
+}
+
+impl<'a> MessageRead<'a> for Leg<'a> {
+    fn from_reader(r: &mut BytesReader, bytes: &'a [u8]) -> Result<Self> {
+        let mut msg = Self::default();
+        while !r.is_eof() {
+            match r.next_tag(bytes) {
+                Ok(10) => msg.connection = Some(r.read_message::<Connection>(bytes)?),
+                Ok(18) => msg.route_id = r.read_string(bytes).map(Cow::Borrowed)?,
+                Ok(29) => msg.transfer_probability = r.read_float(bytes)?,
+                Ok(34) => msg.fallback = Some(r.read_message::<Connection>(bytes)?),
+                Ok(42) => msg.fallback_route_id = r.read_string(bytes).map(Cow::Borrowed)?,
+                Ok(t) => { r.read_unknown(bytes, t)?; }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(msg)
+    }
+}
+
+impl<'a> MessageWrite for Leg<'a> {
+    fn get_size(&self) -> usize {
+        0
+        + self.connection.as_ref().map_or(0, |m| 1 + sizeof_len((m).get_size()))
+        + if self.route_id == "" { 0 } else { 1 + sizeof_len((&self.route_id).len()) }
+        + if self.transfer_probability == 0f32 { 0 } else { 1 + 4 }
+        + self.fallback.as_ref().map_or(0, |m| 1 + sizeof_len((m).get_size()))
+        + if self.fallback_route_id == "" { 0 } else { 1 + sizeof_len((&self.fallback_route_id).len()) }
+    }
+
+    fn write_message<W: WriterBackend>(&self, w: &mut Writer<W>) -> Result<()> {
+        if let Some(ref s) = self.connection { w.write_with_tag(10, |w| w.write_message(s))?; }
+        if self.route_id != "" { w.write_with_tag(18, |w| w.write_string(&**&self.route_id))?; }
+        if self.transfer_probability != 0f32 { w.write_with_tag(29, |w| w.write_float(*&self.transfer_probability))?; }
+        if let Some(ref s) = self.fallback { w.write_with_tag(34, |w| w.write_message(s))?; }
+        if self.fallback_route_id != "" { w.write_with_tag(42, |w| w.write_string(&**&self.fallback_route_id))?; }
+        Ok(())
+    }
+}
+
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct Journey<'a> {
+    pub legs: Vec<Leg<'a>>,
+    pub destination_arrival: Option<Distribution<'a>>,
+}
+
+impl<'a> MessageRead<'a> for Journey<'a> {
+    fn from_reader(r: &mut BytesReader, bytes: &'a [u8]) -> Result<Self> {
+        let mut msg = Self::default();
+        while !r.is_eof() {
+            match r.next_tag(bytes) {
+                Ok(10) => msg.legs.push(r.read_message::<Leg>(bytes)?),
+                Ok(18) => msg.destination_arrival = Some(r.read_message::<Distribution>(bytes)?),
+                Ok(t) => { r.read_unknown(bytes, t)?; }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(msg)
+    }
+}
+
+impl<'a> MessageWrite for Journey<'a> {
+    fn get_size(&self) -> usize {
+        0
+        + self.legs.iter().map(|s| 1 + sizeof_len((s).get_size())).sum::<usize>()
+        + self.destination_arrival.as_ref().map_or(0, |m| 1 + sizeof_len((m).get_size()))
+    }
+
+    fn write_message<W: WriterBackend>(&self, w: &mut Writer<W>) -> Result<()> {
+        for s in &self.legs { w.write_with_tag(10, |w| w.write_message(s))?; }
+        if let Some(ref s) = self.destination_arrival { w.write_with_tag(18, |w| w.write_message(s))?; }
         Ok(())
     }
 }
@@ -384,6 +472,8 @@ pub struct Message<'a> {
     pub timetable: Option<Timetable<'a>>,
     pub query: Option<Query<'a>>,
     pub system: Cow<'a, str>,
+    pub journeys: Vec<Journey<'a>>,
+    pub itinerary: Option<Journey<'a>>,
 }
 
 impl<'a> MessageRead<'a> for Message<'a> {
@@ -394,6 +484,8 @@ impl<'a> MessageRead<'a> for Message<'a> {
                 Ok(10) => msg.timetable = Some(r.read_message::<Timetable>(bytes)?),
                 Ok(18) => msg.query = Some(r.read_message::<Query>(bytes)?),
                 Ok(26) => msg.system = r.read_string(bytes).map(Cow::Borrowed)?,
+                Ok(34) => msg.journeys.push(r.read_message::<Journey>(bytes)?),
+                Ok(42) => msg.itinerary = Some(r.read_message::<Journey>(bytes)?),
                 Ok(t) => { r.read_unknown(bytes, t)?; }
                 Err(e) => return Err(e),
             }
@@ -408,12 +500,16 @@ impl<'a> MessageWrite for Message<'a> {
         + self.timetable.as_ref().map_or(0, |m| 1 + sizeof_len((m).get_size()))
         + self.query.as_ref().map_or(0, |m| 1 + sizeof_len((m).get_size()))
         + if self.system == "" { 0 } else { 1 + sizeof_len((&self.system).len()) }
+        + self.journeys.iter().map(|s| 1 + sizeof_len((s).get_size())).sum::<usize>()
+        + self.itinerary.as_ref().map_or(0, |m| 1 + sizeof_len((m).get_size()))
     }
 
     fn write_message<W: WriterBackend>(&self, w: &mut Writer<W>) -> Result<()> {
         if let Some(ref s) = self.timetable { w.write_with_tag(10, |w| w.write_message(s))?; }
         if let Some(ref s) = self.query { w.write_with_tag(18, |w| w.write_message(s))?; }
         if self.system != "" { w.write_with_tag(26, |w| w.write_string(&**&self.system))?; }
+        for s in &self.journeys { w.write_with_tag(34, |w| w.write_message(s))?; }
+        if let Some(ref s) = self.itinerary { w.write_with_tag(42, |w| w.write_message(s))?; }
         Ok(())
     }
 }
@@ -1,19 +1,239 @@
+#[macro_use]
+extern crate assert_float_eq;
+
 use std::collections::HashMap;
 use rustc_hash::FxHashSet;
+use stost::distribution;
 use stost::distribution_store;
 use stost::query::csameat;
 use stost::query::Queriable;
 use stost::gtfs;
 use stost::query::Query;
+use stost::connection;
+use stost::walking;
+#[cfg(feature = "nigiri")]
+use stost::types;
 
 const CACHE_PATH: &str = "./tests/fixtures/timetable.ign.cache";
+#[cfg(feature = "nigiri")]
 const GTFS_PATH: &str = "../gtfs/swiss-gtfs/2023-11-06/";
+#[cfg(feature = "nigiri")]
 const GTFSRT_PATH: &str = "../gtfs/swiss-gtfs-rt/2023-11-02/";
 
+#[cfg(feature = "nigiri")]
 fn day(year: i32, month: u32, day: u32) -> chrono::NaiveDate {
     chrono::NaiveDate::from_ymd_opt(year, month, day).unwrap()
 }
 
+#[test]
+fn footpaths_without_contraction() {
+    let mut store = distribution_store::Store::new();
+    let mut stations: Vec<connection::Station> = (0..5).map(|i| connection::Station::new(i.to_string(), format!("station{}", i), vec![])).collect();
+    stations[1].footpaths.push(connection::Footpath { target_location_idx: 2, duration: 3 });
+    stations[3].footpaths.push(connection::Footpath { target_location_idx: 4, duration: 5 });
+    let mut connections = vec![
+        connection::Connection::new(0, 0, 1, 0, false, 0, 10, None, 1, 20, None),
+        connection::Connection::new(1, 0, 1, 1, false, 2, 25, None, 3, 35, None),
+        connection::Connection::new(2, 0, 1, 2, false, 1, 40, None, 4, 50, None),
+        // beyond max_dc, so that the expected arrival after transferring at 2 is complete
+        connection::Connection::new(3, 0, 1, 3, false, 2, 200, None, 3, 210, None)
+    ];
+    let mut cut = FxHashSet::default();
+    let mut order = vec![];
+    let mut env = csameat::Environment::new(&mut store, &mut connections, &stations, &mut cut, &mut order, 0);
+    env.preprocess();
+    let station_labels = env.full_query(0, 4, 0, 300);
+    assert_eq!(station_labels[0].len(), 1);
+    // walk from 1 to 2, ride to 3 and walk to the destination
+    assert_eq!(station_labels[0][0].destination_arrival.mean, 40.0);
+    assert_eq!(station_labels[2].last().unwrap().destination_arrival.mean, 40.0);
+    let decision_graph = env.get_decision_graph(0, 4, 0, &station_labels);
+    assert_eq!(decision_graph[0].iter().map(|l| l.connection_id).collect::<Vec<_>>(), vec![0]);
+    assert_eq!(decision_graph[1].iter().map(|l| l.connection_id).collect::<Vec<_>>(), vec![2]);
+    assert_eq!(decision_graph[2].iter().map(|l| l.connection_id).collect::<Vec<_>>(), vec![1, 3]);
+    // the final walks from 3 to the destination are part of the decision graph
+    assert_eq!(decision_graph[3].iter().map(|l| l.destination_arrival.mean).collect::<Vec<_>>(), vec![40.0, 215.0]);
+    let walks: Vec<usize> = decision_graph[3].iter().map(|l| l.connection_id).collect();
+    let connection_pairs = env.relevant_connection_pairs(Query { origin_idx: 0, destination_idx: 4, start_time: 0, max_time: 300 }, &HashMap::new(), 0);
+    assert_eq!(connection_pairs[&(walks[0] as i32)], walks[0] as i32);
+    drop(env);
+    assert_eq!(connections.len(), 6);
+    let walk = connections.iter().find(|c| c.id == 4).unwrap();
+    assert_eq!(walk.product_type, walking::WALKING_PRODUCT_TYPE);
+    assert_eq!((walk.from_idx, walk.to_idx, walk.departure.projected(), walk.arrival.projected()), (3, 4, 35, 40));
+}
+
+#[test]
+fn footpath_transfers_with_walking_profile() {
+    let mut store = distribution_store::Store::new();
+    let mut stations: Vec<connection::Station> = (0..4).map(|i| connection::Station::new(i.to_string(), format!("station{}", i), vec![])).collect();
+    stations[1].footpaths.push(connection::Footpath { target_location_idx: 2, duration: 3 });
+    let connections = vec![
+        connection::Connection::new(0, 0, 1, 0, false, 0, 0, None, 1, 10, None),
+        connection::Connection::new(1, 1, 1, 1, false, 2, 14, None, 3, 20, None),
+        connection::Connection::new(2, 1, 1, 2, false, 2, 200, None, 3, 210, None)
+    ];
+    let mut expected = vec![20.0];
+    for profile in ["", "slow"] {
+        if !profile.is_empty() {
+            store.set_walking_profile(walking::WalkingProfile::named(profile).unwrap());
+            // c1 is caught if the walk takes at most 4 minutes
+            let p = store.walking_distribution(3).cdf(4);
+            assert!(p > 0.0 && p < 1.0);
+            expected.push(p*20.0+(1.0-p)*210.0);
+        }
+        let mut connections = connections.clone();
+        let mut cut = FxHashSet::default();
+        let mut order = vec![];
+        let mut env = csameat::Environment::new(&mut store, &mut connections, &stations, &mut cut, &mut order, 0);
+        env.set_mean_only(false);
+        env.preprocess();
+        let station_labels = env.full_query(0, 3, 0, 300);
+        let a = &station_labels[0][0].destination_arrival;
+        assert_float_absolute_eq!(a.mean, *expected.last().unwrap(), 1e-3);
+        assert_float_absolute_eq!(a.histogram.iter().sum::<f32>(), 1.0, 1e-3);
+    }
+}
+
+#[test]
+fn final_footpaths_on_reused_timetable() {
+    let mut store = distribution_store::Store::new();
+    let mut tt = gtfs::GtfsTimetable::new();
+    tt.stations = (0..3).map(|i| connection::Station::new(i.to_string(), format!("station{}", i), vec![])).collect();
+    tt.stations[1].footpaths.push(connection::Footpath { target_location_idx: 2, duration: 3 });
+    tt.connections = vec![
+        connection::Connection::new(0, 0, 1, 0, false, 0, 10, None, 1, 20, None),
+        connection::Connection::new(1, 0, 1, 1, false, 0, 200, None, 1, 210, None)
+    ];
+    let q = Query { origin_idx: 0, destination_idx: 2, start_time: 0, max_time: 300 };
+    for _ in 0..2 {
+        let mut env = csameat::Environment::from_timetable(&mut store, &mut tt, 0);
+        env.preprocess();
+        env.query(q);
+        drop(env);
+        // the walks of the previous decision graph are replaced
+        assert_eq!(tt.connections.len(), 3);
+        assert_eq!(tt.base_len, Some(2));
+    }
+}
+
+#[test]
+fn full_distributions() {
+    let mut store = distribution_store::Store::new();
+    store.insert_from_distribution(0..0, 0..0, false, 1, distribution::Distribution::uniform(0, 3));
+    let stations: Vec<connection::Station> = (0..3).map(|i| connection::Station::new(i.to_string(), format!("station{}", i), vec![])).collect();
+    let connections = vec![
+        connection::Connection::new(0, 0, 1, 0, false, 0, 10, None, 1, 20, None),
+        connection::Connection::new(1, 0, 1, 1, false, 1, 21, None, 2, 30, None),
+        connection::Connection::new(2, 0, 1, 2, false, 1, 25, None, 2, 40, None),
+        connection::Connection::new(3, 0, 1, 3, false, 1, 200, None, 2, 210, None)
+    ];
+    for mean_only in [true, false] {
+        let mut connections = connections.clone();
+        let mut cut = FxHashSet::default();
+        let mut order = vec![];
+        let mut env = csameat::Environment::new(&mut store, &mut connections, &stations, &mut cut, &mut order, 0);
+        env.set_mean_only(mean_only);
+        env.preprocess();
+        let station_labels = env.full_query(0, 2, 0, 300);
+        // c1 is reached with 1/3 (arrival delay 0), c2 with 2/3
+        let a = &station_labels[0][0].destination_arrival;
+        assert_float_absolute_eq!(a.mean, 31.0/3.0+41.0*2.0/3.0, 1e-3);
+        if mean_only {
+            assert!(a.histogram.is_empty());
+        } else {
+            assert_eq!(a.start, 30);
+            assert_eq!(a.histogram.len(), 13);
+            assert_float_absolute_eq!(a.histogram[0], 1.0/9.0, 1e-3);
+            assert_float_absolute_eq!(a.histogram[5], 0.0, 1e-3);
+            assert_float_absolute_eq!(a.histogram[12], 2.0/9.0, 1e-3);
+            assert_float_absolute_eq!(a.mean(), a.mean, 1e-3);
+        }
+    }
+    let mut connections = connections.clone();
+    let mut cut = FxHashSet::default();
+    let mut order = vec![];
+    let mut env = csameat::Environment::new(&mut store, &mut connections, &stations, &mut cut, &mut order, 0);
+    env.set_max_dc(500);
+    env.preprocess();
+    // no departure beyond max_dc, so the transfer at 1 is never complete
+    assert!(env.full_query(0, 2, 0, 300)[0].is_empty());
+}
+
+#[test]
+fn early_arrivals() {
+    let mut store = distribution_store::Store::new();
+    store.insert_from_distribution(0..0, 0..0, false, 1, distribution::Distribution::uniform(-1, 3));
+    let stations: Vec<connection::Station> = (0..3).map(|i| connection::Station::new(i.to_string(), format!("station{}", i), vec![])).collect();
+    let mut connections = vec![
+        connection::Connection::new(0, 0, 1, 0, false, 0, 10, None, 1, 20, None),
+        connection::Connection::new(1, 1, 1, 1, false, 1, 22, None, 2, 30, None),
+        connection::Connection::new(2, 1, 1, 2, false, 1, 200, None, 2, 210, None)
+    ];
+    let mut cut = FxHashSet::default();
+    let mut order = vec![];
+    let mut env = csameat::Environment::new(&mut store, &mut connections, &stations, &mut cut, &mut order, 0);
+    env.preprocess();
+    let station_labels = env.full_query(0, 2, 0, 300);
+    // arriving one minute early still catches c1, so the transfer is certain
+    assert_float_absolute_eq!(station_labels[0][0].destination_arrival.mean, 30.0, 1e-3);
+    assert_float_absolute_eq!(station_labels[0][0].destination_arrival.feasible_probability, 1.0, 1e-3);
+}
+
+#[test]
+fn lower_bounded_transfer() {
+    let mut store = distribution_store::Store::new();
+    store.insert_from_distribution(0..0, 0..0, false, 1, distribution::Distribution::uniform(-1, 3));
+    let stations: Vec<connection::Station> = (0..3).map(|i| connection::Station::new(i.to_string(), format!("station{}", i), vec![])).collect();
+    let connections = vec![
+        connection::Connection::new(0, 0, 1, 0, false, 0, 10, None, 1, 20, None),
+        connection::Connection::new(1, 1, 1, 1, false, 1, 21, None, 2, 30, None),
+        connection::Connection::new(2, 1, 1, 2, false, 1, 200, None, 2, 210, None)
+    ];
+    for (min_delay, expected) in [(None, 30.0*2.0/3.0+210.0/3.0), (Some(0), 120.0), (Some(1), 210.0)] {
+        let mut connections = connections.clone();
+        let mut cut = FxHashSet::default();
+        let mut order = vec![];
+        let mut env = csameat::Environment::new(&mut store, &mut connections, &stations, &mut cut, &mut order, 0);
+        env.preprocess();
+        if let Some(min_delay) = min_delay {
+            env.update_min_delay(0, false, min_delay);
+        }
+        let station_labels = env.full_query(0, 2, 0, 300);
+        assert_float_absolute_eq!(station_labels[0][0].destination_arrival.mean, expected, 1e-3);
+    }
+}
+
+#[test]
+fn trips_per_route() {
+    let mut store = distribution_store::Store::new();
+    let stations: Vec<connection::Station> = (0..3).map(|i| connection::Station::new(i.to_string(), format!("station{}", i), vec![])).collect();
+    // trip ids restart per route, as in timetables received via the API
+    let mut connections = vec![
+        connection::Connection::new(0, 0, 1, 0, false, 0, 10, None, 1, 20, None),
+        connection::Connection::new(1, 1, 1, 0, false, 1, 12, None, 2, 15, None),
+        connection::Connection::new(2, 1, 1, 1, false, 1, 22, None, 2, 30, None),
+        connection::Connection::new(3, 1, 1, 2, false, 1, 200, None, 2, 210, None)
+    ];
+    let mut cut = FxHashSet::default();
+    let mut order = vec![];
+    let mut env = csameat::Environment::new(&mut store, &mut connections, &stations, &mut cut, &mut order, 0);
+    env.set_mean_only(false);
+    env.preprocess();
+    let q = Query { origin_idx: 0, destination_idx: 2, start_time: 0, max_time: 300 };
+    let decision_graph = env.query(q);
+    // c0 does not continue as c1, although both have trip id 0
+    assert_float_absolute_eq!(decision_graph[0][0].destination_arrival.mean, 30.0, 1e-3);
+    let connection_pairs = env.relevant_connection_pairs(q, &HashMap::new(), 0);
+    assert_eq!(connection_pairs[&0], 0);
+    drop(env);
+    let da = |id: usize| connections.iter().find(|c| c.id == id).unwrap().destination_arrival.borrow().clone().unwrap();
+    assert_float_absolute_eq!(da(0).mean, 30.0, 1e-3);
+    assert_float_absolute_eq!(da(1).mean, 15.0, 1e-3);
+    assert_eq!(da(0).relevance.get(), 1.0);
+    assert_eq!(da(1).relevance.get(), 0.0);
+}
+
 #[test]
 #[ignore]
 fn gtfs_with_contr() {
@@ -21,11 +241,11 @@ fn gtfs_with_contr() {
     store.load_distributions("./data/ch_sbb.csv");
     store.nonnegative();
 
-    let mut tt = gtfs::load_gtfs_cache(CACHE_PATH);
+    let mut tt = gtfs::load_gtfs_cache(CACHE_PATH, 1);
     //gtfs::shorten_footpaths(&mut tt.stations);
     let mut env = csameat::Environment::new(&mut store, &mut tt.connections, &tt.stations, &mut tt.cut, &mut tt.order, 0);
     let contr = gtfs::get_station_contraction(&tt.stations);
-    env.set_station_contraction(&contr);
+    env.set_station_contraction(&contr).unwrap();
     let q = Query {
         origin_idx: 10000,
         destination_idx: 20000,
@@ -55,6 +275,7 @@ fn gtfs_with_contr() {
 
 }
 
+#[cfg(feature = "nigiri")]
 #[test]
 #[ignore]
 fn gtfs_with_rt() {
@@ -67,20 +288,22 @@ fn gtfs_with_rt() {
         connections: vec![],
         cut: FxHashSet::default(),
         order: vec![],
-        transport_and_day_to_connection_id: HashMap::new()
+        transport_and_day_to_connection_id: HashMap::new(),
+        ticks_per_minute: types::one_tick_per_minute(),
+        base_len: None
     };
     let mut routes = vec![];
     let t = gtfs::load_timetable(GTFS_PATH, day(2023, 11, 2), day(2023, 11, 3));
-    let mapping = gtfs::retrieve(&t, &mut tt.stations, &mut routes, &mut tt.connections);
+    let mapping = gtfs::retrieve(&t, &mut tt.stations, &mut routes, &mut tt.connections, 1);
     let mut env = csameat::Environment::new(&mut store, &mut tt.connections, &tt.stations, &mut tt.cut, &mut tt.order, 7500);
     let path = format!("{}2023-11-02T07:00:03+01:00.gtfsrt", GTFSRT_PATH);
-    gtfs::load_realtime(&path, &t, &mapping,
+    gtfs::load_realtime(&path, &t, &mapping, 1,
         |connection_id: usize, is_departure: bool, location_idx: Option<usize>, in_out_allowed: Option<bool>, delay: Option<i16>| {
             env.update(connection_id, is_departure, location_idx, in_out_allowed, delay)
         }
     );
     let contr = gtfs::get_station_contraction(&tt.stations);
-    env.set_station_contraction(&contr);
+    env.set_station_contraction(&contr).unwrap();
     let q = Query {
         origin_idx: 38895,
         destination_idx: 34984,
@@ -1,13 +1,14 @@
 #[macro_use]
 extern crate assert_float_eq;
 
-use std::collections::HashSet;
 use rustc_hash::FxHashSet;
 use stost::connection;
 use stost::distribution_store;
 use stost::wire::serde;
 use stost::query::topocsa;
 use stost::query::recursive;
+use stost::query::Queriable;
+use stost::query::Query;
 
 fn compare_connections(original: &[connection::Connection], new: &[connection::Connection]) {
     let mut i = 0;
@@ -35,11 +36,13 @@ fn topocsa_recursive_identical() {
     let mut stations = vec![];
     let mut routes = vec![];
     let mut connections = vec![];
-    let meta = serde::deserialize_protobuf(bytes, &mut stations, &mut routes, &mut connections, false);
+    let meta = serde::deserialize_protobuf(bytes, &mut stations, &mut routes, &mut connections, false, 1);
     let mut connections_clone = connections.clone();
     let mut cut = FxHashSet::default();
-    topocsa::prepare_and_query(&mut store, &mut connections, &stations, &mut cut, meta.origin_idx, meta.destination_idx, 0, 100, serde::to_mtime(meta.now, meta.start_ts), 0.0, false);
-    recursive::query(&mut store, &mut connections_clone, &stations, &stations[meta.origin_idx], &stations[meta.destination_idx], 0, 100, serde::to_mtime(meta.now, meta.start_ts), HashSet::from_iter(cut.into_iter()));
+    topocsa::prepare_and_query(&mut store, &mut connections, &stations, &mut cut, meta.origin_idx, meta.destination_idx, 0, 100, serde::to_mtime(meta.now, meta.start_ts, 1), 0.0, false);
+    let mut order = vec![];
+    let mut env = recursive::Environment::new(&mut store, &mut connections_clone, &stations, &mut order, serde::to_mtime(meta.now, meta.start_ts, 1));
+    env.query(Query { origin_idx: meta.origin_idx, destination_idx: meta.destination_idx, start_time: 0, max_time: 100 });
 
     compare_connections(&connections_clone, &connections);
 }
@@ -55,15 +58,15 @@ fn topocsa_runs() {
     let mut stations = vec![];
     let mut routes = vec![];
     let mut connections = vec![];
-    let meta = serde::deserialize_protobuf(bytes, &mut stations, &mut routes, &mut connections, false);
+    let meta = serde::deserialize_protobuf(bytes, &mut stations, &mut routes, &mut connections, false, 1);
     let mut cut = FxHashSet::default();
-    topocsa::prepare_and_query(&mut store, &mut connections, &stations, &mut cut, meta.origin_idx, meta.destination_idx, 0, 100, serde::to_mtime(meta.now, meta.start_ts), 0.0, false);
+    topocsa::prepare_and_query(&mut store, &mut connections, &stations, &mut cut, meta.origin_idx, meta.destination_idx, 0, 100, serde::to_mtime(meta.now, meta.start_ts, 1), 0.0, false);
 
     let bytes: Vec<u8> = serde::read_protobuf("./tests/fixtures/basic_out.pb");
     let mut _stations = vec![];
     let mut _routes = vec![];
     let mut original_connections = vec![];
-    let _ = serde::deserialize_protobuf(bytes, &mut _stations, &mut _routes, &mut original_connections, true);
+    let _ = serde::deserialize_protobuf(bytes, &mut _stations, &mut _routes, &mut original_connections, true, 1);
 
     compare_connections(&original_connections, &connections);
 }
@@ -78,8 +81,54 @@ fn recursive_runs() {
     let mut stations = vec![];
     let mut routes = vec![];
     let mut connections = vec![];
-    let meta = serde::deserialize_protobuf(bytes, &mut stations, &mut routes, &mut connections, false); 
-    recursive::query(&mut store, &mut connections, &stations, &stations[meta.origin_idx], &stations[meta.destination_idx], 0, 100, serde::to_mtime(meta.now, meta.start_ts), HashSet::new());
-    let bytes = serde::serialize_protobuf(&stations, &routes, &connections, None, &meta);
+    let meta = serde::deserialize_protobuf(bytes, &mut stations, &mut routes, &mut connections, false, 1); 
+    let mut order = vec![];
+    let mut env = recursive::Environment::new(&mut store, &mut connections, &stations, &mut order, serde::to_mtime(meta.now, meta.start_ts, 1));
+    env.query(Query { origin_idx: meta.origin_idx, destination_idx: meta.destination_idx, start_time: 0, max_time: 100 });
+    let bytes = serde::serialize_protobuf(&stations, &routes, &connections, None, &meta, vec![], None, 1);
     serde::write_protobuf(&bytes, "./tests/fixtures/basic_out.pb");
-}
\ No newline at end of file
+}
+
+#[test]
+fn itinerary_roundtrip() {
+    use std::borrow::Cow;
+    use quick_protobuf::{MessageWrite, Writer};
+    use stost::wire::wire;
+    let stop = |scheduled: i64| Some(wire::StopInfo { scheduled, ..Default::default() });
+    let connection = |from: &'static str, to: &'static str, dep: i64, arr: i64| wire::Connection {
+        from_id: Cow::Borrowed(from), to_id: Cow::Borrowed(to), departure: stop(dep), arrival: stop(arr), ..Default::default()
+    };
+    let start = 1700000000;
+    let request = wire::Message {
+        timetable: Some(wire::Timetable {
+            stations: ["a", "b", "c"].into_iter().map(|id| wire::Station { id: Cow::Borrowed(id), ..Default::default() }).collect(),
+            routes: vec![
+                wire::Route { id: Cow::Borrowed("r1"), product_type: 1, trips: vec![wire::Trip { connections: vec![connection("a", "b", start+600, start+1200)] }], ..Default::default() },
+                wire::Route { id: Cow::Borrowed("r2"), product_type: 1, trips: vec![
+                    wire::Trip { connections: vec![connection("b", "c", start+1500, start+1800)] },
+                    wire::Trip { connections: vec![connection("b", "c", start+2100, start+2400)] }
+                ], ..Default::default() }
+            ],
+            start_time: start
+        }),
+        query: Some(wire::Query { origin: Cow::Borrowed("a"), destination: Cow::Borrowed("c"), ..Default::default() }),
+        itinerary: Some(wire::Journey {
+            legs: vec![
+                wire::Leg { connection: Some(connection("a", "b", start+600, 0)), route_id: Cow::Borrowed("r1"), ..Default::default() },
+                wire::Leg { connection: Some(connection("b", "c", start+2100, 0)), route_id: Cow::Borrowed("r2"), ..Default::default() }
+            ],
+            destination_arrival: None
+        }),
+        ..Default::default()
+    };
+    let mut bytes = Vec::new();
+    request.write_message(&mut Writer::new(&mut bytes)).unwrap();
+    let mut stations = vec![];
+    let mut routes = vec![];
+    let mut connections = vec![];
+    let meta = serde::deserialize_protobuf(bytes, &mut stations, &mut routes, &mut connections, false, 1);
+    let order: Vec<usize> = (0..connections.len()).collect();
+    let legs = serde::resolve_itinerary(&meta.itinerary, &stations, &routes, &connections, &order, meta.start_ts, 1).unwrap();
+    assert_eq!(legs.len(), 2);
+    assert_eq!((legs[1].route_idx, legs[1].trip_id, legs[1].from_idx, legs[1].to_idx), (1, 1, 1, 2));
+}
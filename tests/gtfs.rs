@@ -7,7 +7,7 @@ use serde::Serialize;
 use rmps::Serializer;
 use std::io::Write;
 use std::fs;
-use stost::{distribution_store, walking};
+use stost::{connection, distribution_store, walking, types};
 use stost::query::{topocsa, Query};
 use stost::gtfs;
 use std::time::Instant;
@@ -36,11 +36,13 @@ fn create_gtfs_cache() {
         connections: vec![],
         cut: FxHashSet::default(),
         order: vec![],
-        transport_and_day_to_connection_id: HashMap::new()
+        transport_and_day_to_connection_id: HashMap::new(),
+        ticks_per_minute: types::one_tick_per_minute(),
+        base_len: None
     };
     let mut routes = vec![];
     let t = gtfs::load_timetable(&format!("{}{}", prefix, GTFS_PATH), day(2023, 11, 2), day(2023, 11, 3));
-    tt.transport_and_day_to_connection_id = gtfs::retrieve(&t, &mut tt.stations, &mut routes, &mut tt.connections);
+    tt.transport_and_day_to_connection_id = gtfs::retrieve(&t, &mut tt.stations, &mut routes, &mut tt.connections, 1);
     let start_ts = Instant::now();
     topocsa::prepare(&mut store, &mut tt.connections, &tt.stations, &mut tt.cut, &mut tt.order, 8000, 0.01, true);
     println!("elapsed: {}", start_ts.elapsed().as_millis());
@@ -54,30 +56,78 @@ fn create_gtfs_cache() {
     file.write_all(&buf).expect("error writing file");
 }
 
+fn sampling_network() -> (Vec<connection::Station>, Vec<connection::Connection>) {
+    let stations: Vec<connection::Station> = (0..20).map(|i| {
+        let mut s = connection::Station::new(i.to_string(), "".to_string(), vec![]);
+        s.lon = i as f64*0.1;
+        s
+    }).collect();
+    let connections: Vec<connection::Connection> = (0..19).map(|i| connection::Connection::new(i, 0, 0, 0, false, i, 100, None, i+1, 110, None)).collect();
+    (stations, connections)
+}
+
 #[test]
-#[ignore]
-fn create_simulation_samples() {
-    let samples = gtfs::create_simulation_samples(GTFS_PATH, day(2023, 11, 2), day(2023, 11, 9), None);
-    let buf = serde_json::to_vec(&samples).unwrap();
-    let mut file = fs::OpenOptions::new()
-        .create(true)
-        .write(true)
-        .truncate(true)
-        .open("./benches/samples/samples.ign.json").expect("file not openable");
-    file.write_all(&buf).expect("error writing file");
+fn simulation_samples_reproducible() {
+    use rand::SeedableRng;
+    let (stations, connections) = sampling_network();
+    let conf = gtfs::SamplingConfig { samples: 50, ..Default::default() };
+    let (a, _) = gtfs::sample_origin_destinations(&connections, &stations, &conf, None, |_, _| true, &mut rand::rngs::StdRng::seed_from_u64(3));
+    let (b, _) = gtfs::sample_origin_destinations(&connections, &stations, &conf, None, |_, _| true, &mut rand::rngs::StdRng::seed_from_u64(3));
+    let (c, _) = gtfs::sample_origin_destinations(&connections, &stations, &conf, None, |_, _| true, &mut rand::rngs::StdRng::seed_from_u64(4));
+    let pairs = |s: &Vec<gtfs::OriginDestinationSample>| s.iter().map(|s| (s.from_idx, s.to_idx)).collect::<Vec<_>>();
+    assert_eq!(pairs(&a), pairs(&b));
+    assert_ne!(pairs(&a), pairs(&c));
+    assert!(a.iter().all(|s| s.from_idx < 19 && s.to_idx < 19 && s.from_idx != s.to_idx && s.from_id == s.from_idx.to_string() && s.stratum.is_empty()));
 }
 
 #[test]
-#[ignore]
-fn create_bw_simulation_samples() {
-    let samples = gtfs::create_simulation_samples("../gtfs/german-gtfs/2023-10-30/", day(2023, 11, 2), day(2023, 11, 3), Some((47.525, 7.493, 49.774, 10.514)));
-    let buf = serde_json::to_vec(&samples).unwrap();
-    let mut file = fs::OpenOptions::new()
-        .create(true)
-        .write(true)
-        .truncate(true)
-        .open("./simulation/samples/bw.json").expect("file not openable");
-    file.write_all(&buf).expect("error writing file");
+fn simulation_samples_stratified() {
+    use rand::SeedableRng;
+    let (stations, connections) = sampling_network();
+    let conf = gtfs::SamplingConfig { samples: 20, distance_bands: vec![50.0], ..Default::default() };
+    let (samples, shortfall) = gtfs::sample_origin_destinations(&connections, &stations, &conf, None, |from, to| from < to, &mut rand::rngs::StdRng::seed_from_u64(3));
+    assert_eq!(samples.len(), 20);
+    assert_eq!(shortfall, 0);
+    assert!(samples.iter().all(|s| s.from_idx < s.to_idx));
+    for (i, s) in samples.iter().enumerate() {
+        let far = stations[s.to_idx].lon-stations[s.from_idx].lon > 0.45;
+        assert_eq!(s.stratum, if far { "50+km" } else { "0-50km" });
+        assert_eq!(s.stratum, samples[i%2].stratum);
+    }
+    assert_ne!(samples[0].stratum, samples[1].stratum);
+
+    let demand = vec![(3, 4, 1.0), (5, 3, 3.0)];
+    let conf = gtfs::SamplingConfig { samples: 10, ..Default::default() };
+    let (samples, shortfall) = gtfs::sample_origin_destinations(&connections, &stations, &conf, Some(&demand), |_, _| true, &mut rand::rngs::StdRng::seed_from_u64(3));
+    assert_eq!(samples.len(), 10);
+    assert_eq!(shortfall, 0);
+    assert!(samples.iter().all(|s| (s.from_idx, s.to_idx) == (3, 4) || (s.from_idx, s.to_idx) == (5, 3)));
+}
+
+#[test]
+fn simulation_samples_redistributed() {
+    use rand::SeedableRng;
+    let (stations, connections) = sampling_network();
+    // the third band is empty and pairs of 1 are never reachable, their share goes to the other strata
+    let conf = gtfs::SamplingConfig { samples: 20, distance_bands: vec![50.0, 500.0], ..Default::default() };
+    let (samples, shortfall) = gtfs::sample_origin_destinations(&connections, &stations, &conf, None, |from, to| from != 1 && to != 1, &mut rand::rngs::StdRng::seed_from_u64(3));
+    assert_eq!(samples.len(), 20);
+    assert_eq!(shortfall, 0);
+    assert!(samples.iter().all(|s| s.from_idx != 1 && s.to_idx != 1 && !s.stratum.starts_with("500+")));
+    assert_eq!(samples.iter().filter(|s| s.stratum == "0-50km").count(), 10);
+    assert_eq!(samples[0].stratum, "0-50km");
+    assert_eq!(samples[1].stratum, "50-500km");
+
+    // bounded work if (almost) nothing is reachable
+    let mut calls = 0;
+    let (samples, shortfall) = gtfs::sample_origin_destinations(&connections, &stations, &conf, None, |from, to| {
+        calls += 1;
+        from == 2 && to == 3
+    }, &mut rand::rngs::StdRng::seed_from_u64(3));
+    assert!(samples.iter().all(|s| (s.from_idx, s.to_idx) == (2, 3)));
+    assert_eq!(samples.len()+shortfall, 20);
+    assert!(shortfall > 0);
+    assert!(calls <= 20*20);
 }
 
 #[test]
@@ -86,7 +136,7 @@ fn gtfs() {
     let mut store = distribution_store::Store::new();
     store.load_distributions("./data/ch_sbb.csv");
 
-    let mut tt = gtfs::load_gtfs_cache(CACHE_PATH);
+    let mut tt = gtfs::load_gtfs_cache(CACHE_PATH, 1);
     let mut env = topocsa::Environment::new(&mut store, &mut tt.connections, &tt.stations, &mut tt.cut, &mut tt.order, 8100, 0.01, 0.01, true, false);
     //dbg!(&tt.stations[9032], &tt.stations[34734]);
     let q = Query {
@@ -110,11 +160,11 @@ fn gtfs_with_contr() {
     let mut store = distribution_store::Store::new();
     store.load_distributions("./data/ch_sbb.csv");
 
-    let mut tt = gtfs::load_gtfs_cache(CACHE_PATH);
+    let mut tt = gtfs::load_gtfs_cache(CACHE_PATH, 1);
     //gtfs::shorten_footpaths(&mut tt.stations);
     let mut env = topocsa::Environment::new(&mut store, &mut tt.connections, &tt.stations, &mut tt.cut, &mut tt.order, 0, 0.0, 0.0, true, false);
     let contr = gtfs::get_station_contraction(&tt.stations);
-    env.set_station_contraction(&contr);
+    env.set_station_contraction(&contr).unwrap();
     let q = Query {
         origin_idx: 27224,
         destination_idx: 2645,
@@ -138,7 +188,7 @@ fn gtfs_with_extended_walking() {
     let mut store = distribution_store::Store::new();
     store.load_distributions("./data/ch_sbb.csv");
 
-    let mut tt = gtfs::load_gtfs_cache(CACHE_PATH);
+    let mut tt = gtfs::load_gtfs_cache(CACHE_PATH, 1);
 
     let contr = gtfs::get_station_contraction(&tt.stations);
     let q = Query {
@@ -149,7 +199,7 @@ fn gtfs_with_extended_walking() {
     };
     let rtree = walking::init_rtree(&tt.stations);
     println!("querying...");
-    let (_walking_tt, walking_origin_idx, _walking_destination_idx, station_labels) = walking::query_with_extended_walking(&mut store, &mut tt, q, 7200, &contr, &rtree);
+    let (_walking_tt, walking_origin_idx, _walking_destination_idx, station_labels) = walking::query_with_extended_walking(&mut store, &mut tt, q, 7200, &contr, &rtree, 1);
     let origin_deps = &station_labels[contr.stop_to_group[walking_origin_idx]];
     let best_conn = origin_deps.last().unwrap();
     let second_best_conn = &origin_deps[origin_deps.len()/3];
@@ -169,17 +219,19 @@ fn gtfs_with_relevant_stations() {
         connections: vec![],
         cut: FxHashSet::default(),
         order: vec![],
-        transport_and_day_to_connection_id: HashMap::new()
+        transport_and_day_to_connection_id: HashMap::new(),
+        ticks_per_minute: types::one_tick_per_minute(),
+        base_len: None
     };
     let mut routes = vec![];
     let t = gtfs::load_timetable(GTFS_PATH, day(2023, 11, 2), day(2023, 11, 3));
-    tt.transport_and_day_to_connection_id = gtfs::retrieve(&t, &mut tt.stations, &mut routes, &mut tt.connections);
+    tt.transport_and_day_to_connection_id = gtfs::retrieve(&t, &mut tt.stations, &mut routes, &mut tt.connections, 1);
     let mut env = topocsa::Environment::new(&mut store, &mut tt.connections, &tt.stations, &mut tt.cut, &mut tt.order, 7200, 0.01, 0.001, true, false);
     let contr = gtfs::get_station_contraction(&tt.stations);
-    env.set_station_contraction(&contr);
+    env.set_station_contraction(&contr).unwrap();
     env.preprocess();
     let path = format!("{}2023-11-02T07:00:03+01:00.gtfsrt", GTFSRT_PATH);
-    gtfs::load_realtime(&path, &t, &tt.transport_and_day_to_connection_id,
+    gtfs::load_realtime(&path, &t, &tt.transport_and_day_to_connection_id, 1,
         |connection_id: usize, is_departure: bool, location_idx: Option<usize>, in_out_allowed: Option<bool>, delay: Option<i16>| {
             env.update(connection_id, is_departure, location_idx, in_out_allowed, delay)
         }
@@ -216,23 +268,23 @@ fn gtfs_with_rt() {
     let mut store = distribution_store::Store::new();
     store.load_distributions("./data/ch_sbb.csv");
 
-    let mut tt = gtfs::load_gtfs_cache(CACHE_PATH);
+    let mut tt = gtfs::load_gtfs_cache(CACHE_PATH, 1);
     let t = gtfs::load_timetable(GTFS_PATH, day(2023, 11, 2), day(2023, 11, 3));
     let mut env = topocsa::Environment::new(&mut store, &mut tt.connections, &tt.stations, &mut tt.cut, &mut tt.order, 7850, 0.01, 0.0, true, false);
     let path = format!("{}2023-11-02T07:00:03+01:00.gtfsrt", GTFSRT_PATH);
-    gtfs::load_realtime(&path, &t, &tt.transport_and_day_to_connection_id,
+    gtfs::load_realtime(&path, &t, &tt.transport_and_day_to_connection_id, 1,
         |connection_id: usize, is_departure: bool, location_idx: Option<usize>, in_out_allowed: Option<bool>, delay: Option<i16>| {
             env.update(connection_id, is_departure, location_idx, in_out_allowed, delay)
         }
     );
     let path = format!("{}2023-11-02T11:50:03+01:00.gtfsrt", GTFSRT_PATH);
-    gtfs::load_realtime(&path, &t, &tt.transport_and_day_to_connection_id,
+    gtfs::load_realtime(&path, &t, &tt.transport_and_day_to_connection_id, 1,
         |connection_id: usize, is_departure: bool, location_idx: Option<usize>, in_out_allowed: Option<bool>, delay: Option<i16>| {
             env.update(connection_id, is_departure, location_idx, in_out_allowed, delay)
         }
     );
     let contr = gtfs::get_station_contraction(&tt.stations);
-    env.set_station_contraction(&contr);
+    env.set_station_contraction(&contr).unwrap();
     
     let q = Query {
         origin_idx: 27224,
@@ -258,24 +310,24 @@ fn load_only_gtfs_with_rt() {
     let mut tt = gtfs::GtfsTimetable::new();
     let mut routes = vec![];
     let t = gtfs::load_timetable("/gtfs/swiss-gtfs/2024-01-15/", day(2024, 1, 15), day(2024, 1, 16));
-    let mapping = gtfs::retrieve(&t, &mut tt.stations, &mut routes, &mut tt.connections);    
+    let mapping = gtfs::retrieve(&t, &mut tt.stations, &mut routes, &mut tt.connections, 1);    
     let mut env = topocsa::Environment::new(&mut store, &mut tt.connections, &tt.stations, &mut tt.cut, &mut tt.order, 7800, 0.01, 0.01, true, false);
     let path = "/gtfs/swiss-gtfs-rt/2024-01-15/2024-01-15T10:14:03+01:00.gtfsrt";
-    gtfs::load_realtime(&path, &t, &mapping,
+    gtfs::load_realtime(&path, &t, &mapping, 1,
         |connection_id: usize, is_departure: bool, location_idx: Option<usize>, in_out_allowed: Option<bool>, delay: Option<i16>| {
             env.update(connection_id, is_departure, location_idx, in_out_allowed, delay)
         }
     );
     env.preprocess();
     let path = "/gtfs/swiss-gtfs-rt/2024-01-15/2024-01-15T15:38:03+01:00.gtfsrt";
-    gtfs::load_realtime(&path, &t, &mapping,
+    gtfs::load_realtime(&path, &t, &mapping, 1,
         |connection_id: usize, is_departure: bool, location_idx: Option<usize>, in_out_allowed: Option<bool>, delay: Option<i16>| {
             env.update(connection_id, is_departure, location_idx, in_out_allowed, delay)
         }
     );
     env.preprocess();
     let contr = gtfs::get_station_contraction(&tt.stations);
-    env.set_station_contraction(&contr);
+    env.set_station_contraction(&contr).unwrap();
 
     let q = Query {
         origin_idx: 10000,
@@ -305,11 +357,11 @@ fn gtfs_small() {
     let mut connections = vec![];
     let mut order = vec![];
     let t = gtfs::load_timetable("./tests/fixtures/gtfs_minimal_swiss/", day(2024, 1, 1), day(2024, 1, 10));
-    let map = gtfs::retrieve(&t, &mut stations, &mut routes, &mut connections);
+    let map = gtfs::retrieve(&t, &mut stations, &mut routes, &mut connections, 1);
     let mut cut = FxHashSet::default();
 
     let mut env = topocsa::prepare(&mut store, &mut connections, &stations, &mut cut, &mut order, 0, 0.01, false);
-    gtfs::load_realtime("./tests/fixtures/2024-01-02T01_48_02+01_00.gtfsrt", &t, &map, 
+    gtfs::load_realtime("./tests/fixtures/2024-01-02T01_48_02+01_00.gtfsrt", &t, &map, 1,
         |connection_id: usize, is_departure: bool, location_idx: Option<usize>, in_out_allowed: Option<bool>, delay: Option<i16>| {
             env.update(connection_id, is_departure, location_idx, in_out_allowed, delay)
         }
@@ -335,6 +387,39 @@ fn gtfs_small() {
 
 
 
+#[cfg(feature = "native-gtfs")]
+#[test]
+#[ignore]
+fn native_trips_matched_to_nigiri() {
+    let mut stations = vec![];
+    let mut routes = vec![];
+    let mut connections = vec![];
+    let t = gtfs::load_timetable("./tests/fixtures/gtfs_minimal_swiss/", day(2024, 1, 1), day(2024, 1, 10));
+    let map = gtfs::retrieve(&t, &mut stations, &mut routes, &mut connections, 1);
+    let native = gtfs::native::load_timetable("./tests/fixtures/gtfs_minimal_swiss/", day(2024, 1, 1), day(2024, 1, 10), 1);
+    let native_map = gtfs::native::match_connections(&native, &stations, &connections, &map, t.get_start_day_ts());
+    assert!(!native_map.is_empty());
+    let mut native_stations = vec![];
+    let mut native_connections = vec![];
+    let own_map = gtfs::native::retrieve(&native, &mut native_stations, &mut vec![], &mut native_connections);
+    for (key, id) in &native_map {
+        assert!(map.values().any(|initial| initial == id));
+        // the trips run along the same stops at the same times
+        let own_id = own_map[key];
+        let mut i = 0;
+        loop {
+            let (c, own) = (&connections[id+i], &native_connections[own_id+i]);
+            assert_eq!(stations[c.from_idx].id, native_stations[own.from_idx].id);
+            assert_eq!(stations[c.to_idx].id, native_stations[own.to_idx].id);
+            assert_eq!(c.arrival.scheduled-c.departure.scheduled, own.arrival.scheduled-own.departure.scheduled);
+            i += 1;
+            if !native_connections.get(own_id+i).is_some_and(|next| own.is_consecutive(next)) {
+                break;
+            }
+        }
+    }
+}
+
 #[test]
 #[ignore]
 fn gtfs_repeated() {
@@ -347,10 +432,10 @@ fn gtfs_repeated() {
         let mut tt = gtfs::GtfsTimetable::new();
         let mut routes = vec![];
         let t = gtfs::load_timetable(GTFS_PATH, day(2023, 11, 1), day(2023, 11+i/30, 1+i%30));
-        gtfs::retrieve(&t, &mut tt.stations, &mut routes, &mut tt.connections);    
+        gtfs::retrieve(&t, &mut tt.stations, &mut routes, &mut tt.connections, 1);    
         let mut env = topocsa::Environment::new(&mut store, &mut tt.connections, &tt.stations, &mut tt.cut, &mut tt.order, 0, 0.01, 0.01, true, true);
         let contr = gtfs::get_station_contraction(&tt.stations);
-        env.set_station_contraction(&contr);
+        env.set_station_contraction(&contr).unwrap();
         let q = Query {
             origin_idx: 10000,
             destination_idx: 20000,
@@ -0,0 +1,232 @@
+use rand::rngs::StdRng;
+use rand::Rng;
+use rand::SeedableRng;
+use rustc_hash::FxHashSet;
+use stost::connection;
+use stost::distribution;
+use stost::distribution_store;
+use stost::query::recursive;
+use stost::query::topocsa;
+use stost::query::Queriable;
+use stost::query::Query;
+use stost::types;
+
+const CASES: u64 = 300;
+
+struct Timetable {
+    stations: Vec<connection::Station>,
+    connections: Vec<connection::Connection>,
+    destination_idx: usize
+}
+
+// Random timetable with transfer times, symmetric footpaths, cancellations, realtime delays
+// and trips revisiting stations, which makes cycles likely.
+fn random_timetable(rng: &mut StdRng) -> Timetable {
+    let station_count = rng.gen_range(3..8);
+    let mut stations: Vec<connection::Station> = (0..station_count).map(|i| {
+        let mut s = connection::Station::new(i.to_string(), format!("station{}", i), vec![]);
+        s.transfer_time = rng.gen_range(0..3);
+        s
+    }).collect();
+    for from in 0..station_count {
+        for to in from+1..station_count {
+            if rng.gen_bool(0.15) {
+                let duration = rng.gen_range(1..6);
+                stations[from].footpaths.push(connection::Footpath { target_location_idx: to, duration });
+                stations[to].footpaths.push(connection::Footpath { target_location_idx: from, duration });
+            }
+        }
+    }
+    let mut connections = vec![];
+    for trip in 0..rng.gen_range(3..15) {
+        let product_type = rng.gen_range(1..3);
+        let cancelled = rng.gen_bool(0.1);
+        let mut from_idx = rng.gen_range(0..station_count);
+        let mut time = rng.gen_range(0..60);
+        for _ in 0..rng.gen_range(1..5) {
+            let to_idx = (from_idx+rng.gen_range(1..station_count)) % station_count;
+            let arrival = time+rng.gen_range(1..10);
+            let id = connections.len();
+            connections.push(connection::Connection::new(id, trip, product_type, trip as i32, cancelled,
+                from_idx, time, random_delay(rng),
+                to_idx, arrival, random_delay(rng)));
+            stations[from_idx].add_departure(id);
+            stations[to_idx].arrivals.push(id);
+            from_idx = to_idx;
+            time = arrival+rng.gen_range(0..3);
+        }
+    }
+    let destination_idx = rng.gen_range(0..station_count);
+    Timetable { stations, connections, destination_idx }
+}
+
+fn random_delay(rng: &mut StdRng) -> Option<i16> {
+    if rng.gen_bool(0.3) { Some(rng.gen_range(-2..5)) } else { None }
+}
+
+fn store() -> distribution_store::Store {
+    let mut store = distribution_store::Store::new();
+    store.insert_from_distribution(0..0, 0..0, false, 1, distribution::Distribution::uniform(-1, 4));
+    store.insert_from_distribution(0..0, 0..0, true, 1, distribution::Distribution::uniform(0, 3));
+    store.insert_from_distribution(0..0, 0..0, false, 2, distribution::Distribution::uniform(0, 6));
+    let mut departure = distribution::Distribution::uniform(-1, 3);
+    departure.feasible_probability = 0.95;
+    store.insert_from_distribution(0..0, 0..0, true, 2, departure);
+    store
+}
+
+fn query(t: &Timetable) -> Query {
+    Query { origin_idx: 0, destination_idx: t.destination_idx, start_time: 0, max_time: 200 }
+}
+
+// Destination arrivals indexed by connection id, without materialized footpaths.
+fn destination_arrivals(connections: &[connection::Connection], count: usize) -> Vec<Option<distribution::Distribution>> {
+    let mut arrivals = vec![None; count];
+    for c in connections.iter().filter(|c| c.id < count) {
+        arrivals[c.id] = c.destination_arrival.borrow().clone();
+    }
+    arrivals
+}
+
+fn run_topocsa(t: &Timetable, cut: &mut FxHashSet<(usize, usize)>, epsilon: types::MFloat, mean_only: bool) -> Vec<Option<distribution::Distribution>> {
+    let mut store = store();
+    let mut connections = t.connections.clone();
+    let mut order = vec![];
+    let mut env = topocsa::prepare(&mut store, &mut connections, &t.stations, cut, &mut order, 0, epsilon, mean_only);
+    let station_labels = env.query(query(t));
+    if mean_only {
+        // only the station labels are kept
+        let mut arrivals = vec![None; t.connections.len()];
+        for l in station_labels.into_iter().flatten() {
+            arrivals[l.connection_id] = Some(l.destination_arrival);
+        }
+        return arrivals;
+    }
+    destination_arrivals(&connections, t.connections.len())
+}
+
+// The destination arrivals of the oracle and the transfers it cut to break cycles.
+fn run_recursive(t: &Timetable) -> (Vec<Option<distribution::Distribution>>, FxHashSet<(usize, usize)>) {
+    let mut store = store();
+    let mut connections = t.connections.clone();
+    let mut order = vec![];
+    let mut env = recursive::Environment::new(&mut store, &mut connections, &t.stations, &mut order, 0);
+    env.query(query(t));
+    let cut = env.cut().clone();
+    (destination_arrivals(&connections, t.connections.len()), cut)
+}
+
+fn feasible(d: &Option<distribution::Distribution>) -> types::MFloat {
+    d.as_ref().map_or(0.0, |d| d.feasible_probability)
+}
+
+const DEFAULT_SEED: u64 = 20240;
+
+// Cases are seeded from STOST_SEED if set, otherwise from DEFAULT_SEED, and STOST_SEED=random picks a random seed.
+// The seed of each case is part of the failure messages and printed with the output of failed tests,
+// so that STOST_SEED=<seed> replays it first.
+fn for_each_case<F: FnMut(u64, &Timetable)>(mut property: F) {
+    let base_seed = match std::env::var("STOST_SEED") {
+        Ok(s) if s == "random" => rand::random::<u64>(),
+        Ok(s) => s.parse().expect("invalid STOST_SEED"),
+        Err(_) => DEFAULT_SEED
+    };
+    println!("base seed: {}", base_seed);
+    for i in 0..CASES {
+        let seed = base_seed.wrapping_add(i);
+        let mut rng = StdRng::seed_from_u64(seed);
+        property(seed, &random_timetable(&mut rng));
+    }
+}
+
+#[test]
+fn topocsa_and_recursive_agree() {
+    let mut cases_with_cycles = 0;
+    let mut compared = 0;
+    let mut skipped = vec![];
+    for_each_case(|seed, t| {
+        let mut cut = FxHashSet::default();
+        let expected = run_topocsa(t, &mut cut, 0.0, false);
+        let (actual, recursive_cut) = run_recursive(t);
+        // the topocsa preprocessing also cuts cycles through the destination, and overlapping
+        // cycles may be cut differently depending on the search order
+        if recursive_cut != cut {
+            skipped.push(seed);
+            return;
+        }
+        compared += 1;
+        if !cut.is_empty() {
+            cases_with_cycles += 1;
+        }
+        for (id, (e, a)) in expected.iter().zip(actual.iter()).enumerate() {
+            assert_eq!(feasible(e) > 0.0, feasible(a) > 0.0, "seed {} connection {}", seed, id);
+            if feasible(e) > 0.0 {
+                let (e, a) = (e.as_ref().unwrap(), a.as_ref().unwrap());
+                assert!((e.feasible_probability-a.feasible_probability).abs() < 1e-4, "seed {} connection {}: {:?} {:?}", seed, id, e, a);
+                assert!((e.mean-a.mean).abs() < 1e-3, "seed {} connection {}: {:?} {:?}", seed, id, e, a);
+                assert_eq!(e.start, a.start, "seed {} connection {}", seed, id);
+                assert_eq!(e.histogram.len(), a.histogram.len(), "seed {} connection {}", seed, id);
+                for (x, y) in e.histogram.iter().zip(a.histogram.iter()) {
+                    assert!((x-y).abs() < 1e-4, "seed {} connection {}: {:?} {:?}", seed, id, e, a);
+                }
+            }
+        }
+    });
+    println!("compared: {} skipped because of different cuts: {:?}", compared, skipped);
+    assert!(compared >= CASES*3/4, "only {} cases compared, skipped: {:?}", compared, skipped);
+    assert!(cases_with_cycles > 0);
+}
+
+#[test]
+fn mean_only_agrees_on_means() {
+    for_each_case(|seed, t| {
+        let full = run_topocsa(t, &mut FxHashSet::default(), 0.0, false);
+        let mean_only = run_topocsa(t, &mut FxHashSet::default(), 0.0, true);
+        for (id, (f, m)) in full.iter().zip(mean_only.iter()).enumerate() {
+            if feasible(f) > 1e-3 {
+                let (f, m) = (f.as_ref().unwrap(), m.as_ref().unwrap_or_else(|| panic!("seed {} connection {}: no mean only label", seed, id)));
+                assert!((f.feasible_probability-m.feasible_probability).abs() < 1e-4, "seed {} connection {}: {:?} {:?}", seed, id, f, m);
+                assert!((f.mean-m.mean).abs() < 1e-3, "seed {} connection {}: {:?} {:?}", seed, id, f, m);
+            }
+        }
+    });
+}
+
+#[test]
+fn distributions_are_normalized() {
+    for_each_case(|seed, t| {
+        for (epsilon, mean_only) in [(0.0, false), (0.0, true), (0.001, false), (0.01, true)] {
+            for d in run_topocsa(t, &mut FxHashSet::default(), epsilon, mean_only).iter().flatten() {
+                assert!(d.feasible_probability >= 0.0 && d.feasible_probability <= 1.0, "seed {} epsilon {}: {:?}", seed, epsilon, d);
+                // mean only labels have no histogram
+                if mean_only || d.feasible_probability <= 0.0 {
+                    continue;
+                }
+                let sum: types::MFloat = d.histogram.iter().sum();
+                assert!((sum-1.0).abs() < 1e-3, "seed {} epsilon {}: {:?}", seed, epsilon, d);
+                assert!(d.histogram.iter().all(|p| *p >= 0.0), "seed {} epsilon {}: {:?}", seed, epsilon, d);
+                assert!((d.mean-d.mean()).abs() < 1e-2, "seed {} epsilon {}: {:?}", seed, epsilon, d);
+            }
+        }
+    });
+}
+
+#[test]
+fn epsilon_close_to_exact() {
+    for_each_case(|seed, t| {
+        let mut cut = FxHashSet::default();
+        let exact = run_topocsa(t, &mut cut, 0.0, false);
+        // with epsilon > 0, cycles are unraveled without cutting transfers, so only acyclic cases are comparable
+        if !cut.is_empty() {
+            return;
+        }
+        let approx = run_topocsa(t, &mut FxHashSet::default(), 0.001, false);
+        for (id, (e, a)) in exact.iter().zip(approx.iter()).enumerate() {
+            if feasible(e) > 0.01 {
+                assert!((feasible(e)-feasible(a)).abs() < 0.01, "seed {} connection {}: {:?} {:?}", seed, id, e, a);
+                let (e, a) = (e.as_ref().unwrap(), a.as_ref().unwrap());
+                assert!((e.mean-a.mean).abs() < 0.1, "seed {} connection {}: {:?} {:?}", seed, id, e, a);
+            }
+        }
+    });
+}
@@ -5,6 +5,11 @@ use stost::connection;
 use stost::distribution_store;
 use stost::distribution;
 use stost::types;
+use stost::query::topocsa;
+use stost::query::recursive;
+use stost::query::Queriable;
+use stost::query::Query;
+use rustc_hash::FxHashSet;
 
 fn setup<'a>() -> (distribution_store::Store, connection::Route, connection::Station, connection::Station, connection::Station) {
     let store = distribution_store::Store::new();
@@ -241,6 +246,42 @@ fn with_uniform() {
     assert_float_relative_eq!(a.histogram[14], 0.05);
 }
 
+#[test]
+fn coarse_destination_buckets() {
+    let (mut store, _route, mut station0, mut station1, station2) = setup();
+    let mut connections = vec![
+        connection::Connection::new(0, 0, 1, 1, false, 0, 10, None, 1, 15, Some(3)),
+        connection::Connection::new(1, 0, 1, 2, false, 1, 20, None, 2, 30, None),
+        connection::Connection::new(2, 0, 1, 3, false, 1, 30, None, 2, 40, Some(1))
+    ];
+    station0.add_departure(0);
+    station1.add_departure(1);
+    station1.add_departure(2);
+    let stations = vec![station0, station1, station2];
+    store.insert_from_distribution(0..5, 0..15, false, 1, distribution::Distribution::uniform(-5, 10));
+    store.insert_from_distribution(0..5, 35..45, false, 1, distribution::Distribution::uniform(-2, 6));
+
+    let mut cut = FxHashSet::default();
+    let mut order = vec![];
+    let mut env = topocsa::Environment::new(&mut store, &mut connections, &stations, &mut cut, &mut order, 5, 0.0, 0.0, false, false);
+    env.set_destination_bucket_width(5);
+    env.preprocess();
+    env.query(Query { origin_idx: 0, destination_idx: 2, start_time: 0, max_time: 100 });
+
+    // same as with_uniform in buckets of 5
+    let c0 = connections.iter().filter(|c| c.id == 0).last().unwrap();
+    let binding = c0.destination_arrival.borrow();
+    let a = binding.as_ref().unwrap();
+    assert_eq!(a.bucket_width, 5);
+    assert_eq!(a.start, 30);
+    assert_float_relative_eq!(a.mean, 33.45);
+    assert_float_relative_eq!(a.feasible_probability, 1.0);
+    assert_eq!(a.histogram.len(), 3);
+    assert_float_relative_eq!(a.histogram[0], 0.7);
+    assert_float_relative_eq!(a.histogram[1], 0.05);
+    assert_float_relative_eq!(a.histogram[2], 0.25);
+}
+
 #[test]
 fn infinite_loop() {
     let (mut store, _route, mut station0, mut station1, station2) = setup();
@@ -620,6 +661,39 @@ fn with_cancelled() {
 }
 
 
+#[test]
+fn equal_means_independent_of_order() {
+    // two departures with the same destination arrival, given in both orders
+    let query = |swapped: bool| {
+        let (mut store, _route, mut station0, mut station1, station2) = setup();
+        let (first, second) = if swapped { (2, 1) } else { (1, 2) };
+        let c0 = connection::Connection::new(0, 0, 1, 0, false,
+            0, 5, None,
+            1, 10, Some(0));
+        let early = connection::Connection::new(first, 0, 1, 1, false,
+            1, 11, Some(0),
+            2, 30, None);
+        let late = connection::Connection::new(second, 0, 1, 2, false,
+            1, 14, Some(0),
+            2, 30, None);
+        let mut connections = vec![c0, early, late];
+        connections.sort_by_key(|c| c.id);
+        station0.add_departure(0);
+        station1.add_departure(1);
+        station1.add_departure(2);
+        let stations = vec![station0, station1, station2];
+        store.insert_from_distribution(0..5, 0..20, false, 1, distribution::Distribution::uniform(-5, 10));
+        store.insert_from_distribution(0..5, 0..20, true, 1, distribution::Distribution::uniform(0, 3));
+        stost::query::query(&mut store, &mut connections, &stations, 0, 2, 0, 100, 5);
+        let c0 = connections.iter().find(|c| c.id == 0).unwrap();
+        let a = c0.destination_arrival.borrow().clone().unwrap();
+        (a.start, a.histogram, a.feasible_probability)
+    };
+    let expected = query(false);
+    assert!(expected.2 > 0.0 && expected.2 < 1.0);
+    assert_eq!(expected, query(true));
+}
+
 #[test]
 fn with_out_disallowed() {
     let (mut store, _route, mut station0, mut station1, station2) = setup();
@@ -658,3 +732,63 @@ fn with_out_disallowed() {
     assert_float_relative_eq!(a.feasible_probability, 1.0);
     assert_eq!(a.histogram.len(), 1);
 }
+
+#[test]
+fn recursive_matches_topocsa() {
+    let (mut store, _route, mut station0, mut station1, mut station2) = setup();
+
+    let c0 = connection::Connection::new(0, 0, 1, 0, false,
+        1, 8, None,
+        0, 9, None);
+
+    let c1 = connection::Connection::new(1, 0, 1, 1, false,
+        0, 10, Some(0),
+        1, 11, None);
+
+    let c2 = connection::Connection::new(2, 0, 1, 2, false,
+        1, 12, None,
+        2, 13, None);
+
+    let c3 = connection::Connection::new(3, 0, 1, 3, false,
+        0, 11, Some(0),
+        2, 14, None);
+
+    let c4 = connection::Connection::new(4, 0, 1, 4, false,
+        2, 15, None,
+        0, 16, Some(0));
+
+    let c5 = connection::Connection::new(5, 0, 1, 5, false,
+        1, 20, None,
+        3, 21, None);
+
+    let mut connections = vec![c0, c1, c2, c3, c4, c5];
+    
+    let mut station3 = connection::Station::new("4".to_string(), "station3".to_string(), vec![]);
+    station1.add_departure(0);
+    station0.add_departure(1);
+    station1.add_departure(2);
+    station0.add_departure(3);
+    station2.add_departure(4);
+    station1.add_departure(5);
+    station3.arrivals.push(5);
+    let stations = vec![station0, station1, station2, station3];
+
+    store.insert_from_distribution(0..5, 0..20, false, 1, distribution::Distribution::uniform(-5, 9));
+    store.insert_from_distribution(0..5, 0..20, true, 1, distribution::Distribution::uniform(-5, 9));
+
+    let mut recursive_connections = connections.clone();
+    let mut cut = FxHashSet::default();
+    topocsa::prepare_and_query(&mut store, &mut connections, &stations, &mut cut, 0, 3, 0, 100, 5, 0.0, false);
+    assert!(!cut.is_empty());
+
+    let mut order = vec![];
+    let mut env = recursive::Environment::new(&mut store, &mut recursive_connections, &stations, &mut order, 5);
+    env.query(Query { origin_idx: 0, destination_idx: 3, start_time: 0, max_time: 100 });
+    assert_eq!(env.cut(), &cut);
+
+    for c in &connections {
+        let expected = c.destination_arrival.borrow();
+        let actual = recursive_connections[c.id].destination_arrival.borrow();
+        assert_eq!(expected.as_ref().map(|d| (d.start, d.histogram.clone(), d.feasible_probability)), actual.as_ref().map(|d| (d.start, d.histogram.clone(), d.feasible_probability)));
+    }
+}
@@ -0,0 +1,66 @@
+#[macro_use]
+extern crate assert_float_eq;
+
+use stost::connection;
+use stost::distribution_store;
+use stost::gtfs::GtfsTimetable;
+use stost::types;
+use stost::walking::WalkingProfile;
+use stost::wire::serde::{from_mtime, to_mtime};
+
+#[test]
+fn thirty_second_ticks() {
+    assert_eq!(types::minutes(3, 2), 6);
+    assert_eq!(types::seconds_per_tick(2), 30);
+
+    assert_eq!(to_mtime(1000+90, 1000, 2), 3);
+    assert_eq!(to_mtime(1000+100, 1000, 2), 3);
+    assert_eq!(from_mtime(3, 1000, 2), 1000+90);
+    assert_eq!(to_mtime(1000+90, 1000, 1), 2);
+
+    let profile = WalkingProfile::named("luggage").unwrap();
+    assert_eq!(profile.expected_duration(10, 2), 15);
+}
+
+#[test]
+fn store_with_thirty_second_ticks() {
+    let path = std::env::temp_dir().join("stost_resolution.csv");
+    std::fs::write(&path, "product_type_id,is_departure,prior_ttl_bucket,prior_delay_bucket,latest_sample_delay_bucket,sample_count\n\
+        1,True,NULL,NULL,\"[0,1)\",50\n\
+        1,True,NULL,NULL,\"[1,3)\",50\n\
+        2,True,NULL,NULL,\"[0,1)\",100\n").unwrap();
+    let mut store = distribution_store::Store::with_ticks_per_minute(2);
+    store.load_distributions(path.to_str().unwrap());
+    let d = store.delay_distribution(&connection::StopInfo::new(10, None), true, 1, 0);
+    assert_eq!(d.start, 10);
+    assert_eq!(d.histogram.len(), 6);
+    assert_float_absolute_eq!(d.histogram[0], 0.25);
+    assert_float_absolute_eq!(d.histogram[5], 0.125);
+    assert_float_absolute_eq!(d.mean, 12.0);
+    assert_eq!(store.max_delay, 6);
+
+    // the same distributions in minutes
+    let mut minutes = distribution_store::Store::new();
+    minutes.load_distributions(path.to_str().unwrap());
+    assert_eq!(minutes.max_delay, 3);
+
+    let snapshot = std::env::temp_dir().join("stost_resolution.snapshot");
+    store.save_snapshot(snapshot.to_str().unwrap());
+    let loaded = distribution_store::Store::load_snapshot(snapshot.to_str().unwrap());
+    assert_eq!(loaded.ticks_per_minute(), 2);
+    assert_eq!(loaded.max_delay, 6);
+}
+
+#[test]
+fn timetable_cache_resolution() {
+    let path = std::env::temp_dir().join("stost_resolution.cache");
+    std::fs::write(&path, rmp_serde::to_vec(&GtfsTimetable::with_ticks_per_minute(2)).unwrap()).unwrap();
+    assert_eq!(stost::gtfs::load_gtfs_cache(path.to_str().unwrap(), 2).ticks_per_minute, 2);
+    assert!(std::panic::catch_unwind(|| stost::gtfs::load_gtfs_cache(path.to_str().unwrap(), 1)).is_err());
+}
+
+#[test]
+#[should_panic(expected = "ticks per minute must divide 60")]
+fn invalid_resolution() {
+    distribution_store::Store::with_ticks_per_minute(7);
+}